@@ -0,0 +1,129 @@
+use art32_emu::cpu::block::TRANSLATION_THRESHOLD;
+use art32_emu::cpu::instruction::*;
+use art32_emu::cpu::{BranchCondition, Register};
+use art32_emu::system::{Art32, KERNEL_RAM_START};
+use std::time::Instant;
+
+const WARMUP_INSTRUCTIONS: u64 = 1_000_000;
+const INSTRUCTIONS: u64 = 50_000_000;
+
+fn assemble(program: &[Instruction]) -> Vec<u8> {
+    let mut image = Vec::new();
+    for inst in program {
+        let (word, size) = inst.encode();
+        image.extend_from_slice(&word.to_le_bytes()[..(size as usize)]);
+    }
+    image
+}
+
+/// Sums and increments a 1kB buffer in system RAM over and over again.
+fn guest_loop() -> Vec<u8> {
+    use Instruction::*;
+    use Register::*;
+
+    let setup = [
+        Ldui {
+            rd: S0,
+            imm: 0x2000_0000,
+        },
+        Ldi16 { rd: A0, imm: 0 },
+        Ldi16 { rd: A1, imm: 0 },
+        AluI32 {
+            op: AluOp::Add,
+            rd: A2,
+            rs1: Zero,
+            imm: 256,
+        },
+    ];
+
+    let body = [
+        Load32 {
+            op: LoadOp::Word,
+            rd: A3,
+            rb: S0,
+            offset: 0,
+        },
+        Alu16 {
+            op: AluOp::Add,
+            rd: A0,
+            rs: A3,
+        },
+        Addi16 { rd: A3, imm: 1 },
+        Store32 {
+            op: StoreOp::Word,
+            rs: A3,
+            rb: S0,
+            offset: 0,
+        },
+        AluI32 {
+            op: AluOp::Add,
+            rd: S0,
+            rs1: S0,
+            imm: 4,
+        },
+        Addi16 { rd: A1, imm: 1 },
+        Cmp16 { rs1: A1, rs2: A2 },
+    ];
+
+    let setup_size: u32 = setup.iter().map(Instruction::size).sum();
+    let body_size: u32 = body.iter().map(Instruction::size).sum();
+
+    let loop_branch = Branch16 {
+        cond: BranchCondition::Lt,
+        offset: -((body_size + 2) as i32),
+    };
+    let restart = Branch32 {
+        cond: BranchCondition::True,
+        offset: -((setup_size + body_size + 2 + 4) as i32),
+    };
+
+    let mut program = Vec::new();
+    program.extend_from_slice(&setup);
+    program.extend_from_slice(&body);
+    program.push(loop_branch);
+    program.push(restart);
+    assemble(&program)
+}
+
+fn run(art32: &mut Art32, instructions: u64) -> u64 {
+    let start = art32.retired_instructions();
+    while art32.retired_instructions() - start < instructions {
+        art32.step().unwrap();
+    }
+    art32.retired_instructions() - start
+}
+
+fn measure(kernel: &[u8], instruction_cache: bool, block_translation: bool) -> f64 {
+    let mut art32 = Art32::with_kernel(kernel);
+    art32.set_instruction_cache(instruction_cache);
+    art32.set_block_translation(block_translation.then_some(TRANSLATION_THRESHOLD));
+
+    run(&mut art32, WARMUP_INSTRUCTIONS);
+
+    let start = Instant::now();
+    let retired = run(&mut art32, INSTRUCTIONS);
+    let elapsed = start.elapsed();
+
+    (retired as f64) / elapsed.as_secs_f64() / 1_000_000.0
+}
+
+fn main() {
+    let kernel = guest_loop();
+
+    println!("guest loop at 0x{KERNEL_RAM_START:08X}, {INSTRUCTIONS} instructions");
+
+    let uncached = measure(&kernel, false, false);
+    println!("interpreter:                   {uncached:>8.2} MIPS");
+
+    let cached = measure(&kernel, true, false);
+    println!(
+        "interpreter + predecode cache: {cached:>8.2} MIPS ({:.2}x)",
+        cached / uncached
+    );
+
+    let blocks = measure(&kernel, true, true);
+    println!(
+        "block translation:             {blocks:>8.2} MIPS ({:.2}x)",
+        blocks / uncached
+    );
+}
@@ -2,4 +2,6 @@
     brk => asm { envcall 0 }
     rst => asm { envcall 1 }
     err => asm { envcall 2 }
+    pass => asm { envcall 3 }
+    fail => asm { envcall 4 }
 }
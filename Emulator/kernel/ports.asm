@@ -5,21 +5,56 @@ SOFT_INT_TABLE_END = 0x20
 ILLEGAL_INSTRUCTION_SLOT_ADDR = 0x20
 ACCESS_VIOLATION_SLOT_ADDR = 0x21
 UNALIGNED_ACCESS_SLOT_ADDR = 0x22
+PAGE_FAULT_SLOT_ADDR = 0x23
+FETCH_FAULT_SLOT_ADDR = 0x24
 
 INT_MASK_ADDR = 0x30
 INT_PENDING_ADDR = 0x31
 PRIV_LEVEL_ADDR = 0x32
 INT_RET_ADDR = 0x33
+EXCEPTION_CAUSE_ADDR = 0x34
+FAULT_ADDRESS_ADDR = 0x35
+FAULT_INSTRUCTION_ADDR = 0x36
+INT_NESTING_ADDR = 0x37
+INT_LEVEL_ADDR = 0x38
+INT_STACK_ADDR = 0x39
+INT_DEPTH_ADDR = 0x3A
 
 ALT_FLAGS_REG_ADDR = 0x3F
 ALT_REGS_START = 0x40
 ALT_REGS_END = 0x60
 
+CYCLE_COUNT_LOW_ADDR = 0x60
+CYCLE_COUNT_HIGH_ADDR = 0x61
+RETIRED_COUNT_LOW_ADDR = 0x62
+RETIRED_COUNT_HIGH_ADDR = 0x63
+STALL_COUNT_LOW_ADDR = 0x64
+STALL_COUNT_HIGH_ADDR = 0x65
+
+INT_PRIORITY_TABLE_START = 0x70
+INT_PRIORITY_TABLE_END = 0x80
+
 TIMER_LOW_ADDR = 0x80
 TIMER_HIGH_ADDR = 0x81
 TIMER_ACCURACY_ADDR = 0x82
+TIMER_CONTROL_ADDR = 0x83
+TIMER_COMPARE_ADDR = 0x84
+TIMER_COUNT_ADDR = 0x85
+TIMER_STATUS_ADDR = 0x86
+TIMER_SLOT_ADDR = 0x87
 
 SERIAL_OUT_DATA_ADDR = 0x90
 SERIAL_OUT_COUNT_ADDR = 0x91
 SERIAL_IN_DATA_ADDR = 0x92
 SERIAL_IN_COUNT_ADDR = 0x93
+SERIAL_CONTROL_ADDR = 0x94
+SERIAL_SLOT_ADDR = 0x95
+SERIAL_STATUS_ADDR = 0x96
+
+PAGE_TABLE_BASE_ADDR = 0xA0
+PAGING_CONTROL_ADDR = 0xA1
+TLB_FLUSH_ADDR = 0xA2
+
+PROTECTION_CONTROL_ADDR = 0xA8
+REGION_TABLE_START = 0xB0
+REGION_TABLE_END = 0xD0
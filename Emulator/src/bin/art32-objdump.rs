@@ -0,0 +1,52 @@
+use art32_emu::cpu::disasm::disassemble_image;
+use art32_emu::system::KERNEL_RAM_START;
+use clap::Parser;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+#[derive(Parser)]
+#[command(about = "Prints an annotated disassembly of an Art32 binary image")]
+struct Args {
+    /// Raw binary image to disassemble
+    image: PathBuf,
+
+    /// Address the image is loaded at
+    #[arg(long, default_value_t = KERNEL_RAM_START, value_parser = art32_emu::parse_u32)]
+    base: u32,
+
+    /// Also print runs of zero halfwords instead of collapsing them
+    #[arg(long)]
+    all: bool,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    let image = match std::fs::read(&args.image) {
+        Ok(image) => image,
+        Err(err) => {
+            eprintln!("error: cannot read `{}`: {err}", args.image.display());
+            return ExitCode::FAILURE;
+        }
+    };
+
+    println!("{}:     file format art32-raw\n", args.image.display());
+    println!("Disassembly starting at 0x{:0>8x}:\n", args.base);
+
+    let mut zero_run = 0usize;
+    for line in disassemble_image(&image, args.base) {
+        if !args.all && (line.len == 2) && (line.word == 0) {
+            zero_run += 1;
+            match zero_run {
+                1 => println!("{line}"),
+                2 => println!("\t..."),
+                _ => (),
+            }
+        } else {
+            zero_run = 0;
+            println!("{line}");
+        }
+    }
+
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,146 @@
+use art32_emu::cli::SystemArgs;
+use art32_emu::debug::dap::{DapServer, DapTransport};
+use art32_emu::debug::gdb::GdbServer;
+use art32_emu::debug::SessionEnd;
+use art32_emu::system::{Art32, EnvAction, RunBudget, RunExit, SerialInput, SerialOutput};
+use clap::Parser;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::process::ExitCode;
+use std::time::Duration;
+
+// `Break` and `Pass` exit with success, `Error` and `Fail` with failure, the others follow
+// `timeout(1)`.
+const MACHINE_CHECK_EXIT_CODE: u8 = 3;
+const BUDGET_EXHAUSTED_EXIT_CODE: u8 = 124;
+const SETUP_ERROR_EXIT_CODE: u8 = 125;
+
+#[derive(Parser)]
+#[command(about = "Runs an Art32 guest without a display, until it breaks or fails")]
+struct Args {
+    #[command(flatten)]
+    system: SystemArgs,
+
+    /// Stop after retiring this many instructions
+    #[arg(long)]
+    max_instructions: Option<u64>,
+
+    /// Stop after this many seconds of host time
+    #[arg(long, value_parser = parse_seconds)]
+    max_time: Option<Duration>,
+
+    /// Wait for a GDB client on this address, e.g. `127.0.0.1:1234`, before running. The guest
+    /// keeps running once the client detaches
+    #[arg(long)]
+    gdb: Option<SocketAddr>,
+
+    /// Serve a Debug Adapter Protocol client on `stdio` or on an address, e.g. `127.0.0.1:4711`,
+    /// before running. With `stdio` the serial output is sent to the client. Source breakpoints
+    /// need a `lineMap` launch argument, the `-f addrspan` output of customasm
+    #[arg(long, conflicts_with = "gdb")]
+    dap: Option<DapTransport>,
+}
+
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    s.parse::<f64>()
+        .ok()
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .ok_or_else(|| format!("invalid number of seconds `{s}`"))
+}
+
+fn serve_dap(transport: &DapTransport, art32: &mut Art32) -> io::Result<SessionEnd> {
+    match transport {
+        DapTransport::Stdio => DapServer::new(io::stdin(), io::stdout()).serve(art32),
+        DapTransport::Tcp(addr) => {
+            eprintln!("waiting for a DAP client at {addr}");
+            DapServer::accept(*addr)?.serve(art32)
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let mut args = Args::parse();
+
+    // the serial output goes to the client while it is connected
+    let mut serial_to_client = false;
+    if args.dap == Some(DapTransport::Stdio) {
+        if args.system.serial_input == Some(SerialInput::Stdin) {
+            eprintln!("error: the serial input cannot be stdin while debugging on stdio");
+            return ExitCode::from(SETUP_ERROR_EXIT_CODE);
+        }
+        if args.system.serial_output == SerialOutput::Stdout {
+            args.system.serial_output = SerialOutput::Buffer;
+            serial_to_client = true;
+        }
+    }
+
+    let mut art32 = match args.system.build() {
+        Ok(art32) => art32,
+        Err(err) => {
+            eprintln!("error: {err}");
+            return ExitCode::from(SETUP_ERROR_EXIT_CODE);
+        }
+    };
+    if let Err(err) = args.system.connect_serial(&mut art32) {
+        eprintln!("error: {err}");
+        return ExitCode::from(SETUP_ERROR_EXIT_CODE);
+    }
+
+    let session = if let Some(addr) = args.gdb {
+        eprintln!("waiting for gdb at {addr}");
+        Some(GdbServer::accept(addr).and_then(|mut server| server.serve(&mut art32)))
+    } else {
+        args.dap
+            .as_ref()
+            .map(|transport| serve_dap(transport, &mut art32))
+    };
+    match session {
+        None | Some(Ok(SessionEnd::Detached)) => {}
+        Some(Ok(SessionEnd::Killed)) => {
+            art32.set_serial_input(None).unwrap();
+            art32.set_serial_output(None).unwrap();
+            return ExitCode::SUCCESS;
+        }
+        Some(Err(err)) => {
+            eprintln!("error: debugger connection failed: {err}");
+            return ExitCode::from(SETUP_ERROR_EXIT_CODE);
+        }
+    }
+    if serial_to_client {
+        art32.set_serial_output(Some(SerialOutput::Stdout)).unwrap();
+    }
+
+    let exit = art32.run(RunBudget {
+        instructions: args.max_instructions,
+        cycles: None,
+        time: args.max_time,
+    });
+
+    // restores the terminal and flushes the serial output
+    art32.set_serial_input(None).unwrap();
+    art32.set_serial_output(None).unwrap();
+    std::io::stdout().flush().unwrap();
+
+    match exit {
+        RunExit::Env(EnvAction::Error) => {
+            eprintln!("system caused an error");
+            ExitCode::FAILURE
+        }
+        RunExit::Env(EnvAction::Fail) => {
+            eprintln!("guest test failed");
+            ExitCode::FAILURE
+        }
+        RunExit::Env(_) => ExitCode::SUCCESS,
+        RunExit::MachineCheck(machine_check) => {
+            eprintln!("machine check: {machine_check}");
+            ExitCode::from(MACHINE_CHECK_EXIT_CODE)
+        }
+        RunExit::BudgetExhausted => {
+            eprintln!(
+                "budget exhausted after {} instructions",
+                art32.retired_instructions()
+            );
+            ExitCode::from(BUDGET_EXHAUSTED_EXIT_CODE)
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use art32_emu::cli::{write_report, BoardArgs, ReportFormat, TestResult};
+use art32_emu::system::{ImageSource, RunBudget, SerialOutput};
+use clap::Parser;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+const SETUP_ERROR_EXIT_CODE: u8 = 125;
+
+#[derive(Parser)]
+#[command(
+    about = "Boots every image in a directory as a guest test and reports the results",
+    long_about = "Boots every image in a directory as a guest test and reports the results.\n\n\
+        A test passes with `envcall 3` (or a `brk`) and fails with `envcall 4` (or an `err`), \
+        machine checks and running out of cycles fail it too. Boards with a `test_exit` device \
+        can also end a test by writing 0 (pass) or any other value (fail) to it."
+)]
+struct Args {
+    /// Directory of the test images, run in the order of their file names
+    dir: PathBuf,
+
+    #[command(flatten)]
+    board: BoardArgs,
+
+    /// Fail a test that runs for more than this many cycles
+    #[arg(long, default_value_t = 100_000_000)]
+    max_cycles: u64,
+
+    #[arg(long, value_enum, default_value_t = ReportFormat::Tap)]
+    format: ReportFormat,
+
+    /// Write the report to this file instead of stdout
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+fn test_images(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            paths.push(entry.path());
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+fn run_test(args: &Args, path: &Path) -> TestResult {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let boot = ImageSource {
+        path: path.to_owned(),
+        addr: None,
+    };
+    let mut art32 = match args.board.build(Some(&boot)) {
+        Ok(art32) => art32,
+        Err(err) => return TestResult::setup_error(name, err),
+    };
+    if let Err(err) = art32.set_serial_output(Some(SerialOutput::Buffer)) {
+        return TestResult::setup_error(name, format!("cannot open serial output: {err}"));
+    }
+
+    let budget = RunBudget {
+        cycles: Some(args.max_cycles),
+        ..Default::default()
+    };
+    TestResult::run(name, &mut art32, budget)
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    let paths = match test_images(&args.dir) {
+        Ok(paths) => paths,
+        Err(err) => {
+            eprintln!("error: cannot read `{}`: {err}", args.dir.display());
+            return ExitCode::from(SETUP_ERROR_EXIT_CODE);
+        }
+    };
+    let results: Vec<_> = paths.iter().map(|path| run_test(&args, path)).collect();
+
+    let suite = args
+        .dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "art32".to_owned());
+    let written = match &args.output {
+        Some(path) => File::create(path).and_then(|mut file| {
+            write_report(args.format, &suite, &results, &mut file)?;
+            file.flush()
+        }),
+        None => {
+            let mut stdout = std::io::stdout().lock();
+            write_report(args.format, &suite, &results, &mut stdout).and_then(|()| stdout.flush())
+        }
+    };
+    if let Err(err) = written {
+        eprintln!("error: cannot write report: {err}");
+        return ExitCode::from(SETUP_ERROR_EXIT_CODE);
+    }
+
+    if results.iter().all(TestResult::passed) {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
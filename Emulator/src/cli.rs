@@ -0,0 +1,134 @@
+use crate::cpu::block::TRANSLATION_THRESHOLD;
+use crate::cpu::TimingModel;
+use crate::system::{
+    Art32, Image, ImageSource, MachineDescription, SerialInput, SerialOutput, SerialTiming,
+    TimeSource, KERNEL,
+};
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+
+mod guest_test;
+pub use guest_test::{write_junit, write_report, write_tap, ReportFormat, TestOutcome, TestResult};
+
+#[cfg(test)]
+mod tests;
+
+/// Command line options selecting the emulated board and how it is emulated, shared by every
+/// emulator binary.
+#[derive(Debug, clap::Args)]
+pub struct BoardArgs {
+    /// Built in board (`art32` or `softcore`) or JSON description of the board to emulate,
+    /// defaults to `art32`
+    #[arg(long)]
+    pub machine: Option<PathBuf>,
+    /// Additional image to load, as `<path>@<addr>` for raw images or `<path>` for ELF and
+    /// Intel HEX images. Can be given multiple times
+    #[arg(long)]
+    pub load: Vec<ImageSource>,
+    /// Model the UART's byte time at this baud rate and its FIFO depth
+    #[arg(long)]
+    pub serial_baud: Option<NonZeroU32>,
+    /// Translate frequently executed basic blocks instead of interpreting every instruction.
+    /// Debuggers turn it off again
+    #[arg(long)]
+    pub block_translation: bool,
+    /// Estimate cycles with the Softcore timing model, even if the machine description does not
+    /// enable it
+    #[arg(long)]
+    pub timing_model: bool,
+    /// Guest visible time instead of the one of the machine description: `host`,
+    /// `instructions[:<hz>]` or `cycles[:<hz>]`, emulated time makes runs reproducible
+    #[arg(long)]
+    pub time_source: Option<TimeSource>,
+}
+
+impl BoardArgs {
+    /// Builds the board booting `boot`, or the built in kernel, with its images loaded. Errors are
+    /// ready to be printed.
+    pub fn build(&self, boot: Option<&ImageSource>) -> Result<Art32, String> {
+        let machine = match &self.machine {
+            Some(path) => match path.to_str().and_then(MachineDescription::builtin) {
+                Some(machine) => machine,
+                None => MachineDescription::load(path).map_err(|err| err.to_string())?,
+            },
+            None => MachineDescription::art32(),
+        };
+
+        let kernel = match boot {
+            Some(boot) => {
+                let kernel_region = machine.kernel_region();
+                let addr = boot.addr.unwrap_or(kernel_region.base);
+                Image::load(&boot.path, Some(addr), std::slice::from_ref(kernel_region))
+                    .and_then(|image| image.flatten(kernel_region))
+                    .map_err(|err| format!("`{}`: {err}", boot.path.display()))?
+            }
+            None => KERNEL.to_vec(),
+        };
+
+        let mut art32 = Art32::with_machine(&machine, &kernel).map_err(|err| err.to_string())?;
+        for source in &self.load {
+            source
+                .load(&machine.memory)
+                .and_then(|image| art32.load_image(&image))
+                .map_err(|err| format!("`{}`: {err}", source.path.display()))?;
+        }
+        if let Some(time_source) = self.time_source {
+            art32.set_time_source(time_source);
+        }
+        if self.timing_model {
+            art32.set_timing_model(Some(TimingModel::default()));
+        }
+        if self.block_translation {
+            art32.set_block_translation(Some(TRANSLATION_THRESHOLD));
+        }
+        art32.set_serial_timing(self.serial_baud.map(|baud_rate| SerialTiming {
+            baud_rate,
+            ..SerialTiming::SOFTCORE
+        }));
+        Ok(art32)
+    }
+}
+
+/// Command line options selecting the emulated board, its kernel and the serial connection,
+/// shared by the interactive emulator binaries.
+#[derive(Debug, clap::Args)]
+pub struct SystemArgs {
+    #[command(flatten)]
+    pub board: BoardArgs,
+    /// Kernel image to boot instead of the built in one, as `<path>[@<addr>]`. Raw images are
+    /// loaded at the start of the kernel region unless an address is given, ELF and Intel HEX
+    /// (`.hex`) images at their own addresses
+    #[arg(long)]
+    pub boot: Option<ImageSource>,
+    /// Host source of the serial input: `stdin`, `pty` or `tcp:<addr>`
+    #[arg(long)]
+    pub serial_input: Option<SerialInput>,
+    /// Host destination of the serial output: `stdout`, `file:<path>`, `pty` or `tcp:<addr>`
+    #[arg(long, default_value = "stdout")]
+    pub serial_output: SerialOutput,
+}
+
+impl SystemArgs {
+    /// Builds the board with its images loaded, errors are ready to be printed.
+    pub fn build(&self) -> Result<Art32, String> {
+        self.board.build(self.boot.as_ref())
+    }
+
+    /// Connects the serial port of `art32` to the host and reports where clients can attach.
+    pub fn connect_serial(&self, art32: &mut Art32) -> Result<(), String> {
+        art32
+            .set_serial_input(self.serial_input.clone())
+            .map_err(|err| format!("cannot open serial input: {err}"))?;
+        art32
+            .set_serial_output(Some(self.serial_output.clone()))
+            .map_err(|err| format!("cannot open serial output: {err}"))?;
+
+        if let Some(endpoint) = art32.serial_input_endpoint() {
+            eprintln!("serial input at {endpoint}");
+        }
+        if let Some(endpoint) = art32.serial_output_endpoint() {
+            eprintln!("serial output at {endpoint}");
+        }
+        Ok(())
+    }
+}
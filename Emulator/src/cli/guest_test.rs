@@ -0,0 +1,190 @@
+use crate::system::{Art32, EnvAction, RunBudget, RunExit, TimeSource, SOFTCORE_CLOCK_HZ};
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReportFormat {
+    /// Test Anything Protocol, version 13
+    Tap,
+    /// JUnit XML
+    Junit,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TestOutcome {
+    Passed,
+    /// The test failed for the given reason.
+    Failed(String),
+}
+
+/// The result of running one guest test image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestResult {
+    pub name: String,
+    pub outcome: TestOutcome,
+    pub cycles: u64,
+    pub duration: Duration,
+    /// Everything the guest transmitted on the serial port.
+    pub serial: Vec<u8>,
+}
+
+impl TestResult {
+    /// Runs `art32` until it passes or fails the test or exhausts `budget`. The serial output of
+    /// `art32` is expected to go to a `SerialOutput::Buffer`, timers count cycles so runs are
+    /// reproducible.
+    ///
+    /// `Pass` and `Break` environment calls pass the test, `Fail` and `Error` calls, machine
+    /// checks and an exhausted budget fail it.
+    pub fn run(name: impl Into<String>, art32: &mut Art32, budget: RunBudget) -> Self {
+        art32.set_time_source(TimeSource::Cycles {
+            clock_hz: SOFTCORE_CLOCK_HZ,
+        });
+
+        let start = Instant::now();
+        let outcome = match art32.run(budget) {
+            RunExit::Env(EnvAction::Pass | EnvAction::Break) => TestOutcome::Passed,
+            RunExit::Env(EnvAction::Fail) => TestOutcome::Failed("guest test failed".to_owned()),
+            RunExit::Env(EnvAction::Error) => {
+                TestOutcome::Failed("system caused an error".to_owned())
+            }
+            RunExit::Env(EnvAction::Reset) => unreachable!("resets are carried out by `run`"),
+            RunExit::MachineCheck(machine_check) => {
+                TestOutcome::Failed(format!("machine check: {machine_check}"))
+            }
+            RunExit::BudgetExhausted => {
+                TestOutcome::Failed(format!("budget exhausted after {} cycles", art32.cycles()))
+            }
+        };
+
+        Self {
+            name: name.into(),
+            outcome,
+            cycles: art32.cycles(),
+            duration: start.elapsed(),
+            serial: art32.take_serial_output(),
+        }
+    }
+
+    /// A test that could not be started, e.g. because its image is invalid.
+    pub fn setup_error(name: impl Into<String>, msg: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            outcome: TestOutcome::Failed(msg.into()),
+            cycles: 0,
+            duration: Duration::ZERO,
+            serial: Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub fn passed(&self) -> bool {
+        self.outcome == TestOutcome::Passed
+    }
+}
+
+/// Serial output as text, control characters other than line breaks and tabs would make
+/// the reports invalid.
+fn serial_text(serial: &[u8]) -> String {
+    String::from_utf8_lossy(serial)
+        .chars()
+        .filter(|&c| c != '\r')
+        .map(|c| match c {
+            '\n' | '\t' => c,
+            c if c.is_control() => char::REPLACEMENT_CHARACTER,
+            c => c,
+        })
+        .collect()
+}
+
+pub fn write_report(
+    format: ReportFormat,
+    suite: &str,
+    results: &[TestResult],
+    out: &mut impl Write,
+) -> io::Result<()> {
+    match format {
+        ReportFormat::Tap => write_tap(results, out),
+        ReportFormat::Junit => write_junit(suite, results, out),
+    }
+}
+
+pub fn write_tap(results: &[TestResult], out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "TAP version 13")?;
+    writeln!(out, "1..{}", results.len())?;
+    for (i, result) in results.iter().enumerate() {
+        // `#` would start a directive
+        let name = result.name.replace('#', "\\#");
+        match &result.outcome {
+            TestOutcome::Passed => writeln!(out, "ok {} - {name}", i + 1)?,
+            TestOutcome::Failed(_) => writeln!(out, "not ok {} - {name}", i + 1)?,
+        }
+
+        writeln!(out, "  ---")?;
+        if let TestOutcome::Failed(msg) = &result.outcome {
+            writeln!(out, "  message: {msg:?}")?;
+        }
+        writeln!(out, "  cycles: {}", result.cycles)?;
+        writeln!(
+            out,
+            "  duration_ms: {:.3}",
+            result.duration.as_secs_f64() * 1e3
+        )?;
+        if !result.serial.is_empty() {
+            writeln!(out, "  serial: |-")?;
+            for line in serial_text(&result.serial).lines() {
+                writeln!(out, "    {line}")?;
+            }
+        }
+        writeln!(out, "  ...")?;
+    }
+    Ok(())
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn write_junit(suite: &str, results: &[TestResult], out: &mut impl Write) -> io::Result<()> {
+    let suite = escape_xml(suite);
+    let failures = results.iter().filter(|result| !result.passed()).count();
+    let time: Duration = results.iter().map(|result| result.duration).sum();
+
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<testsuite name="{suite}" tests="{}" failures="{failures}" time="{:.3}">"#,
+        results.len(),
+        time.as_secs_f64()
+    )?;
+    for result in results {
+        writeln!(
+            out,
+            r#"  <testcase name="{}" classname="{suite}" time="{:.3}">"#,
+            escape_xml(&result.name),
+            result.duration.as_secs_f64()
+        )?;
+        if let TestOutcome::Failed(msg) = &result.outcome {
+            writeln!(out, r#"    <failure message="{}"/>"#, escape_xml(msg))?;
+        }
+        if !result.serial.is_empty() {
+            writeln!(
+                out,
+                "    <system-out>{}</system-out>",
+                escape_xml(&serial_text(&result.serial))
+            )?;
+        }
+        writeln!(out, "  </testcase>")?;
+    }
+    writeln!(out, "</testsuite>")
+}
@@ -0,0 +1,130 @@
+use super::{write_junit, write_tap, BoardArgs, TestOutcome, TestResult};
+use crate::cpu::instruction::*;
+use crate::cpu::Register;
+use crate::system::{Art32, EnvAction, RunBudget, SerialOutput};
+use std::time::Duration;
+
+fn guest_test(name: &str, message: &str, action: EnvAction) -> TestResult {
+    use Instruction::*;
+    use Register::*;
+
+    let mut program = Vec::new();
+    for c in message.chars() {
+        program.push(AluI32 {
+            op: AluOp::Add,
+            rd: A0,
+            rs1: Zero,
+            imm: c as i32,
+        });
+        program.push(Out {
+            rs: A0,
+            rb: Zero,
+            offset: 0x90,
+        });
+    }
+    program.push(Envcall(action as u8));
+
+    let mut art32 = Art32::with_kernel(&assemble(&program));
+    art32.set_serial_output(Some(SerialOutput::Buffer)).unwrap();
+    let budget = RunBudget {
+        cycles: Some(1000),
+        ..Default::default()
+    };
+    TestResult::run(name, &mut art32, budget)
+}
+
+#[test]
+fn run_guest_test() {
+    let result = guest_test("pass", "ok\n", EnvAction::Pass);
+    assert_eq!(result.outcome, TestOutcome::Passed);
+    assert_eq!(result.serial, b"ok\n");
+    assert!(result.cycles > 0);
+
+    let result = guest_test("break", "", EnvAction::Break);
+    assert!(result.passed());
+
+    for action in [EnvAction::Fail, EnvAction::Error] {
+        assert!(!guest_test("fail", "", action).passed());
+    }
+
+    // resets start the test over until the budget is exhausted
+    let result = guest_test("reset", "", EnvAction::Reset);
+    assert!(matches!(result.outcome, TestOutcome::Failed(msg) if msg.contains("budget")));
+}
+
+fn results() -> Vec<TestResult> {
+    vec![
+        TestResult {
+            name: "first".to_owned(),
+            outcome: TestOutcome::Passed,
+            cycles: 42,
+            duration: Duration::from_millis(2),
+            serial: b"hello\r\nworld\x07\n".to_vec(),
+        },
+        TestResult::setup_error("<second> & #2", "cannot read image"),
+    ]
+}
+
+#[test]
+fn tap_report() {
+    let mut report = Vec::new();
+    write_tap(&results(), &mut report).unwrap();
+
+    let expected = "\
+TAP version 13
+1..2
+ok 1 - first
+  ---
+  cycles: 42
+  duration_ms: 2.000
+  serial: |-
+    hello
+    world\u{FFFD}
+  ...
+not ok 2 - <second> & \\#2
+  ---
+  message: \"cannot read image\"
+  cycles: 0
+  duration_ms: 0.000
+  ...
+";
+    assert_eq!(String::from_utf8(report).unwrap(), expected);
+}
+
+#[test]
+fn junit_report() {
+    let mut report = Vec::new();
+    write_junit("tests", &results(), &mut report).unwrap();
+
+    let expected = r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuite name="tests" tests="2" failures="1" time="0.002">
+  <testcase name="first" classname="tests" time="0.002">
+    <system-out>hello
+world�
+</system-out>
+  </testcase>
+  <testcase name="&lt;second&gt; &amp; #2" classname="tests" time="0.000">
+    <failure message="cannot read image"/>
+  </testcase>
+</testsuite>
+"#;
+    assert_eq!(String::from_utf8(report).unwrap(), expected);
+}
+
+#[derive(clap::Parser)]
+struct Board {
+    #[command(flatten)]
+    board: BoardArgs,
+}
+
+#[test]
+fn serial_baud() {
+    use clap::Parser;
+
+    let args = Board::try_parse_from(["art32", "--serial-baud", "9600"]).unwrap();
+    assert_eq!(args.board.serial_baud.map(|baud| baud.get()), Some(9600));
+    assert!(args.board.build(None).is_ok());
+
+    // a zero baud rate is a usage error, not a panic when the board is built
+    assert!(Board::try_parse_from(["art32", "--serial-baud", "0"]).is_err());
+}
@@ -1,13 +1,24 @@
 mod register;
 use register::*;
+pub use register::{BranchCondition, Condition, Flags, Register};
 
+pub mod block;
+pub mod cache;
 pub mod interface;
 use interface::*;
 
+pub mod disasm;
+pub mod instruction;
+use instruction::*;
+
+mod timing;
+use timing::Timing;
+pub use timing::TimingModel;
+
 #[cfg(test)]
 mod tests;
 
-use crate::{shuffle_bits, Ashr};
+use crate::Ashr;
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 use strum::{EnumCount, EnumMessage, IntoEnumIterator};
 
@@ -15,13 +26,26 @@ use strum::{EnumCount, EnumMessage, IntoEnumIterator};
     Debug, Clone, Copy, PartialEq, Eq, IntoPrimitive, TryFromPrimitive, EnumCount, EnumMessage,
 )]
 #[repr(usize)]
-enum ExceptionKind {
+pub enum ExceptionKind {
     #[strum(message = "illegal instruction exception")]
     IllegalInstruction = 0,
     #[strum(message = "access violation exception")]
     AccessViolation = 1,
     #[strum(message = "unaligned access exception")]
     UnalignedAccess = 2,
+    /// The faulting address and access type are reported in the fault registers.
+    #[strum(message = "page fault exception")]
+    PageFault = 3,
+    /// Any failed instruction fetch, the exception the access raised otherwise is reported in
+    /// bits [23:16] of the exception cause.
+    #[strum(message = "instruction fetch exception")]
+    FetchFault = 4,
+}
+
+impl std::fmt::Display for ExceptionKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.get_message().unwrap())
+    }
 }
 
 impl From<MemoryError> for ExceptionKind {
@@ -30,6 +54,7 @@ impl From<MemoryError> for ExceptionKind {
         match err {
             MemoryError::AccessViolation => Self::AccessViolation,
             MemoryError::UnalignedAccess => Self::UnalignedAccess,
+            MemoryError::PageFault => Self::PageFault,
         }
     }
 }
@@ -43,6 +68,68 @@ impl From<IoError> for ExceptionKind {
     }
 }
 
+/// The access that caused an exception, reported in bits [15:8] of the exception cause.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoPrimitive)]
+#[repr(u32)]
+enum Access {
+    None = 0,
+    Fetch = 1,
+    Load = 2,
+    Store = 3,
+    IoRead = 4,
+    IoWrite = 5,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MachineCheckCause {
+    Exception(ExceptionKind),
+    Syscall(u8),
+    /// Saving or restoring a nested interrupt frame faulted.
+    InterruptStack(ExceptionKind),
+}
+
+impl std::fmt::Display for MachineCheckCause {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Exception(kind) => write!(f, "{kind}"),
+            Self::Syscall(slot) => write!(f, "software interrupt {slot}"),
+            Self::InterruptStack(kind) => write!(f, "{kind} on the interrupt stack"),
+        }
+    }
+}
+
+/// An exception or software interrupt raised while already servicing an interrupt, with nested
+/// interrupts disabled or a faulting interrupt stack.
+///
+/// There is nowhere to save the interrupted state to, so the CPU halts until it gets reset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MachineCheck {
+    /// Address of the faulting instruction.
+    pub program_counter: u32,
+    /// The raw instruction word, `None` if it could not be fetched or no instruction was involved.
+    pub instruction: Option<u32>,
+    pub cause: MachineCheckCause,
+}
+
+impl std::fmt::Display for MachineCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} inside interrupt handler at 0x{:0>8X}",
+            self.cause, self.program_counter
+        )?;
+
+        if let Some(word) = self.instruction {
+            match Instruction::decode(word) {
+                Ok(inst) => write!(f, ": {}", inst.display(self.program_counter))?,
+                Err(_) => write!(f, ": illegal instruction 0x{word:0>8X}")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
 const HARD_INT_SLOTS: usize = 16;
 const SOFT_INT_SLOTS: usize = 16;
 
@@ -57,9 +144,34 @@ const INT_MASK_ADDR: u32 = INT_CONFIG_START + 0;
 const INT_PENDING_ADDR: u32 = INT_CONFIG_START + 1;
 const PRIV_LEVEL_ADDR: u32 = INT_CONFIG_START + 2;
 const INT_RET_ADDR: u32 = INT_CONFIG_START + 3;
+const EXCEPTION_CAUSE_ADDR: u32 = INT_CONFIG_START + 4;
+const FAULT_ADDRESS_ADDR: u32 = INT_CONFIG_START + 5;
+const FAULT_INSTRUCTION_ADDR: u32 = INT_CONFIG_START + 6;
+const INT_NESTING_ADDR: u32 = INT_CONFIG_START + 7;
+const INT_LEVEL_ADDR: u32 = INT_CONFIG_START + 8;
+const INT_STACK_ADDR: u32 = INT_CONFIG_START + 9;
+const INT_DEPTH_ADDR: u32 = INT_CONFIG_START + 10;
 const ALT_FLAGS_REG_ADDR: u32 = INT_CONFIG_START + 15;
 const ALT_REGS_START: u32 = 0x040;
 const ALT_REGS_END: u32 = ALT_REGS_START + (Register::COUNT as u32) - 1;
+const CYCLE_COUNT_LOW_ADDR: u32 = 0x060;
+const CYCLE_COUNT_HIGH_ADDR: u32 = 0x061;
+const RETIRED_COUNT_LOW_ADDR: u32 = 0x062;
+const RETIRED_COUNT_HIGH_ADDR: u32 = 0x063;
+const STALL_COUNT_LOW_ADDR: u32 = 0x064;
+const STALL_COUNT_HIGH_ADDR: u32 = 0x065;
+const INT_PRIORITY_TABLE_START: u32 = 0x070;
+const INT_PRIORITY_TABLE_END: u32 = INT_PRIORITY_TABLE_START + (HARD_INT_SLOTS as u32) - 1;
+
+/// I/O addresses from the port base up to this many ports above it are handled by the CPU
+/// itself and never reach the I/O bus.
+pub const CPU_PORT_COUNT: u32 = 0x080;
+const_assert!(INT_PRIORITY_TABLE_END < CPU_PORT_COUNT);
+
+const INT_PRIORITY_MASK: u32 = 0xF;
+/// Return address, priority level, flags and registers of a preempted handler.
+const INT_FRAME_WORDS: usize = 3 + Register::COUNT;
+const INT_FRAME_SIZE: u32 = (INT_FRAME_WORDS as u32) * 4;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum InterruptState {
@@ -86,6 +198,8 @@ fn carry_add(lhs: u32, rhs: u32, c_in: bool) -> (u32, bool) {
 
 #[derive(Debug)]
 pub struct Cpu {
+    reset_program_counter: u32,
+    port_base: u32,
     program_counter: u32,
     interrupt_state: InterruptState,
     privilege_level: PrivilegeLevel,
@@ -97,11 +211,34 @@ pub struct Cpu {
     software_interrupt_table: [u32; SOFT_INT_SLOTS],
     exception_table: [u32; ExceptionKind::COUNT],
     interrupt_return_address: u32,
+    nested_interrupts: bool,
+    interrupt_priorities: [u8; HARD_INT_SLOTS],
+    interrupt_level: u8,
+    interrupt_stack_pointer: u32,
+    interrupt_depth: u32,
+    instruction_address: u32,
+    fault: Option<(Access, u32, ExceptionKind)>,
+    exception_cause: u32,
+    fault_address: u32,
+    fault_instruction: u32,
+    machine_check: Option<MachineCheck>,
+    retired_instructions: u64,
+    cycles: u64,
+    stall_cycles: u64,
+    timing: Option<Box<Timing>>,
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Cpu {
     pub fn new() -> Self {
         Self {
+            reset_program_counter: RESET_PROGRAM_COUNTER,
+            port_base: 0,
             program_counter: RESET_PROGRAM_COUNTER,
             interrupt_state: RESET_INTERRUPT_STATE,
             privilege_level: RESET_PRIVILEGE_LEVEL,
@@ -113,15 +250,122 @@ impl Cpu {
             software_interrupt_table: Default::default(),
             exception_table: Default::default(),
             interrupt_return_address: 0,
+            nested_interrupts: false,
+            interrupt_priorities: Default::default(),
+            interrupt_level: 0,
+            interrupt_stack_pointer: 0,
+            interrupt_depth: 0,
+            instruction_address: RESET_PROGRAM_COUNTER,
+            fault: None,
+            exception_cause: 0,
+            fault_address: 0,
+            fault_instruction: 0,
+            machine_check: None,
+            retired_instructions: 0,
+            cycles: 0,
+            stall_cycles: 0,
+            timing: None,
         }
     }
 
     pub fn reset(&mut self) {
-        self.program_counter = RESET_PROGRAM_COUNTER;
+        self.program_counter = self.reset_program_counter;
         self.interrupt_state = RESET_INTERRUPT_STATE;
         self.privilege_level = RESET_PRIVILEGE_LEVEL;
         self.interrupt_mask = 0;
         self.pending_interrupts = 0;
+        self.nested_interrupts = false;
+        self.interrupt_priorities = Default::default();
+        self.interrupt_level = 0;
+        self.interrupt_stack_pointer = 0;
+        self.interrupt_depth = 0;
+        self.fault = None;
+        self.exception_cause = 0;
+        self.fault_address = 0;
+        self.fault_instruction = 0;
+        self.machine_check = None;
+        self.retired_instructions = 0;
+        self.cycles = 0;
+        self.stall_cycles = 0;
+        if let Some(timing) = &mut self.timing {
+            **timing = Timing::new(timing.model().clone());
+        }
+    }
+
+    /// Where execution starts after the next reset.
+    pub fn set_reset_program_counter(&mut self, program_counter: u32) {
+        self.reset_program_counter = program_counter;
+    }
+
+    /// Moves the CPU's own ports to start at the I/O address `port_base`.
+    pub fn set_port_base(&mut self, port_base: u32) {
+        self.port_base = port_base;
+    }
+
+    /// Enables cycle estimation, without a model every instruction takes a single cycle.
+    pub fn set_timing_model(&mut self, model: Option<TimingModel>) {
+        self.timing = model.map(|model| Box::new(Timing::new(model)));
+    }
+
+    #[inline]
+    pub fn retired_instructions(&self) -> u64 {
+        self.retired_instructions
+    }
+
+    #[inline]
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    #[inline]
+    pub fn stall_cycles(&self) -> u64 {
+        self.stall_cycles
+    }
+
+    /// The machine check the CPU halted on, if any.
+    #[inline]
+    pub fn machine_check(&self) -> Option<MachineCheck> {
+        self.machine_check
+    }
+
+    #[inline]
+    pub fn program_counter(&self) -> u32 {
+        self.program_counter
+    }
+
+    pub fn set_program_counter(&mut self, program_counter: u32) {
+        self.program_counter = program_counter;
+    }
+
+    #[inline]
+    pub fn register(&self, reg: Register) -> u32 {
+        self.state.regs.get(reg)
+    }
+
+    /// Writes to `zero` are ignored, like they are for instructions.
+    pub fn set_register(&mut self, reg: Register, value: u32) {
+        self.state.regs.set(reg, value);
+    }
+
+    #[inline]
+    pub fn flags(&self) -> Flags {
+        self.state.flags
+    }
+
+    pub fn set_flags(&mut self, flags: Flags) {
+        self.state.flags = flags;
+    }
+
+    /// The banked register of the interrupted code while servicing an interrupt, the handler's
+    /// otherwise.
+    #[inline]
+    pub fn alt_register(&self, reg: Register) -> u32 {
+        self.alt_state.regs.get(reg)
+    }
+
+    #[inline]
+    pub fn alt_flags(&self) -> Flags {
+        self.alt_state.flags
     }
 
     pub fn signal_interrupt(&mut self, slot: usize) {
@@ -129,6 +373,7 @@ impl Cpu {
         self.pending_interrupts |= 1 << slot;
     }
 
+    #[cfg(feature = "display")]
     pub fn draw_debug_info(
         &self,
         wgpu_state: &crate::display::WgpuState,
@@ -178,17 +423,108 @@ impl Cpu {
         }
     }
 
+    /// Takes the pending interrupt with the highest priority, on equal priority the highest slot.
+    /// In nested mode a handler is only preempted by a strictly higher priority.
     fn next_interrupt(&mut self) -> Option<usize> {
-        if self.interrupt_state == InterruptState::Listening {
-            let pending = self.pending_interrupts & self.interrupt_mask;
-            if pending != 0 {
-                let slot = HARD_INT_SLOTS - (pending.leading_zeros() as usize) - 1;
-                self.pending_interrupts &= !(1 << slot);
-                return Some(slot);
+        if (self.interrupt_state == InterruptState::Servicing) && !self.nested_interrupts {
+            return None;
+        }
+
+        let pending = self.pending_interrupts & self.interrupt_mask;
+        if pending == 0 {
+            return None;
+        }
+
+        let slot = (0..HARD_INT_SLOTS)
+            .filter(|&slot| (pending & (1 << slot)) != 0)
+            .max_by_key(|&slot| (self.interrupt_priorities[slot], slot))?;
+
+        if (self.interrupt_state == InterruptState::Servicing)
+            && (self.interrupt_priorities[slot] <= self.interrupt_level)
+        {
+            return None;
+        }
+
+        self.pending_interrupts &= !(1 << slot);
+        Some(slot)
+    }
+
+    fn hardware_interrupt<Mem: MemoryInterface>(&mut self, slot: usize, mem: &mut Mem) {
+        self.instruction_address = self.program_counter;
+        if self.interrupt(self.hardware_interrupt_table[slot], mem) {
+            self.interrupt_level = self.interrupt_priorities[slot];
+        }
+    }
+
+    /// Enters a handler, saving a preempted handler to the interrupt stack.
+    ///
+    /// Returns `false` if the CPU halted because the interrupt stack was not writable.
+    fn interrupt<Mem: MemoryInterface>(&mut self, new_program_counter: u32, mem: &mut Mem) -> bool {
+        match self.interrupt_state {
+            InterruptState::Listening => {
+                self.enter_interrupt(new_program_counter);
+                true
             }
+            InterruptState::Servicing => match self.push_interrupt_frame(mem) {
+                Ok(()) => {
+                    self.interrupt_return_address = self.program_counter;
+                    self.program_counter = new_program_counter;
+                    true
+                }
+                Err(kind) => {
+                    self.halt(MachineCheckCause::InterruptStack(kind), None);
+                    false
+                }
+            },
         }
+    }
 
-        None
+    fn push_interrupt_frame<Mem: MemoryInterface>(
+        &mut self,
+        mem: &mut Mem,
+    ) -> Result<(), ExceptionKind> {
+        debug_assert!(self.nested_interrupts);
+
+        let frame = [
+            self.interrupt_return_address,
+            self.interrupt_level as u32,
+            self.state.flags.bits() as u32,
+        ]
+        .into_iter()
+        .chain(Register::iter().map(|reg| self.get_reg(reg)));
+
+        let frame_addr = self.interrupt_stack_pointer.wrapping_sub(INT_FRAME_SIZE);
+        for (i, word) in frame.enumerate() {
+            let addr = frame_addr.wrapping_add((i as u32) * 4);
+            mem.write_32(addr, word, PrivilegeLevel::System, false)?;
+        }
+
+        self.interrupt_stack_pointer = frame_addr;
+        self.interrupt_depth += 1;
+        Ok(())
+    }
+
+    fn pop_interrupt_frame<Mem: MemoryInterface>(
+        &mut self,
+        mem: &mut Mem,
+    ) -> Result<(), ExceptionKind> {
+        let mut frame = [0; INT_FRAME_WORDS];
+        for (i, word) in frame.iter_mut().enumerate() {
+            let addr = self.interrupt_stack_pointer.wrapping_add((i as u32) * 4);
+            *word = mem.read_32(addr, PrivilegeLevel::System, false)?;
+        }
+
+        self.program_counter = self.interrupt_return_address;
+        self.interrupt_return_address = frame[0] & !0x1;
+        self.interrupt_level = (frame[1] & INT_PRIORITY_MASK) as u8;
+        self.state.flags = Flags::from_bits_truncate(frame[2] as u8);
+        for (reg, &value) in Register::iter().zip(&frame[3..]) {
+            self.set_reg(reg, value);
+        }
+
+        self.interrupt_stack_pointer = self.interrupt_stack_pointer.wrapping_add(INT_FRAME_SIZE);
+        self.interrupt_depth -= 1;
+        Ok(())
     }
 
     fn enter_interrupt(&mut self, new_program_counter: u32) {
@@ -207,17 +543,54 @@ impl Cpu {
 
         self.program_counter = self.interrupt_return_address;
         self.interrupt_state = InterruptState::Listening;
+        self.interrupt_level = 0;
         std::mem::swap(&mut self.state, &mut self.alt_state);
     }
 
-    fn exception(&mut self, kind: ExceptionKind) {
-        match self.interrupt_state {
-            InterruptState::Servicing => {
-                panic!("{} inside interrupt handler", kind.get_message().unwrap())
-            }
-            InterruptState::Listening => {
-                self.enter_interrupt(self.exception_table[usize::from(kind)]);
-            }
+    fn halt(&mut self, cause: MachineCheckCause, instruction: Option<u32>) {
+        self.program_counter = self.instruction_address;
+        self.machine_check = Some(MachineCheck {
+            program_counter: self.instruction_address,
+            instruction,
+            cause,
+        });
+    }
+
+    /// Records the faulting access, to be reported once the exception is taken.
+    #[cold]
+    fn fault(&mut self, access: Access, addr: u32, err: impl Into<ExceptionKind>) -> ExceptionKind {
+        let err = err.into();
+        self.fault = Some((access, addr, err));
+        match access {
+            Access::Fetch => ExceptionKind::FetchFault,
+            _ => err,
+        }
+    }
+
+    fn exception<Mem: MemoryInterface>(&mut self, kind: ExceptionKind, mem: &mut Mem) {
+        // exceptions without a faulting access report the instruction address instead
+        let (access, addr, err) =
+            self.fault
+                .take()
+                .unwrap_or((Access::None, self.instruction_address, kind));
+        let instruction = match access {
+            Access::Fetch => None,
+            _ => read_instruction(mem, self.instruction_address, PrivilegeLevel::System).ok(),
+        };
+
+        let reason = match kind {
+            ExceptionKind::FetchFault => usize::from(err) as u32,
+            _ => 0,
+        };
+        self.exception_cause =
+            (reason << 16) | (u32::from(access) << 8) | (usize::from(kind) as u32);
+        self.fault_address = addr;
+        self.fault_instruction = instruction.unwrap_or(0);
+
+        if (self.interrupt_state == InterruptState::Servicing) && !self.nested_interrupts {
+            self.halt(MachineCheckCause::Exception(kind), instruction);
+        } else {
+            self.interrupt(self.exception_table[usize::from(kind)], mem);
         }
     }
 
@@ -227,20 +600,21 @@ impl Cpu {
         addr: u32,
         priv_level: PrivilegeLevel,
     ) -> Result<u32, IoError> {
+        let port = addr.wrapping_sub(self.port_base);
         if priv_level == PrivilegeLevel::System {
-            match addr {
+            match port {
                 HARD_INT_TABLE_START..=HARD_INT_TABLE_END => {
                     return Ok(
-                        self.hardware_interrupt_table[(addr - HARD_INT_TABLE_START) as usize]
+                        self.hardware_interrupt_table[(port - HARD_INT_TABLE_START) as usize]
                     );
                 }
                 SOFT_INT_TABLE_START..=SOFT_INT_TABLE_END => {
                     return Ok(
-                        self.software_interrupt_table[(addr - SOFT_INT_TABLE_START) as usize]
+                        self.software_interrupt_table[(port - SOFT_INT_TABLE_START) as usize]
                     );
                 }
                 EXCEPTION_TABLE_START..=EXCEPTION_TABLE_END => {
-                    return Ok(self.exception_table[(addr - EXCEPTION_TABLE_START) as usize]);
+                    return Ok(self.exception_table[(port - EXCEPTION_TABLE_START) as usize]);
                 }
                 INT_MASK_ADDR => {
                     return Ok(self.interrupt_mask as u32);
@@ -254,18 +628,53 @@ impl Cpu {
                 INT_RET_ADDR => {
                     return Ok(self.interrupt_return_address);
                 }
+                EXCEPTION_CAUSE_ADDR => {
+                    return Ok(self.exception_cause);
+                }
+                FAULT_ADDRESS_ADDR => {
+                    return Ok(self.fault_address);
+                }
+                FAULT_INSTRUCTION_ADDR => {
+                    return Ok(self.fault_instruction);
+                }
+                INT_NESTING_ADDR => {
+                    return Ok(self.nested_interrupts as u32);
+                }
+                INT_LEVEL_ADDR => {
+                    return Ok(self.interrupt_level as u32);
+                }
+                INT_STACK_ADDR => {
+                    return Ok(self.interrupt_stack_pointer);
+                }
+                INT_DEPTH_ADDR => {
+                    return Ok(self.interrupt_depth);
+                }
+                INT_PRIORITY_TABLE_START..=INT_PRIORITY_TABLE_END => {
+                    return Ok(
+                        self.interrupt_priorities[(port - INT_PRIORITY_TABLE_START) as usize]
+                            as u32,
+                    );
+                }
                 ALT_FLAGS_REG_ADDR => {
                     return Ok(self.alt_state.flags.bits() as u32);
                 }
                 ALT_REGS_START..=ALT_REGS_END => {
-                    let reg = Register::try_from(addr - ALT_REGS_START).unwrap();
+                    let reg = Register::try_from(port - ALT_REGS_START).unwrap();
                     return Ok(self.alt_state.regs.get(reg));
                 }
                 _ => {}
             }
         }
 
-        io.read(addr, priv_level)
+        match port {
+            CYCLE_COUNT_LOW_ADDR => Ok(self.cycles as u32),
+            CYCLE_COUNT_HIGH_ADDR => Ok((self.cycles >> 32) as u32),
+            RETIRED_COUNT_LOW_ADDR => Ok(self.retired_instructions as u32),
+            RETIRED_COUNT_HIGH_ADDR => Ok((self.retired_instructions >> 32) as u32),
+            STALL_COUNT_LOW_ADDR => Ok(self.stall_cycles as u32),
+            STALL_COUNT_HIGH_ADDR => Ok((self.stall_cycles >> 32) as u32),
+            _ => io.read(addr, priv_level),
+        }
     }
 
     fn write_io<Io: IoInterface>(
@@ -275,20 +684,21 @@ impl Cpu {
         value: u32,
         priv_level: PrivilegeLevel,
     ) -> Result<(), IoError> {
+        let port = addr.wrapping_sub(self.port_base);
         if priv_level == PrivilegeLevel::System {
-            match addr {
+            match port {
                 HARD_INT_TABLE_START..=HARD_INT_TABLE_END => {
-                    self.hardware_interrupt_table[(addr - HARD_INT_TABLE_START) as usize] =
+                    self.hardware_interrupt_table[(port - HARD_INT_TABLE_START) as usize] =
                         value & !0x1;
                     return Ok(());
                 }
                 SOFT_INT_TABLE_START..=SOFT_INT_TABLE_END => {
-                    self.software_interrupt_table[(addr - SOFT_INT_TABLE_START) as usize] =
+                    self.software_interrupt_table[(port - SOFT_INT_TABLE_START) as usize] =
                         value & !0x1;
                     return Ok(());
                 }
                 EXCEPTION_TABLE_START..=EXCEPTION_TABLE_END => {
-                    self.exception_table[(addr - EXCEPTION_TABLE_START) as usize] = value & !0x1;
+                    self.exception_table[(port - EXCEPTION_TABLE_START) as usize] = value & !0x1;
                     return Ok(());
                 }
                 INT_MASK_ADDR => {
@@ -308,12 +718,29 @@ impl Cpu {
                     self.interrupt_return_address = value & !0x1;
                     return Ok(());
                 }
+                INT_NESTING_ADDR => {
+                    self.nested_interrupts = (value & 0x1) != 0;
+                    return Ok(());
+                }
+                INT_LEVEL_ADDR => {
+                    self.interrupt_level = (value & INT_PRIORITY_MASK) as u8;
+                    return Ok(());
+                }
+                INT_STACK_ADDR => {
+                    self.interrupt_stack_pointer = value & !0x3;
+                    return Ok(());
+                }
+                INT_PRIORITY_TABLE_START..=INT_PRIORITY_TABLE_END => {
+                    self.interrupt_priorities[(port - INT_PRIORITY_TABLE_START) as usize] =
+                        (value & INT_PRIORITY_MASK) as u8;
+                    return Ok(());
+                }
                 ALT_FLAGS_REG_ADDR => {
                     self.alt_state.flags = Flags::from_bits_truncate(value as u8);
                     return Ok(());
                 }
                 ALT_REGS_START..=ALT_REGS_END => {
-                    let reg = Register::try_from(addr - ALT_REGS_START).unwrap();
+                    let reg = Register::try_from(port - ALT_REGS_START).unwrap();
                     self.alt_state.regs.set(reg, value);
                     return Ok(());
                 }
@@ -342,681 +769,429 @@ impl Cpu {
     }
 
     #[inline]
-    fn ldi_addi_16(&mut self, instruction: u32) {
-        let rd_rs1 = Register::try_from(shuffle_bits!(instruction { [15:12] => [3:0] })).unwrap();
-
-        let imm = shuffle_bits!(instruction {
-            [11:7] => [4:0],
-            [6:4] => [8:6],
-            sign [3] => [9],
-            [2] => [5],
-        });
+    fn execute_alu(&mut self, op: AluOp, lhs: u32, rhs: u32) -> u32 {
+        let result = match op {
+            AluOp::Add => self.execute_add(lhs, rhs, false),
+            AluOp::Sub => self.execute_add(lhs, !rhs, true),
+            AluOp::And => lhs & rhs,
+            AluOp::Or => lhs | rhs,
+            AluOp::Xor => lhs ^ rhs,
+            AluOp::Shl => lhs << (rhs & 0x1F),
+            AluOp::Lsr => lhs >> (rhs & 0x1F),
+            AluOp::Asr => lhs.ashr(rhs & 0x1F),
+        };
 
-        if (instruction & 0x2) == 0 {
-            self.set_reg(rd_rs1, imm);
-        } else {
-            let sum = self.execute_add(self.get_reg(rd_rs1), imm, false);
-            self.state.flags.set(Flags::ZERO, sum == 0);
-            self.set_reg(rd_rs1, sum);
-        }
+        self.state.flags.set(Flags::ZERO, result == 0);
+        result
     }
 
     #[inline]
-    fn jump_16(&mut self, instruction: u32) {
-        let rb = Register::try_from(shuffle_bits!(instruction { [15:12] => [3:0] })).unwrap();
-
-        let imm = shuffle_bits!(instruction {
-            [11:8] => [4:1],
-            [7] => [5],
-            [6:4] => [8:6],
-            sign [3] => [9],
-        });
-
-        let jump_addr = self.get_reg(rb).wrapping_add(imm) & !0x1;
-        if (instruction & 0x4) != 0 {
-            self.set_reg(Register::Ra, self.program_counter);
-        }
+    fn jump(&mut self, link: Register, rb: Register, offset: i32) {
+        let jump_addr = self.get_reg(rb).wrapping_add(offset as u32) & !0x1;
+        self.set_reg(link, self.program_counter);
         self.program_counter = jump_addr;
     }
 
     #[inline]
-    fn branch_16(&mut self, instruction: u32) {
-        let cond =
-            BranchCondition::try_from(shuffle_bits!(instruction { [14:12] => [2:0] })).unwrap();
-
-        let imm = shuffle_bits!(instruction {
-            [15] => [5],
-            [11:8] => [4:1],
-            [6:4] => [8:6],
-            sign [3] => [9],
-        });
-
+    fn branch(&mut self, cond: BranchCondition, offset: i32) {
         if cond == BranchCondition::Link {
             self.set_reg(Register::Ra, self.program_counter);
         }
 
         if self.state.flags.satisfy_branch(cond) {
-            self.program_counter = self.program_counter.wrapping_add(imm) & !0x1;
+            self.program_counter = self.program_counter.wrapping_add(offset as u32) & !0x1;
         }
     }
 
     #[inline]
-    fn uimm_32(&mut self, instruction: u32) {
-        let rd = Register::try_from(shuffle_bits!(instruction { [16:12] => [4:0] })).unwrap();
-
-        let imm = shuffle_bits!(instruction {
-            sign [31] => [31],
-            [30:27] => [30:27],
-            [26:24] => [12:10],
-            [23:22] => [14:13],
-            [21:17] => [19:15],
-            [11:8] => [26:23],
-            [6:4] => [22:20],
-        });
-
-        if (instruction & 0x8) == 0 {
-            self.set_reg(rd, imm);
-        } else {
-            self.set_reg(rd, self.program_counter.wrapping_add(imm));
-        }
-    }
-
-    #[inline]
-    fn alu_16(&mut self, instruction: u32) {
-        let rd_rs1 = Register::try_from(shuffle_bits!(instruction { [15:12] => [3:0] })).unwrap();
-        let rs2 = Register::try_from(shuffle_bits!(instruction { [11:8] => [3:0] })).unwrap();
-
-        let lhs = self.get_reg(rd_rs1);
-        let rhs = self.get_reg(rs2);
-        let result = match (instruction & 0xE0) >> 5 {
-            0b000 /* add */ => self.execute_add(lhs, rhs, false),
-            0b001 /* sub */ => self.execute_add(lhs, !rhs, true),
-            0b010 /* and */ => lhs & rhs,
-            0b011 /* or */ => lhs | rhs,
-            0b100 /* xor */ =>  lhs ^ rhs,
-            0b101 /* shl */ => lhs << (rhs & 0x1F),
-            0b110 /* lsr */ => lhs >> (rhs & 0x1F),
-            0b111 /* asr */ => lhs.ashr(rhs & 0x1F),
-            _ => unreachable!(),
-        };
-
-        self.set_reg(rd_rs1, result);
-        self.state.flags.set(Flags::ZERO, result == 0);
-    }
-
-    #[inline]
-    fn mov_16(&mut self, instruction: u32) {
-        let rd_rs1 = Register::try_from(shuffle_bits!(instruction { [15:12] => [3:0] })).unwrap();
-        let rs2 = Register::try_from(shuffle_bits!(instruction { [11:8] => [3:0] })).unwrap();
-        let cond = Condition::try_from(shuffle_bits!(instruction { [7:5] => [2:0] })).unwrap();
-
-        if self.state.flags.satisfy(cond) {
-            let value = self.get_reg(rs2);
-            self.set_reg(rd_rs1, value);
-        };
-    }
-
-    #[inline]
-    fn cmp_16(&mut self, instruction: u32) {
-        let rs1 = Register::try_from(shuffle_bits!(instruction { [15:12] => [3:0] })).unwrap();
-        let rs2 = Register::try_from(shuffle_bits!(instruction { [11:8] => [3:0] })).unwrap();
-
-        let lhs = self.get_reg(rs1);
-        let rhs = self.get_reg(rs2);
-        let result = self.execute_add(lhs, !rhs, true);
-        self.state.flags.set(Flags::ZERO, result == 0);
-    }
-
-    #[inline]
-    fn sys_16(&mut self, instruction: u32) -> Result<Option<u8>, ExceptionKind> {
-        match (instruction & 0xF00) >> 8 {
-            0b0000 /* ret */ => {
-                self.program_counter = self.get_reg(Register::Ra) & !0x1;
-            }
-            0b0001 /* sysret */ => {
-                match self.interrupt_state {
-                    InterruptState::Servicing => {
-                        self.leave_interrupt();
-                    }
-                    InterruptState::Listening => {
-                        return Err(ExceptionKind::IllegalInstruction);
-                    }
+    fn sysret<Mem: MemoryInterface>(&mut self, mem: &mut Mem) -> Result<(), ExceptionKind> {
+        match self.interrupt_state {
+            InterruptState::Servicing if self.interrupt_depth > 0 => {
+                if let Err(kind) = self.pop_interrupt_frame(mem) {
+                    self.halt(MachineCheckCause::InterruptStack(kind), None);
                 }
+                Ok(())
             }
-            0b0010 /* fence */ => (),
-            0b0011 /* ifence */ => (),
-            0b0100..=0b1101 => {
-                return Err(ExceptionKind::IllegalInstruction);
-            }
-            0b1110 /* envcall */ => {
-                let code = shuffle_bits!(instruction { [15:12] => [3:0] });
-                return Ok(Some(code as u8));
-            }
-            0b1111 /* syscall */ => {
-                match self.interrupt_state {
-                    InterruptState::Servicing => {
-                        panic!("software interrupt inside interrupt handler")
-                    }
-                    InterruptState::Listening => {
-                        let slot = shuffle_bits!(instruction { [15:12] => [3:0] }) as usize;
-                        self.enter_interrupt(self.software_interrupt_table[slot]);
-                    }
-                }
+            InterruptState::Servicing => {
+                self.leave_interrupt();
+                Ok(())
             }
-            _ => unreachable!(),
+            InterruptState::Listening => Err(ExceptionKind::IllegalInstruction),
         }
-
-        Ok(None)
     }
 
     #[inline]
-    fn alui_16(&mut self, instruction: u32) {
-        let rd_rs1 = Register::try_from(shuffle_bits!(instruction { [15:12] => [3:0] })).unwrap();
-
-        let imm = shuffle_bits!(instruction {
-            [11:7] => [4:0],
-        });
-
-        let lhs = self.get_reg(rd_rs1);
-        let result = match (instruction & 0x60) >> 5 {
-            0b01 /* shli */ => lhs << imm,
-            0b10 /* lsri */ => lhs >> imm,
-            0b11 /* asri */ => lhs.ashr(imm),
-            _ => unreachable!(),
-        };
-
-        self.set_reg(rd_rs1, result);
-        self.state.flags.set(Flags::ZERO, result == 0);
+    fn syscall<Mem: MemoryInterface>(&mut self, slot: u8, mem: &mut Mem) {
+        if (self.interrupt_state == InterruptState::Servicing) && !self.nested_interrupts {
+            let (instruction, _) = Instruction::Syscall(slot).encode();
+            self.halt(MachineCheckCause::Syscall(slot), Some(instruction));
+        } else {
+            self.interrupt(self.software_interrupt_table[slot as usize], mem);
+        }
     }
 
     #[inline]
-    fn mem_16<Mem: MemoryInterface>(
+    fn load<Mem: MemoryInterface>(
         &mut self,
-        instruction: u32,
+        op: LoadOp,
+        rd: Register,
+        addr: u32,
         priv_level: PrivilegeLevel,
+        reserve: bool,
         mem: &mut Mem,
     ) -> Result<(), ExceptionKind> {
-        let rd_rs = Register::try_from(shuffle_bits!(instruction { [15:12] => [3:0] })).unwrap();
-
-        let imm = shuffle_bits!(instruction {
-            [11:9] => [4:2],
-            [8:7] => [6:5],
-        });
-
-        let addr = self.get_reg(Register::Sp).wrapping_add(imm);
-        if (instruction & 0x40) == 0 {
-            let value = mem.read_32(addr, priv_level, false)?;
-            self.set_reg(rd_rs, value);
-        } else {
-            let value = self.get_reg(rd_rs);
-            mem.write_32(addr, value, priv_level, false)?;
-        }
-
-        Ok(())
-    }
-
-    #[inline]
-    fn jump_32(&mut self, instruction: u32) {
-        let rd = Register::try_from(shuffle_bits!(instruction { [16:12] => [4:0] })).unwrap();
-        let rb = Register::try_from(shuffle_bits!(instruction { [21:17] => [4:0] })).unwrap();
-
-        let imm = shuffle_bits!(instruction {
-            sign [31] => [13],
-            [30:27] => [8:5],
-            [26:24] => [12:10],
-            [11:8] => [4:1],
-            [7] => [9],
-        });
-
-        let jump_addr = self.get_reg(rb).wrapping_add(imm) & !0x1;
-        self.set_reg(rd, self.program_counter);
-        self.program_counter = jump_addr;
-    }
-
-    #[inline]
-    fn branch_32(&mut self, instruction: u32) {
-        let cond =
-            BranchCondition::try_from(shuffle_bits!(instruction { [14:12] => [2:0] })).unwrap();
-
-        let imm = shuffle_bits!(instruction {
-            sign [31] => [20],
-            [30:27] => [8:5],
-            [26:24] => [12:10],
-            [21:15] => [19:13],
-            [11:8] => [4:1],
-            [7] => [9],
-        });
-
-        if cond == BranchCondition::Link {
-            self.set_reg(Register::Ra, self.program_counter);
-        }
-
-        if self.state.flags.satisfy_branch(cond) {
-            self.program_counter = self.program_counter.wrapping_add(imm) & !0x1;
+        let value = match op {
+            LoadOp::Word => mem.read_32(addr, priv_level, reserve),
+            LoadOp::ByteU => mem.read_8(addr, priv_level, reserve).map(|v| v as u32),
+            LoadOp::ByteS => mem
+                .read_8(addr, priv_level, reserve)
+                .map(|v| ((v as i8) as i32) as u32),
+            LoadOp::HalfU => mem.read_16(addr, priv_level, reserve).map(|v| v as u32),
+            LoadOp::HalfS => mem
+                .read_16(addr, priv_level, reserve)
+                .map(|v| ((v as i16) as i32) as u32),
         }
-    }
-
-    #[inline]
-    fn alui_32(&mut self, instruction: u32) {
-        let rd = Register::try_from(shuffle_bits!(instruction { [16:12] => [4:0] })).unwrap();
-        let rs1 = Register::try_from(shuffle_bits!(instruction { [21:17] => [4:0] })).unwrap();
-
-        let imm = shuffle_bits!(instruction {
-            sign [31] => [9],
-            [30:27] => [8:5],
-            [11:7] => [4:0],
-        });
-
-        let lhs = self.get_reg(rs1);
-        let result = match (instruction & 0x700_0000) >> 24 {
-            0b000 /* addi */ => self.execute_add(lhs, imm, false),
-            0b001 /* subi */ => self.execute_add(lhs, !imm, true),
-            0b010 /* andi */ => lhs & imm,
-            0b011 /* ori */ => lhs | imm,
-            0b100 /* xori */ => lhs ^ imm,
-            0b101 /* shli */ => lhs << (imm & 0x1F),
-            0b110 /* lsri */ => lhs >> (imm & 0x1F),
-            0b111 /* asri */ => lhs.ashr(imm & 0x1F),
-            _ => unreachable!(),
-        };
-
-        self.set_reg(rd, result);
-        self.state.flags.set(Flags::ZERO, result == 0);
-    }
-
-    #[inline]
-    fn movi_32(&mut self, instruction: u32) {
-        let rd = Register::try_from(shuffle_bits!(instruction { [16:12] => [4:0] })).unwrap();
-        let rs1 = Register::try_from(shuffle_bits!(instruction { [21:17] => [4:0] })).unwrap();
-        let cond = Condition::try_from(shuffle_bits!(instruction { [26:24] => [2:0] })).unwrap();
-
-        let imm = shuffle_bits!(instruction {
-            sign [31] => [9],
-            [30:27] => [8:5],
-            [11:7] => [4:0],
-        });
+        .map_err(|err| self.fault(Access::Load, addr, err))?;
 
-        let value = if self.state.flags.satisfy(cond) {
-            imm
-        } else {
-            self.get_reg(rs1)
-        };
         self.set_reg(rd, value);
+        Ok(())
     }
 
     #[inline]
-    fn mem_32<Mem: MemoryInterface, Io: IoInterface>(
+    fn store<Mem: MemoryInterface>(
         &mut self,
-        instruction: u32,
+        op: StoreOp,
+        rs: Register,
+        addr: u32,
         priv_level: PrivilegeLevel,
+        conditional: bool,
         mem: &mut Mem,
-        io: &mut Io,
-    ) -> Result<(), ExceptionKind> {
-        let rb = Register::try_from(shuffle_bits!(instruction { [21:17] => [4:0] })).unwrap();
-
-        if (instruction & 0x40) == 0 {
-            let imm = shuffle_bits!(instruction {
-                sign [31] => [9],
-                [30:27] => [8:5],
-                [11:7] => [4:0],
-            });
-
-            let rd = Register::try_from(shuffle_bits!(instruction { [16:12] => [4:0] })).unwrap();
-
-            let addr = self.get_reg(rb).wrapping_add(imm);
-
-            match (instruction & 0x700_0000) >> 24 {
-                0b000 | 0b001 /* ld.32 */ => {
-                    let value = mem.read_32(addr, priv_level, false)?;
-                    self.set_reg(rd, value);
-                }
-                0b010 /* ld.8u */ => {
-                    let value = mem.read_8(addr, priv_level, false)?;
-                    self.set_reg(rd, value as u32)
-                }
-                0b011 /* ld.8s */ => {
-                    let value = mem.read_8(addr, priv_level, false)?;
-                    self.set_reg(rd, ((value as i8) as i32) as u32);
-                }
-                0b100 /* ld.16u */ => {
-                    let value = mem.read_16(addr, priv_level, false)?;
-                    self.set_reg(rd, value as u32);
-                }
-                0b101 /* ld.16s */ => {
-                    let value = mem.read_16(addr, priv_level, false)?;
-                    self.set_reg(rd, ((value as i16) as i32) as u32);
-                }
-                0b110 | 0b111 /* in */ => {
-                    let value = self.read_io(io, addr, priv_level)?;
-                    self.set_reg(rd, value);
-                }
-                _ => unreachable!(),
-            }
-        } else {
-            let imm = shuffle_bits!(instruction {
-                sign [31] => [9],
-                [30:27] => [8:5],
-                [16:12] => [4:0],
-            });
-
-            let rs = Register::try_from(shuffle_bits!(instruction { [11:8] => [3:0], [7] => [4] }))
-                .unwrap();
-
-            let addr = self.get_reg(rb).wrapping_add(imm);
-
-            match (instruction & 0x600_0000) >> 25 {
-                0b00 /* st.32 */ => {
-                    let value = self.get_reg(rs);
-                    mem.write_32(addr, value, priv_level, false)?;
-                }
-                0b01 /* st.8 */ => {
-                    let value = self.get_reg(rs) as u8;
-                    mem.write_8(addr, value, priv_level, false)?;
-                }
-                0b10 /* st.16 */ => {
-                    let value = self.get_reg(rs) as u16;
-                    mem.write_16(addr, value, priv_level, false)?;
-                }
-                0b11 /* out */ => {
-                    let value = self.get_reg(rs);
-                    self.write_io(io, addr, value, priv_level)?;
-                }
-                _ => unreachable!(),
-            }
+    ) -> Result<bool, ExceptionKind> {
+        let value = self.get_reg(rs);
+        let written = match op {
+            StoreOp::Word => mem.write_32(addr, value, priv_level, conditional),
+            StoreOp::Byte => mem.write_8(addr, value as u8, priv_level, conditional),
+            StoreOp::Half => mem.write_16(addr, value as u16, priv_level, conditional),
         }
+        .map_err(|err| self.fault(Access::Store, addr, err))?;
 
-        Ok(())
-    }
-
-    #[inline]
-    fn alu_32(&mut self, instruction: u32) {
-        let rs1 = Register::try_from(shuffle_bits!(instruction { [21:17] => [4:0] })).unwrap();
-        let rd = Register::try_from(shuffle_bits!(instruction { [16:12] => [4:0] })).unwrap();
-        let rs2 =
-            Register::try_from(shuffle_bits!(instruction { [11:8] => [3:0], [7] => [4] })).unwrap();
-
-        let lhs = self.get_reg(rs1);
-        let rhs = self.get_reg(rs2);
-        let result = match (instruction & 0x700_0000) >> 24 {
-            0b000 /* add */ => self.execute_add(lhs, rhs, false),
-            0b001 /* sub */ => self.execute_add(lhs, !rhs, true),
-            0b010 /* and */ => lhs & rhs,
-            0b011 /* or */ => lhs | rhs,
-            0b100 /* xor */ => lhs ^ rhs,
-            0b101 /* shl */ => lhs << (rhs & 0x1F),
-            0b110 /* lsr */ => lhs >> (rhs & 0x1F),
-            0b111 /* asr */ => lhs.ashr(rhs & 0x1F),
-            _ => unreachable!(),
-        };
-
-        self.set_reg(rd, result);
-        self.state.flags.set(Flags::ZERO, result == 0);
-    }
-
-    #[inline]
-    fn mov_32(&mut self, instruction: u32) {
-        let rs1 = Register::try_from(shuffle_bits!(instruction { [21:17] => [4:0] })).unwrap();
-        let rd = Register::try_from(shuffle_bits!(instruction { [16:12] => [4:0] })).unwrap();
-        let rs2 =
-            Register::try_from(shuffle_bits!(instruction { [11:8] => [3:0], [7] => [4] })).unwrap();
-        let cond = Condition::try_from(shuffle_bits!(instruction { [26:24] => [2:0] })).unwrap();
-
-        let value = if self.state.flags.satisfy(cond) {
-            self.get_reg(rs2)
-        } else {
-            self.get_reg(rs1)
-        };
-        self.set_reg(rd, value);
+        Ok(written)
     }
 
     #[inline]
-    fn aluc_32(&mut self, instruction: u32) -> Result<(), ExceptionKind> {
-        let rs1 = Register::try_from(shuffle_bits!(instruction { [21:17] => [4:0] })).unwrap();
-        let rd = Register::try_from(shuffle_bits!(instruction { [16:12] => [4:0] })).unwrap();
-        let rs2 =
-            Register::try_from(shuffle_bits!(instruction { [11:8] => [3:0], [7] => [4] })).unwrap();
-
-        let lhs = self.get_reg(rs1);
-        let rhs = self.get_reg(rs2);
+    fn execute_carry(&mut self, op: CarryOp, lhs: u32, rhs: u32) -> u32 {
         let c_in = self.state.flags.contains(Flags::CARRY);
-        let result = match (instruction & 0x700_0000) >> 24 {
-            0b000 /* addc */ => self.execute_add(lhs, rhs, c_in),
-            0b001 /* subc */ => self.execute_add(lhs, !rhs, c_in),
-            0b010..=0b111 => return Err(ExceptionKind::IllegalInstruction),
-            _ => unreachable!(),
+        let result = match op {
+            CarryOp::Addc => self.execute_add(lhs, rhs, c_in),
+            CarryOp::Subc => self.execute_add(lhs, !rhs, c_in),
         };
 
-        self.set_reg(rd, result);
         if result != 0 {
             self.state.flags.remove(Flags::ZERO);
         }
 
-        Ok(())
+        result
     }
 
     #[inline]
-    fn mul_32(&mut self, instruction: u32) {
-        let rs1 = Register::try_from(shuffle_bits!(instruction { [21:17] => [4:0] })).unwrap();
-        let rd = Register::try_from(shuffle_bits!(instruction { [16:12] => [4:0] })).unwrap();
-        let rs2 =
-            Register::try_from(shuffle_bits!(instruction { [11:8] => [3:0], [7] => [4] })).unwrap();
-
-        let lhs = self.get_reg(rs1);
-        let rhs = self.get_reg(rs2);
-        let result = match (instruction & 0x700_0000) >> 24 {
-            0b000 /* mul */ => {
+    fn execute_mul_div(&mut self, op: MulDivOp, lhs: u32, rhs: u32) -> u32 {
+        match op {
+            MulDivOp::Mul => {
                 let result = u32::wrapping_mul(lhs, rhs);
                 self.state.flags.set(Flags::ZERO, result == 0);
                 result
             }
-            0b001 /* mulhuu */ => {
+            MulDivOp::Mulhuu => {
                 let result = (u64::wrapping_mul(lhs as u64, rhs as u64) >> 32) as u32;
                 if result != 0 {
                     self.state.flags.remove(Flags::ZERO);
                 }
                 result
             }
-            0b010 /* mulhss */ => {
-                let result = (i64::wrapping_mul((lhs as i32) as i64, (rhs as i32) as i64) >> 32) as u32;
+            MulDivOp::Mulhss => {
+                let result =
+                    (i64::wrapping_mul((lhs as i32) as i64, (rhs as i32) as i64) >> 32) as u32;
                 if result != 0 {
                     self.state.flags.remove(Flags::ZERO);
                 }
                 result
             }
-            0b011 /* mulhus */ => {
-                let result = (i64::wrapping_mul((lhs as u64) as i64, (rhs as i32) as i64) >> 32) as u32;
+            MulDivOp::Mulhus => {
+                let result =
+                    (i64::wrapping_mul((lhs as u64) as i64, (rhs as i32) as i64) >> 32) as u32;
                 if result != 0 {
                     self.state.flags.remove(Flags::ZERO);
                 }
                 result
             }
-            0b100 /* divu */ => {
-                if rhs == 0 { u32::MAX } else { u32::wrapping_div(lhs, rhs) }
+            MulDivOp::Divu => {
+                if rhs == 0 {
+                    u32::MAX
+                } else {
+                    u32::wrapping_div(lhs, rhs)
+                }
             }
-            0b101 /* divs */ => {
+            MulDivOp::Divs => {
                 if rhs == 0 {
-                    if (lhs as i32) < 0 { i32::MIN as u32 } else { i32::MAX as u32 }
+                    if (lhs as i32) < 0 {
+                        i32::MIN as u32
+                    } else {
+                        i32::MAX as u32
+                    }
                 } else {
                     i32::wrapping_div(lhs as i32, rhs as i32) as u32
                 }
             }
-            0b110 /* remu */ => {
-                if rhs == 0 { 0 } else { u32::wrapping_rem(lhs, rhs) }
+            MulDivOp::Remu => {
+                if rhs == 0 {
+                    0
+                } else {
+                    u32::wrapping_rem(lhs, rhs)
+                }
             }
-            0b111 /* rems */ => {
-                if rhs == 0 { 0 } else { i32::wrapping_rem(lhs as i32, rhs as i32) as u32 }
+            MulDivOp::Rems => {
+                if rhs == 0 {
+                    0
+                } else {
+                    i32::wrapping_rem(lhs as i32, rhs as i32) as u32
+                }
             }
-            _ => unreachable!(),
-        };
-
-        self.set_reg(rd, result);
-    }
-
-    #[inline]
-    fn fpu3_32(&mut self, instruction: u32) -> Result<(), ExceptionKind> {
-        let rs1 = Register::try_from(shuffle_bits!(instruction { [21:17] => [4:0] })).unwrap();
-        let rd = Register::try_from(shuffle_bits!(instruction { [16:12] => [4:0] })).unwrap();
-        let rs2 =
-            Register::try_from(shuffle_bits!(instruction { [11:8] => [3:0], [7] => [4] })).unwrap();
-
-        let lhs = f32::from_bits(self.get_reg(rs1));
-        let rhs = f32::from_bits(self.get_reg(rs2));
-        let result = match (instruction & 0x700_0000) >> 24 {
-            0b000 /* fadd */ => lhs + rhs,
-            0b001 /* fsub */ => lhs - rhs,
-            0b010 /* fmul */ => lhs * rhs,
-            0b011 /* fdiv */ => lhs / rhs,
-            0b100 | 0b101 => return Err(ExceptionKind::IllegalInstruction),
-            0b110 /* fmin */ => lhs.min(rhs),
-            0b111 /* fmax */ => lhs.max(rhs),
-            _ => unreachable!(),
-        };
-
-        self.set_reg(rd, result.to_bits());
-
-        Ok(())
+        }
     }
 
     #[inline]
-    fn fpu2_32(&mut self, instruction: u32) {
-        let rs = Register::try_from(shuffle_bits!(instruction { [21:17] => [4:0] })).unwrap();
-        let rd = Register::try_from(shuffle_bits!(instruction { [16:12] => [4:0] })).unwrap();
-
-        let value = f32::from_bits(self.get_reg(rs));
-        let result = match (instruction & 0x700_0000) >> 24 {
-            0b000 /* ffloor */ => value.floor(),
-            0b001 /* fceil */ => value.ceil(),
-            0b010 /* fround */ => value.round(),
-            0b011 /* ftrunc */ => value.trunc(),
-            0b100 /* fabs */ => value.abs(),
-            0b101 /* fneg */ => -value,
-            0b110 /* fsqrt */ => value.sqrt(),
-            0b111 /* frsqrt */ => value.sqrt().recip(),
-            _ => unreachable!(),
-        };
-
-        self.set_reg(rd, result.to_bits());
+    fn execute_fpu3(op: Fpu3Op, lhs: f32, rhs: f32) -> f32 {
+        match op {
+            Fpu3Op::Add => lhs + rhs,
+            Fpu3Op::Sub => lhs - rhs,
+            Fpu3Op::Mul => lhs * rhs,
+            Fpu3Op::Div => lhs / rhs,
+            Fpu3Op::Min => lhs.min(rhs),
+            Fpu3Op::Max => lhs.max(rhs),
+        }
     }
 
     #[inline]
-    fn fcmp_32(&mut self, instruction: u32) {
-        let rs1 = Register::try_from(shuffle_bits!(instruction { [21:17] => [4:0] })).unwrap();
-        let rd = Register::try_from(shuffle_bits!(instruction { [16:12] => [4:0] })).unwrap();
-        let rs2 =
-            Register::try_from(shuffle_bits!(instruction { [11:8] => [3:0], [7] => [4] })).unwrap();
-
-        let lhs = f32::from_bits(self.get_reg(rs1));
-        let rhs = f32::from_bits(self.get_reg(rs2));
-        let result = match (instruction & 0x300_0000) >> 24 {
-            0b00 /* eq */ => lhs == rhs,
-            0b01 /* ne */ => lhs != rhs,
-            0b10 /* lt */ => lhs < rhs,
-            0b11 /* ge */ => lhs >= rhs,
-            _ => unreachable!(),
-        };
-
-        self.set_reg(rd, result as u32);
+    fn execute_fpu2(op: Fpu2Op, value: f32) -> f32 {
+        match op {
+            Fpu2Op::Floor => value.floor(),
+            Fpu2Op::Ceil => value.ceil(),
+            Fpu2Op::Round => value.round(),
+            Fpu2Op::Trunc => value.trunc(),
+            Fpu2Op::Abs => value.abs(),
+            Fpu2Op::Neg => -value,
+            Fpu2Op::Sqrt => value.sqrt(),
+            Fpu2Op::Rsqrt => value.sqrt().recip(),
+        }
     }
 
     #[inline]
-    fn cvt_32(&mut self, instruction: u32) -> Result<(), ExceptionKind> {
-        let rs = Register::try_from(shuffle_bits!(instruction { [21:17] => [4:0] })).unwrap();
-        let rd = Register::try_from(shuffle_bits!(instruction { [16:12] => [4:0] })).unwrap();
-
-        let value = self.get_reg(rs);
-        match (instruction & 0x700_0000) >> 24 {
-            0b000 /* ftoi */ => {
-                let result = f32::from_bits(value) as u32;
-                self.set_reg(rd, result);
-            }
-            0b001 /* itof */ => {
-                let result = value as f32;
-                self.set_reg(rd, result.to_bits());
-            }
-            0b010..=0b111 => {
-                return Err(ExceptionKind::IllegalInstruction);
-            }
-            _ => unreachable!(),
+    fn execute_fcmp(op: FcmpOp, lhs: f32, rhs: f32) -> bool {
+        match op {
+            FcmpOp::Eq => lhs == rhs,
+            FcmpOp::Ne => lhs != rhs,
+            FcmpOp::Lt => lhs < rhs,
+            FcmpOp::Ge => lhs >= rhs,
         }
-
-        Ok(())
     }
 
     #[inline]
-    fn ldr_32<Mem: MemoryInterface>(
+    fn execute<Mem: MemoryInterface, Io: IoInterface>(
         &mut self,
-        instruction: u32,
+        instruction: Instruction,
         priv_level: PrivilegeLevel,
         mem: &mut Mem,
-    ) -> Result<(), ExceptionKind> {
-        let rb = Register::try_from(shuffle_bits!(instruction { [21:17] => [4:0] })).unwrap();
-        let rd = Register::try_from(shuffle_bits!(instruction { [16:12] => [4:0] })).unwrap();
-
-        let addr = self.get_reg(rb);
-        match (instruction & 0x700_0000) >> 24 {
-            0b000 | 0b001 /* ldr.32 */ => {
-                let value = mem.read_32(addr, priv_level, true)?;
+        io: &mut Io,
+    ) -> Result<Option<u8>, ExceptionKind> {
+        match instruction {
+            Instruction::Ldi16 { rd, imm } => self.set_reg(rd, imm as u32),
+            Instruction::Addi16 { rd, imm } => {
+                let sum = self.execute_add(self.get_reg(rd), imm as u32, false);
+                self.state.flags.set(Flags::ZERO, sum == 0);
+                self.set_reg(rd, sum);
+            }
+            Instruction::Jump16 { link, rb, offset } => {
+                let link = if link { Register::Ra } else { Register::Zero };
+                self.jump(link, rb, offset);
+            }
+            Instruction::Branch16 { cond, offset } | Instruction::Branch32 { cond, offset } => {
+                self.branch(cond, offset);
+            }
+            Instruction::Alu16 { op, rd, rs } => {
+                let result = self.execute_alu(op, self.get_reg(rd), self.get_reg(rs));
+                self.set_reg(rd, result);
+            }
+            Instruction::Mov16 { cond, rd, rs } => {
+                if self.state.flags.satisfy(cond) {
+                    self.set_reg(rd, self.get_reg(rs));
+                }
+            }
+            Instruction::Cmp16 { rs1, rs2 } => {
+                let result = self.execute_add(self.get_reg(rs1), !self.get_reg(rs2), true);
+                self.state.flags.set(Flags::ZERO, result == 0);
+            }
+            Instruction::Ret => {
+                self.program_counter = self.get_reg(Register::Ra) & !0x1;
+            }
+            Instruction::Sysret => self.sysret(mem)?,
+            Instruction::Fence => (),
+            Instruction::Ifence => mem.flush_instruction_cache(),
+            Instruction::Envcall(code) => return Ok(Some(code)),
+            Instruction::Syscall(slot) => self.syscall(slot, mem),
+            Instruction::Shift16 { op, rd, shamt } => {
+                let result = self.execute_alu(op.into(), self.get_reg(rd), shamt);
+                self.set_reg(rd, result);
+            }
+            Instruction::Load16 { rd, offset } => {
+                let addr = self.get_reg(Register::Sp).wrapping_add(offset);
+                self.load(LoadOp::Word, rd, addr, priv_level, false, mem)?;
+            }
+            Instruction::Store16 { rs, offset } => {
+                let addr = self.get_reg(Register::Sp).wrapping_add(offset);
+                self.store(StoreOp::Word, rs, addr, priv_level, false, mem)?;
+            }
+            Instruction::Ldui { rd, imm } => self.set_reg(rd, imm),
+            Instruction::Apcui { rd, imm } => {
+                self.set_reg(rd, self.program_counter.wrapping_add(imm));
+            }
+            Instruction::Jump32 { rd, rb, offset } => self.jump(rd, rb, offset),
+            Instruction::AluI32 { op, rd, rs1, imm } => {
+                let result = self.execute_alu(op, self.get_reg(rs1), imm as u32);
+                self.set_reg(rd, result);
+            }
+            Instruction::MovI32 { cond, rd, rs1, imm } => {
+                let value = if self.state.flags.satisfy(cond) {
+                    imm as u32
+                } else {
+                    self.get_reg(rs1)
+                };
                 self.set_reg(rd, value);
             }
-            0b010 /* ldr.8u */ => {
-                let value = mem.read_8(addr, priv_level, true)?;
-                self.set_reg(rd, value as u32);
+            Instruction::Load32 { op, rd, rb, offset } => {
+                let addr = self.get_reg(rb).wrapping_add(offset as u32);
+                self.load(op, rd, addr, priv_level, false, mem)?;
             }
-            0b011 /* ldr.8s */ => {
-                let value = mem.read_8(addr, priv_level, true)?;
-                self.set_reg(rd, ((value as i8) as i32) as u32);
+            Instruction::In { rd, rb, offset } => {
+                let addr = self.get_reg(rb).wrapping_add(offset as u32);
+                let value = self
+                    .read_io(io, addr, priv_level)
+                    .map_err(|err| self.fault(Access::IoRead, addr, err))?;
+                self.set_reg(rd, value);
             }
-            0b100 /* ldr.16u */ => {
-                let value = mem.read_16(addr, priv_level, true)?;
-                self.set_reg(rd, value as u32);
+            Instruction::Store32 { op, rs, rb, offset } => {
+                let addr = self.get_reg(rb).wrapping_add(offset as u32);
+                self.store(op, rs, addr, priv_level, false, mem)?;
             }
-            0b101 /* ldr.16s */ => {
-                let value = mem.read_16(addr, priv_level, true)?;
-                self.set_reg(rd, ((value as i16) as i32) as u32);
+            Instruction::Out { rs, rb, offset } => {
+                let addr = self.get_reg(rb).wrapping_add(offset as u32);
+                self.write_io(io, addr, self.get_reg(rs), priv_level)
+                    .map_err(|err| self.fault(Access::IoWrite, addr, err))?;
             }
-            0b110 | 0b111 => {
-                return Err(ExceptionKind::IllegalInstruction);
+            Instruction::Alu32 { op, rd, rs1, rs2 } => {
+                let result = self.execute_alu(op, self.get_reg(rs1), self.get_reg(rs2));
+                self.set_reg(rd, result);
+            }
+            Instruction::Mov32 { cond, rd, rs1, rs2 } => {
+                let value = if self.state.flags.satisfy(cond) {
+                    self.get_reg(rs2)
+                } else {
+                    self.get_reg(rs1)
+                };
+                self.set_reg(rd, value);
+            }
+            Instruction::AluC { op, rd, rs1, rs2 } => {
+                let result = self.execute_carry(op, self.get_reg(rs1), self.get_reg(rs2));
+                self.set_reg(rd, result);
+            }
+            Instruction::MulDiv { op, rd, rs1, rs2 } => {
+                let result = self.execute_mul_div(op, self.get_reg(rs1), self.get_reg(rs2));
+                self.set_reg(rd, result);
+            }
+            Instruction::Fpu3 { op, rd, rs1, rs2 } => {
+                let lhs = f32::from_bits(self.get_reg(rs1));
+                let rhs = f32::from_bits(self.get_reg(rs2));
+                self.set_reg(rd, Self::execute_fpu3(op, lhs, rhs).to_bits());
+            }
+            Instruction::Fpu2 { op, rd, rs } => {
+                let value = f32::from_bits(self.get_reg(rs));
+                self.set_reg(rd, Self::execute_fpu2(op, value).to_bits());
+            }
+            Instruction::Fcmp { op, rd, rs1, rs2 } => {
+                let lhs = f32::from_bits(self.get_reg(rs1));
+                let rhs = f32::from_bits(self.get_reg(rs2));
+                self.set_reg(rd, Self::execute_fcmp(op, lhs, rhs) as u32);
+            }
+            Instruction::Cvt { op, rd, rs } => {
+                let value = self.get_reg(rs);
+                let result = match op {
+                    CvtOp::Ftoi => f32::from_bits(value) as u32,
+                    CvtOp::Itof => (value as f32).to_bits(),
+                };
+                self.set_reg(rd, result);
+            }
+            Instruction::Ldr { op, rd, rb } => {
+                let addr = self.get_reg(rb);
+                self.load(op, rd, addr, priv_level, true, mem)?;
+            }
+            Instruction::Stc { op, rd, rs, rb } => {
+                let addr = self.get_reg(rb);
+                let written = self.store(op, rs, addr, priv_level, true, mem)?;
+                self.set_reg(rd, written as u32);
             }
-            _ => unreachable!(),
         }
 
-        Ok(())
+        Ok(None)
     }
 
     #[inline]
-    fn stc_32<Mem: MemoryInterface>(
+    fn execute_next<Mem: MemoryInterface, Io: IoInterface>(
         &mut self,
-        instruction: u32,
+        mem: &mut Mem,
+        io: &mut Io,
+    ) -> Result<Option<u8>, ExceptionKind> {
+        debug_assert_eq!(self.program_counter & 0x1, 0);
+        let priv_level = self.effective_privilege_level();
+        let addr = self.program_counter;
+        self.instruction_address = addr;
+        let decoded = mem
+            .fetch(addr, priv_level)
+            .map_err(|err| self.fault(Access::Fetch, addr, err))?;
+        self.program_counter = addr.wrapping_add(decoded.size);
+
+        let instruction = decoded
+            .instruction
+            .map_err(|_| ExceptionKind::IllegalInstruction)?;
+        self.execute_timed(instruction, addr, decoded.size, priv_level, mem, io)
+    }
+
+    #[inline]
+    fn execute_timed<Mem: MemoryInterface, Io: IoInterface>(
+        &mut self,
+        instruction: Instruction,
+        addr: u32,
+        size: u32,
         priv_level: PrivilegeLevel,
         mem: &mut Mem,
-    ) -> Result<(), ExceptionKind> {
-        let rb = Register::try_from(shuffle_bits!(instruction { [21:17] => [4:0] })).unwrap();
-        let rd = Register::try_from(shuffle_bits!(instruction { [16:12] => [4:0] })).unwrap();
-        let rs =
-            Register::try_from(shuffle_bits!(instruction { [11:8] => [3:0], [7] => [4] })).unwrap();
-
-        let addr = self.get_reg(rb);
-        let result = match (instruction & 0x600_0000) >> 25 {
-            0b00 /* stc.32 */ => {
-                let value = self.get_reg(rs);
-                mem.write_32(addr, value, priv_level, true)?
-            }
-            0b01 /* stc.8 */ => {
-                let value = self.get_reg(rs) as u8;
-                mem.write_8(addr, value, priv_level, true)?
-            }
-            0b10 /* stc.16 */ => {
-                let value = self.get_reg(rs) as u16;
-                mem.write_16(addr, value, priv_level, true)?
-            }
-            0b11 => {
-                return Err(ExceptionKind::IllegalInstruction);
-            }
-            _ => unreachable!(),
+        io: &mut Io,
+    ) -> Result<Option<u8>, ExceptionKind> {
+        let Some(timing) = &mut self.timing else {
+            let code = self.execute(instruction, priv_level, mem, io)?;
+            self.retired_instructions += 1;
+            self.cycles += 1;
+            return Ok(code);
         };
-        self.set_reg(rd, result as u32);
 
-        Ok(())
+        let mut stalls = timing.issue(&instruction, addr, size, self.cycles, &self.state.regs);
+        let code = self.execute(instruction, priv_level, mem, io)?;
+
+        let timing = self.timing.as_mut().unwrap();
+        let next_addr = addr.wrapping_add(size);
+        stalls += timing.retire(&instruction, next_addr, self.program_counter);
+
+        self.retired_instructions += 1;
+        self.cycles += 1 + (stalls as u64);
+        self.stall_cycles += stalls as u64;
+        Ok(code)
     }
 
     #[inline]
@@ -1026,114 +1201,86 @@ impl Cpu {
         io: &mut Io,
     ) -> Result<Option<u8>, ExceptionKind> {
         if let Some(slot) = self.next_interrupt() {
-            self.enter_interrupt(self.hardware_interrupt_table[slot]);
+            self.hardware_interrupt(slot, mem);
             return Ok(None);
         }
 
-        debug_assert_eq!(self.program_counter & 0x1, 0);
-        let priv_level = self.effective_privilege_level();
-        let lower_inst = mem.read_16(self.program_counter, priv_level, false)?;
-
-        let instruction = lower_inst as u32;
-        self.program_counter = self.program_counter.wrapping_add(2);
-
-        // Instruction set:
-        // https://docs.google.com/spreadsheets/d/1VGV9Hp17HtE5oG_ltB0xSQ0j2AvfDYW9LLvOI28e6qM/edit?usp=sharing
+        self.execute_next(mem, io)
+    }
 
-        if (instruction & 0x1) == 0 {
-            self.ldi_addi_16(instruction);
-        } else if (instruction & 0x2) == 0 {
-            self.jump_16(instruction);
-        } else if (instruction & 0x4) == 0 {
-            if (instruction & 0x80) == 0 {
-                self.branch_16(instruction);
-            } else {
-                let upper_inst = mem.read_16(self.program_counter, priv_level, false)?;
+    fn step_block_inner<Mem: MemoryInterface, Io: IoInterface>(
+        &mut self,
+        mem: &mut Mem,
+        io: &mut Io,
+        max_instructions: usize,
+    ) -> Result<Option<u8>, ExceptionKind> {
+        if let Some(slot) = self.next_interrupt() {
+            self.hardware_interrupt(slot, mem);
+            return Ok(None);
+        }
 
-                let instruction = instruction | ((upper_inst as u32) << 16);
-                self.program_counter = self.program_counter.wrapping_add(2);
+        let priv_level = self.effective_privilege_level();
+        let Some(block) = mem.fetch_block(self.program_counter, priv_level) else {
+            return self.execute_next(mem, io);
+        };
 
-                self.uimm_32(instruction);
-            }
-        } else {
-            match (instruction & 0x18) >> 3 {
-                0b00 => self.alu_16(instruction),
-                0b01 => self.mov_16(instruction),
-                0b10 => {
-                    if (instruction & 0x60) == 0 {
-                        if (instruction & 0x80) == 0 {
-                            self.cmp_16(instruction);
-                        } else {
-                            return self.sys_16(instruction);
-                        }
-                    } else {
-                        self.alui_16(instruction);
-                    }
-                }
-                0b11 => {
-                    if (instruction & 0x20) == 0 {
-                        self.mem_16(instruction, priv_level, mem)?;
-                    } else {
-                        let upper_inst = mem.read_16(self.program_counter, priv_level, false)?;
-
-                        let instruction = instruction | ((upper_inst as u32) << 16);
-                        self.program_counter = self.program_counter.wrapping_add(2);
-
-                        match (instruction & 0xC0_0000) >> 22 {
-                            0b00 => {
-                                if (instruction & 0x40) == 0 {
-                                    self.jump_32(instruction);
-                                } else {
-                                    self.branch_32(instruction);
-                                }
-                            }
-                            0b01 => {
-                                if (instruction & 0x40) == 0 {
-                                    self.alui_32(instruction);
-                                } else {
-                                    self.movi_32(instruction);
-                                }
-                            }
-                            0b10 => self.mem_32(instruction, priv_level, mem, io)?,
-                            0b11 => {
-                                match shuffle_bits!(instruction { [31:27] => [5:1], [6] => [0] }) {
-                                    0b000000 => self.alu_32(instruction),
-                                    0b000001 => self.mov_32(instruction),
-                                    0b000010 => self.aluc_32(instruction)?,
-                                    0b000011 => self.mul_32(instruction),
-                                    0b000100 => self.fpu3_32(instruction)?,
-                                    0b000101 => self.fpu2_32(instruction),
-                                    0b000110 => self.fcmp_32(instruction),
-                                    0b000111 => self.cvt_32(instruction)?,
-                                    0b001000 | 0b001010 | 0b001100 | 0b001110 => {
-                                        self.ldr_32(instruction, priv_level, mem)?;
-                                    }
-                                    0b001001 | 0b001011 | 0b001101 | 0b001111 => {
-                                        self.stc_32(instruction, priv_level, mem)?;
-                                    }
-                                    _ => return Err(ExceptionKind::IllegalInstruction),
-                                }
-                            }
-                            _ => unreachable!(),
-                        }
-                    }
-                }
-                _ => unreachable!(),
+        for &(instruction, size) in block.instructions().iter().take(max_instructions) {
+            let addr = self.program_counter;
+            self.instruction_address = addr;
+            self.program_counter = addr.wrapping_add(size);
+            let code = self.execute_timed(instruction, addr, size, priv_level, mem, io)?;
+
+            // the rest of the block is stale if it got overwritten or has to be fetched with
+            // different permissions or translations
+            if code.is_some()
+                || !block.is_valid()
+                || (self.effective_privilege_level() != priv_level)
+                || mem.is_remapped()
+            {
+                return Ok(code);
             }
         }
 
         Ok(None)
     }
 
+    /// Executes a single instruction or enters a pending interrupt. Does nothing after a
+    /// machine check.
     pub fn step<Mem: MemoryInterface, Io: IoInterface>(
         &mut self,
         mem: &mut Mem,
         io: &mut Io,
     ) -> Option<u8> {
+        if self.machine_check.is_some() {
+            return None;
+        }
+
         match self.step_inner(mem, io) {
             Ok(code) => code,
             Err(kind) => {
-                self.exception(kind);
+                self.exception(kind, mem);
+                None
+            }
+        }
+    }
+
+    /// Executes up to one translated block, but no more than `max_instructions` of it, falling
+    /// back to a single instruction if the memory does not provide blocks. Interrupts are only
+    /// taken at block boundaries.
+    pub fn step_block<Mem: MemoryInterface, Io: IoInterface>(
+        &mut self,
+        mem: &mut Mem,
+        io: &mut Io,
+        max_instructions: usize,
+    ) -> Option<u8> {
+        if self.machine_check.is_some() {
+            return None;
+        }
+
+        match self.step_block_inner(mem, io, max_instructions) {
+            Ok(code) => code,
+            Err(kind) => {
+                self.exception(kind, mem);
                 None
             }
         }
@@ -0,0 +1,218 @@
+use super::instruction::{Decoded, Instruction};
+use super::interface::PrivilegeLevel;
+use crate::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+const PAGE_BITS: u32 = 12;
+const MAX_BLOCK_LEN: usize = 64;
+
+/// How often execution has to reach an address before a block starting there gets translated,
+/// code running only a few times is cheaper to interpret.
+pub const TRANSLATION_THRESHOLD: u32 = 16;
+
+/// A straight-line run of predecoded instructions, ending at the first control flow instruction.
+#[derive(Debug)]
+pub struct Block {
+    start: u32,
+    end: u32,
+    priv_level: PrivilegeLevel,
+    instructions: Box<[(Instruction, u32)]>,
+    valid: AtomicBool,
+}
+
+impl Block {
+    /// Translates the block starting at the virtual address `addr`, fetching instructions through
+    /// `fetch`. It is keyed and invalidated by `start`, the physical address `addr` maps to, so it
+    /// never crosses into the next page, which may be mapped anywhere.
+    ///
+    /// The block stops in front of the first instruction that cannot be fetched or decoded, so
+    /// that the interpreter raises the exception at the correct address. Returns `None` if not
+    /// even the first instruction is usable.
+    ///
+    /// I/O instructions only ever start a block. Devices and the counters they report are
+    /// brought up to date between steps, so they are current for the first instruction only.
+    pub fn translate<F>(
+        mut addr: u32,
+        start: u32,
+        priv_level: PrivilegeLevel,
+        mut fetch: F,
+    ) -> Option<Self>
+    where
+        F: FnMut(u32) -> Option<Decoded>,
+    {
+        let mut instructions = Vec::new();
+        let mut end = start;
+
+        while instructions.len() < MAX_BLOCK_LEN {
+            let Some(Decoded {
+                instruction: Ok(instruction),
+                size,
+            }) = fetch(addr)
+            else {
+                break;
+            };
+            if is_io(&instruction) && !instructions.is_empty() {
+                break;
+            }
+            if (end >> PAGE_BITS) != (end.wrapping_add(size - 1) >> PAGE_BITS) {
+                break;
+            }
+
+            instructions.push((instruction, size));
+            addr = addr.wrapping_add(size);
+            end = end.wrapping_add(size);
+
+            if ends_block(&instruction) {
+                break;
+            }
+        }
+
+        if instructions.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            start,
+            end,
+            priv_level,
+            instructions: instructions.into_boxed_slice(),
+            valid: AtomicBool::new(true),
+        })
+    }
+
+    #[inline]
+    pub fn instructions(&self) -> &[(Instruction, u32)] {
+        &self.instructions
+    }
+
+    /// A block becomes invalid as soon as any of its instructions gets overwritten.
+    #[inline]
+    pub fn is_valid(&self) -> bool {
+        self.valid.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    fn overlaps(&self, addr: u32, size: u32) -> bool {
+        let offset = addr.wrapping_sub(self.start);
+        let len = self.end.wrapping_sub(self.start);
+        (offset < len) || (self.start.wrapping_sub(addr) < size)
+    }
+
+    fn pages(&self) -> impl Iterator<Item = u32> {
+        let first = self.start >> PAGE_BITS;
+        let last = self.end.wrapping_sub(1) >> PAGE_BITS;
+        (0..=last.wrapping_sub(first)).map(move |i| first.wrapping_add(i))
+    }
+}
+
+fn ends_block(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Jump16 { .. }
+            | Instruction::Branch16 { .. }
+            | Instruction::Ret
+            | Instruction::Sysret
+            | Instruction::Fence
+            | Instruction::Ifence
+            | Instruction::Envcall(_)
+            | Instruction::Syscall(_)
+            | Instruction::Jump32 { .. }
+            | Instruction::Branch32 { .. }
+    )
+}
+
+fn is_io(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::In { .. } | Instruction::Out { .. }
+    )
+}
+
+/// Translated blocks keyed by their start address.
+///
+/// Like the `InstructionCache`, its owner has to invalidate every address that gets written to.
+#[derive(Default)]
+pub struct BlockCache {
+    blocks: HashMap<u32, Arc<Block>>,
+    pages: HashMap<u32, Vec<u32>>,
+    threshold: u32,
+    /// How often execution reached addresses no block has been translated for yet.
+    hits: HashMap<u32, u32>,
+}
+
+impl BlockCache {
+    /// Blocks get translated once execution reached their start `threshold` times.
+    pub fn new(threshold: u32) -> Self {
+        Self {
+            threshold,
+            ..Self::default()
+        }
+    }
+
+    /// Counts an execution of the code at `addr` that has not been translated, returns whether
+    /// it ran often enough to translate it now.
+    #[inline]
+    pub fn is_hot(&mut self, addr: u32) -> bool {
+        let hits = self.hits.entry(addr).or_default();
+        if *hits >= self.threshold {
+            return true;
+        }
+        *hits += 1;
+        false
+    }
+
+    #[inline]
+    pub fn get(&self, addr: u32, priv_level: PrivilegeLevel) -> Option<Arc<Block>> {
+        self.blocks
+            .get(&addr)
+            .filter(|block| block.priv_level == priv_level)
+            .cloned()
+    }
+
+    pub fn insert(&mut self, block: Block) -> Arc<Block> {
+        let block = Arc::new(block);
+        self.hits.remove(&block.start);
+        for page in block.pages() {
+            self.pages.entry(page).or_default().push(block.start);
+        }
+
+        if let Some(old) = self.blocks.insert(block.start, Arc::clone(&block)) {
+            old.valid.store(false, Ordering::Relaxed);
+        }
+
+        block
+    }
+
+    /// Drops every block overlapping the `size` bytes starting at `addr`.
+    #[inline]
+    pub fn invalidate(&mut self, addr: u32, size: u32) {
+        if self.blocks.is_empty() {
+            return;
+        }
+
+        let Some(starts) = self.pages.get_mut(&(addr >> PAGE_BITS)) else {
+            return;
+        };
+
+        starts.retain(|start| match self.blocks.get(start) {
+            Some(block) if block.overlaps(addr, size) => {
+                block.valid.store(false, Ordering::Relaxed);
+                self.blocks.remove(start);
+                false
+            }
+            Some(_) => true,
+            None => false,
+        });
+    }
+
+    pub fn flush(&mut self) {
+        for block in self.blocks.values() {
+            block.valid.store(false, Ordering::Relaxed);
+        }
+
+        self.blocks.clear();
+        self.pages.clear();
+        self.hits.clear();
+    }
+}
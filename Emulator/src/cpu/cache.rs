@@ -0,0 +1,100 @@
+use super::instruction::Decoded;
+use crate::HashMap;
+
+const PAGE_BITS: u32 = 12;
+const PAGE_SLOTS: usize = 1 << (PAGE_BITS - 1);
+
+type Page = [Option<Decoded>; PAGE_SLOTS];
+
+#[inline]
+const fn page_index(addr: u32) -> u32 {
+    addr >> PAGE_BITS
+}
+
+#[inline]
+const fn slot_index(addr: u32) -> usize {
+    ((addr as usize) & ((1 << PAGE_BITS) - 1)) >> 1
+}
+
+/// Predecoded instructions keyed by their address, grouped into pages of 4kB.
+///
+/// The cache has no knowledge of memory contents, its owner has to invalidate
+/// every address that gets written to.
+#[derive(Default)]
+pub struct InstructionCache {
+    pages: HashMap<u32, Box<Page>>,
+    last_page: Option<(u32, Box<Page>)>,
+}
+
+impl InstructionCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    fn page_mut(&mut self, index: u32) -> Option<&mut Page> {
+        if let Some((last_index, _)) = &self.last_page {
+            if *last_index != index {
+                let page = self.pages.remove(&index)?;
+                let (last_index, last_page) = self.last_page.replace((index, page)).unwrap();
+                self.pages.insert(last_index, last_page);
+            }
+        } else {
+            let page = self.pages.remove(&index)?;
+            self.last_page = Some((index, page));
+        }
+
+        self.last_page.as_mut().map(|(_, page)| page.as_mut())
+    }
+
+    #[inline]
+    pub fn get(&mut self, addr: u32) -> Option<Decoded> {
+        debug_assert_eq!(addr & 0x1, 0);
+        self.page_mut(page_index(addr))?[slot_index(addr)]
+    }
+
+    pub fn insert(&mut self, addr: u32, decoded: Decoded) {
+        debug_assert_eq!(addr & 0x1, 0);
+
+        let index = page_index(addr);
+        if self.page_mut(index).is_none() {
+            let page: Box<Page> = vec![None; PAGE_SLOTS]
+                .into_boxed_slice()
+                .try_into()
+                .unwrap();
+            self.pages.insert(index, page);
+        }
+
+        self.page_mut(index).unwrap()[slot_index(addr)] = Some(decoded);
+    }
+
+    /// Drops every instruction overlapping the `size` bytes starting at `addr`.
+    #[inline]
+    pub fn invalidate(&mut self, addr: u32, size: u32) {
+        if self.last_page.is_none() {
+            return;
+        }
+
+        // a 32 bit instruction starting one halfword earlier overlaps the write as well
+        let start = (addr & !0x1).wrapping_sub(2);
+        let end = addr.wrapping_add(size).wrapping_add(1) & !0x1;
+        for i in 0..(end.wrapping_sub(start) >> 1) {
+            let inst_addr = start.wrapping_add(i << 1);
+            let index = page_index(inst_addr);
+
+            let page = match &mut self.last_page {
+                Some((last_index, page)) if *last_index == index => Some(page),
+                _ => self.pages.get_mut(&index),
+            };
+
+            if let Some(page) = page {
+                page[slot_index(inst_addr)] = None;
+            }
+        }
+    }
+
+    pub fn flush(&mut self) {
+        self.pages.clear();
+        self.last_page = None;
+    }
+}
@@ -0,0 +1,97 @@
+use super::instruction::{instruction_len, Illegal, Instruction};
+
+/// Disassembles a single instruction located at `address`.
+///
+/// For 16 bit instructions the upper halfword of `instruction` is ignored.
+pub fn disassemble(instruction: u32, address: u32) -> Result<String, Illegal> {
+    Instruction::decode(instruction).map(|inst| inst.display(address).to_string())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub address: u32,
+    pub len: u32,
+    pub word: u32,
+    pub instruction: Result<Instruction, Illegal>,
+}
+
+impl std::fmt::Display for Line {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:0>8x}:  {:0>4x} ", self.address, self.word & 0xFFFF)?;
+
+        if self.len == 4 {
+            write!(f, "{:0>4x}", self.word >> 16)?;
+        } else {
+            f.write_str("    ")?;
+        }
+
+        match &self.instruction {
+            Ok(inst) => write!(f, "    {}", inst.display(self.address)),
+            Err(err) => write!(f, "    {err}"),
+        }
+    }
+}
+
+pub struct Listing<'a> {
+    image: &'a [u8],
+    base: u32,
+    offset: usize,
+}
+
+impl Iterator for Listing<'_> {
+    type Item = Line;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let remaining = self.image.get(self.offset..)?;
+        if remaining.is_empty() {
+            return None;
+        }
+
+        let address = self.base.wrapping_add(self.offset as u32);
+
+        let Some(lower) = remaining.get(0..2) else {
+            self.offset += remaining.len();
+            return Some(Line {
+                address,
+                len: remaining.len() as u32,
+                word: remaining[0] as u32,
+                instruction: Err(Illegal),
+            });
+        };
+
+        let lower = u16::from_le_bytes([lower[0], lower[1]]);
+        let len = instruction_len(lower);
+
+        let (word, instruction) = if len == 4 {
+            match remaining.get(2..4) {
+                Some(upper) => {
+                    let upper = u16::from_le_bytes([upper[0], upper[1]]);
+                    let word = (lower as u32) | ((upper as u32) << 16);
+                    (word, Instruction::decode(word))
+                }
+                None => (lower as u32, Err(Illegal)),
+            }
+        } else {
+            (lower as u32, Instruction::decode(lower as u32))
+        };
+
+        let len = len.min(remaining.len() as u32);
+        self.offset += len as usize;
+
+        Some(Line {
+            address,
+            len,
+            word,
+            instruction,
+        })
+    }
+}
+
+/// Disassembles a raw binary image that is loaded at address `base`.
+pub fn disassemble_image(image: &[u8], base: u32) -> Listing<'_> {
+    Listing {
+        image,
+        base,
+        offset: 0,
+    }
+}
@@ -0,0 +1,1025 @@
+use super::register::*;
+use crate::shuffle_bits;
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use strum::{Display, EnumIter};
+
+// Instruction set:
+// https://docs.google.com/spreadsheets/d/1VGV9Hp17HtE5oG_ltB0xSQ0j2AvfDYW9LLvOI28e6qM/edit?usp=sharing
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Illegal;
+
+impl std::fmt::Display for Illegal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("<illegal>")
+    }
+}
+
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, IntoPrimitive, TryFromPrimitive, EnumIter)]
+#[repr(u8)]
+#[strum(serialize_all = "lowercase")]
+pub enum AluOp {
+    Add,
+    Sub,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Lsr,
+    Asr,
+}
+
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, IntoPrimitive, TryFromPrimitive, EnumIter)]
+#[repr(u8)]
+#[strum(serialize_all = "lowercase")]
+pub enum ShiftOp {
+    Shl = 1,
+    Lsr = 2,
+    Asr = 3,
+}
+
+impl From<ShiftOp> for AluOp {
+    #[inline]
+    fn from(op: ShiftOp) -> Self {
+        match op {
+            ShiftOp::Shl => Self::Shl,
+            ShiftOp::Lsr => Self::Lsr,
+            ShiftOp::Asr => Self::Asr,
+        }
+    }
+}
+
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, IntoPrimitive, TryFromPrimitive, EnumIter)]
+#[repr(u8)]
+#[strum(serialize_all = "lowercase")]
+pub enum CarryOp {
+    Addc,
+    Subc,
+}
+
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, IntoPrimitive, TryFromPrimitive, EnumIter)]
+#[repr(u8)]
+#[strum(serialize_all = "lowercase")]
+pub enum MulDivOp {
+    Mul,
+    Mulhuu,
+    Mulhss,
+    Mulhus,
+    Divu,
+    Divs,
+    Remu,
+    Rems,
+}
+
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, IntoPrimitive, TryFromPrimitive, EnumIter)]
+#[repr(u8)]
+pub enum Fpu3Op {
+    #[strum(serialize = "fadd")]
+    Add = 0,
+    #[strum(serialize = "fsub")]
+    Sub = 1,
+    #[strum(serialize = "fmul")]
+    Mul = 2,
+    #[strum(serialize = "fdiv")]
+    Div = 3,
+    #[strum(serialize = "fmin")]
+    Min = 6,
+    #[strum(serialize = "fmax")]
+    Max = 7,
+}
+
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, IntoPrimitive, TryFromPrimitive, EnumIter)]
+#[repr(u8)]
+pub enum Fpu2Op {
+    #[strum(serialize = "ffloor")]
+    Floor,
+    #[strum(serialize = "fceil")]
+    Ceil,
+    #[strum(serialize = "fround")]
+    Round,
+    #[strum(serialize = "ftrunc")]
+    Trunc,
+    #[strum(serialize = "fabs")]
+    Abs,
+    #[strum(serialize = "fneg")]
+    Neg,
+    #[strum(serialize = "fsqrt")]
+    Sqrt,
+    #[strum(serialize = "frsqrt")]
+    Rsqrt,
+}
+
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, IntoPrimitive, TryFromPrimitive, EnumIter)]
+#[repr(u8)]
+#[strum(serialize_all = "lowercase")]
+pub enum FcmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Ge,
+}
+
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, IntoPrimitive, TryFromPrimitive, EnumIter)]
+#[repr(u8)]
+#[strum(serialize_all = "lowercase")]
+pub enum CvtOp {
+    Ftoi,
+    Itof,
+}
+
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, IntoPrimitive, TryFromPrimitive, EnumIter)]
+#[repr(u8)]
+pub enum LoadOp {
+    #[strum(serialize = "32")]
+    Word = 0b000,
+    #[strum(serialize = "8u")]
+    ByteU = 0b010,
+    #[strum(serialize = "8s")]
+    ByteS = 0b011,
+    #[strum(serialize = "16u")]
+    HalfU = 0b100,
+    #[strum(serialize = "16s")]
+    HalfS = 0b101,
+}
+
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, IntoPrimitive, TryFromPrimitive, EnumIter)]
+#[repr(u8)]
+pub enum StoreOp {
+    #[strum(serialize = "32")]
+    Word = 0b00,
+    #[strum(serialize = "8")]
+    Byte = 0b01,
+    #[strum(serialize = "16")]
+    Half = 0b10,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Ldi16 {
+        rd: Register,
+        imm: i32,
+    },
+    Addi16 {
+        rd: Register,
+        imm: i32,
+    },
+    Jump16 {
+        link: bool,
+        rb: Register,
+        offset: i32,
+    },
+    Branch16 {
+        cond: BranchCondition,
+        offset: i32,
+    },
+    Alu16 {
+        op: AluOp,
+        rd: Register,
+        rs: Register,
+    },
+    Mov16 {
+        cond: Condition,
+        rd: Register,
+        rs: Register,
+    },
+    Cmp16 {
+        rs1: Register,
+        rs2: Register,
+    },
+    Ret,
+    Sysret,
+    Fence,
+    Ifence,
+    Envcall(u8),
+    Syscall(u8),
+    Shift16 {
+        op: ShiftOp,
+        rd: Register,
+        shamt: u32,
+    },
+    Load16 {
+        rd: Register,
+        offset: u32,
+    },
+    Store16 {
+        rs: Register,
+        offset: u32,
+    },
+    Ldui {
+        rd: Register,
+        imm: u32,
+    },
+    Apcui {
+        rd: Register,
+        imm: u32,
+    },
+    Jump32 {
+        rd: Register,
+        rb: Register,
+        offset: i32,
+    },
+    Branch32 {
+        cond: BranchCondition,
+        offset: i32,
+    },
+    AluI32 {
+        op: AluOp,
+        rd: Register,
+        rs1: Register,
+        imm: i32,
+    },
+    MovI32 {
+        cond: Condition,
+        rd: Register,
+        rs1: Register,
+        imm: i32,
+    },
+    Load32 {
+        op: LoadOp,
+        rd: Register,
+        rb: Register,
+        offset: i32,
+    },
+    In {
+        rd: Register,
+        rb: Register,
+        offset: i32,
+    },
+    Store32 {
+        op: StoreOp,
+        rs: Register,
+        rb: Register,
+        offset: i32,
+    },
+    Out {
+        rs: Register,
+        rb: Register,
+        offset: i32,
+    },
+    Alu32 {
+        op: AluOp,
+        rd: Register,
+        rs1: Register,
+        rs2: Register,
+    },
+    Mov32 {
+        cond: Condition,
+        rd: Register,
+        rs1: Register,
+        rs2: Register,
+    },
+    AluC {
+        op: CarryOp,
+        rd: Register,
+        rs1: Register,
+        rs2: Register,
+    },
+    MulDiv {
+        op: MulDivOp,
+        rd: Register,
+        rs1: Register,
+        rs2: Register,
+    },
+    Fpu3 {
+        op: Fpu3Op,
+        rd: Register,
+        rs1: Register,
+        rs2: Register,
+    },
+    Fpu2 {
+        op: Fpu2Op,
+        rd: Register,
+        rs: Register,
+    },
+    Fcmp {
+        op: FcmpOp,
+        rd: Register,
+        rs1: Register,
+        rs2: Register,
+    },
+    Cvt {
+        op: CvtOp,
+        rd: Register,
+        rs: Register,
+    },
+    Ldr {
+        op: LoadOp,
+        rd: Register,
+        rb: Register,
+    },
+    Stc {
+        op: StoreOp,
+        rd: Register,
+        rs: Register,
+        rb: Register,
+    },
+}
+
+/// Returns the size in bytes of the instruction starting with the halfword `lower_inst`.
+pub const fn instruction_len(lower_inst: u16) -> u32 {
+    let is_32 = if (lower_inst & 0x3) != 0x3 {
+        false
+    } else if (lower_inst & 0x4) == 0 {
+        (lower_inst & 0x80) != 0
+    } else {
+        ((lower_inst & 0x18) == 0x18) && ((lower_inst & 0x20) != 0)
+    };
+
+    if is_32 {
+        4
+    } else {
+        2
+    }
+}
+
+/// Encodes `program` into a little endian code image.
+#[cfg(test)]
+pub(crate) fn assemble(program: &[Instruction]) -> Vec<u8> {
+    let mut image = Vec::new();
+    for inst in program {
+        let (word, size) = inst.encode();
+        image.extend_from_slice(&word.to_le_bytes()[..(size as usize)]);
+    }
+    image
+}
+
+#[inline]
+fn op<T: TryFrom<u8>>(bits: u32) -> Result<T, Illegal> {
+    T::try_from(bits as u8).map_err(|_| Illegal)
+}
+
+#[inline]
+fn reg_16(instruction: u32) -> Register {
+    Register::try_from(shuffle_bits!(instruction { [15:12] => [3:0] })).unwrap()
+}
+
+#[inline]
+fn rs2_16(instruction: u32) -> Register {
+    Register::try_from(shuffle_bits!(instruction { [11:8] => [3:0] })).unwrap()
+}
+
+#[inline]
+fn rd_32(instruction: u32) -> Register {
+    Register::try_from(shuffle_bits!(instruction { [16:12] => [4:0] })).unwrap()
+}
+
+#[inline]
+fn rs1_32(instruction: u32) -> Register {
+    Register::try_from(shuffle_bits!(instruction { [21:17] => [4:0] })).unwrap()
+}
+
+#[inline]
+fn rs2_32(instruction: u32) -> Register {
+    Register::try_from(shuffle_bits!(instruction { [11:8] => [3:0], [7] => [4] })).unwrap()
+}
+
+#[inline]
+fn imm_32(instruction: u32) -> i32 {
+    shuffle_bits!(instruction {
+        sign [31] => [9],
+        [30:27] => [8:5],
+        [11:7] => [4:0],
+    }) as i32
+}
+
+fn decode_16(instruction: u32) -> Result<Instruction, Illegal> {
+    let inst = if (instruction & 0x1) == 0 {
+        let rd = reg_16(instruction);
+        let imm = shuffle_bits!(instruction {
+            [11:7] => [4:0],
+            [6:4] => [8:6],
+            sign [3] => [9],
+            [2] => [5],
+        }) as i32;
+
+        if (instruction & 0x2) == 0 {
+            Instruction::Ldi16 { rd, imm }
+        } else {
+            Instruction::Addi16 { rd, imm }
+        }
+    } else if (instruction & 0x2) == 0 {
+        Instruction::Jump16 {
+            link: (instruction & 0x4) != 0,
+            rb: reg_16(instruction),
+            offset: shuffle_bits!(instruction {
+                [11:8] => [4:1],
+                [7] => [5],
+                [6:4] => [8:6],
+                sign [3] => [9],
+            }) as i32,
+        }
+    } else if (instruction & 0x4) == 0 {
+        Instruction::Branch16 {
+            cond: op(shuffle_bits!(instruction { [14:12] => [2:0] }))?,
+            offset: shuffle_bits!(instruction {
+                [15] => [5],
+                [11:8] => [4:1],
+                [6:4] => [8:6],
+                sign [3] => [9],
+            }) as i32,
+        }
+    } else {
+        match (instruction & 0x18) >> 3 {
+            0b00 => Instruction::Alu16 {
+                op: op((instruction & 0xE0) >> 5)?,
+                rd: reg_16(instruction),
+                rs: rs2_16(instruction),
+            },
+            0b01 => Instruction::Mov16 {
+                cond: op((instruction & 0xE0) >> 5)?,
+                rd: reg_16(instruction),
+                rs: rs2_16(instruction),
+            },
+            0b10 => {
+                if (instruction & 0x60) == 0 {
+                    if (instruction & 0x80) == 0 {
+                        Instruction::Cmp16 {
+                            rs1: reg_16(instruction),
+                            rs2: rs2_16(instruction),
+                        }
+                    } else {
+                        let arg = shuffle_bits!(instruction { [15:12] => [3:0] }) as u8;
+                        match (instruction & 0xF00) >> 8 {
+                            0b0000 => Instruction::Ret,
+                            0b0001 => Instruction::Sysret,
+                            0b0010 => Instruction::Fence,
+                            0b0011 => Instruction::Ifence,
+                            0b1110 => Instruction::Envcall(arg),
+                            0b1111 => Instruction::Syscall(arg),
+                            _ => return Err(Illegal),
+                        }
+                    }
+                } else {
+                    Instruction::Shift16 {
+                        op: op((instruction & 0x60) >> 5)?,
+                        rd: reg_16(instruction),
+                        shamt: shuffle_bits!(instruction { [11:7] => [4:0] }),
+                    }
+                }
+            }
+            0b11 => {
+                let rd_rs = reg_16(instruction);
+                let offset = shuffle_bits!(instruction {
+                    [11:9] => [4:2],
+                    [8:7] => [6:5],
+                });
+
+                if (instruction & 0x40) == 0 {
+                    Instruction::Load16 { rd: rd_rs, offset }
+                } else {
+                    Instruction::Store16 { rs: rd_rs, offset }
+                }
+            }
+            _ => unreachable!(),
+        }
+    };
+
+    Ok(inst)
+}
+
+fn decode_32(instruction: u32) -> Result<Instruction, Illegal> {
+    if (instruction & 0x4) == 0 {
+        let rd = rd_32(instruction);
+        let imm = shuffle_bits!(instruction {
+            sign [31] => [31],
+            [30:27] => [30:27],
+            [26:24] => [12:10],
+            [23:22] => [14:13],
+            [21:17] => [19:15],
+            [11:8] => [26:23],
+            [6:4] => [22:20],
+        });
+
+        return if (instruction & 0x8) == 0 {
+            Ok(Instruction::Ldui { rd, imm })
+        } else {
+            Ok(Instruction::Apcui { rd, imm })
+        };
+    }
+
+    let rd = rd_32(instruction);
+    let rs1 = rs1_32(instruction);
+    let rs2 = rs2_32(instruction);
+    let op_bits = (instruction & 0x700_0000) >> 24;
+
+    let inst = match (instruction & 0xC0_0000) >> 22 {
+        0b00 => {
+            if (instruction & 0x40) == 0 {
+                Instruction::Jump32 {
+                    rd,
+                    rb: rs1,
+                    offset: shuffle_bits!(instruction {
+                        sign [31] => [13],
+                        [30:27] => [8:5],
+                        [26:24] => [12:10],
+                        [11:8] => [4:1],
+                        [7] => [9],
+                    }) as i32,
+                }
+            } else {
+                Instruction::Branch32 {
+                    cond: op(shuffle_bits!(instruction { [14:12] => [2:0] }))?,
+                    offset: shuffle_bits!(instruction {
+                        sign [31] => [20],
+                        [30:27] => [8:5],
+                        [26:24] => [12:10],
+                        [21:15] => [19:13],
+                        [11:8] => [4:1],
+                        [7] => [9],
+                    }) as i32,
+                }
+            }
+        }
+        0b01 => {
+            let imm = imm_32(instruction);
+
+            if (instruction & 0x40) == 0 {
+                Instruction::AluI32 {
+                    op: op(op_bits)?,
+                    rd,
+                    rs1,
+                    imm,
+                }
+            } else {
+                Instruction::MovI32 {
+                    cond: op(op_bits)?,
+                    rd,
+                    rs1,
+                    imm,
+                }
+            }
+        }
+        0b10 => {
+            if (instruction & 0x40) == 0 {
+                let offset = imm_32(instruction);
+                match op_bits {
+                    0b001 => Instruction::Load32 {
+                        op: LoadOp::Word,
+                        rd,
+                        rb: rs1,
+                        offset,
+                    },
+                    0b110 | 0b111 => Instruction::In {
+                        rd,
+                        rb: rs1,
+                        offset,
+                    },
+                    _ => Instruction::Load32 {
+                        op: op(op_bits)?,
+                        rd,
+                        rb: rs1,
+                        offset,
+                    },
+                }
+            } else {
+                let offset = shuffle_bits!(instruction {
+                    sign [31] => [9],
+                    [30:27] => [8:5],
+                    [16:12] => [4:0],
+                }) as i32;
+
+                match op_bits >> 1 {
+                    0b11 => Instruction::Out {
+                        rs: rs2,
+                        rb: rs1,
+                        offset,
+                    },
+                    store_bits => Instruction::Store32 {
+                        op: op(store_bits)?,
+                        rs: rs2,
+                        rb: rs1,
+                        offset,
+                    },
+                }
+            }
+        }
+        0b11 => match shuffle_bits!(instruction { [31:27] => [5:1], [6] => [0] }) {
+            0b000000 => Instruction::Alu32 {
+                op: op(op_bits)?,
+                rd,
+                rs1,
+                rs2,
+            },
+            0b000001 => Instruction::Mov32 {
+                cond: op(op_bits)?,
+                rd,
+                rs1,
+                rs2,
+            },
+            0b000010 => Instruction::AluC {
+                op: op(op_bits)?,
+                rd,
+                rs1,
+                rs2,
+            },
+            0b000011 => Instruction::MulDiv {
+                op: op(op_bits)?,
+                rd,
+                rs1,
+                rs2,
+            },
+            0b000100 => Instruction::Fpu3 {
+                op: op(op_bits)?,
+                rd,
+                rs1,
+                rs2,
+            },
+            0b000101 => Instruction::Fpu2 {
+                op: op(op_bits)?,
+                rd,
+                rs: rs1,
+            },
+            0b000110 => Instruction::Fcmp {
+                op: op(op_bits & 0b11)?,
+                rd,
+                rs1,
+                rs2,
+            },
+            0b000111 => Instruction::Cvt {
+                op: op(op_bits)?,
+                rd,
+                rs: rs1,
+            },
+            0b001000 | 0b001010 | 0b001100 | 0b001110 => Instruction::Ldr {
+                op: if op_bits == 0b001 {
+                    LoadOp::Word
+                } else {
+                    op(op_bits)?
+                },
+                rd,
+                rb: rs1,
+            },
+            0b001001 | 0b001011 | 0b001101 | 0b001111 => Instruction::Stc {
+                op: op(op_bits >> 1)?,
+                rd,
+                rs: rs2,
+                rb: rs1,
+            },
+            _ => return Err(Illegal),
+        },
+        _ => unreachable!(),
+    };
+
+    Ok(inst)
+}
+
+#[inline]
+fn reg_bits(reg: Register) -> u32 {
+    u32::from(reg)
+}
+
+#[inline]
+fn rs2_bits(reg: Register) -> u32 {
+    let reg = u32::from(reg);
+    shuffle_bits!(reg { [3:0] => [11:8], [4] => [7] })
+}
+
+#[inline]
+fn imm_bits(imm: i32) -> u32 {
+    let imm = imm as u32;
+    shuffle_bits!(imm {
+        [9] => [31],
+        [8:5] => [30:27],
+        [4:0] => [11:7],
+    })
+}
+
+#[inline]
+fn r_type(group: u32, op: u8, rd: Register, rs1: Register, rs2: Register) -> u32 {
+    ((group >> 1) << 27)
+        | ((group & 0x1) << 6)
+        | ((op as u32) << 24)
+        | (0b11 << 22)
+        | (reg_bits(rs1) << 17)
+        | (reg_bits(rd) << 12)
+        | rs2_bits(rs2)
+        | 0b0111111
+}
+
+/// An instruction word as seen by the fetch stage, together with its size in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decoded {
+    pub instruction: Result<Instruction, Illegal>,
+    pub size: u32,
+}
+
+impl Decoded {
+    #[inline]
+    pub fn new(word: u32) -> Self {
+        Self {
+            instruction: Instruction::decode(word),
+            size: instruction_len(word as u16),
+        }
+    }
+}
+
+impl Instruction {
+    pub fn decode(instruction: u32) -> Result<Self, Illegal> {
+        if instruction_len(instruction as u16) == 4 {
+            decode_32(instruction)
+        } else {
+            decode_16(instruction & 0xFFFF)
+        }
+    }
+
+    /// Encodes the instruction, returning the instruction word and its size in bytes.
+    ///
+    /// Fields must be representable in the chosen format, e.g. 16 bit forms only address the
+    /// lower 16 registers. Out of range fields are truncated.
+    pub fn encode(&self) -> (u32, u32) {
+        let word = match *self {
+            Self::Ldi16 { rd, imm } | Self::Addi16 { rd, imm } => {
+                debug_assert!((-512..=511).contains(&imm));
+                let imm = imm as u32;
+                let add = matches!(self, Self::Addi16 { .. }) as u32;
+                shuffle_bits!(imm {
+                    [4:0] => [11:7],
+                    [8:6] => [6:4],
+                    [9] => [3],
+                    [5] => [2],
+                }) | (reg_bits(rd) << 12)
+                    | (add << 1)
+            }
+            Self::Jump16 { link, rb, offset } => {
+                let offset = offset as u32;
+                shuffle_bits!(offset {
+                    [4:1] => [11:8],
+                    [5] => [7],
+                    [8:6] => [6:4],
+                    [9] => [3],
+                }) | (reg_bits(rb) << 12)
+                    | ((link as u32) << 2)
+                    | 0b001
+            }
+            Self::Branch16 { cond, offset } => {
+                let offset = offset as u32;
+                shuffle_bits!(offset {
+                    [5] => [15],
+                    [4:1] => [11:8],
+                    [8:6] => [6:4],
+                    [9] => [3],
+                }) | (u32::from(cond) << 12)
+                    | 0b011
+            }
+            Self::Alu16 { op, rd, rs } => {
+                (reg_bits(rd) << 12) | (reg_bits(rs) << 8) | ((u8::from(op) as u32) << 5) | 0b00111
+            }
+            Self::Mov16 { cond, rd, rs } => {
+                (reg_bits(rd) << 12) | (reg_bits(rs) << 8) | (u32::from(cond) << 5) | 0b01111
+            }
+            Self::Cmp16 { rs1, rs2 } => (reg_bits(rs1) << 12) | (reg_bits(rs2) << 8) | 0x17,
+            Self::Ret => 0x0097,
+            Self::Sysret => 0x0197,
+            // bits [15:12] are ignored, but kernel/art32.asm sets them for a fence
+            Self::Fence => (0xF << 12) | 0x0297,
+            Self::Ifence => 0x0397,
+            Self::Envcall(code) => (((code & 0xF) as u32) << 12) | 0x0E97,
+            Self::Syscall(slot) => (((slot & 0xF) as u32) << 12) | 0x0F97,
+            Self::Shift16 { op, rd, shamt } => {
+                (reg_bits(rd) << 12)
+                    | ((shamt & 0x1F) << 7)
+                    | ((u8::from(op) as u32) << 5)
+                    | 0b10111
+            }
+            Self::Load16 { rd: reg, offset } | Self::Store16 { rs: reg, offset } => {
+                let store = matches!(self, Self::Store16 { .. }) as u32;
+                shuffle_bits!(offset {
+                    [4:2] => [11:9],
+                    [6:5] => [8:7],
+                }) | (reg_bits(reg) << 12)
+                    | (store << 6)
+                    | 0b0011111
+            }
+            Self::Ldui { rd, imm } | Self::Apcui { rd, imm } => {
+                let pc_relative = matches!(self, Self::Apcui { .. }) as u32;
+                shuffle_bits!(imm {
+                    [31:27] => [31:27],
+                    [12:10] => [26:24],
+                    [14:13] => [23:22],
+                    [19:15] => [21:17],
+                    [26:23] => [11:8],
+                    [22:20] => [6:4],
+                }) | (reg_bits(rd) << 12)
+                    | (pc_relative << 3)
+                    | 0b1000_0011
+            }
+            Self::Jump32 { rd, rb, offset } => {
+                let offset = offset as u32;
+                shuffle_bits!(offset {
+                    [13] => [31],
+                    [8:5] => [30:27],
+                    [12:10] => [26:24],
+                    [4:1] => [11:8],
+                    [9] => [7],
+                }) | (reg_bits(rb) << 17)
+                    | (reg_bits(rd) << 12)
+                    | 0b0111111
+            }
+            Self::Branch32 { cond, offset } => {
+                let offset = offset as u32;
+                shuffle_bits!(offset {
+                    [20] => [31],
+                    [8:5] => [30:27],
+                    [12:10] => [26:24],
+                    [19:13] => [21:15],
+                    [4:1] => [11:8],
+                    [9] => [7],
+                }) | (u32::from(cond) << 12)
+                    | 0b1111111
+            }
+            Self::AluI32 { op, rd, rs1, imm } => {
+                imm_bits(imm)
+                    | ((u8::from(op) as u32) << 24)
+                    | (0b01 << 22)
+                    | (reg_bits(rs1) << 17)
+                    | (reg_bits(rd) << 12)
+                    | 0b0111111
+            }
+            Self::MovI32 { cond, rd, rs1, imm } => {
+                imm_bits(imm)
+                    | (u32::from(cond) << 24)
+                    | (0b01 << 22)
+                    | (reg_bits(rs1) << 17)
+                    | (reg_bits(rd) << 12)
+                    | 0b1111111
+            }
+            Self::Load32 { op, rd, rb, offset } => {
+                imm_bits(offset)
+                    | ((u8::from(op) as u32) << 24)
+                    | (0b10 << 22)
+                    | (reg_bits(rb) << 17)
+                    | (reg_bits(rd) << 12)
+                    | 0b0111111
+            }
+            Self::In { rd, rb, offset } => {
+                imm_bits(offset)
+                    | (0b110 << 24)
+                    | (0b10 << 22)
+                    | (reg_bits(rb) << 17)
+                    | (reg_bits(rd) << 12)
+                    | 0b0111111
+            }
+            Self::Store32 { op, rs, rb, offset } => {
+                let offset = offset as u32;
+                shuffle_bits!(offset {
+                    [9] => [31],
+                    [8:5] => [30:27],
+                    [4:0] => [16:12],
+                }) | ((u8::from(op) as u32) << 25)
+                    | (0b10 << 22)
+                    | (reg_bits(rb) << 17)
+                    | rs2_bits(rs)
+                    | 0b1111111
+            }
+            Self::Out { rs, rb, offset } => {
+                let offset = offset as u32;
+                shuffle_bits!(offset {
+                    [9] => [31],
+                    [8:5] => [30:27],
+                    [4:0] => [16:12],
+                }) | (0b11 << 25)
+                    | (0b10 << 22)
+                    | (reg_bits(rb) << 17)
+                    | rs2_bits(rs)
+                    | 0b1111111
+            }
+            Self::Alu32 { op, rd, rs1, rs2 } => r_type(0b000000, op.into(), rd, rs1, rs2),
+            Self::Mov32 { cond, rd, rs1, rs2 } => r_type(0b000001, cond.into(), rd, rs1, rs2),
+            Self::AluC { op, rd, rs1, rs2 } => r_type(0b000010, op.into(), rd, rs1, rs2),
+            Self::MulDiv { op, rd, rs1, rs2 } => r_type(0b000011, op.into(), rd, rs1, rs2),
+            Self::Fpu3 { op, rd, rs1, rs2 } => r_type(0b000100, op.into(), rd, rs1, rs2),
+            Self::Fpu2 { op, rd, rs } => r_type(0b000101, op.into(), rd, rs, Register::Zero),
+            Self::Fcmp { op, rd, rs1, rs2 } => r_type(0b000110, op.into(), rd, rs1, rs2),
+            Self::Cvt { op, rd, rs } => r_type(0b000111, op.into(), rd, rs, Register::Zero),
+            Self::Ldr { op, rd, rb } => r_type(0b001000, op.into(), rd, rb, Register::Zero),
+            Self::Stc { op, rd, rs, rb } => r_type(0b001001, u8::from(op) << 1, rd, rb, rs),
+        };
+
+        (word, instruction_len(word as u16))
+    }
+
+    /// Returns the size of the encoded instruction in bytes.
+    #[inline]
+    pub fn size(&self) -> u32 {
+        match self {
+            Self::Ldi16 { .. }
+            | Self::Addi16 { .. }
+            | Self::Jump16 { .. }
+            | Self::Branch16 { .. }
+            | Self::Alu16 { .. }
+            | Self::Mov16 { .. }
+            | Self::Cmp16 { .. }
+            | Self::Ret
+            | Self::Sysret
+            | Self::Fence
+            | Self::Ifence
+            | Self::Envcall(_)
+            | Self::Syscall(_)
+            | Self::Shift16 { .. }
+            | Self::Load16 { .. }
+            | Self::Store16 { .. } => 2,
+            _ => 4,
+        }
+    }
+
+    /// Formats the instruction in assembler syntax, resolving PC relative
+    /// targets as if the instruction was located at `address`.
+    pub fn display(&self, address: u32) -> InstructionDisplay<'_> {
+        InstructionDisplay {
+            instruction: self,
+            address,
+        }
+    }
+}
+
+pub struct InstructionDisplay<'a> {
+    instruction: &'a Instruction,
+    address: u32,
+}
+
+impl std::fmt::Display for InstructionDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let next_address = self.address.wrapping_add(self.instruction.size());
+
+        let fmt_branch = |f: &mut std::fmt::Formatter<'_>, cond, offset: i32| {
+            let target = next_address.wrapping_add(offset as u32) & !0x1;
+            match cond {
+                BranchCondition::True => write!(f, "jr 0x{target:0>8X}"),
+                BranchCondition::Link => write!(f, "jrl 0x{target:0>8X}"),
+                _ => write!(f, "br.{cond} 0x{target:0>8X}"),
+            }
+        };
+
+        let fmt_mov = |f: &mut std::fmt::Formatter<'_>, cond, rd, rs1, rs2| match cond {
+            Condition::True => write!(f, "mov {rd}, {rs2}"),
+            _ => write!(f, "mov.{cond} {rd}, {rs1}, {rs2}"),
+        };
+
+        match *self.instruction {
+            Instruction::Ldi16 { rd, imm } => write!(f, "ldi {rd}, {imm}"),
+            Instruction::Addi16 { rd, imm } => write!(f, "addi {rd}, {rd}, {imm}"),
+            Instruction::Jump16 { link, rb, offset } => {
+                if link {
+                    write!(f, "jl {}, {rb}, {offset}", Register::Ra)
+                } else {
+                    write!(f, "j {rb}, {offset}")
+                }
+            }
+            Instruction::Branch16 { cond, offset } | Instruction::Branch32 { cond, offset } => {
+                fmt_branch(f, cond, offset)
+            }
+            Instruction::Alu16 { op, rd, rs } => write!(f, "{op} {rd}, {rd}, {rs}"),
+            Instruction::Mov16 { cond, rd, rs } => fmt_mov(f, cond, rd, rd, rs),
+            Instruction::Cmp16 { rs1, rs2 } => write!(f, "cmp {rs1}, {rs2}"),
+            Instruction::Ret => f.write_str("ret"),
+            Instruction::Sysret => f.write_str("sysret"),
+            Instruction::Fence => f.write_str("fence"),
+            Instruction::Ifence => f.write_str("ifence"),
+            Instruction::Envcall(code) => write!(f, "envcall {code}"),
+            Instruction::Syscall(slot) => write!(f, "syscall {slot}"),
+            Instruction::Shift16 { op, rd, shamt } => write!(f, "{op}i {rd}, {rd}, {shamt}"),
+            Instruction::Load16 { rd, offset } => {
+                write!(f, "ld.32 {rd}, [{}, {offset}]", Register::Sp)
+            }
+            Instruction::Store16 { rs, offset } => {
+                write!(f, "st.32 [{}, {offset}], {rs}", Register::Sp)
+            }
+            Instruction::Ldui { rd, imm } => write!(f, "ldui {rd}, 0x{imm:0>8X}"),
+            Instruction::Apcui { rd, imm } => write!(
+                f,
+                "apcui {rd}, 0x{imm:0>8X}  ; 0x{:0>8X}",
+                next_address.wrapping_add(imm)
+            ),
+            Instruction::Jump32 { rd, rb, offset } => {
+                if rd == Register::Zero {
+                    write!(f, "j {rb}, {offset}")
+                } else {
+                    write!(f, "jl {rd}, {rb}, {offset}")
+                }
+            }
+            Instruction::AluI32 { op, rd, rs1, imm } => write!(f, "{op}i {rd}, {rs1}, {imm}"),
+            Instruction::MovI32 { cond, rd, rs1, imm } => match cond {
+                Condition::True => write!(f, "ldi {rd}, {imm}"),
+                _ => write!(f, "movi.{cond} {rd}, {rs1}, {imm}"),
+            },
+            Instruction::Load32 { op, rd, rb, offset } => {
+                write!(f, "ld.{op} {rd}, [{rb}, {offset}]")
+            }
+            Instruction::In { rd, rb, offset } => write!(f, "in {rd}, [{rb}, {offset}]"),
+            Instruction::Store32 { op, rs, rb, offset } => {
+                write!(f, "st.{op} [{rb}, {offset}], {rs}")
+            }
+            Instruction::Out { rs, rb, offset } => write!(f, "out [{rb}, {offset}], {rs}"),
+            Instruction::Alu32 { op, rd, rs1, rs2 } => write!(f, "{op} {rd}, {rs1}, {rs2}"),
+            Instruction::Mov32 { cond, rd, rs1, rs2 } => fmt_mov(f, cond, rd, rs1, rs2),
+            Instruction::AluC { op, rd, rs1, rs2 } => write!(f, "{op} {rd}, {rs1}, {rs2}"),
+            Instruction::MulDiv { op, rd, rs1, rs2 } => write!(f, "{op} {rd}, {rs1}, {rs2}"),
+            Instruction::Fpu3 { op, rd, rs1, rs2 } => write!(f, "{op} {rd}, {rs1}, {rs2}"),
+            Instruction::Fpu2 { op, rd, rs } => write!(f, "{op} {rd}, {rs}"),
+            Instruction::Fcmp { op, rd, rs1, rs2 } => write!(f, "fcmp.{op} {rd}, {rs1}, {rs2}"),
+            Instruction::Cvt { op, rd, rs } => write!(f, "{op} {rd}, {rs}"),
+            Instruction::Ldr { op, rd, rb } => write!(f, "ldr.{op} {rd}, [{rb}]"),
+            Instruction::Stc { op, rd, rs, rb } => write!(f, "stc.{op} {rd}, [{rb}], {rs}"),
+        }
+    }
+}
@@ -1,4 +1,7 @@
+use super::block::Block;
+use super::instruction::{instruction_len, Decoded};
 use num_enum::{IntoPrimitive, TryFromPrimitive};
+use std::sync::Arc;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, IntoPrimitive, TryFromPrimitive)]
 #[repr(u8)]
@@ -28,6 +31,8 @@ impl From<PrivilegeLevel> for u32 {
 pub enum MemoryError {
     AccessViolation,
     UnalignedAccess,
+    /// The address has no valid translation or its page does not permit the access.
+    PageFault,
 }
 
 pub trait MemoryInterface {
@@ -75,6 +80,59 @@ pub trait MemoryInterface {
         priv_level: PrivilegeLevel,
         conditional: bool,
     ) -> Result<bool, MemoryError>;
+
+    /// Reads an instruction halfword. Unlike `read_16` this is an instruction fetch, so it needs
+    /// execute instead of read permission.
+    fn fetch_16(&mut self, addr: u32, priv_level: PrivilegeLevel) -> Result<u16, MemoryError>;
+
+    #[inline]
+    fn fetch(&mut self, addr: u32, priv_level: PrivilegeLevel) -> Result<Decoded, MemoryError> {
+        fetch_uncached(self, addr, priv_level)
+    }
+
+    #[inline]
+    fn flush_instruction_cache(&mut self) {}
+
+    /// Whether translations or access permissions changed since the instruction caches were last
+    /// flushed, translated blocks must not keep running past such a change.
+    #[inline]
+    fn is_remapped(&self) -> bool {
+        false
+    }
+
+    /// Returns the translated block starting at `addr`, if the memory supports block translation.
+    #[inline]
+    fn fetch_block(&mut self, _addr: u32, _priv_level: PrivilegeLevel) -> Option<Arc<Block>> {
+        None
+    }
+}
+
+/// Reads the raw instruction word at `addr`, the upper half is only read for 32 bit instructions.
+#[inline]
+pub fn read_instruction<Mem: MemoryInterface + ?Sized>(
+    mem: &mut Mem,
+    addr: u32,
+    priv_level: PrivilegeLevel,
+) -> Result<u32, MemoryError> {
+    let lower_inst = mem.fetch_16(addr, priv_level)?;
+    let mut instruction = lower_inst as u32;
+
+    if instruction_len(lower_inst) == 4 {
+        let upper_inst = mem.fetch_16(addr.wrapping_add(2), priv_level)?;
+        instruction |= (upper_inst as u32) << 16;
+    }
+
+    Ok(instruction)
+}
+
+/// Fetches and decodes the instruction at `addr` without going through any cache.
+#[inline]
+pub fn fetch_uncached<Mem: MemoryInterface + ?Sized>(
+    mem: &mut Mem,
+    addr: u32,
+    priv_level: PrivilegeLevel,
+) -> Result<Decoded, MemoryError> {
+    read_instruction(mem, addr, priv_level).map(Decoded::new)
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
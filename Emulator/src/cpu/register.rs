@@ -7,7 +7,7 @@ use strum::{Display, EnumCount, EnumIter};
 )]
 #[repr(u8)]
 #[strum(serialize_all = "lowercase")]
-pub(super) enum Register {
+pub enum Register {
     Zero,
     Ra,
     Sp,
@@ -101,7 +101,7 @@ impl RegisterFile {
 #[derive(Debug, Display, Clone, Copy, PartialEq, Eq, IntoPrimitive, TryFromPrimitive, EnumIter)]
 #[repr(u8)]
 #[strum(serialize_all = "lowercase")]
-pub(super) enum Condition {
+pub enum Condition {
     Eq,
     Ne,
     Lt,
@@ -132,7 +132,7 @@ impl From<Condition> for u32 {
 #[derive(Debug, Display, Clone, Copy, PartialEq, Eq, IntoPrimitive, TryFromPrimitive, EnumIter)]
 #[repr(u8)]
 #[strum(serialize_all = "lowercase")]
-pub(super) enum BranchCondition {
+pub enum BranchCondition {
     Eq,
     Ne,
     Lt,
@@ -163,7 +163,7 @@ impl From<BranchCondition> for u32 {
 bitflags! {
     #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
     #[repr(transparent)]
-    pub(super) struct Flags : u8 {
+    pub struct Flags : u8 {
         const CARRY = 0x1;
         const ZERO = 0x2;
         const SIGN = 0x4;
@@ -196,7 +196,7 @@ impl std::fmt::Display for Flags {
 }
 
 impl Flags {
-    pub(super) fn satisfy(self, condition: Condition) -> bool {
+    pub fn satisfy(self, condition: Condition) -> bool {
         match condition {
             Condition::Eq => self.contains(Self::ZERO),
             Condition::Ne => !self.contains(Self::ZERO),
@@ -209,7 +209,7 @@ impl Flags {
         }
     }
 
-    pub(super) fn satisfy_branch(self, condition: BranchCondition) -> bool {
+    pub fn satisfy_branch(self, condition: BranchCondition) -> bool {
         match condition {
             BranchCondition::Eq => self.contains(Self::ZERO),
             BranchCondition::Ne => !self.contains(Self::ZERO),
@@ -1,4 +1,10 @@
+mod cache;
+mod decode;
+mod disasm;
+mod exception;
 mod instruction;
+mod interrupt;
+mod timing;
 
 use super::interface::*;
 use super::{BranchCondition, Condition, Cpu, Flags, Register, RESET_PROGRAM_COUNTER};
@@ -38,6 +44,18 @@ fn br_cond() -> impl Strategy<Value = BranchCondition> {
     any::<proptest::sample::Selector>().prop_map(|sel| sel.select(BranchCondition::iter()))
 }
 
+/// Packs a little endian image into the words of a `TestMemory`, zero padding the last one.
+fn words(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks(4)
+        .map(|chunk| {
+            let mut word = [0; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            u32::from_le_bytes(word)
+        })
+        .collect()
+}
+
 struct TestMemory<'a> {
     mem: &'a mut [u32],
     pass_cond: bool,
@@ -186,6 +204,11 @@ impl MemoryInterface for TestMemory<'_> {
             Err(MemoryError::AccessViolation)
         }
     }
+
+    #[inline]
+    fn fetch_16(&mut self, addr: u32, priv_level: PrivilegeLevel) -> Result<u16, MemoryError> {
+        self.read_16(addr, priv_level, false)
+    }
 }
 
 struct TestIo;
@@ -0,0 +1,58 @@
+use super::super::cache::InstructionCache;
+use super::super::instruction::Decoded;
+use proptest::prelude::*;
+use test_strategy::proptest;
+
+fn decoded_32() -> Decoded {
+    // ldui zero, 0x00000000
+    Decoded::new(0x0000_0083)
+}
+
+#[proptest]
+fn insert_get(#[strategy(0u32..0x8000)] offset: u32, base: u32) {
+    let addr = base.wrapping_add(offset) & !0x1;
+
+    let mut cache = InstructionCache::new();
+    prop_assert_eq!(cache.get(addr), None);
+
+    cache.insert(addr, decoded_32());
+    prop_assert_eq!(cache.get(addr), Some(decoded_32()));
+    prop_assert_eq!(cache.get(addr.wrapping_add(2)), None);
+    prop_assert_eq!(cache.get(addr.wrapping_add(0x1000)), None);
+}
+
+#[proptest]
+fn invalidate_overlapping(
+    #[strategy(0u32..0x2000)] inst_offset: u32,
+    #[strategy(0u32..0x2000)] write_offset: u32,
+    #[strategy(proptest::sample::select(vec![1u32, 2, 4]))] write_size: u32,
+    base: u32,
+) {
+    let inst_addr = base.wrapping_add(inst_offset) & !0x1;
+    let write_addr = (base & !0x1).wrapping_add(write_offset) & !(write_size - 1);
+
+    let mut cache = InstructionCache::new();
+    cache.insert(inst_addr, decoded_32());
+    cache.invalidate(write_addr, write_size);
+
+    let inst_start = inst_addr.wrapping_sub(base & !0x1);
+    let write_start = write_addr.wrapping_sub(base & !0x1);
+    let overlaps = (inst_start < write_start.wrapping_add(write_size))
+        && (write_start < inst_start.wrapping_add(4));
+
+    prop_assert_eq!(cache.get(inst_addr).is_none(), overlaps);
+}
+
+#[test]
+fn flush() {
+    let mut cache = InstructionCache::new();
+    for addr in (0x1000_0000..0x1000_4000).step_by(0x100) {
+        cache.insert(addr, decoded_32());
+    }
+
+    cache.flush();
+
+    for addr in (0x1000_0000..0x1000_4000).step_by(0x100) {
+        assert_eq!(cache.get(addr), None);
+    }
+}
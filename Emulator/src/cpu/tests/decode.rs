@@ -0,0 +1,227 @@
+use super::super::instruction::*;
+use super::super::Register;
+use super::{br_cond, cond, reg16, reg32};
+use proptest::prelude::*;
+use strum::IntoEnumIterator;
+use test_strategy::proptest;
+
+fn op<T: IntoEnumIterator + std::fmt::Debug>() -> impl Strategy<Value = T> {
+    any::<proptest::sample::Selector>().prop_map(|sel| sel.select(T::iter()))
+}
+
+fn even(range: std::ops::RangeInclusive<i32>) -> impl Strategy<Value = i32> {
+    range.prop_map(|x| x & !0x1)
+}
+
+fn instruction_16() -> impl Strategy<Value = Instruction> {
+    prop_oneof![
+        (reg16(), -512..=511).prop_map(|(rd, imm)| Instruction::Ldi16 { rd, imm }),
+        (reg16(), -512..=511).prop_map(|(rd, imm)| Instruction::Addi16 { rd, imm }),
+        (any::<bool>(), reg16(), even(-512..=511))
+            .prop_map(|(link, rb, offset)| Instruction::Jump16 { link, rb, offset }),
+        (br_cond(), even(-512..=511))
+            .prop_map(|(cond, offset)| Instruction::Branch16 { cond, offset }),
+        (op(), reg16(), reg16()).prop_map(|(op, rd, rs)| Instruction::Alu16 { op, rd, rs }),
+        (cond(), reg16(), reg16()).prop_map(|(cond, rd, rs)| Instruction::Mov16 { cond, rd, rs }),
+        (reg16(), reg16()).prop_map(|(rs1, rs2)| Instruction::Cmp16 { rs1, rs2 }),
+        Just(Instruction::Ret),
+        Just(Instruction::Sysret),
+        Just(Instruction::Fence),
+        Just(Instruction::Ifence),
+        (0u8..16).prop_map(Instruction::Envcall),
+        (0u8..16).prop_map(Instruction::Syscall),
+        (op(), reg16(), 0u32..32).prop_map(|(op, rd, shamt)| Instruction::Shift16 {
+            op,
+            rd,
+            shamt
+        }),
+        (reg16(), 0u32..32).prop_map(|(rd, offset)| Instruction::Load16 {
+            rd,
+            offset: offset << 2
+        }),
+        (reg16(), 0u32..32).prop_map(|(rs, offset)| Instruction::Store16 {
+            rs,
+            offset: offset << 2
+        }),
+    ]
+}
+
+fn instruction_32() -> impl Strategy<Value = Instruction> {
+    let imm10 = || -512..=511;
+
+    prop_oneof![
+        (reg32(), any::<u32>()).prop_map(|(rd, imm)| Instruction::Ldui {
+            rd,
+            imm: imm & !0x3FF
+        }),
+        (reg32(), any::<u32>()).prop_map(|(rd, imm)| Instruction::Apcui {
+            rd,
+            imm: imm & !0x3FF
+        }),
+        (reg32(), reg32(), even(-8192..=8191)).prop_map(|(rd, rb, offset)| Instruction::Jump32 {
+            rd,
+            rb,
+            offset
+        }),
+        (br_cond(), even(-1_048_576..=1_048_575))
+            .prop_map(|(cond, offset)| Instruction::Branch32 { cond, offset }),
+        (op(), reg32(), reg32(), imm10()).prop_map(|(op, rd, rs1, imm)| Instruction::AluI32 {
+            op,
+            rd,
+            rs1,
+            imm
+        }),
+        (cond(), reg32(), reg32(), imm10()).prop_map(|(cond, rd, rs1, imm)| Instruction::MovI32 {
+            cond,
+            rd,
+            rs1,
+            imm
+        }),
+        (op(), reg32(), reg32(), imm10()).prop_map(|(op, rd, rb, offset)| Instruction::Load32 {
+            op,
+            rd,
+            rb,
+            offset
+        }),
+        (reg32(), reg32(), imm10()).prop_map(|(rd, rb, offset)| Instruction::In { rd, rb, offset }),
+        (op(), reg32(), reg32(), imm10()).prop_map(|(op, rs, rb, offset)| Instruction::Store32 {
+            op,
+            rs,
+            rb,
+            offset
+        }),
+        (reg32(), reg32(), imm10()).prop_map(|(rs, rb, offset)| Instruction::Out {
+            rs,
+            rb,
+            offset
+        }),
+        (op(), reg32(), reg32(), reg32()).prop_map(|(op, rd, rs1, rs2)| Instruction::Alu32 {
+            op,
+            rd,
+            rs1,
+            rs2
+        }),
+        (cond(), reg32(), reg32(), reg32()).prop_map(|(cond, rd, rs1, rs2)| Instruction::Mov32 {
+            cond,
+            rd,
+            rs1,
+            rs2
+        }),
+        (op(), reg32(), reg32(), reg32()).prop_map(|(op, rd, rs1, rs2)| Instruction::AluC {
+            op,
+            rd,
+            rs1,
+            rs2
+        }),
+        (op(), reg32(), reg32(), reg32()).prop_map(|(op, rd, rs1, rs2)| Instruction::MulDiv {
+            op,
+            rd,
+            rs1,
+            rs2
+        }),
+        (op(), reg32(), reg32(), reg32()).prop_map(|(op, rd, rs1, rs2)| Instruction::Fpu3 {
+            op,
+            rd,
+            rs1,
+            rs2
+        }),
+        (op(), reg32(), reg32()).prop_map(|(op, rd, rs)| Instruction::Fpu2 { op, rd, rs }),
+        (op(), reg32(), reg32(), reg32()).prop_map(|(op, rd, rs1, rs2)| Instruction::Fcmp {
+            op,
+            rd,
+            rs1,
+            rs2
+        }),
+        (op(), reg32(), reg32()).prop_map(|(op, rd, rs)| Instruction::Cvt { op, rd, rs }),
+        (op(), reg32(), reg32()).prop_map(|(op, rd, rb)| Instruction::Ldr { op, rd, rb }),
+        (op(), reg32(), reg32(), reg32()).prop_map(|(op, rd, rs, rb)| Instruction::Stc {
+            op,
+            rd,
+            rs,
+            rb
+        }),
+    ]
+}
+
+#[proptest]
+fn encode_decode_16(#[strategy(instruction_16())] inst: Instruction) {
+    let (word, len) = inst.encode();
+
+    prop_assert_eq!(len, 2);
+    prop_assert_eq!(word >> 16, 0);
+    prop_assert_eq!(Instruction::decode(word), Ok(inst));
+}
+
+#[proptest]
+fn encode_decode_32(#[strategy(instruction_32())] inst: Instruction) {
+    let (word, len) = inst.encode();
+
+    prop_assert_eq!(len, 4);
+    prop_assert_eq!(inst.size(), 4);
+    prop_assert_eq!(Instruction::decode(word), Ok(inst));
+}
+
+#[proptest]
+fn decode_encode(word: u32) {
+    if let Ok(inst) = Instruction::decode(word) {
+        let (encoded, len) = inst.encode();
+
+        prop_assert_eq!(len, instruction_len(word as u16));
+        prop_assert_eq!(len, inst.size());
+        prop_assert_eq!(Instruction::decode(encoded), Ok(inst));
+    }
+}
+
+#[test]
+fn system_ops() {
+    // the words of the `ret` to `syscall` rules in kernel/art32.asm
+    for (word, inst) in [
+        (0x0097, Instruction::Ret),
+        (0x0197, Instruction::Sysret),
+        (0xF297, Instruction::Fence),
+        (0x0397, Instruction::Ifence),
+        (0x5E97, Instruction::Envcall(5)),
+        (0x3F97, Instruction::Syscall(3)),
+    ] {
+        assert_eq!(Instruction::decode(word), Ok(inst));
+        assert_eq!(inst.encode(), (word, 2), "{inst:?}");
+    }
+    assert_eq!(Instruction::decode(0x0297), Ok(Instruction::Fence));
+}
+
+#[test]
+fn decode_encode_all_16() {
+    for word in 0..=u16::MAX {
+        if instruction_len(word) != 2 {
+            continue;
+        }
+
+        if let Ok(inst) = Instruction::decode(word as u32) {
+            let (encoded, len) = inst.encode();
+            assert_eq!(len, 2);
+
+            match inst {
+                // these ignore bits [15:12]
+                Instruction::Ret
+                | Instruction::Sysret
+                | Instruction::Fence
+                | Instruction::Ifence => {
+                    assert_eq!(encoded & 0x0FFF, (word as u32) & 0x0FFF, "{inst:?}");
+                }
+                _ => assert_eq!(encoded, word as u32, "{inst:?}"),
+            }
+        }
+    }
+}
+
+#[test]
+fn register_zero_encodes_to_zero() {
+    assert_eq!(
+        Instruction::Ldi16 {
+            rd: Register::Zero,
+            imm: 0
+        }
+        .encode(),
+        (0, 2)
+    );
+}
@@ -0,0 +1,71 @@
+use super::super::disasm::{disassemble, disassemble_image};
+use super::super::instruction::{instruction_len, Illegal, Instruction};
+
+const BASE: u32 = 0x1000_0000;
+
+#[test]
+fn format_16() {
+    assert_eq!(disassemble(0x9800, BASE).unwrap(), "ldi a1, 16");
+    assert_eq!(disassemble(0x2C7E, BASE).unwrap(), "addi sp, sp, -8");
+    assert_eq!(disassemble(0x8917, BASE).unwrap(), "cmp a0, a1");
+    assert_eq!(disassemble(0x68CF, BASE).unwrap(), "mov s0, a0");
+    assert_eq!(disassemble(0x105F, BASE).unwrap(), "st.32 [sp, 0], ra");
+    assert_eq!(disassemble(0x621F, BASE).unwrap(), "ld.32 s0, [sp, 4]");
+    assert_eq!(disassemble(0x0097, BASE).unwrap(), "ret");
+    assert_eq!(disassemble(0x0197, BASE).unwrap(), "sysret");
+    assert_eq!(disassemble(0x2E97, BASE).unwrap(), "envcall 2");
+}
+
+#[test]
+fn branch_targets() {
+    assert_eq!(
+        disassemble(0xAB7B, BASE + 0x26).unwrap(),
+        "br.lt 0x1000001E"
+    );
+    assert_eq!(disassemble(0xEF7B, BASE + 0x52).unwrap(), "jr 0x10000052");
+    assert_eq!(disassemble(0x7103, BASE + 0x62).unwrap(), "jrl 0x10000066");
+}
+
+#[test]
+fn format_32() {
+    assert_eq!(
+        disassemble(0x0E81_007F, BASE).unwrap(),
+        "out [zero, 48], zero"
+    );
+    assert_eq!(
+        disassemble(0x1002_2083, BASE).unwrap(),
+        "ldui sp, 0x10008000"
+    );
+    assert_eq!(
+        disassemble(0x2680_98BF, BASE).unwrap(),
+        "in a1, [zero, 145]"
+    );
+    assert_eq!(disassemble(0x028C_803F, BASE).unwrap(), "ld.8u a0, [s0, 0]");
+}
+
+#[test]
+fn reserved_encodings() {
+    // sys 0b0100..=0b1101
+    assert_eq!(disassemble(0x0497, BASE), Err(Illegal));
+    // fpu3 0b100
+    assert_eq!(disassemble(0x14C0_003F, BASE), Err(Illegal));
+    // cvt 0b010
+    assert_eq!(disassemble(0x1AC0_007F, BASE), Err(Illegal));
+    // opcode group 0b010000
+    assert_eq!(disassemble(0x40C0_003F, BASE), Err(Illegal));
+}
+
+#[test]
+fn image_listing() {
+    let image = [0x7F, 0x00, 0x81, 0x0E, 0x97, 0x00, 0x00];
+    let lines: Vec<_> = disassemble_image(&image, BASE).collect();
+
+    assert_eq!(lines.len(), 3);
+    assert_eq!((lines[0].address, lines[0].len), (BASE, 4));
+    assert_eq!((lines[1].address, lines[1].len), (BASE + 4, 2));
+    assert_eq!(lines[1].instruction, Ok(Instruction::Ret));
+    assert_eq!((lines[2].address, lines[2].len), (BASE + 6, 1));
+    assert_eq!(lines[2].instruction, Err(Illegal));
+    assert_eq!(instruction_len(0x007F), 4);
+    assert_eq!(instruction_len(0x0097), 2);
+}
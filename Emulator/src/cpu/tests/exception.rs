@@ -0,0 +1,132 @@
+use super::super::instruction::*;
+use super::super::interface::PrivilegeLevel;
+use super::super::{Cpu, ExceptionKind, InterruptState, Register, RESET_PROGRAM_COUNTER};
+use super::{words, TestIo, TestMemory};
+use strum::EnumCount;
+use Instruction::*;
+use Register::*;
+
+const HANDLER_OFFSET: usize = 0x20;
+
+/// Runs `program` in user mode, with every exception vector pointing to a handler that reads the
+/// exception cause, fault address and faulting instruction into `a0`, `a1` and `a2`.
+fn run_to_handler(program: &[Instruction], steps: usize, cpu: &mut Cpu) {
+    let handler = (0x34..0x37).zip([A0, A1, A2]).map(|(offset, rd)| In {
+        rd,
+        rb: Zero,
+        offset,
+    });
+
+    let mut bytes = assemble(program);
+    assert!(bytes.len() <= HANDLER_OFFSET);
+    bytes.resize(HANDLER_OFFSET, 0);
+    bytes.extend(assemble(&handler.collect::<Vec<_>>()));
+
+    let mut mem = words(&bytes);
+    let mut mem = TestMemory::new(&mut mem, false);
+
+    cpu.interrupt_state = InterruptState::Listening;
+    cpu.privilege_level = PrivilegeLevel::User;
+    cpu.exception_table = [RESET_PROGRAM_COUNTER + (HANDLER_OFFSET as u32); ExceptionKind::COUNT];
+
+    for _ in 0..(steps + 3) {
+        assert!(cpu.step(&mut mem, &mut TestIo).is_none());
+    }
+    assert_eq!(cpu.interrupt_state, InterruptState::Servicing);
+}
+
+fn cause(kind: ExceptionKind, access: u32) -> u32 {
+    (access << 8) | (usize::from(kind) as u32)
+}
+
+#[test]
+fn load_fault() {
+    let faulting = Load32 {
+        op: LoadOp::Word,
+        rd: A0,
+        rb: Zero,
+        offset: 0x100,
+    };
+
+    let mut cpu = Cpu::new();
+    run_to_handler(&[faulting], 1, &mut cpu);
+
+    assert_eq!(cpu.get_reg(A0), cause(ExceptionKind::AccessViolation, 2));
+    assert_eq!(cpu.get_reg(A1), 0x100);
+    assert_eq!(cpu.get_reg(A2), faulting.encode().0);
+}
+
+#[test]
+fn store_fault() {
+    let faulting = Store32 {
+        op: StoreOp::Word,
+        rs: A0,
+        rb: A3,
+        offset: 6,
+    };
+
+    let mut cpu = Cpu::new();
+    cpu.set_reg(A3, RESET_PROGRAM_COUNTER);
+    run_to_handler(&[Ldi16 { rd: A0, imm: 1 }, faulting], 2, &mut cpu);
+
+    assert_eq!(cpu.get_reg(A0), cause(ExceptionKind::UnalignedAccess, 3));
+    assert_eq!(cpu.get_reg(A1), RESET_PROGRAM_COUNTER + 6);
+    assert_eq!(cpu.get_reg(A2), faulting.encode().0);
+}
+
+#[test]
+fn io_fault() {
+    let read = In {
+        rd: A0,
+        rb: Zero,
+        offset: 0x34,
+    };
+    let write = Out {
+        rs: A0,
+        rb: Zero,
+        offset: 0x30,
+    };
+
+    let mut cpu = Cpu::new();
+    run_to_handler(&[read], 1, &mut cpu);
+    assert_eq!(cpu.get_reg(A0), cause(ExceptionKind::AccessViolation, 4));
+    assert_eq!(cpu.get_reg(A1), 0x34);
+    assert_eq!(cpu.get_reg(A2), read.encode().0);
+
+    let mut cpu = Cpu::new();
+    run_to_handler(&[write], 1, &mut cpu);
+    assert_eq!(cpu.get_reg(A0), cause(ExceptionKind::AccessViolation, 5));
+    assert_eq!(cpu.get_reg(A1), 0x30);
+    assert_eq!(cpu.get_reg(A2), write.encode().0);
+}
+
+#[test]
+fn fetch_fault() {
+    let jump = Jump32 {
+        rd: Zero,
+        rb: Zero,
+        offset: 0x100,
+    };
+
+    let mut cpu = Cpu::new();
+    run_to_handler(&[jump], 2, &mut cpu);
+
+    let reason = usize::from(ExceptionKind::AccessViolation) as u32;
+    assert_eq!(
+        cpu.get_reg(A0),
+        (reason << 16) | cause(ExceptionKind::FetchFault, 1)
+    );
+    assert_eq!(cpu.get_reg(A1), 0x100);
+    assert_eq!(cpu.get_reg(A2), 0);
+    assert_eq!(cpu.interrupt_return_address, 0x100);
+}
+
+#[test]
+fn illegal_instruction() {
+    let mut cpu = Cpu::new();
+    run_to_handler(&[Ldi16 { rd: A0, imm: 1 }, Sysret], 2, &mut cpu);
+
+    assert_eq!(cpu.get_reg(A0), cause(ExceptionKind::IllegalInstruction, 0));
+    assert_eq!(cpu.get_reg(A1), RESET_PROGRAM_COUNTER + 2);
+    assert_eq!(cpu.get_reg(A2), Sysret.encode().0);
+}
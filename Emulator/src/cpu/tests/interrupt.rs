@@ -0,0 +1,184 @@
+use super::super::instruction::*;
+use super::super::{BranchCondition, Cpu, InterruptState, Register, RESET_PROGRAM_COUNTER};
+use super::{words, TestIo, TestMemory};
+use Instruction::*;
+use Register::*;
+
+const USER: u32 = 0x80;
+const HANDLERS: [u32; 3] = [0x90, 0xB0, 0xC0];
+const EXCEPTION_HANDLER: u32 = 0xD0;
+const STACK_TOP: u32 = 0x1FC;
+const MEM_SIZE: usize = 0x200;
+
+/// Sets up hardware interrupt slots 1 to 3 with the given priorities and nested interrupts,
+/// then returns to a user mode loop.
+fn kernel(priorities: [i32; 3]) -> Vec<u32> {
+    let offset = |imm| AluI32 {
+        op: AluOp::Add,
+        rd: A0,
+        rs1: S0,
+        imm,
+    };
+    let out = |offset| Out {
+        rs: A0,
+        rb: Zero,
+        offset,
+    };
+
+    let mut setup = vec![Ldui {
+        rd: S0,
+        imm: RESET_PROGRAM_COUNTER,
+    }];
+    for (slot, (priority, handler)) in (1..).zip(priorities.into_iter().zip(HANDLERS)) {
+        setup.extend([
+            Ldi16 {
+                rd: A0,
+                imm: priority,
+            },
+            out(0x70 + slot),
+        ]);
+        setup.extend([offset(handler as i32), out(slot)]);
+    }
+    setup.extend([
+        offset(EXCEPTION_HANDLER as i32),
+        out(0x20),
+        out(0x21),
+        out(0x22),
+    ]);
+    setup.extend([offset(STACK_TOP as i32), out(0x39)]);
+    setup.extend([Ldi16 { rd: A0, imm: 1 }, out(0x37)]);
+    setup.extend([Ldi16 { rd: A0, imm: 0xE }, out(0x30)]);
+    setup.extend([offset(USER as i32), out(0x33), Sysret]);
+
+    let nop = Ldi16 { rd: Zero, imm: 0 };
+    let mut handler_1 = vec![Ldi16 { rd: A1, imm: 11 }];
+    handler_1.extend([nop; 6]);
+    handler_1.push(Sysret);
+    let handler_2 = [
+        Ldi16 { rd: A1, imm: 22 },
+        Load32 {
+            op: LoadOp::Word,
+            rd: A0,
+            rb: Zero,
+            offset: 0x100,
+        },
+        Sysret,
+    ];
+    let handler_3 = [Ldi16 { rd: A1, imm: 33 }, Sysret];
+
+    let mut bytes = vec![0; MEM_SIZE];
+    for (addr, program) in [
+        (0, setup.as_slice()),
+        (
+            USER,
+            &[Branch16 {
+                cond: BranchCondition::True,
+                offset: -2,
+            }],
+        ),
+        (HANDLERS[0], &handler_1),
+        (HANDLERS[1], &handler_2),
+        (HANDLERS[2], &handler_3),
+        (EXCEPTION_HANDLER, &[Sysret]),
+    ] {
+        let code = assemble(program);
+        let addr = addr as usize;
+        bytes[addr..(addr + code.len())].copy_from_slice(&code);
+    }
+
+    words(&bytes)
+}
+
+/// Signals slot 1, then slots 2 and 3 while its handler runs, and returns the handlers in the
+/// order they got entered together with the deepest nesting level reached.
+fn preemption_order(priorities: [i32; 3]) -> (Vec<u32>, u32) {
+    let mut mem = kernel(priorities);
+    let mut mem = TestMemory::new(&mut mem, false);
+    let mut cpu = Cpu::new();
+
+    let mut step = |cpu: &mut Cpu| assert!(cpu.step(&mut mem, &mut TestIo).is_none());
+    let pc = |cpu: &Cpu| cpu.program_counter - RESET_PROGRAM_COUNTER;
+
+    for _ in 0..100 {
+        if pc(&cpu) == USER {
+            break;
+        }
+        step(&mut cpu);
+    }
+    assert_eq!(pc(&cpu), USER);
+
+    cpu.signal_interrupt(1);
+    step(&mut cpu);
+    assert_eq!(pc(&cpu), HANDLERS[0]);
+    step(&mut cpu);
+    step(&mut cpu);
+
+    cpu.signal_interrupt(2);
+    cpu.signal_interrupt(3);
+
+    let mut order = vec![HANDLERS[0]];
+    let mut max_depth = 0;
+    for _ in 0..100 {
+        step(&mut cpu);
+        max_depth = max_depth.max(cpu.interrupt_depth);
+
+        if HANDLERS.contains(&pc(&cpu)) || (pc(&cpu) == EXCEPTION_HANDLER) {
+            order.push(pc(&cpu));
+        }
+
+        // the preempted handler gets its registers back
+        if pc(&cpu) == HANDLERS[0] + 14 {
+            assert_eq!(cpu.get_reg(A1), 11);
+        }
+
+        if (cpu.interrupt_state == InterruptState::Listening) && (cpu.pending_interrupts == 0) {
+            break;
+        }
+    }
+
+    assert_eq!(cpu.interrupt_state, InterruptState::Listening);
+    assert_eq!(pc(&cpu), USER);
+    assert_eq!(cpu.interrupt_depth, 0);
+    assert_eq!(cpu.interrupt_level, 0);
+    assert_eq!(
+        cpu.interrupt_stack_pointer,
+        RESET_PROGRAM_COUNTER + STACK_TOP
+    );
+    (order, max_depth)
+}
+
+#[test]
+fn higher_priority_preempts() {
+    let [h1, h2, h3] = HANDLERS;
+    let (order, max_depth) = preemption_order([1, 2, 3]);
+
+    assert_eq!(order, [h1, h3, h2, EXCEPTION_HANDLER]);
+    assert_eq!(max_depth, 2);
+}
+
+#[test]
+fn lower_priority_waits() {
+    let [h1, h2, h3] = HANDLERS;
+    let (order, max_depth) = preemption_order([3, 2, 1]);
+
+    assert_eq!(order, [h1, h2, EXCEPTION_HANDLER, h3]);
+    assert_eq!(max_depth, 1);
+}
+
+#[test]
+fn equal_priority_waits() {
+    let [h1, h2, h3] = HANDLERS;
+    let (order, max_depth) = preemption_order([0, 0, 0]);
+
+    assert_eq!(order, [h1, h3, h2, EXCEPTION_HANDLER]);
+    assert_eq!(max_depth, 1);
+}
+
+#[test]
+fn priority_decides_between_pending() {
+    let [h1, h2, h3] = HANDLERS;
+    let (order, max_depth) = preemption_order([1, 3, 2]);
+
+    assert_eq!(order, [h1, h2, EXCEPTION_HANDLER, h3]);
+    assert_eq!(max_depth, 2);
+}
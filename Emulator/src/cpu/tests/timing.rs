@@ -0,0 +1,249 @@
+use super::super::instruction::*;
+use super::super::interface::PrivilegeLevel;
+use super::super::{
+    BranchCondition, Cpu, InterruptState, Register, TimingModel, RESET_PROGRAM_COUNTER,
+};
+use super::{words, TestIo, TestMemory};
+use Instruction::*;
+use Register::*;
+
+fn run_with(model: TimingModel, program: &[Instruction], steps: usize, cpu: &mut Cpu) {
+    let mut bytes = assemble(program);
+    bytes.resize(bytes.len().next_multiple_of(4) + 4, 0);
+
+    let mut mem = words(&bytes);
+    let mut mem = TestMemory::new(&mut mem, false);
+
+    cpu.set_timing_model(Some(model));
+    for _ in 0..steps {
+        assert!(cpu.step(&mut mem, &mut TestIo).is_none());
+    }
+}
+
+fn run(program: &[Instruction], steps: usize, cpu: &mut Cpu) -> (u64, u64) {
+    run_with(TimingModel::default(), program, steps, cpu);
+    (cpu.cycles(), cpu.stall_cycles())
+}
+
+fn load_base() -> Instruction {
+    Ldui {
+        rd: A2,
+        imm: RESET_PROGRAM_COUNTER,
+    }
+}
+
+fn load(rd: Register) -> Instruction {
+    Load32 {
+        op: LoadOp::Word,
+        rd,
+        rb: A2,
+        offset: 0,
+    }
+}
+
+#[test]
+fn single_cycle() {
+    let program = [
+        Ldi16 { rd: A0, imm: 1 },
+        Ldi16 { rd: A1, imm: 2 },
+        Alu16 {
+            op: AluOp::Add,
+            rd: A0,
+            rs: A1,
+        },
+    ];
+
+    assert_eq!(run(&program, 3, &mut Cpu::new()), (3, 0));
+}
+
+#[test]
+fn untimed() {
+    let mut cpu = Cpu::new();
+
+    // ldi zero, 0
+    let mut mem = [0x0000_0000];
+    let mut mem = TestMemory::new(&mut mem, false);
+    cpu.step(&mut mem, &mut TestIo);
+    cpu.step(&mut mem, &mut TestIo);
+
+    assert_eq!((cpu.cycles(), cpu.stall_cycles()), (2, 0));
+    assert_eq!(cpu.retired_instructions(), 2);
+}
+
+#[test]
+fn load_use() {
+    let add = Alu16 {
+        op: AluOp::Add,
+        rd: A1,
+        rs: A0,
+    };
+
+    let program = [load_base(), load(A0), add];
+    assert_eq!(run(&program, 3, &mut Cpu::new()), (5, 2));
+
+    let program = [load_base(), load(A0), Ldi16 { rd: A3, imm: 0 }, add];
+    assert_eq!(run(&program, 4, &mut Cpu::new()), (5, 1));
+
+    let program = [
+        load_base(),
+        load(A0),
+        Ldi16 { rd: A3, imm: 0 },
+        Ldi16 { rd: A4, imm: 0 },
+        add,
+    ];
+    assert_eq!(run(&program, 5, &mut Cpu::new()), (5, 0));
+
+    let program = [load_base(), load(A0), load(Zero), add];
+    assert_eq!(run(&program, 4, &mut Cpu::new()), (5, 1));
+}
+
+#[test]
+fn divide() {
+    let program = [
+        Ldi16 { rd: A0, imm: 7 },
+        Ldi16 { rd: A1, imm: 2 },
+        MulDiv {
+            op: MulDivOp::Divu,
+            rd: A2,
+            rs1: A0,
+            rs2: A1,
+        },
+        MulDiv {
+            op: MulDivOp::Mul,
+            rd: A2,
+            rs1: A0,
+            rs2: A1,
+        },
+    ];
+
+    assert_eq!(run(&program, 4, &mut Cpu::new()), (37, 33));
+}
+
+#[test]
+fn float_divide() {
+    let fdiv = [Fpu3 {
+        op: Fpu3Op::Div,
+        rd: A2,
+        rs1: A0,
+        rs2: A1,
+    }];
+
+    for (lhs, rhs, stalls) in [
+        (1.0f32, 3.0f32, 26),
+        (1.0, 0.0, 0),
+        (0.0, 3.0, 0),
+        (f32::INFINITY, 3.0, 0),
+        (f32::NAN, 3.0, 0),
+    ] {
+        let mut cpu = Cpu::new();
+        cpu.set_reg(A0, lhs.to_bits());
+        cpu.set_reg(A1, rhs.to_bits());
+        assert_eq!(run(&fdiv, 1, &mut cpu), (1 + stalls, stalls));
+    }
+}
+
+#[test]
+fn float_sqrt() {
+    let fsqrt = [Fpu2 {
+        op: Fpu2Op::Sqrt,
+        rd: A1,
+        rs: A0,
+    }];
+
+    for (value, stalls) in [(2.0f32, 13), (0.0, 0), (-2.0, 0), (f32::INFINITY, 0)] {
+        let mut cpu = Cpu::new();
+        cpu.set_reg(A0, value.to_bits());
+        assert_eq!(run(&fsqrt, 1, &mut cpu), (1 + stalls, stalls));
+    }
+}
+
+#[test]
+fn branches() {
+    let taken = [
+        Branch16 {
+            cond: BranchCondition::True,
+            offset: 2,
+        },
+        Ldi16 { rd: A0, imm: 1 },
+        Ldi16 { rd: A1, imm: 1 },
+    ];
+    let mut cpu = Cpu::new();
+    assert_eq!(run(&taken, 2, &mut cpu), (4, 2));
+    assert_eq!(cpu.get_reg(A0), 0);
+
+    let not_taken = [
+        Cmp16 { rs1: A0, rs2: Zero },
+        Branch16 {
+            cond: BranchCondition::Ne,
+            offset: 2,
+        },
+        Ldi16 { rd: A0, imm: 1 },
+    ];
+    assert_eq!(run(&not_taken, 3, &mut Cpu::new()), (4, 1));
+}
+
+#[test]
+fn fetch_refill() {
+    let program = [
+        Branch16 {
+            cond: BranchCondition::True,
+            offset: 4,
+        },
+        Ldi16 { rd: A0, imm: 1 },
+        Ldi16 { rd: A0, imm: 1 },
+        // odd halfword
+        Ldui {
+            rd: A1,
+            imm: 0x1000_0000,
+        },
+        Ldui {
+            rd: A1,
+            imm: 0x1000_0000,
+        },
+    ];
+
+    assert_eq!(run(&program, 3, &mut Cpu::new()), (6, 3));
+}
+
+#[test]
+fn wait_states() {
+    let model = TimingModel {
+        wait_states: vec![(RESET_PROGRAM_COUNTER..=RESET_PROGRAM_COUNTER + 0xFFFF, 2)],
+        ..Default::default()
+    };
+
+    let program = [load_base(), load(A0)];
+    let mut cpu = Cpu::new();
+    run_with(model, &program, 2, &mut cpu);
+
+    // two fetches and one load
+    assert_eq!((cpu.cycles(), cpu.stall_cycles()), (8, 6));
+}
+
+#[test]
+fn counters() {
+    let program: Vec<Instruction> = (0x60..0x66)
+        .zip([A0, A1, A3, A4, A5, A6])
+        .map(|(offset, rd)| In {
+            rd,
+            rb: Zero,
+            offset,
+        })
+        .collect();
+
+    let mut cpu = Cpu::new();
+    cpu.interrupt_state = InterruptState::Listening;
+    cpu.privilege_level = PrivilegeLevel::User;
+    cpu.cycles = 0x0000_0001_FFFF_FFFF;
+    cpu.retired_instructions = 0x0000_0002_0000_0000;
+    cpu.stall_cycles = 0x0000_0003_0000_0005;
+
+    run_with(TimingModel::default(), &program, program.len(), &mut cpu);
+
+    assert_eq!(cpu.get_reg(A0), 0xFFFF_FFFF);
+    assert_eq!(cpu.get_reg(A1), 0x0000_0002);
+    assert_eq!(cpu.get_reg(A3), 0x0000_0002);
+    assert_eq!(cpu.get_reg(A4), 0x0000_0002);
+    assert_eq!(cpu.get_reg(A5), 0x0000_0005);
+    assert_eq!(cpu.get_reg(A6), 0x0000_0003);
+}
@@ -0,0 +1,264 @@
+use super::instruction::{Fpu2Op, Fpu3Op, Instruction, MulDivOp};
+use super::register::{Register, RegisterFile};
+use std::ops::RangeInclusive;
+
+/// Cycle estimates for the Softcore pipeline.
+///
+/// All latencies are extra cycles on top of the single cycle every instruction takes to issue.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimingModel {
+    /// Fetching stalls while a jump or branch is in the decode stage (`cpu.qrz`).
+    pub jump_stall: u32,
+    /// Fetching stalls another cycle while a taken jump is being executed (`cpu.qrz`).
+    pub taken_jump_stall: u32,
+    /// A 32 bit instruction at an odd halfword jump target needs a second fetch (`fetch_unit.qrz`).
+    pub fetch_refill_stall: u32,
+    /// Decoding stalls until a loaded register leaves the memory stage (`cpu.qrz`).
+    pub load_use_stall: u32,
+    /// The DSP multiplier completes within the single ALU cycle (`mult.qrz`).
+    pub mul_latency: u32,
+    /// Shift-subtract division, one cycle per bit plus setup and finalize (`alu.qrz`).
+    pub div_latency: u32,
+    /// Mantissa division, unless the result follows from the operand classes (`fpu.qrz`).
+    pub fdiv_latency: u32,
+    /// Three Newton-Raphson iterations of four cycles each (`fpu.qrz`).
+    pub fsqrt_latency: u32,
+    /// Additional cycles for every access to the given address ranges.
+    ///
+    /// The SRAM interface is double pumped, so no wait states are needed at the 40MHz core clock.
+    pub wait_states: Vec<(RangeInclusive<u32>, u32)>,
+}
+
+impl Default for TimingModel {
+    fn default() -> Self {
+        Self {
+            jump_stall: 1,
+            taken_jump_stall: 1,
+            fetch_refill_stall: 1,
+            load_use_stall: 2,
+            mul_latency: 0,
+            div_latency: 33,
+            fdiv_latency: 26,
+            fsqrt_latency: 13,
+            wait_states: Vec::new(),
+        }
+    }
+}
+
+impl TimingModel {
+    #[inline]
+    fn wait_states(&self, addr: u32) -> u32 {
+        self.wait_states
+            .iter()
+            .find(|(range, _)| range.contains(&addr))
+            .map_or(0, |&(_, cycles)| cycles)
+    }
+
+    fn latency(&self, instruction: &Instruction, regs: &RegisterFile) -> u32 {
+        match *instruction {
+            Instruction::MulDiv { op, .. } => match op {
+                MulDivOp::Mul | MulDivOp::Mulhuu | MulDivOp::Mulhss | MulDivOp::Mulhus => {
+                    self.mul_latency
+                }
+                MulDivOp::Divu | MulDivOp::Divs | MulDivOp::Remu | MulDivOp::Rems => {
+                    self.div_latency
+                }
+            },
+            Instruction::Fpu3 {
+                op: Fpu3Op::Div,
+                rs1,
+                rs2,
+                ..
+            } => {
+                let lhs = FloatClass::of(regs.get(rs1));
+                let rhs = FloatClass::of(regs.get(rs2));
+                if (lhs == FloatClass::Normal) && (rhs == FloatClass::Normal) {
+                    self.fdiv_latency
+                } else {
+                    0
+                }
+            }
+            Instruction::Fpu2 {
+                op: Fpu2Op::Sqrt | Fpu2Op::Rsqrt,
+                rs,
+                ..
+            } => {
+                let value = regs.get(rs);
+                if (FloatClass::of(value) == FloatClass::Normal) && ((value as i32) >= 0) {
+                    self.fsqrt_latency
+                } else {
+                    0
+                }
+            }
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FloatClass {
+    Zero,
+    Normal,
+    Infinity,
+    Nan,
+}
+
+impl FloatClass {
+    // the FPU flushes subnormals to zero
+    fn of(bits: u32) -> Self {
+        let exponent = (bits >> 23) & 0xFF;
+        let mantissa = bits & 0x7F_FFFF;
+        match (exponent, mantissa) {
+            (0, _) => Self::Zero,
+            (0xFF, 0) => Self::Infinity,
+            (0xFF, _) => Self::Nan,
+            _ => Self::Normal,
+        }
+    }
+}
+
+/// Registers read by the decode stage.
+fn sources(instruction: &Instruction) -> [Register; 2] {
+    use Instruction::*;
+
+    match *instruction {
+        Addi16 { rd, .. } | Shift16 { rd, .. } => [rd, Register::Zero],
+        Alu16 { rd, rs, .. } | Mov16 { rd, rs, .. } => [rd, rs],
+        Jump16 { rb, .. }
+        | Jump32 { rb, .. }
+        | Load32 { rb, .. }
+        | In { rb, .. }
+        | Ldr { rb, .. } => [rb, Register::Zero],
+        Ret => [Register::Ra, Register::Zero],
+        Load16 { .. } => [Register::Sp, Register::Zero],
+        Store16 { rs, .. } => [Register::Sp, rs],
+        AluI32 { rs1, .. } | MovI32 { rs1, .. } => [rs1, Register::Zero],
+        Store32 { rs, rb, .. } | Out { rs, rb, .. } | Stc { rs, rb, .. } => [rb, rs],
+        Cmp16 { rs1, rs2 }
+        | Alu32 { rs1, rs2, .. }
+        | Mov32 { rs1, rs2, .. }
+        | AluC { rs1, rs2, .. }
+        | MulDiv { rs1, rs2, .. }
+        | Fpu3 { rs1, rs2, .. }
+        | Fcmp { rs1, rs2, .. } => [rs1, rs2],
+        Fpu2 { rs, .. } | Cvt { rs, .. } => [rs, Register::Zero],
+        Ldi16 { .. } | Branch16 { .. } | Branch32 { .. } | Ldui { .. } | Apcui { .. } => {
+            [Register::Zero; 2]
+        }
+        Sysret | Fence | Ifence | Envcall(_) | Syscall(_) => [Register::Zero; 2],
+    }
+}
+
+/// The register written by the memory stage, if any.
+fn load_target(instruction: &Instruction) -> Option<Register> {
+    match *instruction {
+        Instruction::Load16 { rd, .. }
+        | Instruction::Load32 { rd, .. }
+        | Instruction::In { rd, .. }
+        | Instruction::Ldr { rd, .. } => Some(rd),
+        _ => None,
+    }
+}
+
+/// The memory address accessed by the instruction, computed before it executes.
+fn data_address(instruction: &Instruction, regs: &RegisterFile) -> Option<u32> {
+    match *instruction {
+        Instruction::Load16 { offset, .. } | Instruction::Store16 { offset, .. } => {
+            Some(regs.get(Register::Sp).wrapping_add(offset))
+        }
+        Instruction::Load32 { rb, offset, .. } | Instruction::Store32 { rb, offset, .. } => {
+            Some(regs.get(rb).wrapping_add(offset as u32))
+        }
+        Instruction::Ldr { rb, .. } | Instruction::Stc { rb, .. } => Some(regs.get(rb)),
+        _ => None,
+    }
+}
+
+fn is_jump(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Jump16 { .. }
+            | Instruction::Branch16 { .. }
+            | Instruction::Ret
+            | Instruction::Sysret
+            | Instruction::Syscall(_)
+            | Instruction::Jump32 { .. }
+            | Instruction::Branch32 { .. }
+    )
+}
+
+/// Pipeline state carried between instructions.
+#[derive(Debug)]
+pub(super) struct Timing {
+    model: TimingModel,
+    /// The two most recent loads together with the cycle they were decoded in.
+    loads: [(Register, u64); 2],
+    jump_target_odd: bool,
+}
+
+impl Timing {
+    pub(super) fn new(model: TimingModel) -> Self {
+        Self {
+            model,
+            loads: [(Register::Zero, 0); 2],
+            jump_target_odd: false,
+        }
+    }
+
+    pub(super) fn model(&self) -> &TimingModel {
+        &self.model
+    }
+
+    /// Returns the stall cycles of `instruction` issued in `cycle`.
+    pub(super) fn issue(
+        &mut self,
+        instruction: &Instruction,
+        addr: u32,
+        size: u32,
+        cycle: u64,
+        regs: &RegisterFile,
+    ) -> u32 {
+        let mut stalls = self.model.wait_states(addr);
+
+        if std::mem::take(&mut self.jump_target_odd) && (size == 4) {
+            stalls += self.model.fetch_refill_stall;
+        }
+
+        let decode_cycle = cycle + (stalls as u64);
+        let load_use = sources(instruction)
+            .into_iter()
+            .filter(|&reg| reg != Register::Zero)
+            .flat_map(|reg| self.loads.iter().filter(move |(load, _)| *load == reg))
+            .map(|&(_, load_cycle)| {
+                (load_cycle + (self.model.load_use_stall as u64) + 1).saturating_sub(decode_cycle)
+            })
+            .max()
+            .unwrap_or(0);
+        stalls += load_use as u32;
+
+        if let Some(target) = load_target(instruction) {
+            let load_cycle = decode_cycle + load_use;
+            self.loads = [(target, load_cycle), self.loads[0]];
+        }
+
+        if let Some(data_addr) = data_address(instruction, regs) {
+            stalls += self.model.wait_states(data_addr);
+        }
+
+        stalls + self.model.latency(instruction, regs)
+    }
+
+    /// Returns the stall cycles caused by the control flow after `instruction` executed.
+    pub(super) fn retire(&mut self, instruction: &Instruction, next_addr: u32, pc: u32) -> u32 {
+        if !is_jump(instruction) {
+            return 0;
+        }
+
+        if pc == next_addr {
+            self.model.jump_stall
+        } else {
+            self.jump_target_odd = (pc & 0x2) != 0;
+            self.model.jump_stall + self.model.taken_jump_stall
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use crate::cpu::instruction::Instruction;
+use crate::cpu::{BranchCondition, MachineCheck, Register};
+use crate::system::{Art32, EnvAction};
+use std::collections::BTreeSet;
+
+pub mod console;
+pub mod dap;
+pub mod gdb;
+
+#[cfg(test)]
+mod tests;
+
+/// Why `resume` handed control back to the debugger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The single step requested has been executed.
+    Step,
+    /// The program counter reached a breakpoint.
+    Breakpoint,
+    /// The debugger asked the guest to stop.
+    Interrupted,
+    /// Any environment call except `Reset`, which is carried out.
+    Env(EnvAction),
+    MachineCheck(MachineCheck),
+}
+
+/// How a debugging session ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionEnd {
+    /// The client detached or closed the connection, the guest can keep running.
+    Detached,
+    /// The client asked to kill the guest.
+    Killed,
+}
+
+/// A function activation found by `backtrace`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    /// The current instruction of the innermost frame, the return address of the others.
+    pub pc: u32,
+    pub fp: u32,
+}
+
+/// The deepest call chain `backtrace` follows, in case the frame records form a cycle.
+const MAX_FRAMES: usize = 64;
+
+/// Walks the call stack through the frame records, innermost frame first.
+///
+/// A function keeping a frame pointer saves `ra` at `fp - 4` and the caller's `fp` at `fp - 8`.
+/// The walk stops at a zero or unreadable `fp`, as well as at records not further up the stack
+/// than the previous one.
+pub fn backtrace(art32: &mut Art32) -> Vec<Frame> {
+    let mut frames = vec![Frame {
+        pc: art32.cpu().program_counter(),
+        fp: art32.cpu().register(Register::Fp),
+    }];
+
+    let mut fp = frames[0].fp;
+    while (fp != 0) && ((fp & 0x3) == 0) && (frames.len() < MAX_FRAMES) {
+        let mut record = [0; 8];
+        if art32.read_memory(fp.wrapping_sub(8), &mut record) != record.len() {
+            break;
+        }
+        let caller_fp = u32::from_le_bytes([record[0], record[1], record[2], record[3]]);
+        let ra = u32::from_le_bytes([record[4], record[5], record[6], record[7]]);
+        if ra == 0 {
+            break;
+        }
+
+        frames.push(Frame {
+            pc: ra,
+            fp: caller_fp,
+        });
+        if caller_fp <= fp {
+            break;
+        }
+        fp = caller_fp;
+    }
+    frames
+}
+
+/// Decodes the instruction at `addr`.
+pub fn instruction_at(art32: &mut Art32, addr: u32) -> Option<Instruction> {
+    let mut word = [0; 4];
+    // a 16 bit instruction can be the last one before unmapped memory
+    if art32.read_memory(addr, &mut word) < 2 {
+        return None;
+    }
+    Instruction::decode(u32::from_le_bytes(word)).ok()
+}
+
+/// Where a call at the program counter returns to, `None` if the next instruction is no call.
+pub fn call_return_address(art32: &mut Art32) -> Option<u32> {
+    let pc = art32.cpu().program_counter();
+    let inst = instruction_at(art32, pc)?;
+    let is_call = match inst {
+        Instruction::Branch16 { cond, .. } | Instruction::Branch32 { cond, .. } => {
+            cond == BranchCondition::Link
+        }
+        Instruction::Jump16 { link, .. } => link,
+        Instruction::Jump32 { rd, .. } => rd != Register::Zero,
+        _ => false,
+    };
+    is_call.then(|| pc.wrapping_add(inst.size()))
+}
+
+/// Where the current function returns to, by its frame record or else by `ra` for functions
+/// without a frame.
+pub fn return_address(art32: &mut Art32) -> u32 {
+    match backtrace(art32).get(1) {
+        Some(caller) => caller.pc,
+        None => art32.cpu().register(Register::Ra),
+    }
+}
+
+/// Executes instructions until one of `breakpoints` is reached, or after a single instruction
+/// if `single_step` is set. The instruction at the current program counter is always executed,
+/// so execution can resume from a breakpoint.
+///
+/// `interrupted` is polled every few thousand instructions and stops the guest when it returns
+/// true. Block translation has to be disabled for breakpoints inside of blocks to be hit.
+pub fn resume(
+    art32: &mut Art32,
+    breakpoints: &BTreeSet<u32>,
+    single_step: bool,
+    mut interrupted: impl FnMut() -> bool,
+) -> StopReason {
+    // a power of two, polling the host on every step would dominate small steps
+    const POLL_INTERVAL: u64 = 0x1000;
+
+    let mut steps = 0u64;
+    let reason = loop {
+        if steps > 0 {
+            if breakpoints.contains(&art32.cpu().program_counter()) {
+                break StopReason::Breakpoint;
+            }
+            if ((steps & (POLL_INTERVAL - 1)) == 0) && interrupted() {
+                break StopReason::Interrupted;
+            }
+        }
+
+        match art32.step() {
+            Ok(Some(EnvAction::Reset)) => art32.reset(),
+            Ok(Some(action)) => break StopReason::Env(action),
+            Ok(None) => {}
+            Err(machine_check) => break StopReason::MachineCheck(machine_check),
+        }
+        steps += 1;
+
+        if single_step {
+            break StopReason::Step;
+        }
+    };
+
+    art32.flush_serial_output();
+    reason
+}
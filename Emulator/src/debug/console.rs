@@ -0,0 +1,707 @@
+use super::{backtrace, call_return_address, return_address};
+use crate::cpu::disasm::disassemble_image;
+use crate::cpu::Register;
+use crate::system::{Art32, EnvAction};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::str::FromStr;
+use strum::IntoEnumIterator;
+
+const HELP: &str = "\
+break <addr> [if <operand> <op> <operand>]  stop at an address, optionally only if the condition holds
+watch <addr> [<len>]                        stop when the memory range changes, 4 bytes by default
+delete [<id>]                               remove a breakpoint or watchpoint, all of them without an id
+info                                        list breakpoints and watchpoints
+continue                                    run until something stops the guest
+step [<count>]                              execute instructions, 1 by default
+next                                        step over calls by `jrl` and `br.link`
+finish                                      run until the current function returns
+until <addr>                                run until the program counter reaches an address
+pause                                       stop a running guest
+x <addr> [<len>]                            dump memory, 64 bytes by default
+write <addr> <value> [<size>]               write a value of 1, 2 or 4 bytes to memory
+set <reg> <value>                           set a register or `pc`
+regs                                        show the registers
+disas [<addr>] [<count>]                    disassemble at an address, around the program counter by default
+bt                                          show the call stack
+reset                                       reset the system
+operands are numbers, registers, `pc` or memory words as `[<addr>]`, ops are == != < <= > >=";
+
+const DEFAULT_DUMP_LEN: u32 = 64;
+const MAX_DUMP_LEN: u32 = 0x10000;
+const DUMP_LINE_LEN: usize = 16;
+const DEFAULT_DISASSEMBLY_COUNT: u32 = 8;
+const MAX_DISASSEMBLY_COUNT: u32 = 0x1000;
+/// How far `disas` looks for instructions in front of the program counter, in bytes.
+const DISASSEMBLY_CONTEXT: u32 = 8;
+
+fn parse_number(arg: Option<&str>, what: &str) -> Result<u32, String> {
+    let arg = arg.ok_or_else(|| format!("missing {what}"))?;
+    crate::parse_u32(arg).map_err(|_| format!("invalid {what} `{arg}`"))
+}
+
+fn parse_register(name: &str) -> Option<Register> {
+    Register::iter().find(|reg| reg.to_string() == name)
+}
+
+/// A value a breakpoint condition compares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Register(Register),
+    Pc,
+    /// The word in memory at an address.
+    Memory(u32),
+    Value(u32),
+}
+
+impl Operand {
+    /// The current value, `None` if it is in memory that cannot be read.
+    fn value(self, art32: &mut Art32) -> Option<u32> {
+        match self {
+            Self::Register(reg) => Some(art32.cpu().register(reg)),
+            Self::Pc => Some(art32.cpu().program_counter()),
+            Self::Memory(addr) => {
+                let mut word = [0; 4];
+                (art32.read_memory(addr, &mut word) == word.len()).then(|| u32::from_le_bytes(word))
+            }
+            Self::Value(value) => Some(value),
+        }
+    }
+}
+
+impl FromStr for Operand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "pc" {
+            Ok(Self::Pc)
+        } else if let Some(reg) = parse_register(s) {
+            Ok(Self::Register(reg))
+        } else if let Some(addr) = s.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            parse_number(Some(addr), "address").map(Self::Memory)
+        } else {
+            parse_number(Some(s), "operand").map(Self::Value)
+        }
+    }
+}
+
+impl std::fmt::Display for Operand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Register(reg) => write!(f, "{reg}"),
+            Self::Pc => f.write_str("pc"),
+            Self::Memory(addr) => write!(f, "[0x{addr:0>8x}]"),
+            Self::Value(value) => write!(f, "0x{value:x}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    const SYMBOLS: [(Self, &'static str); 6] = [
+        (Self::Equal, "=="),
+        (Self::NotEqual, "!="),
+        (Self::Less, "<"),
+        (Self::LessOrEqual, "<="),
+        (Self::Greater, ">"),
+        (Self::GreaterOrEqual, ">="),
+    ];
+
+    fn symbol(self) -> &'static str {
+        Self::SYMBOLS.iter().find(|(op, _)| *op == self).unwrap().1
+    }
+}
+
+impl FromStr for Comparison {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::SYMBOLS
+            .iter()
+            .find(|(_, symbol)| *symbol == s)
+            .map(|(op, _)| *op)
+            .ok_or_else(|| format!("invalid comparison `{s}`"))
+    }
+}
+
+/// Condition of a breakpoint, values compare unsigned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub lhs: Operand,
+    pub op: Comparison,
+    pub rhs: Operand,
+}
+
+impl Condition {
+    /// Whether the condition holds, it does not if an operand cannot be read.
+    fn holds(&self, art32: &mut Art32) -> bool {
+        let (Some(lhs), Some(rhs)) = (self.lhs.value(art32), self.rhs.value(art32)) else {
+            return false;
+        };
+        match self.op {
+            Comparison::Equal => lhs == rhs,
+            Comparison::NotEqual => lhs != rhs,
+            Comparison::Less => lhs < rhs,
+            Comparison::LessOrEqual => lhs <= rhs,
+            Comparison::Greater => lhs > rhs,
+            Comparison::GreaterOrEqual => lhs >= rhs,
+        }
+    }
+}
+
+impl std::fmt::Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.lhs, self.op.symbol(), self.rhs)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Break {
+        addr: u32,
+        condition: Option<Condition>,
+    },
+    Watch {
+        addr: u32,
+        len: u32,
+    },
+    /// Removes one breakpoint or watchpoint by its id, or all of them.
+    Delete(Option<usize>),
+    Info,
+    Continue,
+    Step(u64),
+    Next,
+    Finish,
+    Until(u32),
+    Pause,
+    Examine {
+        addr: u32,
+        len: u32,
+    },
+    Write {
+        addr: u32,
+        value: u32,
+        size: u32,
+    },
+    /// Sets a register or the program counter.
+    Set {
+        target: Operand,
+        value: u32,
+    },
+    Registers,
+    /// Disassembles at an address, or around the program counter.
+    Disassemble {
+        addr: Option<u32>,
+        count: u32,
+    },
+    Backtrace,
+    Reset,
+    Help,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut args = s.split_whitespace();
+        let name = args.next().ok_or("missing command")?;
+
+        let command = match name {
+            "break" | "b" => {
+                let addr = parse_number(args.next(), "address")?;
+                let condition = match args.next() {
+                    Some("if") => {
+                        let mut next = || args.next().ok_or("incomplete condition");
+                        let lhs = next()?.parse()?;
+                        let op = next()?.parse()?;
+                        let rhs = next()?.parse()?;
+                        Some(Condition { lhs, op, rhs })
+                    }
+                    Some(arg) => return Err(format!("expected `if`, found `{arg}`")),
+                    None => None,
+                };
+                Self::Break { addr, condition }
+            }
+            "watch" | "w" => {
+                let addr = parse_number(args.next(), "address")?;
+                let len = match args.next() {
+                    Some(len) => parse_number(Some(len), "length")?,
+                    None => 4,
+                };
+                if len == 0 {
+                    return Err("the length must not be zero".to_owned());
+                }
+                Self::Watch { addr, len }
+            }
+            "delete" | "d" => match args.next() {
+                Some(id) => Self::Delete(Some(parse_number(Some(id), "id")? as usize)),
+                None => Self::Delete(None),
+            },
+            "info" | "i" => Self::Info,
+            "continue" | "c" => Self::Continue,
+            "step" | "s" => {
+                let count = match args.next() {
+                    Some(count) => parse_number(Some(count), "count")?,
+                    None => 1,
+                };
+                if count == 0 {
+                    return Err("the count must not be zero".to_owned());
+                }
+                Self::Step(count as u64)
+            }
+            "next" | "n" => Self::Next,
+            "finish" | "fin" => Self::Finish,
+            "until" | "u" => Self::Until(parse_number(args.next(), "address")?),
+            "pause" => Self::Pause,
+            "x" => {
+                let addr = parse_number(args.next(), "address")?;
+                let len = match args.next() {
+                    Some(len) => parse_number(Some(len), "length")?,
+                    None => DEFAULT_DUMP_LEN,
+                };
+                Self::Examine {
+                    addr,
+                    len: len.min(MAX_DUMP_LEN),
+                }
+            }
+            "write" => {
+                let addr = parse_number(args.next(), "address")?;
+                let value = parse_number(args.next(), "value")?;
+                let size = match args.next() {
+                    Some(size) => parse_number(Some(size), "size")?,
+                    None => 4,
+                };
+                if !matches!(size, 1 | 2 | 4) {
+                    return Err(format!("invalid size `{size}`, expected 1, 2 or 4"));
+                }
+                if (size < 4) && ((value >> (size * 8)) != 0) {
+                    return Err(format!("0x{value:x} does not fit into {size} bytes"));
+                }
+                Self::Write { addr, value, size }
+            }
+            "set" => {
+                let target = match args.next().ok_or("missing register")?.parse()? {
+                    target @ (Operand::Register(_) | Operand::Pc) => target,
+                    target => return Err(format!("`{target}` is no register")),
+                };
+                let value = parse_number(args.next(), "value")?;
+                Self::Set { target, value }
+            }
+            "regs" | "registers" => Self::Registers,
+            "disas" | "disassemble" => {
+                let addr = args
+                    .next()
+                    .map(|addr| parse_number(Some(addr), "address"))
+                    .transpose()?;
+                let count = match args.next() {
+                    Some(count) => parse_number(Some(count), "count")?,
+                    None => DEFAULT_DISASSEMBLY_COUNT,
+                };
+                Self::Disassemble {
+                    addr,
+                    count: count.min(MAX_DISASSEMBLY_COUNT),
+                }
+            }
+            "bt" | "backtrace" => Self::Backtrace,
+            "reset" => Self::Reset,
+            "help" | "h" | "?" => Self::Help,
+            _ => return Err(format!("unknown command `{name}`, try `help`")),
+        };
+
+        match args.next() {
+            Some(arg) => Err(format!("unexpected argument `{arg}`")),
+            None => Ok(command),
+        }
+    }
+}
+
+struct Breakpoint {
+    addr: u32,
+    condition: Option<Condition>,
+}
+
+struct Watchpoint {
+    addr: u32,
+    /// The contents of the range when last checked, only readable bytes are watched.
+    data: Vec<u8>,
+}
+
+impl Watchpoint {
+    fn read(art32: &mut Art32, addr: u32, len: u32) -> Vec<u8> {
+        let mut data = vec![0; len as usize];
+        let read = art32.read_memory(addr, &mut data);
+        data.truncate(read);
+        data
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Execution {
+    Stopped,
+    Running {
+        /// Instructions left to execute for `step`.
+        steps: Option<u64>,
+        /// Where `next`, `finish` and `until` stop.
+        target: Option<u32>,
+    },
+}
+
+/// Debugger driven by text commands, see `help` for the list.
+///
+/// Commands only change the state of the debugger, the guest executes in `run`, so a frontend
+/// can execute it in slices and stay responsive.
+pub struct Console {
+    breakpoints: BTreeMap<usize, Breakpoint>,
+    watchpoints: BTreeMap<usize, Watchpoint>,
+    next_id: usize,
+    execution: Execution,
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Self {
+            breakpoints: BTreeMap::new(),
+            watchpoints: BTreeMap::new(),
+            next_id: 1,
+            execution: Execution::Stopped,
+        }
+    }
+}
+
+impl Console {
+    pub fn is_running(&self) -> bool {
+        self.execution != Execution::Stopped
+    }
+
+    /// Starts running like `continue`, unless the guest already runs.
+    pub fn resume(&mut self) {
+        if !self.is_running() {
+            self.execution = Execution::Running {
+                steps: None,
+                target: None,
+            };
+        }
+    }
+
+    /// Stops a running guest and shows where it stopped.
+    pub fn pause(&mut self, art32: &mut Art32, out: &mut impl Write) -> io::Result<()> {
+        if self.is_running() {
+            self.stop(art32, Some("paused"), out)?;
+        }
+        Ok(())
+    }
+
+    fn stop(
+        &mut self,
+        art32: &mut Art32,
+        reason: Option<&str>,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        self.execution = Execution::Stopped;
+        art32.flush_serial_output();
+        if let Some(reason) = reason {
+            writeln!(out, "{reason}")?;
+        }
+        let pc = art32.cpu().program_counter();
+        Self::disassemble(art32, pc, 1, out)
+    }
+
+    /// Executes a command line, errors are reported to `out`.
+    pub fn execute(
+        &mut self,
+        art32: &mut Art32,
+        line: &str,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+        match line.parse() {
+            Ok(command) => self.execute_command(art32, command, out),
+            Err(err) => writeln!(out, "error: {err}"),
+        }
+    }
+
+    pub fn execute_command(
+        &mut self,
+        art32: &mut Art32,
+        command: Command,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        let running = |steps, target| Execution::Running { steps, target };
+
+        match command {
+            Command::Break { addr, condition } => {
+                let id = self.next_id;
+                self.next_id += 1;
+                self.breakpoints.insert(id, Breakpoint { addr, condition });
+                writeln!(out, "breakpoint {id} at 0x{addr:0>8x}")?;
+            }
+            Command::Watch { addr, len } => {
+                let data = Watchpoint::read(art32, addr, len);
+                if data.is_empty() {
+                    return writeln!(out, "error: cannot read memory at 0x{addr:0>8x}");
+                }
+
+                let id = self.next_id;
+                self.next_id += 1;
+                writeln!(
+                    out,
+                    "watchpoint {id} at 0x{addr:0>8x}, {} bytes",
+                    data.len()
+                )?;
+                self.watchpoints.insert(id, Watchpoint { addr, data });
+            }
+            Command::Delete(Some(id)) => {
+                if self.breakpoints.remove(&id).is_none() && self.watchpoints.remove(&id).is_none()
+                {
+                    writeln!(out, "error: no breakpoint or watchpoint {id}")?;
+                }
+            }
+            Command::Delete(None) => {
+                self.breakpoints.clear();
+                self.watchpoints.clear();
+            }
+            Command::Info => {
+                for (id, breakpoint) in &self.breakpoints {
+                    write!(out, "{id:<4}breakpoint  0x{:0>8x}", breakpoint.addr)?;
+                    match &breakpoint.condition {
+                        Some(condition) => writeln!(out, " if {condition}")?,
+                        None => writeln!(out)?,
+                    }
+                }
+                for (id, watchpoint) in &self.watchpoints {
+                    writeln!(
+                        out,
+                        "{id:<4}watchpoint  0x{:0>8x}, {} bytes",
+                        watchpoint.addr,
+                        watchpoint.data.len()
+                    )?;
+                }
+            }
+            Command::Continue => self.execution = running(None, None),
+            Command::Step(count) => self.execution = running(Some(count), None),
+            Command::Next => {
+                self.execution = match call_return_address(art32) {
+                    Some(addr) => running(None, Some(addr)),
+                    None => running(Some(1), None),
+                }
+            }
+            Command::Finish => self.execution = running(None, Some(return_address(art32))),
+            Command::Until(addr) => self.execution = running(None, Some(addr)),
+            Command::Pause => {
+                if self.is_running() {
+                    self.pause(art32, out)?;
+                } else {
+                    writeln!(out, "error: the guest is not running")?;
+                }
+            }
+            Command::Examine { addr, len } => {
+                let mut bytes = vec![0; len as usize];
+                let read = art32.read_memory(addr, &mut bytes);
+                for (i, line) in bytes[..read].chunks(DUMP_LINE_LEN).enumerate() {
+                    let line_addr = addr.wrapping_add((i * DUMP_LINE_LEN) as u32);
+                    write!(out, "{line_addr:0>8x}: ")?;
+                    for i in 0..DUMP_LINE_LEN {
+                        match line.get(i) {
+                            Some(byte) => write!(out, " {byte:0>2x}")?,
+                            None => write!(out, "   ")?,
+                        }
+                    }
+                    let text: String = line
+                        .iter()
+                        .map(|&byte| match byte {
+                            0x20..=0x7E => byte as char,
+                            _ => '.',
+                        })
+                        .collect();
+                    writeln!(out, "  |{text}|")?;
+                }
+                if read < bytes.len() {
+                    let unreadable = addr.wrapping_add(read as u32);
+                    writeln!(out, "error: cannot read memory at 0x{unreadable:0>8x}")?;
+                }
+            }
+            Command::Write { addr, value, size } => {
+                let bytes = &value.to_le_bytes()[..(size as usize)];
+                if art32.write_memory(addr, bytes) < bytes.len() {
+                    writeln!(out, "error: cannot write memory at 0x{addr:0>8x}")?;
+                }
+                // the debugger's own writes do not trigger watchpoints
+                for watchpoint in self.watchpoints.values_mut() {
+                    let len = watchpoint.data.len() as u32;
+                    watchpoint.data = Watchpoint::read(art32, watchpoint.addr, len);
+                }
+            }
+            Command::Set { target, value } => match target {
+                Operand::Register(reg) => art32.cpu_mut().set_register(reg, value),
+                Operand::Pc => art32.cpu_mut().set_program_counter(value),
+                Operand::Memory(_) | Operand::Value(_) => unreachable!("rejected by the parser"),
+            },
+            Command::Registers => {
+                let cpu = art32.cpu();
+                writeln!(
+                    out,
+                    "pc    0x{:0>8x}  flags {}",
+                    cpu.program_counter(),
+                    cpu.flags()
+                )?;
+                for (i, reg) in Register::iter().enumerate() {
+                    let separator = if (i % 4) == 3 { "\n" } else { "  " };
+                    write!(out, "{reg:<5} 0x{:0>8x}{separator}", cpu.register(reg))?;
+                }
+            }
+            Command::Disassemble {
+                addr: Some(addr),
+                count,
+            } => Self::disassemble(art32, addr, count, out)?,
+            Command::Disassemble { addr: None, count } => {
+                // instructions differ in length, so the ones in front of the program counter
+                // are found by decoding from a bit further back until one ends at it
+                let pc = art32.cpu().program_counter();
+                let mut bytes = vec![0; DISASSEMBLY_CONTEXT as usize];
+                let start = (2..=DISASSEMBLY_CONTEXT)
+                    .rev()
+                    .step_by(2)
+                    .map(|back| pc.wrapping_sub(back))
+                    .find(|&start| {
+                        let len = pc.wrapping_sub(start) as usize;
+                        (art32.read_memory(start, &mut bytes[..len]) == len)
+                            && disassemble_image(&bytes[..len], start)
+                                .any(|line| line.address.wrapping_add(line.len) == pc)
+                    })
+                    .unwrap_or(pc);
+                Self::disassemble(art32, start, count, out)?;
+            }
+            Command::Backtrace => {
+                for (i, frame) in backtrace(art32).iter().enumerate() {
+                    writeln!(out, "#{i:<3}0x{:0>8x}  fp 0x{:0>8x}", frame.pc, frame.fp)?;
+                }
+            }
+            Command::Reset => {
+                art32.reset();
+                let pc = art32.cpu().program_counter();
+                Self::disassemble(art32, pc, 1, out)?;
+            }
+            Command::Help => writeln!(out, "{HELP}")?,
+        }
+        Ok(())
+    }
+
+    /// Disassembles `count` instructions from `addr`, marking the program counter.
+    fn disassemble(
+        art32: &mut Art32,
+        addr: u32,
+        count: u32,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        let pc = art32.cpu().program_counter();
+        let mut bytes = vec![0; (count as usize) * 4];
+        let read = art32.read_memory(addr, &mut bytes);
+        for line in disassemble_image(&bytes[..read], addr).take(count as usize) {
+            let marker = if line.address == pc { "=>" } else { "  " };
+            writeln!(out, "{marker} {line}")?;
+        }
+        if read == 0 {
+            writeln!(out, "error: cannot read memory at 0x{addr:0>8x}")?;
+        }
+        Ok(())
+    }
+
+    /// Why the guest has to stop after an instruction, `Some(None)` for a silent stop.
+    fn check_stop(&mut self, art32: &mut Art32) -> Option<Option<String>> {
+        let mut changed = Vec::new();
+        for (id, watchpoint) in &mut self.watchpoints {
+            let data = Watchpoint::read(art32, watchpoint.addr, watchpoint.data.len() as u32);
+            if data != watchpoint.data {
+                let hex = |data: &[u8]| {
+                    data.iter()
+                        .map(|byte| format!("{byte:0>2x}"))
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                };
+                changed.push(format!(
+                    "watchpoint {id}: 0x{:0>8x} changed\n  old: {}\n  new: {}",
+                    watchpoint.addr,
+                    hex(&watchpoint.data),
+                    hex(&data)
+                ));
+                watchpoint.data = data;
+            }
+        }
+        if !changed.is_empty() {
+            return Some(Some(changed.join("\n")));
+        }
+
+        let pc = art32.cpu().program_counter();
+        for (id, breakpoint) in &self.breakpoints {
+            if (breakpoint.addr == pc)
+                && breakpoint
+                    .condition
+                    .is_none_or(|condition| condition.holds(art32))
+            {
+                return Some(Some(format!("breakpoint {id}")));
+            }
+        }
+
+        let Execution::Running { steps, target } = self.execution else {
+            return None;
+        };
+        if (steps == Some(1)) || (target == Some(pc)) {
+            return Some(None);
+        }
+        if let Some(steps) = steps {
+            self.execution = Execution::Running {
+                steps: Some(steps - 1),
+                target,
+            };
+        }
+        None
+    }
+
+    /// Executes up to `max_steps` instructions while the guest runs, returns whether it still
+    /// runs afterwards. Block translation gets disabled so breakpoints are hit.
+    pub fn run(
+        &mut self,
+        art32: &mut Art32,
+        max_steps: u64,
+        out: &mut impl Write,
+    ) -> io::Result<bool> {
+        art32.set_block_translation(None);
+
+        for _ in 0..max_steps {
+            if !self.is_running() {
+                break;
+            }
+
+            let reason = match art32.step() {
+                Ok(Some(EnvAction::Reset)) => {
+                    writeln!(out, "system reset requested")?;
+                    art32.reset();
+                    None
+                }
+                Ok(Some(EnvAction::Break)) => Some(Some("break".to_owned())),
+                Ok(Some(EnvAction::Error)) => Some(Some("system caused an error".to_owned())),
+                Ok(Some(action @ (EnvAction::Pass | EnvAction::Fail))) => {
+                    Some(Some(format!("guest test finished: {action:?}")))
+                }
+                Ok(None) => None,
+                Err(machine_check) => Some(Some(format!("machine check: {machine_check}"))),
+            };
+
+            if let Some(reason) = reason.or_else(|| self.check_stop(art32)) {
+                self.stop(art32, reason.as_deref(), out)?;
+            }
+        }
+        Ok(self.is_running())
+    }
+}
@@ -0,0 +1,689 @@
+use super::{backtrace, call_return_address, resume, return_address, SessionEnd, StopReason};
+use crate::cpu::disasm::disassemble;
+use crate::cpu::instruction::instruction_len;
+use crate::cpu::Register;
+use crate::system::{Art32, EnvAction};
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use strum::IntoEnumIterator;
+
+const THREAD_ID: u64 = 1;
+const REGISTERS_REFERENCE: u64 = 1;
+const ALT_REGISTERS_REFERENCE: u64 = 2;
+/// The largest memory read, clients ask for at most a few pages at once.
+const MAX_READ_SIZE: usize = 0x10000;
+
+/// Where the adapter talks to its client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DapTransport {
+    Stdio,
+    /// The first client connecting to a local TCP listener.
+    Tcp(SocketAddr),
+}
+
+impl FromStr for DapTransport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stdio" => Ok(Self::Stdio),
+            _ => s
+                .parse()
+                .map(Self::Tcp)
+                .map_err(|_| format!("expected `stdio` or a socket address, found `{s}`")),
+        }
+    }
+}
+
+/// Addresses of assembly source lines, read from the `addrspan` output of customasm
+/// (`customasm kernel.asm -f addrspan -o kernel.map`).
+///
+/// Every entry is `<output offset>:<address> | <path>:<line>:<column>:<end line>:<end column>`,
+/// with hexadecimal offsets and addresses and lines counted from zero. Relative paths are
+/// relative to the file, `;` starts a comment.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LineMap {
+    lines: HashMap<(PathBuf, u32), u32>,
+    locations: HashMap<u32, (PathBuf, u32)>,
+}
+
+impl LineMap {
+    pub fn parse(text: &str, base_dir: &Path) -> Result<Self, String> {
+        let mut map = Self::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let invalid = || {
+                format!(
+                    "line {}: expected `<offset>:<address> | <path>:<line>:<column>:<end line>:<end column>`",
+                    i + 1
+                )
+            };
+            let (position, span) = line.split_once('|').ok_or_else(invalid)?;
+            let (_, addr) = position.trim().split_once(':').ok_or_else(invalid)?;
+            let mut span = span.trim().rsplitn(5, ':');
+            let (Some(_), Some(_), Some(_), Some(line), Some(path)) = (
+                span.next(),
+                span.next(),
+                span.next(),
+                span.next(),
+                span.next(),
+            ) else {
+                return Err(invalid());
+            };
+            let addr = u32::from_str_radix(addr.trim(), 16).map_err(|_| invalid())?;
+            let line = line.parse::<u32>().map_err(|_| invalid())? + 1;
+            let path = normalize(&base_dir.join(path));
+
+            let first = map.lines.entry((path.clone(), line)).or_insert(addr);
+            *first = (*first).min(addr);
+            map.locations.entry(addr).or_insert((path, line));
+        }
+        Ok(map)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("cannot read `{}`: {err}", path.display()))?;
+        Self::parse(&text, path.parent().unwrap_or(Path::new("")))
+    }
+
+    /// The first address of a source line, counted from one.
+    pub fn address(&self, path: &Path, line: u32) -> Option<u32> {
+        self.lines.get(&(normalize(path), line)).copied()
+    }
+
+    /// The source line an address belongs to.
+    pub fn location(&self, addr: u32) -> Option<(&Path, u32)> {
+        let (path, line) = self.locations.get(&addr)?;
+        Some((path, *line))
+    }
+}
+
+/// Makes paths of the same file compare equal, as far as the file system allows.
+fn normalize(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| {
+            bits | ((byte as u32) << (16 - (i * 8)))
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[((bits >> (18 - (i * 6))) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+fn format_u32(value: u32) -> String {
+    format!("0x{value:0>8X}")
+}
+
+/// Reads the messages of the client, framed by `Content-Length` headers.
+fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut len = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                len = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let len = len.ok_or_else(|| io::Error::other("message without a content length"))?;
+    let mut content = vec![0; len];
+    reader.read_exact(&mut content)?;
+    serde_json::from_slice(&content)
+        .map(Some)
+        .map_err(io::Error::other)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Execution {
+    Stopped,
+    Running,
+    /// Running to a temporary breakpoint, to step over a call or out of a function.
+    RunningTo(u32),
+}
+
+/// A Debug Adapter Protocol server, debugging an `Art32` for a single client.
+///
+/// The CPU is the only thread. Stack frames are found with `backtrace`, every frame shows the
+/// registers and the banked `alt_state` registers of the CPU. Breakpoints are set on addresses
+/// or, with a `LineMap` given as `lineMap` in the launch arguments, on source lines.
+pub struct DapServer<W: Write> {
+    messages: Receiver<io::Result<Value>>,
+    pending: VecDeque<Value>,
+    disconnected: bool,
+    output: W,
+    seq: u64,
+    execution: Execution,
+    stop_on_entry: bool,
+    line_map: LineMap,
+    source_breakpoints: HashMap<PathBuf, Vec<u32>>,
+    instruction_breakpoints: Vec<u32>,
+    breakpoints: BTreeSet<u32>,
+}
+
+impl DapServer<TcpStream> {
+    /// Listens on `addr` and waits for a single client.
+    pub fn accept(addr: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let (stream, _) = listener.accept()?;
+        Ok(Self::new(stream.try_clone()?, stream))
+    }
+}
+
+impl<W: Write> DapServer<W> {
+    /// Serves the client reading from `input` on a thread of its own, so requests like `pause`
+    /// arrive while the guest runs.
+    pub fn new(input: impl Read + Send + 'static, output: W) -> Self {
+        let (sender, messages) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut reader = BufReader::new(input);
+            loop {
+                let message = read_message(&mut reader).transpose();
+                let end = !matches!(message, Some(Ok(_)));
+                if let Some(message) = message {
+                    if sender.send(message).is_err() {
+                        break;
+                    }
+                }
+                if end {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            messages,
+            pending: VecDeque::new(),
+            disconnected: false,
+            output,
+            seq: 0,
+            execution: Execution::Stopped,
+            stop_on_entry: false,
+            line_map: LineMap::default(),
+            source_breakpoints: HashMap::new(),
+            instruction_breakpoints: Vec::new(),
+            breakpoints: BTreeSet::new(),
+        }
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        let content = message.to_string();
+        write!(
+            self.output,
+            "Content-Length: {}\r\n\r\n{content}",
+            content.len()
+        )?;
+        self.output.flush()
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(msg) => response["message"] = json!(msg),
+        }
+        self.send(response)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    /// Forwards the serial output of the guest, if it goes to a `SerialOutput::Buffer`.
+    fn flush_serial(&mut self, art32: &mut Art32) -> io::Result<()> {
+        let serial = art32.take_serial_output();
+        if serial.is_empty() {
+            return Ok(());
+        }
+        let output = String::from_utf8_lossy(&serial).into_owned();
+        self.event("output", json!({ "category": "stdout", "output": output }))
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<String>) -> io::Result<()> {
+        self.execution = Execution::Stopped;
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        if let Some(text) = text {
+            body["text"] = json!(text);
+        }
+        self.event("stopped", body)
+    }
+
+    /// Moves the client's messages to the queue without blocking, returns whether there are any
+    /// to handle.
+    fn poll_messages(&mut self) -> bool {
+        loop {
+            match self.messages.try_recv() {
+                Ok(Ok(message)) => self.pending.push_back(message),
+                Ok(Err(_)) | Err(TryRecvError::Disconnected) => {
+                    self.disconnected = true;
+                    return true;
+                }
+                Err(TryRecvError::Empty) => return !self.pending.is_empty(),
+            }
+        }
+    }
+
+    fn report_stop(&mut self, art32: &mut Art32, reason: StopReason) -> io::Result<()> {
+        self.flush_serial(art32)?;
+        match reason {
+            StopReason::Interrupted => Ok(()),
+            StopReason::Step => self.stopped("step", None),
+            StopReason::Breakpoint => {
+                let pc = art32.cpu().program_counter();
+                if (self.execution == Execution::RunningTo(pc)) && !self.breakpoints.contains(&pc) {
+                    self.stopped("step", None)
+                } else {
+                    self.stopped("breakpoint", None)
+                }
+            }
+            StopReason::Env(EnvAction::Break) => self.stopped("breakpoint", Some("brk".to_owned())),
+            StopReason::Env(action @ (EnvAction::Pass | EnvAction::Fail)) => {
+                self.execution = Execution::Stopped;
+                let exit_code = if action == EnvAction::Pass { 0 } else { 1 };
+                self.event("exited", json!({ "exitCode": exit_code }))?;
+                self.event("terminated", json!({}))
+            }
+            StopReason::Env(EnvAction::Error) => {
+                self.stopped("exception", Some("system caused an error".to_owned()))
+            }
+            StopReason::Env(EnvAction::Reset) => unreachable!("resets are carried out by `resume`"),
+            StopReason::MachineCheck(machine_check) => {
+                self.stopped("exception", Some(format!("machine check: {machine_check}")))
+            }
+        }
+    }
+
+    /// Runs the guest until it stops or a message of the client arrives. Serial output is
+    /// forwarded whenever `resume` polls, not only once the guest stops.
+    fn run(&mut self, art32: &mut Art32) -> io::Result<()> {
+        let mut breakpoints = self.breakpoints.clone();
+        if let Execution::RunningTo(addr) = self.execution {
+            breakpoints.insert(addr);
+        }
+        loop {
+            // a breakpoint at the program counter is reported before an interruption, so
+            // resuming again does not skip it
+            let reason = resume(art32, &breakpoints, false, || true);
+            if (reason != StopReason::Interrupted) || self.poll_messages() {
+                return self.report_stop(art32, reason);
+            }
+            self.flush_serial(art32)?;
+        }
+    }
+
+    fn step(&mut self, art32: &mut Art32) -> io::Result<()> {
+        let reason = resume(art32, &BTreeSet::new(), true, || false);
+        self.report_stop(art32, reason)
+    }
+
+    fn update_breakpoints(&mut self) {
+        self.breakpoints = self
+            .source_breakpoints
+            .values()
+            .flatten()
+            .chain(&self.instruction_breakpoints)
+            .copied()
+            .collect();
+    }
+
+    /// Serves the client until it disconnects. Block translation gets disabled so breakpoints
+    /// are hit.
+    pub fn serve(&mut self, art32: &mut Art32) -> io::Result<SessionEnd> {
+        art32.set_block_translation(None);
+
+        loop {
+            if (self.execution != Execution::Stopped)
+                && self.pending.is_empty()
+                && !self.disconnected
+            {
+                self.run(art32)?;
+                continue;
+            }
+
+            let message = match self.pending.pop_front() {
+                Some(message) => message,
+                None if self.disconnected => return Ok(SessionEnd::Detached),
+                None => match self.messages.recv() {
+                    Ok(Ok(message)) => message,
+                    Ok(Err(err)) => return Err(err),
+                    Err(_) => return Ok(SessionEnd::Detached),
+                },
+            };
+
+            if message["type"] != "request" {
+                continue;
+            }
+            if let Some(end) = self.handle(art32, &message)? {
+                return Ok(end);
+            }
+        }
+    }
+
+    fn handle(&mut self, art32: &mut Art32, request: &Value) -> io::Result<Option<SessionEnd>> {
+        let args = &request["arguments"];
+        let command = request["command"].as_str().unwrap_or_default();
+        match command {
+            "initialize" => {
+                let capabilities = json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsReadMemoryRequest": true,
+                    "supportsInstructionBreakpoints": true,
+                    "supportsDisassembleRequest": true,
+                    "supportsSteppingGranularity": true,
+                    "supportsTerminateRequest": true,
+                });
+                self.respond(request, Ok(capabilities))?;
+                self.event("initialized", json!({}))?;
+            }
+            "launch" | "attach" => {
+                self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+                let result = match args["lineMap"].as_str() {
+                    Some(path) => LineMap::load(Path::new(path)).map(|line_map| {
+                        self.line_map = line_map;
+                        Value::Null
+                    }),
+                    None => Ok(Value::Null),
+                };
+                self.respond(request, result)?;
+            }
+            "configurationDone" => {
+                self.respond(request, Ok(Value::Null))?;
+                if self.stop_on_entry {
+                    self.stopped("entry", None)?;
+                } else {
+                    self.execution = Execution::Running;
+                }
+            }
+            "setBreakpoints" => {
+                let path = PathBuf::from(args["source"]["path"].as_str().unwrap_or_default());
+                let lines = args["breakpoints"].as_array().cloned().unwrap_or_default();
+
+                let mut addresses = Vec::new();
+                let breakpoints: Vec<_> = lines
+                    .iter()
+                    .map(|breakpoint| {
+                        let line = breakpoint["line"].as_u64().unwrap_or_default() as u32;
+                        match self.line_map.address(&path, line) {
+                            Some(addr) => {
+                                addresses.push(addr);
+                                json!({
+                                    "verified": true,
+                                    "line": line,
+                                    "instructionReference": format_u32(addr),
+                                })
+                            }
+                            None => json!({
+                                "verified": false,
+                                "line": line,
+                                "message": "no code at this line",
+                            }),
+                        }
+                    })
+                    .collect();
+
+                self.source_breakpoints.insert(normalize(&path), addresses);
+                self.update_breakpoints();
+                self.respond(request, Ok(json!({ "breakpoints": breakpoints })))?;
+            }
+            "setInstructionBreakpoints" => {
+                let requested = args["breakpoints"].as_array().cloned().unwrap_or_default();
+
+                self.instruction_breakpoints.clear();
+                let breakpoints: Vec<_> = requested
+                    .iter()
+                    .map(|breakpoint| {
+                        let addr = breakpoint["instructionReference"]
+                            .as_str()
+                            .and_then(|reference| crate::parse_u32(reference).ok());
+                        match addr {
+                            Some(addr) => {
+                                let offset = breakpoint["offset"].as_i64().unwrap_or_default();
+                                let addr = addr.wrapping_add(offset as u32);
+                                self.instruction_breakpoints.push(addr);
+                                json!({
+                                    "verified": true,
+                                    "instructionReference": format_u32(addr),
+                                })
+                            }
+                            None => json!({
+                                "verified": false,
+                                "message": "invalid instruction reference",
+                            }),
+                        }
+                    })
+                    .collect();
+
+                self.update_breakpoints();
+                self.respond(request, Ok(json!({ "breakpoints": breakpoints })))?;
+            }
+            "setExceptionBreakpoints" => {
+                self.respond(request, Ok(json!({ "breakpoints": [] })))?;
+            }
+            "threads" => {
+                let threads = json!({ "threads": [{ "id": THREAD_ID, "name": "cpu" }] });
+                self.respond(request, Ok(threads))?;
+            }
+            "stackTrace" => {
+                let frames: Vec<_> = backtrace(art32)
+                    .iter()
+                    .enumerate()
+                    .map(|(id, frame)| {
+                        let mut stack_frame = json!({
+                            "id": id,
+                            "name": format_u32(frame.pc),
+                            "line": 0,
+                            "column": 0,
+                            "instructionPointerReference": format_u32(frame.pc),
+                        });
+                        if let Some((path, line)) = self.line_map.location(frame.pc) {
+                            let name = path.file_name().map(|name| name.to_string_lossy());
+                            stack_frame["source"] = json!({ "name": name, "path": path });
+                            stack_frame["line"] = json!(line);
+                            stack_frame["column"] = json!(1);
+                        }
+                        stack_frame
+                    })
+                    .collect();
+
+                let body = json!({ "stackFrames": frames, "totalFrames": frames.len() });
+                self.respond(request, Ok(body))?;
+            }
+            "scopes" => {
+                let scopes = json!({ "scopes": [
+                    {
+                        "name": "Registers",
+                        "presentationHint": "registers",
+                        "variablesReference": REGISTERS_REFERENCE,
+                        "expensive": false,
+                    },
+                    {
+                        "name": "Alternate registers",
+                        "presentationHint": "registers",
+                        "variablesReference": ALT_REGISTERS_REFERENCE,
+                        "expensive": false,
+                    },
+                ]});
+                self.respond(request, Ok(scopes))?;
+            }
+            "variables" => {
+                let cpu = art32.cpu();
+                let variable = |name: &str, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+
+                let variables: Vec<_> = match args["variablesReference"].as_u64() {
+                    Some(REGISTERS_REFERENCE) => {
+                        std::iter::once(variable("pc", format_u32(cpu.program_counter())))
+                            .chain(Register::iter().map(|reg| {
+                                variable(&reg.to_string(), format_u32(cpu.register(reg)))
+                            }))
+                            .chain(std::iter::once(variable("flags", cpu.flags().to_string())))
+                            .collect()
+                    }
+                    Some(ALT_REGISTERS_REFERENCE) => Register::iter()
+                        .map(|reg| variable(&reg.to_string(), format_u32(cpu.alt_register(reg))))
+                        .chain(std::iter::once(variable(
+                            "flags",
+                            cpu.alt_flags().to_string(),
+                        )))
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                self.respond(request, Ok(json!({ "variables": variables })))?;
+            }
+            "readMemory" => {
+                let result = match args["memoryReference"]
+                    .as_str()
+                    .and_then(|reference| crate::parse_u32(reference).ok())
+                {
+                    Some(addr) => {
+                        let offset = args["offset"].as_i64().unwrap_or_default();
+                        let addr = addr.wrapping_add(offset as u32);
+                        let count = args["count"].as_u64().unwrap_or_default() as usize;
+
+                        let mut bytes = vec![0; count.min(MAX_READ_SIZE)];
+                        let read = art32.read_memory(addr, &mut bytes);
+                        Ok(json!({
+                            "address": format_u32(addr),
+                            "data": base64(&bytes[..read]),
+                            "unreadableBytes": bytes.len() - read,
+                        }))
+                    }
+                    None => Err("invalid memory reference".to_owned()),
+                };
+                self.respond(request, result)?;
+            }
+            "disassemble" => {
+                let result = match args["memoryReference"]
+                    .as_str()
+                    .and_then(|reference| crate::parse_u32(reference).ok())
+                {
+                    Some(addr) => {
+                        let offset = args["offset"].as_i64().unwrap_or_default();
+                        let instruction_offset =
+                            args["instructionOffset"].as_i64().unwrap_or_default();
+                        let count = args["instructionCount"].as_u64().unwrap_or_default();
+
+                        // instructions are at least two bytes long, earlier ones are found by
+                        // decoding from further back
+                        let mut addr = addr
+                            .wrapping_add(offset as u32)
+                            .wrapping_add((instruction_offset * 2) as u32);
+                        let mut instructions = Vec::new();
+                        for _ in 0..count {
+                            let mut word = [0; 4];
+                            let read = art32.read_memory(addr, &mut word);
+                            let word = u32::from_le_bytes(word);
+                            let len = instruction_len(word as u16);
+                            let text = match disassemble(word, addr) {
+                                Ok(text) if read >= (len as usize) => text,
+                                _ => "??".to_owned(),
+                            };
+                            let bytes = match len {
+                                2 => format!("{:0>4X}", word & 0xFFFF),
+                                _ => format!("{word:0>8X}"),
+                            };
+
+                            let mut instruction = json!({
+                                "address": format_u32(addr),
+                                "instructionBytes": bytes,
+                                "instruction": text,
+                            });
+                            if let Some((path, line)) = self.line_map.location(addr) {
+                                instruction["location"] = json!({ "path": path });
+                                instruction["line"] = json!(line);
+                            }
+                            instructions.push(instruction);
+                            addr = addr.wrapping_add(len);
+                        }
+                        Ok(json!({ "instructions": instructions }))
+                    }
+                    None => Err("invalid memory reference".to_owned()),
+                };
+                self.respond(request, result)?;
+            }
+            "continue" => {
+                self.respond(request, Ok(json!({ "allThreadsContinued": true })))?;
+                self.execution = Execution::Running;
+            }
+            "next" => {
+                self.respond(request, Ok(Value::Null))?;
+                match call_return_address(art32) {
+                    Some(addr) => self.execution = Execution::RunningTo(addr),
+                    None => self.step(art32)?,
+                }
+            }
+            "stepIn" => {
+                self.respond(request, Ok(Value::Null))?;
+                self.step(art32)?;
+            }
+            "stepOut" => {
+                self.respond(request, Ok(Value::Null))?;
+                self.execution = Execution::RunningTo(return_address(art32));
+            }
+            "pause" => {
+                self.respond(request, Ok(Value::Null))?;
+                if self.execution != Execution::Stopped {
+                    self.flush_serial(art32)?;
+                    self.stopped("pause", None)?;
+                }
+            }
+            "disconnect" => {
+                self.respond(request, Ok(Value::Null))?;
+                return Ok(Some(if args["terminateDebuggee"] == true {
+                    SessionEnd::Killed
+                } else {
+                    SessionEnd::Detached
+                }));
+            }
+            "terminate" => {
+                self.respond(request, Ok(Value::Null))?;
+                self.event("terminated", json!({}))?;
+                return Ok(Some(SessionEnd::Killed));
+            }
+            _ => {
+                self.respond(request, Err(format!("unsupported request `{command}`")))?;
+            }
+        }
+        Ok(None)
+    }
+}
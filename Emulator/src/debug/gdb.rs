@@ -0,0 +1,432 @@
+use super::{resume, SessionEnd, StopReason};
+use crate::cpu::{Flags, Register};
+use crate::system::{Art32, EnvAction};
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use strum::{EnumCount, IntoEnumIterator};
+
+/// Size of the packets the server accepts, in bytes.
+const PACKET_SIZE: usize = 0x1000;
+/// The largest memory read, its hex encoding fills a packet.
+const MAX_READ_SIZE: usize = (PACKET_SIZE / 2) - 8;
+
+const PC_REGNUM: usize = Register::COUNT;
+const FLAGS_REGNUM: usize = Register::COUNT + 1;
+const REG_COUNT: usize = Register::COUNT + 2;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGABRT: u8 = 6;
+
+const INTERRUPT: u8 = 0x03;
+
+fn target_xml() -> String {
+    let mut xml = String::from(concat!(
+        r#"<?xml version="1.0"?>"#,
+        "\n",
+        r#"<!DOCTYPE target SYSTEM "gdb-target.dtd">"#,
+        "\n",
+        r#"<target version="1.0">"#,
+        "\n",
+        r#"  <feature name="org.art32.core">"#,
+        "\n",
+        r#"    <flags id="art32_flags" size="4">"#,
+        "\n",
+        r#"      <field name="C" start="0" end="0"/>"#,
+        "\n",
+        r#"      <field name="Z" start="1" end="1"/>"#,
+        "\n",
+        r#"      <field name="S" start="2" end="2"/>"#,
+        "\n",
+        r#"      <field name="O" start="3" end="3"/>"#,
+        "\n",
+        r#"    </flags>"#,
+        "\n",
+    ));
+    for reg in Register::iter() {
+        let ty = match reg {
+            Register::Sp | Register::Fp => "data_ptr",
+            Register::Ra => "code_ptr",
+            _ => "uint32",
+        };
+        writeln!(
+            xml,
+            r#"    <reg name="{reg}" bitsize="32" type="{ty}" regnum="{}"/>"#,
+            usize::from(reg)
+        )
+        .unwrap();
+    }
+    writeln!(
+        xml,
+        r#"    <reg name="pc" bitsize="32" type="code_ptr" regnum="{PC_REGNUM}"/>"#
+    )
+    .unwrap();
+    writeln!(
+        xml,
+        r#"    <reg name="flags" bitsize="32" type="art32_flags" regnum="{FLAGS_REGNUM}"/>"#
+    )
+    .unwrap();
+    xml.push_str("  </feature>\n</target>\n");
+    xml
+}
+
+fn hex_u32(value: u32) -> String {
+    hex(&value.to_le_bytes())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(hex, "{byte:0>2x}").unwrap();
+    }
+    hex
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if (hex.len() & 0x1) != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..(i + 2))?, 16).ok())
+        .collect()
+}
+
+/// A register value as sent by gdb, in target byte order.
+fn parse_hex_u32(hex: &str) -> Option<u32> {
+    let bytes: [u8; 4] = parse_hex(hex)?.try_into().ok()?;
+    Some(u32::from_le_bytes(bytes))
+}
+
+fn parse_number(s: &str) -> Option<u32> {
+    u32::from_str_radix(s, 16).ok()
+}
+
+/// Parses the `<addr>,<len>` arguments of memory packets.
+fn parse_range(s: &str) -> Option<(u32, usize)> {
+    let (addr, len) = s.split_once(',')?;
+    Some((parse_number(addr)?, parse_number(len)? as usize))
+}
+
+fn read_register(art32: &Art32, regnum: usize) -> Option<u32> {
+    match regnum {
+        PC_REGNUM => Some(art32.cpu().program_counter()),
+        FLAGS_REGNUM => Some(art32.cpu().flags().bits() as u32),
+        _ => Some(art32.cpu().register(Register::try_from(regnum).ok()?)),
+    }
+}
+
+fn write_register(art32: &mut Art32, regnum: usize, value: u32) -> Option<()> {
+    match regnum {
+        PC_REGNUM => art32.cpu_mut().set_program_counter(value),
+        FLAGS_REGNUM => art32
+            .cpu_mut()
+            .set_flags(Flags::from_bits_truncate(value as u8)),
+        _ => art32
+            .cpu_mut()
+            .set_register(Register::try_from(regnum).ok()?, value),
+    }
+    Some(())
+}
+
+/// A server for the GDB remote serial protocol, debugging an `Art32` for a single client.
+///
+/// Registers are numbered like `Register`, followed by the program counter and the flags.
+/// Memory is accessed through the kernel's address translation without permission checks,
+/// breakpoints are kept by the server instead of being patched into memory.
+pub struct GdbServer {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    no_ack: bool,
+    breakpoints: BTreeSet<u32>,
+    last_stop: String,
+}
+
+impl GdbServer {
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            buffer: Vec::new(),
+            no_ack: false,
+            breakpoints: BTreeSet::new(),
+            last_stop: format!("S{SIGTRAP:0>2x}"),
+        })
+    }
+
+    /// Listens on `addr` and waits for a single client.
+    pub fn accept(addr: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let (stream, _) = listener.accept()?;
+        Self::new(stream)
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        if self.buffer.is_empty() {
+            let mut buffer = [0; 0x400];
+            let len = self.stream.read(&mut buffer)?;
+            if len == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.buffer.extend_from_slice(&buffer[..len]);
+        }
+        Ok(self.buffer.remove(0))
+    }
+
+    /// Checks for a Ctrl-C from the client without blocking, a closed connection stops the
+    /// guest as well.
+    fn poll_interrupt(&mut self) -> bool {
+        let mut buffer = [0; 0x400];
+        let result = self
+            .stream
+            .set_nonblocking(true)
+            .and_then(|()| self.stream.read(&mut buffer));
+        let _ = self.stream.set_nonblocking(false);
+
+        match result {
+            Ok(0) => return true,
+            Ok(len) => self.buffer.extend_from_slice(&buffer[..len]),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+            Err(_) => return true,
+        }
+        match self.buffer.iter().position(|&byte| byte == INTERRUPT) {
+            Some(index) => {
+                self.buffer.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Reads the next packet, acknowledging it. Returns `None` for a Ctrl-C outside of a packet.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.read_byte()? {
+                b'$' => {}
+                INTERRUPT => return Ok(None),
+                // acknowledgments and noise between packets
+                _ => continue,
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    b'#' => break,
+                    byte => data.push(byte),
+                }
+            }
+            let checksum = [self.read_byte()?, self.read_byte()?];
+            let expected = data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+            let valid = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|checksum| u8::from_str_radix(checksum, 16).ok())
+                == Some(expected);
+
+            if !self.no_ack {
+                self.stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) -> io::Result<()> {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        let packet = format!("${data}#{checksum:0>2x}");
+        loop {
+            self.stream.write_all(packet.as_bytes())?;
+            if self.no_ack {
+                return Ok(());
+            }
+            // anything but a retransmission request counts as an acknowledgment
+            loop {
+                match self.read_byte()? {
+                    b'+' => return Ok(()),
+                    b'-' => break,
+                    INTERRUPT => continue,
+                    byte => {
+                        self.buffer.insert(0, byte);
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    fn stop_reply(reason: StopReason) -> String {
+        let signal = match reason {
+            StopReason::Step | StopReason::Breakpoint => SIGTRAP,
+            StopReason::Interrupted => SIGINT,
+            StopReason::Env(EnvAction::Break | EnvAction::Pass) => SIGTRAP,
+            StopReason::Env(EnvAction::Error | EnvAction::Fail) => SIGABRT,
+            StopReason::Env(EnvAction::Reset) => unreachable!("resets are carried out by `resume`"),
+            StopReason::MachineCheck(_) => SIGILL,
+        };
+        format!("S{signal:0>2x}")
+    }
+
+    /// Serves the client until it detaches or kills the guest. Block translation gets disabled
+    /// so breakpoints are hit.
+    pub fn serve(&mut self, art32: &mut Art32) -> io::Result<SessionEnd> {
+        art32.set_block_translation(None);
+
+        loop {
+            let packet = match self.read_packet() {
+                Ok(Some(packet)) => packet,
+                // the guest is already stopped
+                Ok(None) => continue,
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Ok(SessionEnd::Detached)
+                }
+                Err(err) => return Err(err),
+            };
+
+            let reply = match packet.as_bytes().first() {
+                Some(b'?') => self.last_stop.clone(),
+                Some(b'g') => (0..REG_COUNT)
+                    .filter_map(|regnum| read_register(art32, regnum))
+                    .map(hex_u32)
+                    .collect(),
+                Some(b'G') => {
+                    let values = &packet[1..];
+                    if values.len() < (REG_COUNT * 8) {
+                        "E01".to_owned()
+                    } else {
+                        for regnum in 0..REG_COUNT {
+                            let value = &values[(regnum * 8)..((regnum + 1) * 8)];
+                            if let Some(value) = parse_hex_u32(value) {
+                                write_register(art32, regnum, value);
+                            }
+                        }
+                        "OK".to_owned()
+                    }
+                }
+                Some(b'p') => parse_number(&packet[1..])
+                    .and_then(|regnum| read_register(art32, regnum as usize))
+                    .map_or_else(|| "E01".to_owned(), hex_u32),
+                Some(b'P') => packet[1..]
+                    .split_once('=')
+                    .and_then(|(regnum, value)| {
+                        write_register(art32, parse_number(regnum)? as usize, parse_hex_u32(value)?)
+                    })
+                    .map_or_else(|| "E01".to_owned(), |()| "OK".to_owned()),
+                Some(b'm') => match parse_range(&packet[1..]) {
+                    Some((addr, len)) => {
+                        let mut bytes = vec![0; len.min(MAX_READ_SIZE)];
+                        match art32.read_memory(addr, &mut bytes) {
+                            0 if len > 0 => "E01".to_owned(),
+                            read => hex(&bytes[..read]),
+                        }
+                    }
+                    None => "E01".to_owned(),
+                },
+                Some(b'M') => packet[1..]
+                    .split_once(':')
+                    .and_then(|(range, data)| {
+                        let (addr, len) = parse_range(range)?;
+                        let data = parse_hex(data)?;
+                        (data.len() == len && art32.write_memory(addr, &data) == len).then_some(())
+                    })
+                    .map_or_else(|| "E01".to_owned(), |()| "OK".to_owned()),
+                Some(b'c' | b's') => {
+                    if let Some(addr) = packet.get(1..).filter(|addr| !addr.is_empty()) {
+                        match parse_number(addr) {
+                            Some(addr) => art32.cpu_mut().set_program_counter(addr),
+                            None => {
+                                self.send_packet("E01")?;
+                                continue;
+                            }
+                        }
+                    }
+
+                    let single_step = packet.starts_with('s');
+                    let breakpoints = std::mem::take(&mut self.breakpoints);
+                    let reason = resume(art32, &breakpoints, single_step, || self.poll_interrupt());
+                    self.breakpoints = breakpoints;
+
+                    // shown on the client's console, the stop reply only carries a signal
+                    if let StopReason::MachineCheck(machine_check) = reason {
+                        let message = format!("machine check: {machine_check}\n");
+                        self.send_packet(&format!("O{}", hex(message.as_bytes())))?;
+                    }
+                    self.last_stop = Self::stop_reply(reason);
+                    self.last_stop.clone()
+                }
+                Some(b'Z' | b'z') => {
+                    let insert = packet.starts_with('Z');
+                    match packet[1..].split_once(',') {
+                        // software and hardware breakpoints are the same to the emulator
+                        Some(("0" | "1", args)) => {
+                            match args.split(',').next().and_then(parse_number) {
+                                Some(addr) => {
+                                    if insert {
+                                        self.breakpoints.insert(addr);
+                                    } else {
+                                        self.breakpoints.remove(&addr);
+                                    }
+                                    "OK".to_owned()
+                                }
+                                None => "E01".to_owned(),
+                            }
+                        }
+                        _ => String::new(),
+                    }
+                }
+                Some(b'H') => "OK".to_owned(),
+                Some(b'T') => "OK".to_owned(),
+                Some(b'D') => {
+                    self.send_packet("OK")?;
+                    return Ok(SessionEnd::Detached);
+                }
+                Some(b'k') => return Ok(SessionEnd::Killed),
+                Some(b'v') if packet.starts_with("vKill") => {
+                    self.send_packet("OK")?;
+                    return Ok(SessionEnd::Killed);
+                }
+                Some(b'q' | b'Q' | b'v') => self.query(&packet),
+                _ => String::new(),
+            };
+            self.send_packet(&reply)?;
+        }
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            format!("PacketSize={PACKET_SIZE:x};qXfer:features:read+;QStartNoAckMode+")
+        } else if packet == "QStartNoAckMode" {
+            // the acknowledgment of this packet has already been sent
+            self.no_ack = true;
+            "OK".to_owned()
+        } else if let Some(args) = packet.strip_prefix("qXfer:features:read:") {
+            let Some((annex, range)) = args.split_once(':') else {
+                return "E01".to_owned();
+            };
+            if annex != "target.xml" {
+                return "E00".to_owned();
+            }
+            let Some((offset, len)) = parse_range(range) else {
+                return "E01".to_owned();
+            };
+
+            let xml = target_xml();
+            let start = (offset as usize).min(xml.len());
+            let end = start
+                .saturating_add(len.min(PACKET_SIZE - 8))
+                .min(xml.len());
+            let kind = if end == xml.len() { 'l' } else { 'm' };
+            format!("{kind}{}", &xml[start..end])
+        } else {
+            match packet {
+                "qAttached" => "1".to_owned(),
+                "qC" => "QC1".to_owned(),
+                "qfThreadInfo" => "m1".to_owned(),
+                "qsThreadInfo" => "l".to_owned(),
+                _ => String::new(),
+            }
+        }
+    }
+}
@@ -0,0 +1,196 @@
+mod console;
+mod dap;
+mod gdb;
+
+use super::{backtrace, call_return_address, resume, return_address, Frame, StopReason};
+use crate::cpu::instruction::*;
+use crate::cpu::{BranchCondition, Register};
+use crate::system::{Art32, EnvAction, KERNEL_RAM_START};
+use std::collections::BTreeSet;
+
+/// Calls a function adding 5 to `a0` and breaks once it returned.
+fn calling_kernel() -> Vec<u8> {
+    use Instruction::*;
+    use Register::*;
+
+    assemble(&[
+        Branch32 {
+            cond: BranchCondition::Link,
+            offset: 4,
+        },
+        Envcall(EnvAction::Break as u8),
+        Ldi16 { rd: Zero, imm: 0 },
+        AluI32 {
+            op: AluOp::Add,
+            rd: A0,
+            rs1: A0,
+            imm: 5,
+        },
+        Ret,
+    ])
+}
+
+const CALL_RETURN: u32 = KERNEL_RAM_START + 4;
+const FUNCTION: u32 = KERNEL_RAM_START + 8;
+
+/// Counts `a0` up forever, the loop starts at `LOOP_START`.
+fn counting_kernel() -> Vec<u8> {
+    use Instruction::*;
+    use Register::*;
+
+    assemble(&[
+        AluI32 {
+            op: AluOp::Add,
+            rd: A0,
+            rs1: Zero,
+            imm: 0,
+        },
+        AluI32 {
+            op: AluOp::Add,
+            rd: A0,
+            rs1: A0,
+            imm: 1,
+        },
+        Branch16 {
+            cond: BranchCondition::True,
+            offset: -6,
+        },
+    ])
+}
+
+const LOOP_START: u32 = KERNEL_RAM_START + 4;
+
+#[test]
+fn resume_step() {
+    let mut art32 = Art32::with_kernel(&counting_kernel());
+    let breakpoints = BTreeSet::new();
+
+    let start = art32.cpu().program_counter();
+    assert_eq!(
+        resume(&mut art32, &breakpoints, true, || false),
+        StopReason::Step
+    );
+    assert_eq!(art32.cpu().program_counter(), start + 4);
+    assert_eq!(art32.retired_instructions(), 1);
+}
+
+#[test]
+fn resume_breakpoint() {
+    let mut art32 = Art32::with_kernel(&counting_kernel());
+    let breakpoints = BTreeSet::from([LOOP_START]);
+
+    for count in 0..3 {
+        assert_eq!(
+            resume(&mut art32, &breakpoints, false, || false),
+            StopReason::Breakpoint
+        );
+        assert_eq!(art32.cpu().program_counter(), LOOP_START);
+        assert_eq!(art32.cpu().register(Register::A0), count);
+    }
+}
+
+#[test]
+fn resume_interrupted() {
+    let mut art32 = Art32::with_kernel(&counting_kernel());
+    assert_eq!(
+        resume(&mut art32, &BTreeSet::new(), false, || true),
+        StopReason::Interrupted
+    );
+    assert!(art32.retired_instructions() > 0);
+}
+
+#[test]
+fn resume_env_call() {
+    let mut art32 = Art32::with_kernel(&assemble(&[
+        Instruction::Envcall(EnvAction::Reset as u8),
+        Instruction::Envcall(EnvAction::Break as u8),
+    ]));
+    let breakpoints = BTreeSet::new();
+
+    // the reset is carried out and starts over
+    assert_eq!(
+        resume(&mut art32, &breakpoints, true, || false),
+        StopReason::Step
+    );
+    assert_eq!(art32.retired_instructions(), 0);
+
+    let mut art32 = Art32::with_kernel(&assemble(&[Instruction::Envcall(EnvAction::Break as u8)]));
+    assert_eq!(
+        resume(&mut art32, &breakpoints, false, || false),
+        StopReason::Env(EnvAction::Break)
+    );
+
+    let mut art32 = Art32::with_kernel(&[0xFF; 4]);
+    assert!(matches!(
+        resume(&mut art32, &breakpoints, false, || false),
+        StopReason::MachineCheck(_)
+    ));
+}
+
+#[test]
+fn memory_access() {
+    let mut art32 = Art32::with_kernel(&counting_kernel());
+    let addr = KERNEL_RAM_START + 0x100;
+
+    assert_eq!(art32.write_memory(addr, &[1, 2, 3, 4]), 4);
+    let mut bytes = [0; 4];
+    assert_eq!(art32.read_memory(addr, &mut bytes), 4);
+    assert_eq!(bytes, [1, 2, 3, 4]);
+
+    // stops at the end of the kernel RAM
+    let end = KERNEL_RAM_START + 0x8000;
+    assert_eq!(art32.read_memory(end - 2, &mut bytes), 2);
+    assert_eq!(art32.write_memory(end - 1, &[0; 4]), 1);
+    assert_eq!(art32.read_memory(0, &mut bytes), 0);
+}
+
+#[test]
+fn calls() {
+    let mut art32 = Art32::with_kernel(&calling_kernel());
+    let breakpoints = BTreeSet::new();
+
+    assert_eq!(call_return_address(&mut art32), Some(CALL_RETURN));
+    resume(&mut art32, &breakpoints, true, || false);
+    assert_eq!(art32.cpu().program_counter(), FUNCTION);
+    assert_eq!(call_return_address(&mut art32), None);
+    // a function without a frame returns through `ra`
+    assert_eq!(return_address(&mut art32), CALL_RETURN);
+}
+
+#[test]
+fn frame_records() {
+    let mut art32 = Art32::with_kernel(&counting_kernel());
+    let inner = KERNEL_RAM_START + 0x4100;
+    let outer = KERNEL_RAM_START + 0x4200;
+
+    let mut records = Vec::new();
+    records.extend_from_slice(&outer.to_le_bytes());
+    records.extend_from_slice(&0x1111u32.to_le_bytes());
+    art32.write_memory(inner - 8, &records);
+    let mut records = Vec::new();
+    records.extend_from_slice(&0u32.to_le_bytes());
+    records.extend_from_slice(&0x2222u32.to_le_bytes());
+    art32.write_memory(outer - 8, &records);
+
+    assert_eq!(backtrace(&mut art32).len(), 1);
+    art32.cpu_mut().set_register(Register::Fp, inner);
+    assert_eq!(
+        backtrace(&mut art32),
+        [
+            Frame {
+                pc: KERNEL_RAM_START,
+                fp: inner,
+            },
+            Frame {
+                pc: 0x1111,
+                fp: outer,
+            },
+            Frame { pc: 0x2222, fp: 0 },
+        ]
+    );
+    assert_eq!(return_address(&mut art32), 0x1111);
+
+    // records pointing down the stack end the walk
+    art32.write_memory(outer - 8, &inner.to_le_bytes());
+    assert_eq!(backtrace(&mut art32).len(), 3);
+}
@@ -0,0 +1,254 @@
+use super::super::console::{Command, Comparison, Condition, Console, Operand};
+use super::{calling_kernel, counting_kernel, CALL_RETURN, FUNCTION, LOOP_START};
+use crate::cpu::instruction::*;
+use crate::cpu::{BranchCondition, Register};
+use crate::system::{Art32, KERNEL_RAM_START};
+
+/// Stores the count in `a0` to `WATCHED` on every iteration.
+fn storing_kernel() -> Vec<u8> {
+    use Instruction::*;
+    use Register::*;
+
+    assemble(&[
+        Ldui {
+            rd: S0,
+            imm: KERNEL_RAM_START,
+        },
+        AluI32 {
+            op: AluOp::Add,
+            rd: A0,
+            rs1: A0,
+            imm: 1,
+        },
+        Store32 {
+            op: StoreOp::Word,
+            rs: A0,
+            rb: S0,
+            offset: 0x100,
+        },
+        Branch16 {
+            cond: BranchCondition::True,
+            offset: -10,
+        },
+    ])
+}
+
+const WATCHED: u32 = KERNEL_RAM_START + 0x100;
+
+/// Executes a command line and returns what it printed.
+fn execute(console: &mut Console, art32: &mut Art32, line: &str) -> String {
+    let mut out = Vec::new();
+    console.execute(art32, line, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+/// Runs the guest until it stops and returns what was printed.
+fn run(console: &mut Console, art32: &mut Art32) -> String {
+    let mut out = Vec::new();
+    assert!(!console.run(art32, 100_000, &mut out).unwrap());
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn parse() {
+    assert_eq!(
+        "b 0x10000004 if a0 >= [0x10000100]".parse(),
+        Ok(Command::Break {
+            addr: 0x1000_0004,
+            condition: Some(Condition {
+                lhs: Operand::Register(Register::A0),
+                op: Comparison::GreaterOrEqual,
+                rhs: Operand::Memory(0x1000_0100),
+            }),
+        })
+    );
+    assert_eq!("step".parse(), Ok(Command::Step(1)));
+    assert_eq!("s 10".parse(), Ok(Command::Step(10)));
+    assert_eq!(
+        "set pc 0x10000000".parse(),
+        Ok(Command::Set {
+            target: Operand::Pc,
+            value: KERNEL_RAM_START,
+        })
+    );
+    assert_eq!(
+        "disas".parse(),
+        Ok(Command::Disassemble {
+            addr: None,
+            count: 8
+        })
+    );
+    assert_eq!(
+        "write 0x100 0xAB 1".parse(),
+        Ok(Command::Write {
+            addr: 0x100,
+            value: 0xAB,
+            size: 1,
+        })
+    );
+
+    for invalid in [
+        "frobnicate",
+        "break",
+        "break 0x10 when a0 == 1",
+        "break 0x10 if a0 ==",
+        "break 0x10 if a0 ~ 1",
+        "step 0",
+        "set 5 1",
+        "write 0x100 0x1FF 1",
+        "write 0x100 1 3",
+        "continue now",
+    ] {
+        assert!(invalid.parse::<Command>().is_err(), "{invalid}");
+    }
+}
+
+#[test]
+fn conditional_breakpoint() {
+    let mut art32 = Art32::with_kernel(&counting_kernel());
+    let mut console = Console::default();
+
+    let out = execute(
+        &mut console,
+        &mut art32,
+        &format!("break 0x{LOOP_START:x} if a0 == 3"),
+    );
+    assert_eq!(out, format!("breakpoint 1 at 0x{LOOP_START:0>8x}\n"));
+    assert_eq!(
+        execute(&mut console, &mut art32, "info"),
+        format!("1   breakpoint  0x{LOOP_START:0>8x} if a0 == 0x3\n")
+    );
+
+    execute(&mut console, &mut art32, "continue");
+    assert!(console.is_running());
+    let out = run(&mut console, &mut art32);
+    assert!(out.starts_with(&format!("breakpoint 1\n=> {LOOP_START:0>8x}:")));
+    assert_eq!(art32.cpu().program_counter(), LOOP_START);
+    assert_eq!(art32.cpu().register(Register::A0), 3);
+
+    // stops again on the next match only
+    execute(&mut console, &mut art32, "set a0 9");
+    execute(&mut console, &mut art32, "continue");
+    let mut out = Vec::new();
+    assert!(console.run(&mut art32, 100, &mut out).unwrap());
+    execute(&mut console, &mut art32, "delete 1");
+    assert_eq!(execute(&mut console, &mut art32, "info"), "");
+    let out = execute(&mut console, &mut art32, "pause");
+    assert!(out.starts_with("paused\n=> "));
+    assert!(!console.is_running());
+    assert!(art32.cpu().register(Register::A0) > 9);
+
+    assert_eq!(
+        execute(&mut console, &mut art32, "delete 1"),
+        "error: no breakpoint or watchpoint 1\n"
+    );
+}
+
+#[test]
+fn watchpoint() {
+    let mut art32 = Art32::with_kernel(&storing_kernel());
+    let mut console = Console::default();
+
+    let out = execute(&mut console, &mut art32, &format!("watch 0x{WATCHED:x}"));
+    assert_eq!(out, format!("watchpoint 1 at 0x{WATCHED:0>8x}, 4 bytes\n"));
+
+    for count in 1..=2 {
+        execute(&mut console, &mut art32, "c");
+        let out = run(&mut console, &mut art32);
+        assert!(out.starts_with(&format!(
+            "watchpoint 1: 0x{WATCHED:0>8x} changed\n  old: {:0>2x} 00 00 00\n  new: {count:0>2x} 00 00 00\n",
+            count - 1
+        )));
+        assert_eq!(art32.cpu().register(Register::A0), count);
+    }
+
+    // the console's own writes are not reported
+    execute(&mut console, &mut art32, &format!("write 0x{WATCHED:x} 7"));
+    execute(&mut console, &mut art32, "set a0 0x40");
+    execute(&mut console, &mut art32, "c");
+    let out = run(&mut console, &mut art32);
+    assert!(out.contains("old: 07 00 00 00\n  new: 41 00 00 00"));
+}
+
+#[test]
+fn stepping() {
+    let mut art32 = Art32::with_kernel(&calling_kernel());
+    let mut console = Console::default();
+
+    // `next` runs the whole call
+    execute(&mut console, &mut art32, "next");
+    let out = run(&mut console, &mut art32);
+    assert!(out.starts_with(&format!("=> {CALL_RETURN:0>8x}:")), "{out}");
+    assert_eq!(art32.cpu().register(Register::A0), 5);
+
+    art32.reset();
+    execute(&mut console, &mut art32, "step");
+    run(&mut console, &mut art32);
+    assert_eq!(art32.cpu().program_counter(), FUNCTION);
+    execute(&mut console, &mut art32, "finish");
+    run(&mut console, &mut art32);
+    assert_eq!(art32.cpu().program_counter(), CALL_RETURN);
+
+    art32.reset();
+    execute(&mut console, &mut art32, "step 2");
+    run(&mut console, &mut art32);
+    assert_eq!(art32.cpu().program_counter(), FUNCTION + 4);
+
+    art32.reset();
+    execute(&mut console, &mut art32, &format!("until 0x{FUNCTION:x}"));
+    run(&mut console, &mut art32);
+    assert_eq!(art32.cpu().program_counter(), FUNCTION);
+
+    // the `brk` after the call stops the guest
+    execute(&mut console, &mut art32, "continue");
+    let out = run(&mut console, &mut art32);
+    assert!(out.starts_with("break\n"), "{out}");
+}
+
+#[test]
+fn inspection() {
+    let mut art32 = Art32::with_kernel(&counting_kernel());
+    let mut console = Console::default();
+
+    execute(&mut console, &mut art32, "write 0x10000100 0x64636261");
+    execute(&mut console, &mut art32, "write 0x10000104 0x7F 1");
+    assert_eq!(
+        execute(&mut console, &mut art32, "x 0x10000100 5"),
+        format!("10000100:  61 62 63 64 7f{}  |abcd.|\n", "   ".repeat(11))
+    );
+    assert_eq!(
+        execute(&mut console, &mut art32, "x 0 4"),
+        "error: cannot read memory at 0x00000000\n"
+    );
+
+    execute(&mut console, &mut art32, "set sp 0x1234");
+    let regs = execute(&mut console, &mut art32, "regs");
+    assert!(regs.starts_with(&format!("pc    0x{KERNEL_RAM_START:0>8x}  flags ____\n")));
+    assert!(regs.contains("sp    0x00001234"));
+    assert_eq!(regs.lines().count(), 9);
+
+    // around the program counter, the instructions in front of it included
+    execute(
+        &mut console,
+        &mut art32,
+        &format!("set pc 0x{LOOP_START:x}"),
+    );
+    assert_eq!(
+        execute(&mut console, &mut art32, "disas 2"),
+        "error: cannot read memory at 0x00000002\n"
+    );
+    let disassembly = execute(&mut console, &mut art32, "disas");
+    let lines = disassembly.lines().collect::<Vec<_>>();
+    assert!(lines[0].starts_with(&format!("   {KERNEL_RAM_START:0>8x}:")));
+    assert!(lines[1].starts_with(&format!("=> {LOOP_START:0>8x}:")));
+    assert!(lines[1].contains("addi"));
+
+    let bt = execute(&mut console, &mut art32, "bt");
+    assert_eq!(bt, format!("#0  0x{LOOP_START:0>8x}  fp 0x00000000\n"));
+
+    assert!(execute(&mut console, &mut art32, "help").contains("watch <addr>"));
+    assert_eq!(
+        execute(&mut console, &mut art32, "step 0"),
+        "error: the count must not be zero\n"
+    );
+}
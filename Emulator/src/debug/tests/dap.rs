@@ -0,0 +1,365 @@
+use super::super::dap::{DapServer, DapTransport, LineMap};
+use super::super::SessionEnd;
+use super::{calling_kernel, counting_kernel, CALL_RETURN, FUNCTION, LOOP_START};
+use crate::cpu::disasm::disassemble;
+use crate::cpu::instruction::assemble;
+use crate::cpu::Register;
+use crate::system::{Art32, EnvAction, SerialOutput};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::thread::JoinHandle;
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    seq: u64,
+    events: VecDeque<Value>,
+}
+
+impl Client {
+    fn read_message(&mut self) -> Value {
+        let mut len = 0;
+        loop {
+            let mut header = String::new();
+            self.reader.read_line(&mut header).unwrap();
+            match header.trim_end() {
+                "" => break,
+                header => len = header["Content-Length: ".len()..].parse().unwrap(),
+            }
+        }
+        let mut content = vec![0; len];
+        self.reader.read_exact(&mut content).unwrap();
+        serde_json::from_slice(&content).unwrap()
+    }
+
+    fn send(&mut self, command: &str, arguments: Value) -> u64 {
+        self.seq += 1;
+        let request = json!({
+            "seq": self.seq,
+            "type": "request",
+            "command": command,
+            "arguments": arguments,
+        })
+        .to_string();
+        write!(
+            self.writer,
+            "Content-Length: {}\r\n\r\n{request}",
+            request.len()
+        )
+        .unwrap();
+        self.seq
+    }
+
+    /// Sends a request and returns the body of its successful response.
+    fn request(&mut self, command: &str, arguments: Value) -> Value {
+        let seq = self.send(command, arguments);
+        loop {
+            let message = self.read_message();
+            if message["type"] == "response" {
+                assert_eq!(message["request_seq"], seq);
+                assert_eq!(message["success"], true, "{message}");
+                return message["body"].clone();
+            }
+            self.events.push_back(message);
+        }
+    }
+
+    fn event(&mut self, event: &str) -> Value {
+        loop {
+            let message = match self.events.pop_front() {
+                Some(message) => message,
+                None => self.read_message(),
+            };
+            if message["event"] == event {
+                return message["body"].clone();
+            }
+        }
+    }
+
+    fn register(&mut self, reference: u64, name: &str) -> String {
+        let variables = self.request("variables", json!({ "variablesReference": reference }));
+        let variable = variables["variables"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|variable| variable["name"] == name)
+            .unwrap();
+        variable["value"].as_str().unwrap().to_owned()
+    }
+}
+
+fn connect(kernel: Vec<u8>) -> (Client, JoinHandle<(Art32, SessionEnd)>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut art32 = Art32::with_kernel(&kernel);
+        art32.set_serial_output(Some(SerialOutput::Buffer)).unwrap();
+        let end = DapServer::new(stream.try_clone().unwrap(), stream)
+            .serve(&mut art32)
+            .unwrap();
+        (art32, end)
+    });
+
+    let stream = TcpStream::connect(addr).unwrap();
+    let client = Client {
+        reader: BufReader::new(stream.try_clone().unwrap()),
+        writer: stream,
+        seq: 0,
+        events: VecDeque::new(),
+    };
+    (client, server)
+}
+
+fn start(client: &mut Client, launch: Value) {
+    let capabilities = client.request("initialize", json!({ "adapterID": "art32" }));
+    assert_eq!(capabilities["supportsReadMemoryRequest"], true);
+    client.event("initialized");
+    client.request("launch", launch);
+}
+
+#[test]
+fn transport() {
+    assert_eq!("stdio".parse(), Ok(DapTransport::Stdio));
+    assert_eq!(
+        "127.0.0.1:4711".parse(),
+        Ok(DapTransport::Tcp("127.0.0.1:4711".parse().unwrap()))
+    );
+    assert!("4711".parse::<DapTransport>().is_err());
+}
+
+#[test]
+fn line_map() {
+    let text = "\
+; location (output offset:address | path:line:column:end line:end column)
+0:10000000 | loop.asm:1:4:1:13
+4:10000004 | loop.asm:3:4:3:18
+6:10000006 | loop.asm:3:4:3:18
+8:10000008 | sub/f.asm:9:4:9:12
+";
+    let map = LineMap::parse(text, Path::new("/src")).unwrap();
+    assert_eq!(
+        map.address(Path::new("/src/loop.asm"), 4),
+        Some(0x1000_0004)
+    );
+    assert_eq!(map.address(Path::new("/src/loop.asm"), 3), None);
+    assert_eq!(
+        map.location(0x1000_0008),
+        Some((Path::new("/src/sub/f.asm"), 10))
+    );
+
+    let err = LineMap::parse("4:10000004 | loop.asm:3", Path::new("/src")).unwrap_err();
+    assert!(err.starts_with("line 1"));
+    let err = LineMap::parse("10000004 loop.asm:3:4:3:18", Path::new("/src")).unwrap_err();
+    assert!(err.starts_with("line 1"));
+}
+
+#[test]
+fn breakpoints_and_state() {
+    let dir = std::env::temp_dir().join(format!("art32-dap-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("loop.asm");
+    std::fs::write(
+        &source,
+        "start:\n    ldi a0, 0\n.loop:\n    addi a0, a0, 1\n    jr .loop\n",
+    )
+    .unwrap();
+    let map_path = dir.join("loop.map");
+    std::fs::write(&map_path, format!("4:{LOOP_START:x} | loop.asm:3:4:3:18\n")).unwrap();
+
+    let (mut client, server) = connect(counting_kernel());
+    start(&mut client, json!({ "lineMap": map_path }));
+
+    let breakpoints = client.request(
+        "setBreakpoints",
+        json!({ "source": { "path": source }, "breakpoints": [{ "line": 4 }, { "line": 5 }] }),
+    );
+    assert_eq!(breakpoints["breakpoints"][0]["verified"], true);
+    assert_eq!(breakpoints["breakpoints"][1]["verified"], false);
+    client.request("setExceptionBreakpoints", json!({ "filters": [] }));
+    client.request("configurationDone", json!({}));
+
+    let stopped = client.event("stopped");
+    assert_eq!(stopped["reason"], "breakpoint");
+    assert_eq!(stopped["threadId"], 1);
+
+    let threads = client.request("threads", json!({}));
+    assert_eq!(threads["threads"][0]["id"], 1);
+    let trace = client.request("stackTrace", json!({ "threadId": 1 }));
+    let frame = &trace["stackFrames"][0];
+    assert_eq!(
+        frame["instructionPointerReference"],
+        format!("0x{LOOP_START:0>8X}")
+    );
+    assert_eq!(frame["line"], 4);
+    assert_eq!(frame["source"]["name"], "loop.asm");
+
+    let scopes = client.request("scopes", json!({ "frameId": 0 }));
+    let registers = scopes["scopes"][0]["variablesReference"].as_u64().unwrap();
+    let alt_registers = scopes["scopes"][1]["variablesReference"].as_u64().unwrap();
+    assert_eq!(
+        client.register(registers, "pc"),
+        format!("0x{LOOP_START:0>8X}")
+    );
+    assert_eq!(client.register(registers, "a0"), "0x00000000");
+    assert_eq!(client.register(alt_registers, "a0"), "0x00000000");
+
+    // a whole group of three bytes, encoded without padding
+    let memory = client.request(
+        "readMemory",
+        json!({ "memoryReference": format!("0x{LOOP_START:X}"), "offset": -4, "count": 3 }),
+    );
+    let kernel = counting_kernel();
+    let bits = u32::from_be_bytes([0, kernel[0], kernel[1], kernel[2]]);
+    let data: String = (0..4)
+        .map(|i| BASE64[((bits >> (18 - (i * 6))) & 0x3F) as usize] as char)
+        .collect();
+    assert_eq!(memory["data"], data);
+    assert_eq!(memory["unreadableBytes"], 0);
+
+    let memory = client.request("readMemory", json!({ "memoryReference": "0", "count": 4 }));
+    assert_eq!(memory["data"], "");
+    assert_eq!(memory["unreadableBytes"], 4);
+
+    let disassembly = client.request(
+        "disassemble",
+        json!({
+            "memoryReference": format!("0x{LOOP_START:X}"),
+            "instructionOffset": 0,
+            "instructionCount": 2,
+        }),
+    );
+    let instructions = disassembly["instructions"].as_array().unwrap();
+    let word = u32::from_le_bytes([kernel[4], kernel[5], kernel[6], kernel[7]]);
+    assert_eq!(
+        instructions[0]["instruction"],
+        disassemble(word, LOOP_START).unwrap()
+    );
+    assert_eq!(instructions[0]["line"], 4);
+    assert_eq!(
+        instructions[1]["address"],
+        format!("0x{:0>8X}", LOOP_START + 4)
+    );
+    assert_eq!(
+        instructions[1]["instructionBytes"].as_str().unwrap().len(),
+        4
+    );
+
+    client.request("stepIn", json!({ "threadId": 1 }));
+    assert_eq!(client.event("stopped")["reason"], "step");
+    assert_eq!(client.register(registers, "a0"), "0x00000001");
+
+    client.request("continue", json!({ "threadId": 1 }));
+    assert_eq!(client.event("stopped")["reason"], "breakpoint");
+    assert_eq!(
+        client.register(registers, "pc"),
+        format!("0x{LOOP_START:0>8X}")
+    );
+
+    // instruction breakpoints stay when the source ones are cleared
+    client.request(
+        "setBreakpoints",
+        json!({ "source": { "path": source }, "breakpoints": [] }),
+    );
+    let breakpoints = client.request(
+        "setInstructionBreakpoints",
+        json!({ "breakpoints": [{ "instructionReference": format!("0x{LOOP_START:X}") }] }),
+    );
+    assert_eq!(breakpoints["breakpoints"][0]["verified"], true);
+    client.request("continue", json!({ "threadId": 1 }));
+    assert_eq!(client.event("stopped")["reason"], "breakpoint");
+
+    // runs until paused without breakpoints
+    client.request("setInstructionBreakpoints", json!({ "breakpoints": [] }));
+    client.request("continue", json!({ "threadId": 1 }));
+    client.request("pause", json!({ "threadId": 1 }));
+    assert_eq!(client.event("stopped")["reason"], "pause");
+
+    client.request("disconnect", json!({}));
+    let (art32, end) = server.join().unwrap();
+    assert_eq!(end, SessionEnd::Detached);
+    assert!(art32.cpu().register(Register::A0) > 1);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn serial_output_while_running() {
+    use crate::cpu::instruction::Instruction::*;
+
+    // transmits forever, the guest never stops by itself
+    let (mut client, server) = connect(assemble(&[
+        Ldi16 {
+            rd: Register::A0,
+            imm: b'x' as i32,
+        },
+        Out {
+            rs: Register::A0,
+            rb: Register::Zero,
+            offset: 0x90,
+        },
+        Branch16 {
+            cond: crate::cpu::BranchCondition::True,
+            offset: -6,
+        },
+    ]));
+    start(&mut client, json!({}));
+    client.request("configurationDone", json!({}));
+
+    let output = client.event("output");
+    assert_eq!(output["category"], "stdout");
+    assert!(output["output"].as_str().unwrap().starts_with('x'));
+
+    client.request("pause", json!({ "threadId": 1 }));
+    assert_eq!(client.event("stopped")["reason"], "pause");
+    client.request("disconnect", json!({ "terminateDebuggee": true }));
+    assert_eq!(server.join().unwrap().1, SessionEnd::Killed);
+}
+
+#[test]
+fn stepping_calls() {
+    let (mut client, server) = connect(calling_kernel());
+    start(&mut client, json!({ "stopOnEntry": true }));
+    client.request(
+        "setInstructionBreakpoints",
+        json!({ "breakpoints": [{ "instructionReference": format!("0x{FUNCTION:X}") }] }),
+    );
+    client.request("configurationDone", json!({}));
+    assert_eq!(client.event("stopped")["reason"], "entry");
+
+    // a breakpoint inside the function still stops stepping over its call
+    client.request("next", json!({ "threadId": 1 }));
+    assert_eq!(client.event("stopped")["reason"], "breakpoint");
+    client.request("stepOut", json!({ "threadId": 1 }));
+    assert_eq!(client.event("stopped")["reason"], "step");
+    assert_eq!(client.register(1, "pc"), format!("0x{CALL_RETURN:0>8X}"));
+    assert_eq!(client.register(1, "a0"), "0x00000005");
+
+    client.request("setInstructionBreakpoints", json!({ "breakpoints": [] }));
+    client.request("continue", json!({ "threadId": 1 }));
+    let stopped = client.event("stopped");
+    assert_eq!(stopped["reason"], "breakpoint");
+    assert_eq!(stopped["text"], "brk");
+
+    client.request("terminate", json!({}));
+    client.event("terminated");
+    assert_eq!(server.join().unwrap().1, SessionEnd::Killed);
+}
+
+#[test]
+fn guest_exit() {
+    let (mut client, server) = connect(assemble(&[crate::cpu::instruction::Instruction::Envcall(
+        EnvAction::Fail as u8,
+    )]));
+    start(&mut client, json!({}));
+    client.request("configurationDone", json!({}));
+    assert_eq!(client.event("exited")["exitCode"], 1);
+    client.event("terminated");
+
+    client.request("disconnect", json!({ "terminateDebuggee": true }));
+    assert_eq!(server.join().unwrap().1, SessionEnd::Killed);
+}
@@ -0,0 +1,183 @@
+use super::super::gdb::GdbServer;
+use super::super::SessionEnd;
+use super::{counting_kernel, LOOP_START};
+use crate::cpu::Register;
+use crate::system::{Art32, KERNEL_RAM_START};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread::JoinHandle;
+
+struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    fn read_byte(&mut self) -> u8 {
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    fn send(&mut self, data: &str) {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(self.stream, "${data}#{checksum:0>2x}").unwrap();
+        assert_eq!(self.read_byte(), b'+');
+    }
+
+    fn request(&mut self, data: &str) -> String {
+        self.send(data);
+        self.reply()
+    }
+
+    fn reply(&mut self) -> String {
+        assert_eq!(self.read_byte(), b'$');
+        let mut data = Vec::new();
+        loop {
+            match self.read_byte() {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+        let checksum = [self.read_byte(), self.read_byte()];
+        let expected = data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        assert_eq!(
+            std::str::from_utf8(&checksum).unwrap(),
+            format!("{expected:0>2x}")
+        );
+        self.stream.write_all(b"+").unwrap();
+        String::from_utf8(data).unwrap()
+    }
+}
+
+fn connect(kernel: Vec<u8>) -> (Client, JoinHandle<(Art32, SessionEnd)>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut art32 = Art32::with_kernel(&kernel);
+        let end = GdbServer::new(stream).unwrap().serve(&mut art32).unwrap();
+        (art32, end)
+    });
+
+    let stream = TcpStream::connect(addr).unwrap();
+    (Client { stream }, server)
+}
+
+fn hex_u32(value: u32) -> String {
+    value
+        .to_le_bytes()
+        .iter()
+        .map(|byte| format!("{byte:0>2x}"))
+        .collect()
+}
+
+#[test]
+fn queries() {
+    let (mut client, server) = connect(counting_kernel());
+
+    let supported = client.request("qSupported:multiprocess+;swbreak+");
+    assert!(supported.contains("qXfer:features:read+"));
+    assert_eq!(client.request("?"), "S05");
+    assert_eq!(client.request("qAttached"), "1");
+    assert_eq!(client.request("vMustReplyEmpty"), "");
+
+    // read in chunks like gdb does
+    let mut xml = String::new();
+    loop {
+        let chunk = client.request(&format!(
+            "qXfer:features:read:target.xml:{:x},80",
+            xml.len()
+        ));
+        xml.push_str(&chunk[1..]);
+        if chunk.starts_with('l') {
+            break;
+        }
+        assert!(chunk.starts_with('m'));
+    }
+    assert!(xml.starts_with("<?xml"));
+    assert!(xml.contains(r#"<reg name="sp" bitsize="32" type="data_ptr" regnum="2"/>"#));
+    assert!(xml.contains(r#"<reg name="pc" bitsize="32" type="code_ptr" regnum="32"/>"#));
+    assert!(xml.trim_end().ends_with("</target>"));
+
+    assert_eq!(client.request("D"), "OK");
+    assert_eq!(server.join().unwrap().1, SessionEnd::Detached);
+}
+
+#[test]
+fn registers_and_memory() {
+    let (mut client, server) = connect(counting_kernel());
+
+    let regs = client.request("g");
+    assert_eq!(regs.len(), 34 * 8);
+    assert_eq!(&regs[(32 * 8)..(33 * 8)], hex_u32(KERNEL_RAM_START));
+
+    assert_eq!(
+        client.request(&format!("P8={}", hex_u32(0x1234_5678))),
+        "OK"
+    );
+    assert_eq!(client.request("p8"), hex_u32(0x1234_5678));
+    assert_eq!(client.request("P21=0f000000"), "OK");
+    assert_eq!(client.request("p21"), "0f000000");
+    // `zero` stays zero
+    assert_eq!(client.request("P0=01000000"), "OK");
+    assert_eq!(client.request("p0"), "00000000");
+    assert_eq!(client.request("p22"), "E01");
+
+    let addr = KERNEL_RAM_START + 0x100;
+    assert_eq!(client.request(&format!("M{addr:x},4:deadbeef")), "OK");
+    assert_eq!(client.request(&format!("m{addr:x},4")), "deadbeef");
+    assert_eq!(client.request("m0,4"), "E01");
+    // a partial read returns what is accessible
+    let end = KERNEL_RAM_START + 0x8000;
+    assert_eq!(client.request(&format!("m{:x},4", end - 2)).len(), 4);
+
+    client.send("k");
+    let (art32, end) = server.join().unwrap();
+    assert_eq!(end, SessionEnd::Killed);
+    assert_eq!(art32.cpu().register(Register::A0), 0x1234_5678);
+}
+
+#[test]
+fn execution() {
+    let (mut client, server) = connect(counting_kernel());
+
+    assert_eq!(client.request("s"), "S05");
+    assert_eq!(client.request("p20"), hex_u32(LOOP_START));
+
+    assert_eq!(client.request(&format!("Z0,{LOOP_START:x},2")), "OK");
+    for count in 1..3 {
+        assert_eq!(client.request("c"), "S05");
+        assert_eq!(client.request("p20"), hex_u32(LOOP_START));
+        assert_eq!(client.request("p8"), hex_u32(count));
+    }
+    assert_eq!(client.request(&format!("z0,{LOOP_START:x},2")), "OK");
+    assert_eq!(client.request("Z2,0,4"), "");
+
+    // runs until interrupted
+    client.send("c");
+    client.stream.write_all(&[0x03]).unwrap();
+    assert_eq!(client.reply(), "S02");
+    assert_eq!(client.request("?"), "S02");
+
+    // acknowledgments can be turned off
+    assert_eq!(client.request("QStartNoAckMode"), "OK");
+    client.stream.write_all(b"$c0#93").unwrap();
+    client.stream.write_all(&[0x03]).unwrap();
+
+    // the machine check is reported on the console before the stop
+    let output = client.reply();
+    assert!(output.starts_with('O'));
+    let output: Vec<_> = (1..output.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&output[i..(i + 2)], 16).unwrap())
+        .collect();
+    assert!(String::from_utf8(output)
+        .unwrap()
+        .starts_with("machine check: "));
+    assert_eq!(client.reply(), "S04");
+
+    drop(client);
+    let (art32, end) = server.join().unwrap();
+    assert_eq!(end, SessionEnd::Detached);
+    assert!(art32.cpu().machine_check().is_some());
+}
@@ -0,0 +1,81 @@
+#[macro_use]
+extern crate static_assertions;
+
+pub mod cli;
+pub mod cpu;
+pub mod debug;
+#[cfg(feature = "display")]
+pub mod display;
+mod memory;
+pub mod system;
+
+type HashMap<K, V> = ahash::AHashMap<K, V>;
+
+trait Ashr<Rhs = Self> {
+    type Output;
+
+    fn ashr(self, rhs: Rhs) -> Self::Output;
+}
+
+impl Ashr for u32 {
+    type Output = Self;
+
+    #[inline]
+    fn ashr(self, rhs: Self) -> Self::Output {
+        ((self as i32) >> rhs) as u32
+    }
+}
+
+macro_rules! shuffle_bits {
+    ($input:ident { [$src_end:literal : $src_start:literal] => [$dst_end:literal : $dst_start:literal] $(,)? }) => {{
+        const_assert!($src_start >= 0);
+        const_assert!($dst_start >= 0);
+        const_assert!($src_end >= $src_start);
+        const_assert!($dst_end >= $dst_start);
+        const_assert_eq!($src_end - $src_start, $dst_end - $dst_start);
+
+        let mask = !((!0) << ($src_end - $src_start + 1));
+        (($input >> $src_start) & mask) << $dst_start
+    }};
+    ($input:ident { [$src:literal] => [$dst:literal] $(,)? }) => {{
+        const_assert!($src >= 0);
+        const_assert!($dst >= 0);
+
+        (($input >> $src) & 0x1) << $dst
+    }};
+    ($input:ident { sign [$src:literal] => [$dst:literal] $(,)? }) => {{
+        const_assert!($src >= 0);
+        const_assert!($dst >= 0);
+
+        let bit = ($input >> $src) & 0x1;
+        let sign = (!bit).wrapping_add(1);
+        sign << $dst
+    }};
+    ($input:ident { [$src_end:literal : $src_start:literal] => [$dst_end:literal : $dst_start:literal], $($t:tt)+ }) => {
+        $crate::shuffle_bits!($input { [$src_end : $src_start] => [$dst_end : $dst_start] })
+        | $crate::shuffle_bits!($input { $($t)+ })
+    };
+    ($input:ident { [$src:literal] => [$dst:literal], $($t:tt)+ }) => {
+        $crate::shuffle_bits!($input { [$src] => [$dst] })
+        | $crate::shuffle_bits!($input { $($t)+ })
+    };
+    ($input:ident { sign [$src:literal] => [$dst:literal], $($t:tt)+ }) => {
+        $crate::shuffle_bits!($input { sign [$src] => [$dst] })
+        | $crate::shuffle_bits!($input { $($t)+ })
+    };
+}
+
+pub(crate) use shuffle_bits;
+
+/// Parses a number given on the command line, accepting `0x`/`0b` prefixes and `_` separators.
+pub fn parse_u32(s: &str) -> Result<u32, std::num::ParseIntError> {
+    let s = s.replace('_', "");
+
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        u32::from_str_radix(hex, 16)
+    } else if let Some(bin) = s.strip_prefix("0b").or_else(|| s.strip_prefix("0B")) {
+        u32::from_str_radix(bin, 2)
+    } else {
+        s.parse()
+    }
+}
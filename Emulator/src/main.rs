@@ -1,81 +1,44 @@
-#[macro_use]
-extern crate static_assertions;
-
-mod cpu;
-mod display;
-mod memory;
-mod system;
-
-type HashMap<K, V> = ahash::AHashMap<K, V>;
-
-trait Ashr<Rhs = Self> {
-    type Output;
-
-    fn ashr(self, rhs: Rhs) -> Self::Output;
-}
-
-impl Ashr for u32 {
-    type Output = Self;
-
-    #[inline]
-    fn ashr(self, rhs: Self) -> Self::Output {
-        ((self as i32) >> rhs) as u32
-    }
-}
-
-macro_rules! shuffle_bits {
-    ($input:ident { [$src_end:literal : $src_start:literal] => [$dst_end:literal : $dst_start:literal] $(,)? }) => {{
-        const_assert!($src_start >= 0);
-        const_assert!($dst_start >= 0);
-        const_assert!($src_end >= $src_start);
-        const_assert!($dst_end >= $dst_start);
-        const_assert_eq!($src_end - $src_start, $dst_end - $dst_start);
-
-        let mask = !((!0) << ($src_end - $src_start + 1));
-        (($input >> $src_start) & mask) << $dst_start
-    }};
-    ($input:ident { [$src:literal] => [$dst:literal] $(,)? }) => {{
-        const_assert!($src >= 0);
-        const_assert!($dst >= 0);
-
-        (($input >> $src) & 0x1) << $dst
-    }};
-    ($input:ident { sign [$src:literal] => [$dst:literal] $(,)? }) => {{
-        const_assert!($src >= 0);
-        const_assert!($dst >= 0);
-
-        let bit = ($input >> $src) & 0x1;
-        let sign = (!bit).wrapping_add(1);
-        sign << $dst
-    }};
-    ($input:ident { [$src_end:literal : $src_start:literal] => [$dst_end:literal : $dst_start:literal], $($t:tt)+ }) => {
-        $crate::shuffle_bits!($input { [$src_end : $src_start] => [$dst_end : $dst_start] })
-        | $crate::shuffle_bits!($input { $($t)+ })
-    };
-    ($input:ident { [$src:literal] => [$dst:literal], $($t:tt)+ }) => {
-        $crate::shuffle_bits!($input { [$src] => [$dst] })
-        | $crate::shuffle_bits!($input { $($t)+ })
-    };
-    ($input:ident { sign [$src:literal] => [$dst:literal], $($t:tt)+ }) => {
-        $crate::shuffle_bits!($input { sign [$src] => [$dst] })
-        | $crate::shuffle_bits!($input { $($t)+ })
-    };
+use art32_emu::cli::SystemArgs;
+use clap::Parser;
+
+#[derive(Parser)]
+#[command(about = "Emulates the Art32 system")]
+struct Args {
+    #[command(flatten)]
+    system: SystemArgs,
+
+    /// Read debugger commands from the terminal, `help` lists them
+    #[arg(long)]
+    console: bool,
 }
 
-use shuffle_bits;
-
 fn main() {
-    use std::io::Write;
+    use art32_emu::debug::console::Console;
+    use art32_emu::display;
+    use art32_emu::system::SerialInput;
+    use std::io::{BufRead, Write};
     use std::sync::atomic::{self, AtomicBool};
+    use std::sync::mpsc;
     use std::sync::{Arc, Mutex};
     use std::thread;
     use std::time::Duration;
-    use system::EnvAction;
     use winit::dpi::PhysicalSize;
     use winit::event::{ElementState, Event, ModifiersState, VirtualKeyCode, WindowEvent};
     use winit::event_loop::EventLoop;
     use winit::window::WindowBuilder;
 
+    let args = Args::parse();
+
+    if args.console && (args.system.serial_input == Some(SerialInput::Stdin)) {
+        eprintln!("error: the serial input cannot be stdin while using the console");
+        std::process::exit(1);
+    }
+
+    let mut art32 = args.system.build().unwrap_or_else(|err| {
+        eprintln!("error: {err}");
+        std::process::exit(1);
+    });
+
     const INITIAL_WINDOW_WIDTH: u32 = 800;
     const INITIAL_WINDOW_HEIGHT: u32 = 600;
 
@@ -99,7 +62,29 @@ fn main() {
 
     let run = Arc::new(AtomicBool::new(false));
     let exit = Arc::new(AtomicBool::new(false));
-    let art32 = Arc::new(Mutex::new(system::Art32::new()));
+    if let Err(err) = args.system.connect_serial(&mut art32) {
+        eprintln!("error: {err}");
+        std::process::exit(1);
+    }
+
+    let art32 = Arc::new(Mutex::new(art32));
+
+    // commands of the console and the keyboard shortcuts, executed by the emulation thread
+    let (command_sender, commands) = mpsc::channel::<String>();
+    let console = args.console;
+    if console {
+        let command_sender = command_sender.clone();
+        thread::spawn(move || {
+            print!("(art32) ");
+            std::io::stdout().flush().unwrap();
+            for line in std::io::stdin().lock().lines() {
+                let Ok(line) = line else { break };
+                if command_sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+    }
 
     let run_clone = Arc::clone(&run);
     let exit_clone = Arc::clone(&exit);
@@ -110,33 +95,51 @@ fn main() {
         let exit = exit_clone;
         let art32 = art32_clone;
 
-        const INNER_ITER_COUNT: u32 = 10000;
+        // without the console only the reasons the guest stopped are reported
+        let mut out: Box<dyn Write> = if console {
+            Box::new(std::io::stdout())
+        } else {
+            Box::new(std::io::stderr())
+        };
+        let prompt = |out: &mut Box<dyn Write>| {
+            if console {
+                write!(out, "(art32) ").unwrap();
+            }
+            out.flush().unwrap();
+        };
+
+        let mut debugger = Console::default();
+        const INNER_ITER_COUNT: u64 = 10000;
         'outer: loop {
             if exit.load(atomic::Ordering::Acquire) {
                 break 'outer;
             }
 
-            if run.load(atomic::Ordering::Acquire) {
+            {
                 let mut art32 = art32.lock().unwrap();
 
-                'inner: for _ in 0..INNER_ITER_COUNT {
-                    match art32.step() {
-                        Some(EnvAction::Break) => {
-                            run.store(false, atomic::Ordering::Release);
-                            break 'inner;
-                        }
-                        Some(EnvAction::Reset) => {
-                            println!("system reset requested");
-                            art32.reset();
-                        }
-                        Some(EnvAction::Error) => {
-                            panic!("system caused an error");
-                        }
-                        None => (),
+                for line in commands.try_iter() {
+                    debugger.execute(&mut art32, &line, &mut out).unwrap();
+                    run.store(debugger.is_running(), atomic::Ordering::Release);
+                    if !debugger.is_running() {
+                        prompt(&mut out);
                     }
                 }
 
-                std::io::stdout().flush().unwrap();
+                if run.load(atomic::Ordering::Acquire) {
+                    debugger.resume();
+                    if !debugger
+                        .run(&mut art32, INNER_ITER_COUNT, &mut out)
+                        .unwrap()
+                    {
+                        run.store(false, atomic::Ordering::Release);
+                        prompt(&mut out);
+                    }
+                    art32.flush_serial_output();
+                } else if debugger.is_running() {
+                    debugger.pause(&mut art32, &mut out).unwrap();
+                    prompt(&mut out);
+                }
             }
 
             thread::sleep(Duration::from_millis(1));
@@ -154,6 +157,12 @@ fn main() {
             } if window_id == window.id() => {
                 exit.store(true, atomic::Ordering::Release);
                 thread_handle.take().unwrap().join().unwrap();
+
+                // the event loop never returns, so terminals have to be restored and logs flushed here
+                let mut art32 = art32.lock().unwrap();
+                art32.set_serial_input(None).unwrap();
+                art32.set_serial_output(None).unwrap();
+
                 control_flow.set_exit();
             }
             Event::WindowEvent {
@@ -195,22 +204,7 @@ fn main() {
                         }
                         Some(VirtualKeyCode::C) => {
                             if !run.load(atomic::Ordering::Acquire) {
-                                let mut art32 = art32.lock().unwrap();
-                                match art32.step() {
-                                    Some(EnvAction::Break) => {
-                                        // already in single step mode
-                                    }
-                                    Some(EnvAction::Reset) => {
-                                        println!("system reset requested");
-                                        art32.reset();
-                                    }
-                                    Some(EnvAction::Error) => {
-                                        panic!("system caused an error");
-                                    }
-                                    None => (),
-                                }
-
-                                std::io::stdout().flush().unwrap();
+                                command_sender.send("step".to_owned()).unwrap();
                             }
                         }
                         _ => (),
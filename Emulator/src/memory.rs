@@ -13,7 +13,9 @@ impl Memory {
     #[inline]
     pub fn reset(&mut self, data: &[u8]) {
         let mem: &mut [u8] = cast_slice_mut(&mut self.0);
-        mem.copy_from_slice(data);
+        let (init, rest) = mem.split_at_mut(data.len());
+        init.copy_from_slice(data);
+        rest.fill(0);
     }
 
     #[inline]
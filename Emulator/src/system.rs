@@ -1,17 +1,57 @@
+use crate::cpu::block::{Block, BlockCache};
+use crate::cpu::cache::InstructionCache;
+use crate::cpu::instruction::Decoded;
 use crate::cpu::interface::*;
-use crate::cpu::Cpu;
+use crate::cpu::{Cpu, MachineCheck, TimingModel};
 use crate::memory::Memory;
-use std::collections::VecDeque;
+use std::num::NonZeroU32;
+use std::sync::Arc;
+
+mod image;
+pub use image::{Image, ImageError, ImageFormat, ImageSource, Segment};
+
+mod machine;
+use machine::Device;
+pub use machine::{Devices, MachineDescription, MachineError, MemoryAccess, MemoryRegion};
+
+mod paging;
+use paging::{Paging, PagingControl, PAGE_SIZE};
+
+mod protection;
+use protection::{Protection, ProtectionControl, RegionFlags, REGION_COUNT};
+
+mod serial;
+use serial::{HostInput, HostOutput, Serial, SerialControl, SerialStatus};
+pub use serial::{SerialInput, SerialOutput, SerialTiming};
+
+mod timer;
+use timer::{Timer, TimerControl};
+
+mod vdp;
+use vdp::Vdp;
+
+#[cfg(test)]
+mod tests;
 
 const KERNEL_RAM_SIZE: u32 = 0x0000_8000; // 32kB
 pub const KERNEL_RAM_START: u32 = 0x1000_0000;
-const KERNEL_RAM_END: u32 = KERNEL_RAM_START + KERNEL_RAM_SIZE - 1;
 
 const SYSTEM_RAM_SIZE: u32 = 0x0010_0000; // 1MB
 const SYSTEM_RAM_START: u32 = 0x2000_0000;
-const SYSTEM_RAM_END: u32 = SYSTEM_RAM_START + SYSTEM_RAM_SIZE - 1;
 
-const KERNEL: &'static [u8; KERNEL_RAM_SIZE as usize] = include_bytes!("../kernel/kernel.bin");
+/// Nominal frequency of the Softcore `clk40` clock domain.
+pub const SOFTCORE_CLOCK_HZ: NonZeroU32 = NonZeroU32::new(40_000_000).unwrap();
+
+/// The kernel image built into the emulator, written for `MachineDescription::art32`.
+pub const KERNEL: &[u8; KERNEL_RAM_SIZE as usize] = include_bytes!("../kernel/kernel.bin");
+
+/// The kind of memory access checked by paging and protection regions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AccessKind {
+    Read,
+    Write,
+    Execute,
+}
 
 #[derive(Debug, Default)]
 #[repr(transparent)]
@@ -41,10 +81,130 @@ impl Reservation {
     }
 }
 
+/// A memory region of the machine, backed by host memory.
+struct Ram {
+    start: u32,
+    /// Offset of the last byte.
+    last: u32,
+    system_access: MemoryAccess,
+    user_access: MemoryAccess,
+    memory: Memory,
+}
+
+impl Ram {
+    fn new(region: &MemoryRegion) -> Self {
+        Self {
+            start: region.base,
+            last: region.size - 1,
+            system_access: region.access,
+            user_access: if region.system_only {
+                MemoryAccess::empty()
+            } else {
+                region.access
+            },
+            memory: Memory::new(region.size),
+        }
+    }
+
+    #[inline]
+    fn offset(&self, addr: u32) -> Option<u32> {
+        let offset = addr.wrapping_sub(self.start);
+        (offset <= self.last).then_some(offset)
+    }
+
+    #[inline]
+    fn permits(&self, priv_level: PrivilegeLevel, kind: AccessKind) -> bool {
+        match priv_level {
+            PrivilegeLevel::System => self.system_access.permits(kind),
+            PrivilegeLevel::User => self.user_access.permits(kind),
+        }
+    }
+}
+
 pub struct Mmu<'a> {
-    kernel_ram: &'a mut Memory,
-    system_ram: &'a mut Memory,
+    memory: &'a mut [Ram],
+    paging: &'a Paging,
+    protection: &'a Protection,
     reservation: &'a mut Reservation,
+    instruction_cache: Option<&'a mut InstructionCache>,
+    block_cache: Option<&'a mut BlockCache>,
+    /// Read from memory the accessing code cannot reach instead of an access violation.
+    open_bus: Option<u32>,
+}
+
+impl Mmu<'_> {
+    /// Translates `addr` and checks the `len` bytes at the physical address are accessible.
+    #[inline]
+    fn check_access(
+        &self,
+        addr: u32,
+        len: u32,
+        priv_level: PrivilegeLevel,
+        kind: AccessKind,
+    ) -> Result<u32, MemoryError> {
+        let memory = &*self.memory;
+        let addr = self.paging.translate(addr, priv_level, kind, |addr| {
+            memory
+                .iter()
+                .find_map(|ram| Some(ram.memory.read_32(ram.offset(addr)?)))
+        })?;
+
+        self.protection.check(addr, len, priv_level, kind)?;
+        Ok(addr)
+    }
+
+    /// Checks that the halfword at `addr` can be fetched and returns its physical address.
+    #[inline]
+    fn check_fetch(&mut self, addr: u32, priv_level: PrivilegeLevel) -> Result<u32, MemoryError> {
+        if (addr & 0x1) != 0 {
+            return Err(MemoryError::UnalignedAccess);
+        }
+
+        let addr = self.check_access(addr, 2, priv_level, AccessKind::Execute)?;
+        self.ram(addr, priv_level, AccessKind::Execute)?;
+        Ok(addr)
+    }
+
+    /// Finds the RAM at the physical address `addr`, returning its memory and the offset into it.
+    #[inline]
+    fn ram(
+        &mut self,
+        addr: u32,
+        priv_level: PrivilegeLevel,
+        kind: AccessKind,
+    ) -> Result<(&mut Memory, u32), MemoryError> {
+        for ram in self.memory.iter_mut() {
+            if let Some(offset) = ram.offset(addr) {
+                if ram.permits(priv_level, kind) {
+                    return Ok((&mut ram.memory, offset));
+                }
+                break;
+            }
+        }
+
+        Err(MemoryError::AccessViolation)
+    }
+
+    /// Translated blocks skip the access checks after their first instruction, so they are dropped
+    /// whenever the translations or protection regions change.
+    #[inline]
+    fn flush_if_remapped(&mut self) {
+        // both flags have to be cleared
+        if self.paging.take_remapped() | self.protection.take_changed() {
+            self.flush_instruction_cache();
+        }
+    }
+
+    #[inline]
+    fn invalidate(&mut self, addr: u32, size: u32) {
+        if let Some(cache) = self.instruction_cache.as_deref_mut() {
+            cache.invalidate(addr, size);
+        }
+
+        if let Some(cache) = self.block_cache.as_deref_mut() {
+            cache.invalidate(addr, size);
+        }
+    }
 }
 
 impl MemoryInterface for Mmu<'_> {
@@ -58,18 +218,14 @@ impl MemoryInterface for Mmu<'_> {
             return Err(MemoryError::UnalignedAccess);
         }
 
+        let addr = self.check_access(addr, 4, priv_level, AccessKind::Read)?;
         if reserve {
             self.reservation.take(addr);
         }
 
-        match addr {
-            KERNEL_RAM_START..=KERNEL_RAM_END if priv_level == PrivilegeLevel::System => {
-                Ok(self.kernel_ram.read_32(addr - KERNEL_RAM_START))
-            }
-            SYSTEM_RAM_START..=SYSTEM_RAM_END => {
-                Ok(self.system_ram.read_32(addr - SYSTEM_RAM_START))
-            }
-            _ => Err(MemoryError::AccessViolation),
+        match self.ram(addr, priv_level, AccessKind::Read) {
+            Ok((memory, offset)) => Ok(memory.read_32(offset)),
+            Err(err) => self.open_bus.ok_or(err),
         }
     }
 
@@ -83,18 +239,14 @@ impl MemoryInterface for Mmu<'_> {
             return Err(MemoryError::UnalignedAccess);
         }
 
+        let addr = self.check_access(addr, 2, priv_level, AccessKind::Read)?;
         if reserve {
             self.reservation.take(addr);
         }
 
-        match addr {
-            KERNEL_RAM_START..=KERNEL_RAM_END if priv_level == PrivilegeLevel::System => {
-                Ok(self.kernel_ram.read_16(addr - KERNEL_RAM_START))
-            }
-            SYSTEM_RAM_START..=SYSTEM_RAM_END => {
-                Ok(self.system_ram.read_16(addr - SYSTEM_RAM_START))
-            }
-            _ => Err(MemoryError::AccessViolation),
+        match self.ram(addr, priv_level, AccessKind::Read) {
+            Ok((memory, offset)) => Ok(memory.read_16(offset)),
+            Err(err) => self.open_bus.map(|value| value as u16).ok_or(err),
         }
     }
 
@@ -104,18 +256,14 @@ impl MemoryInterface for Mmu<'_> {
         priv_level: PrivilegeLevel,
         reserve: bool,
     ) -> Result<u8, MemoryError> {
+        let addr = self.check_access(addr, 1, priv_level, AccessKind::Read)?;
         if reserve {
             self.reservation.take(addr);
         }
 
-        match addr {
-            KERNEL_RAM_START..=KERNEL_RAM_END if priv_level == PrivilegeLevel::System => {
-                Ok(self.kernel_ram.read_8(addr - KERNEL_RAM_START))
-            }
-            SYSTEM_RAM_START..=SYSTEM_RAM_END => {
-                Ok(self.system_ram.read_8(addr - SYSTEM_RAM_START))
-            }
-            _ => Err(MemoryError::AccessViolation),
+        match self.ram(addr, priv_level, AccessKind::Read) {
+            Ok((memory, offset)) => Ok(memory.read_8(offset)),
+            Err(err) => self.open_bus.map(|value| value as u8).ok_or(err),
         }
     }
 
@@ -130,26 +278,26 @@ impl MemoryInterface for Mmu<'_> {
             return Err(MemoryError::UnalignedAccess);
         }
 
+        let addr = self.check_access(addr, 4, priv_level, AccessKind::Write)?;
         let is_reserved = self.reservation.check_write(addr);
         let do_write = is_reserved | !conditional;
 
-        match addr {
-            KERNEL_RAM_START..=KERNEL_RAM_END if priv_level == PrivilegeLevel::System => {
+        match self.ram(addr, priv_level, AccessKind::Write) {
+            Ok((memory, offset)) => {
                 if do_write {
-                    self.kernel_ram.write_32(addr - KERNEL_RAM_START, value);
+                    memory.write_32(offset, value);
+                    self.invalidate(addr, 4);
                 }
-
-                Ok(do_write)
             }
-            SYSTEM_RAM_START..=SYSTEM_RAM_END => {
-                if do_write {
-                    self.system_ram.write_32(addr - SYSTEM_RAM_START, value);
+            Err(err) => {
+                // otherwise dropped by the open bus
+                if self.open_bus.is_none() {
+                    return Err(err);
                 }
-
-                Ok(do_write)
             }
-            _ => Err(MemoryError::AccessViolation),
         }
+
+        Ok(do_write)
     }
 
     fn write_16(
@@ -163,26 +311,26 @@ impl MemoryInterface for Mmu<'_> {
             return Err(MemoryError::UnalignedAccess);
         }
 
+        let addr = self.check_access(addr, 2, priv_level, AccessKind::Write)?;
         let is_reserved = self.reservation.check_write(addr);
         let do_write = is_reserved | !conditional;
 
-        match addr {
-            KERNEL_RAM_START..=KERNEL_RAM_END if priv_level == PrivilegeLevel::System => {
+        match self.ram(addr, priv_level, AccessKind::Write) {
+            Ok((memory, offset)) => {
                 if do_write {
-                    self.kernel_ram.write_16(addr - KERNEL_RAM_START, value);
+                    memory.write_16(offset, value);
+                    self.invalidate(addr, 2);
                 }
-
-                Ok(do_write)
             }
-            SYSTEM_RAM_START..=SYSTEM_RAM_END => {
-                if do_write {
-                    self.system_ram.write_16(addr - SYSTEM_RAM_START, value);
+            Err(err) => {
+                // otherwise dropped by the open bus
+                if self.open_bus.is_none() {
+                    return Err(err);
                 }
-
-                Ok(do_write)
             }
-            _ => Err(MemoryError::AccessViolation),
         }
+
+        Ok(do_write)
     }
 
     fn write_8(
@@ -192,86 +340,575 @@ impl MemoryInterface for Mmu<'_> {
         priv_level: PrivilegeLevel,
         conditional: bool,
     ) -> Result<bool, MemoryError> {
+        let addr = self.check_access(addr, 1, priv_level, AccessKind::Write)?;
         let is_reserved = self.reservation.check_write(addr);
         let do_write = is_reserved | !conditional;
 
-        match addr {
-            KERNEL_RAM_START..=KERNEL_RAM_END if priv_level == PrivilegeLevel::System => {
+        match self.ram(addr, priv_level, AccessKind::Write) {
+            Ok((memory, offset)) => {
                 if do_write {
-                    self.kernel_ram.write_8(addr - KERNEL_RAM_START, value);
+                    memory.write_8(offset, value);
+                    self.invalidate(addr, 1);
                 }
-
-                Ok(do_write)
             }
-            SYSTEM_RAM_START..=SYSTEM_RAM_END => {
-                if do_write {
-                    self.system_ram.write_8(addr - SYSTEM_RAM_START, value);
+            Err(err) => {
+                // otherwise dropped by the open bus
+                if self.open_bus.is_none() {
+                    return Err(err);
                 }
+            }
+        }
+
+        Ok(do_write)
+    }
+
+    #[inline]
+    fn fetch_16(&mut self, addr: u32, priv_level: PrivilegeLevel) -> Result<u16, MemoryError> {
+        if (addr & 0x1) != 0 {
+            return Err(MemoryError::UnalignedAccess);
+        }
+
+        let addr = self.check_access(addr, 2, priv_level, AccessKind::Execute)?;
+        let (memory, offset) = self.ram(addr, priv_level, AccessKind::Execute)?;
+        Ok(memory.read_16(offset))
+    }
+
+    fn fetch(&mut self, addr: u32, priv_level: PrivilegeLevel) -> Result<Decoded, MemoryError> {
+        self.flush_if_remapped();
+        if self.instruction_cache.is_none() {
+            return fetch_uncached(self, addr, priv_level);
+        }
+
+        // performs the access checks, a hit has the same permissions as the original fetch
+        let physical_addr = self.check_fetch(addr, priv_level)?;
 
-                Ok(do_write)
+        let cache = self.instruction_cache.as_deref_mut().unwrap();
+        if let Some(decoded) = cache.get(physical_addr) {
+            // the upper half may lie in another protection region or RAM
+            if decoded.size == 4 {
+                self.check_fetch(addr.wrapping_add(2), priv_level)?;
             }
-            _ => Err(MemoryError::AccessViolation),
+            return Ok(decoded);
+        }
+
+        let decoded = fetch_uncached(self, addr, priv_level)?;
+        // the upper half of an instruction crossing a page boundary may be mapped anywhere, so
+        // invalidating by physical address would miss writes to it
+        if (physical_addr & (PAGE_SIZE - 1)) + decoded.size <= PAGE_SIZE {
+            self.instruction_cache
+                .as_deref_mut()
+                .unwrap()
+                .insert(physical_addr, decoded);
+        }
+        Ok(decoded)
+    }
+
+    fn flush_instruction_cache(&mut self) {
+        if let Some(cache) = self.instruction_cache.as_deref_mut() {
+            cache.flush();
+        }
+
+        if let Some(cache) = self.block_cache.as_deref_mut() {
+            cache.flush();
+        }
+    }
+
+    #[inline]
+    fn is_remapped(&self) -> bool {
+        self.paging.is_remapped() || self.protection.is_changed()
+    }
+
+    fn fetch_block(&mut self, addr: u32, priv_level: PrivilegeLevel) -> Option<Arc<Block>> {
+        self.block_cache.as_ref()?;
+        self.flush_if_remapped();
+
+        // performs the access checks, a hit has the same permissions as the original fetch
+        let physical_addr = self.check_fetch(addr, priv_level).ok()?;
+
+        let cache = self.block_cache.as_deref_mut().unwrap();
+        if let Some(block) = cache.get(physical_addr, priv_level) {
+            return Some(block);
+        }
+        if !cache.is_hot(physical_addr) {
+            return None;
+        }
+
+        let block = Block::translate(addr, physical_addr, priv_level, |inst_addr| {
+            self.fetch(inst_addr, priv_level).ok()
+        })?;
+        Some(self.block_cache.as_deref_mut().unwrap().insert(block))
+    }
+}
+
+// Port numbers relative to the I/O base address of their device.
+
+const TIMER_LOW_PORT: u32 = 0x0;
+const TIMER_HIGH_PORT: u32 = 0x1;
+const TIMER_ACCURACY_PORT: u32 = 0x2;
+const TIMER_CONTROL_PORT: u32 = 0x3;
+const TIMER_COMPARE_PORT: u32 = 0x4;
+const TIMER_COUNT_PORT: u32 = 0x5;
+const TIMER_STATUS_PORT: u32 = 0x6;
+const TIMER_SLOT_PORT: u32 = 0x7;
+const TIMER_PORTS: u32 = TIMER_SLOT_PORT + 1;
+
+const SERIAL_OUT_DATA_PORT: u32 = 0x0;
+const SERIAL_OUT_COUNT_PORT: u32 = 0x1;
+const SERIAL_IN_DATA_PORT: u32 = 0x2;
+const SERIAL_IN_COUNT_PORT: u32 = 0x3;
+const SERIAL_CONTROL_PORT: u32 = 0x4;
+const SERIAL_SLOT_PORT: u32 = 0x5;
+const SERIAL_STATUS_PORT: u32 = 0x6;
+const SERIAL_PORTS: u32 = SERIAL_STATUS_PORT + 1;
+
+const PAGE_TABLE_BASE_PORT: u32 = 0x0;
+const PAGING_CONTROL_PORT: u32 = 0x1;
+const TLB_FLUSH_PORT: u32 = 0x2;
+const PAGING_PORTS: u32 = TLB_FLUSH_PORT + 1;
+
+const PROTECTION_CONTROL_PORT: u32 = 0x0;
+/// Every region takes four ports, base, size and flags followed by an unused one.
+const REGION_TABLE_START: u32 = 0x8;
+const REGION_TABLE_END: u32 = REGION_TABLE_START + (REGION_COUNT as u32) * 4 - 1;
+const PROTECTION_PORTS: u32 = REGION_TABLE_END + 1;
+
+// The Softcore devices ignore writes to their read-only ports, like the hardware does.
+const SERIAL_CONTROLLER_RX_DATA_PORT: u32 = 0x0;
+const SERIAL_CONTROLLER_TX_DATA_PORT: u32 = 0x1;
+const SERIAL_CONTROLLER_RX_COUNT_PORT: u32 = 0x2;
+const SERIAL_CONTROLLER_TX_COUNT_PORT: u32 = 0x3;
+const SERIAL_CONTROLLER_PORTS: u32 = SERIAL_CONTROLLER_TX_COUNT_PORT + 1;
+
+const VDP_H_OFFSET_PORT: u32 = 0x0;
+const VDP_V_OFFSET_PORT: u32 = 0x1;
+const VDP_H_BLANK_PORT: u32 = 0x2;
+const VDP_V_BLANK_PORT: u32 = 0x3;
+const VDP_PORTS: u32 = VDP_V_BLANK_PORT + 1;
+
+const CYCLE_COUNT_LOW_PORT: u32 = 0x0;
+const CYCLE_COUNT_HIGH_PORT: u32 = 0x1;
+const STALL_COUNT_LOW_PORT: u32 = 0x2;
+const STALL_COUNT_HIGH_PORT: u32 = 0x3;
+const MICROS_LOW_PORT: u32 = 0x4;
+const MICROS_HIGH_PORT: u32 = 0x5;
+const COUNTER_PORTS: u32 = MICROS_HIGH_PORT + 1;
+
+/// Red, green and blue intensity in the low three bytes.
+const LED_MASK: u32 = 0x00FF_FFFF;
+const LED_PORTS: u32 = 1;
+
+const SYSCALL_ADDRESS_PORTS: u32 = 1;
+
+/// Writing zero ends a guest test as passed, anything else as failed.
+const TEST_EXIT_PORTS: u32 = 1;
+
+/// Where the time reported at `TIMER_LOW_PORT`/`TIMER_HIGH_PORT` comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimeSource {
+    /// Wall clock time of the host, guest runs are not reproducible.
+    #[default]
+    Host,
+    /// Every retired instruction takes one period of the nominal clock.
+    Instructions { clock_hz: NonZeroU32 },
+    /// Every cycle takes one period of the nominal clock, the same as `Instructions` without a
+    /// timing model.
+    Cycles { clock_hz: NonZeroU32 },
+}
+
+impl TimeSource {
+    /// Frequency of the nominal clock the ticks are counted in.
+    #[inline]
+    fn clock_hz(self) -> NonZeroU32 {
+        match self {
+            // host time only affects the time ports, the ticks are still cycles
+            Self::Host => SOFTCORE_CLOCK_HZ,
+            Self::Instructions { clock_hz } | Self::Cycles { clock_hz } => clock_hz,
         }
     }
 }
 
-const TIMER_LOW_ADDR: u32 = 0x080;
-const TIMER_HIGH_ADDR: u32 = 0x081;
-const TIMER_ACCURACY_ADDR: u32 = 0x082;
+impl std::str::FromStr for TimeSource {
+    type Err = String;
 
-const SERIAL_OUT_DATA_ADDR: u32 = 0x90;
-const SERIAL_OUT_COUNT_ADDR: u32 = 0x91;
-const SERIAL_IN_DATA_ADDR: u32 = 0x92;
-const SERIAL_IN_COUNT_ADDR: u32 = 0x93;
+    /// `host`, `instructions` or `cycles`, the latter two optionally with the frequency of the
+    /// nominal clock as `:<hz>`. It defaults to the Softcore's clock.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, clock_hz) = match s.split_once(':') {
+            Some((name, clock_hz)) => {
+                let clock_hz = crate::parse_u32(clock_hz)
+                    .ok()
+                    .and_then(NonZeroU32::new)
+                    .ok_or_else(|| format!("invalid clock frequency `{clock_hz}`"))?;
+                (name, Some(clock_hz))
+            }
+            None => (s, None),
+        };
+        let clock_hz = clock_hz.unwrap_or(SOFTCORE_CLOCK_HZ);
+
+        match name {
+            "host" if s == name => Ok(Self::Host),
+            "instructions" => Ok(Self::Instructions { clock_hz }),
+            "cycles" => Ok(Self::Cycles { clock_hz }),
+            _ => Err(format!(
+                "unknown time source `{s}`, expected `host`, `instructions[:<hz>]` or `cycles[:<hz>]`"
+            )),
+        }
+    }
+}
 
 pub struct IoBus<'a> {
+    devices: Devices,
+    open_bus: Option<u32>,
     start_time: &'a std::time::Instant,
-    serial_buffer: &'a mut VecDeque<u8>,
+    time_source: TimeSource,
+    ticks: u64,
+    cycles: u64,
+    stall_cycles: u64,
+    timer: &'a mut Timer,
+    serial: &'a mut Serial,
+    paging: &'a Paging,
+    protection: &'a Protection,
+    vdp: &'a mut Vdp,
+    led: &'a mut u32,
+    syscall_address: &'a mut u32,
+    test_exit: &'a mut Option<EnvAction>,
 }
 
-impl IoInterface for IoBus<'_> {
-    fn read(&mut self, addr: u32, priv_level: PrivilegeLevel) -> Result<u32, IoError> {
-        match addr {
-            TIMER_LOW_ADDR => Ok(self.start_time.elapsed().as_nanos() as u32),
-            TIMER_HIGH_ADDR => Ok((self.start_time.elapsed().as_nanos() >> 32) as u32),
-            TIMER_ACCURACY_ADDR => Ok(1),
+impl IoBus<'_> {
+    fn time_ns(&self) -> u64 {
+        match self.time_source {
+            TimeSource::Host => self.start_time.elapsed().as_nanos() as u64,
+            TimeSource::Instructions { clock_hz } | TimeSource::Cycles { clock_hz } => {
+                (((self.ticks as u128) * 1_000_000_000) / (clock_hz.get() as u128)) as u64
+            }
+        }
+    }
 
-            SERIAL_OUT_DATA_ADDR => Err(IoError::AccessViolation),
-            SERIAL_OUT_COUNT_ADDR => Ok(u32::MAX),
-            SERIAL_IN_DATA_ADDR => Ok(self.serial_buffer.pop_front().unwrap_or(0) as u32),
-            SERIAL_IN_COUNT_ADDR => Ok(self.serial_buffer.len() as u32),
+    fn time_accuracy_ns(&self) -> u32 {
+        match self.time_source {
+            TimeSource::Host => 1,
+            TimeSource::Instructions { clock_hz } | TimeSource::Cycles { clock_hz } => {
+                1_000_000_000u32.div_ceil(clock_hz.get())
+            }
+        }
+    }
 
+    fn read_timer(&mut self, port: u32, priv_level: PrivilegeLevel) -> Result<u32, IoError> {
+        match port {
+            TIMER_LOW_PORT => Ok(self.time_ns() as u32),
+            TIMER_HIGH_PORT => Ok((self.time_ns() >> 32) as u32),
+            TIMER_ACCURACY_PORT => Ok(self.time_accuracy_ns()),
+            TIMER_CONTROL_PORT..=TIMER_SLOT_PORT if priv_level != PrivilegeLevel::System => {
+                Err(IoError::AccessViolation)
+            }
+            TIMER_CONTROL_PORT => Ok(self.timer.control.bits()),
+            TIMER_COMPARE_PORT => Ok(self.timer.compare),
+            TIMER_COUNT_PORT => Ok(self.timer.count),
+            TIMER_STATUS_PORT => Ok(self.timer.matched as u32),
+            TIMER_SLOT_PORT => Ok(self.timer.slot as u32),
             _ => Err(IoError::AccessViolation),
         }
     }
 
-    fn write(&mut self, addr: u32, value: u32, priv_level: PrivilegeLevel) -> Result<(), IoError> {
-        match addr {
-            TIMER_LOW_ADDR => Err(IoError::AccessViolation),
-            TIMER_HIGH_ADDR => Err(IoError::AccessViolation),
-            TIMER_ACCURACY_ADDR => Err(IoError::AccessViolation),
-
-            SERIAL_OUT_DATA_ADDR => {
-                let value = value as u8;
-                if let Some(c) = char::from_u32(value as u32) {
-                    print!("{c}");
+    fn read_serial(&mut self, port: u32, priv_level: PrivilegeLevel) -> Result<u32, IoError> {
+        match port {
+            SERIAL_OUT_DATA_PORT => Err(IoError::AccessViolation),
+            SERIAL_OUT_COUNT_PORT => Ok(self.serial.tx_space()),
+            SERIAL_IN_DATA_PORT => Ok(self.serial.rx_buffer.pop_front().unwrap_or(0) as u32),
+            SERIAL_IN_COUNT_PORT => Ok(self.serial.rx_buffer.len() as u32),
+            SERIAL_CONTROL_PORT..=SERIAL_STATUS_PORT if priv_level != PrivilegeLevel::System => {
+                Err(IoError::AccessViolation)
+            }
+            SERIAL_CONTROL_PORT => Ok(self.serial.control.bits()),
+            SERIAL_SLOT_PORT => Ok(self.serial.slot as u32),
+            SERIAL_STATUS_PORT => Ok(self.serial.status.bits()),
+            _ => Err(IoError::AccessViolation),
+        }
+    }
+
+    fn read_paging(&mut self, port: u32, priv_level: PrivilegeLevel) -> Result<u32, IoError> {
+        match port {
+            PAGE_TABLE_BASE_PORT..=TLB_FLUSH_PORT if priv_level != PrivilegeLevel::System => {
+                Err(IoError::AccessViolation)
+            }
+            PAGE_TABLE_BASE_PORT => Ok(self.paging.base()),
+            PAGING_CONTROL_PORT => Ok(self.paging.control().bits()),
+            TLB_FLUSH_PORT => Err(IoError::AccessViolation),
+            _ => Err(IoError::AccessViolation),
+        }
+    }
+
+    fn read_protection(&mut self, port: u32, priv_level: PrivilegeLevel) -> Result<u32, IoError> {
+        match port {
+            PROTECTION_CONTROL_PORT | REGION_TABLE_START..=REGION_TABLE_END
+                if priv_level != PrivilegeLevel::System =>
+            {
+                Err(IoError::AccessViolation)
+            }
+            PROTECTION_CONTROL_PORT => Ok(self.protection.control().bits()),
+            REGION_TABLE_START..=REGION_TABLE_END => {
+                let region = self
+                    .protection
+                    .region(((port - REGION_TABLE_START) / 4) as usize);
+                match port % 4 {
+                    0 => Ok(region.base),
+                    1 => Ok(region.size),
+                    2 => Ok(region.flags.bits()),
+                    _ => Err(IoError::AccessViolation),
+                }
+            }
+            _ => Err(IoError::AccessViolation),
+        }
+    }
+
+    fn read_serial_controller(&mut self, port: u32) -> Result<u32, IoError> {
+        match port {
+            SERIAL_CONTROLLER_RX_DATA_PORT => {
+                Ok(self.serial.rx_buffer.pop_front().unwrap_or(0) as u32)
+            }
+            SERIAL_CONTROLLER_TX_DATA_PORT => Ok(0),
+            SERIAL_CONTROLLER_RX_COUNT_PORT => Ok(self.serial.rx_buffer.len() as u32),
+            SERIAL_CONTROLLER_TX_COUNT_PORT => Ok(self.serial.tx_count()),
+            _ => Err(IoError::AccessViolation),
+        }
+    }
+
+    fn read_vdp(&mut self, port: u32) -> Result<u32, IoError> {
+        let (h_blank, v_blank) = Vdp::blanking(self.ticks);
+        match port {
+            VDP_H_OFFSET_PORT => Ok(self.vdp.h_offset()),
+            VDP_V_OFFSET_PORT => Ok(self.vdp.v_offset()),
+            VDP_H_BLANK_PORT => Ok(h_blank as u32),
+            VDP_V_BLANK_PORT => Ok(v_blank as u32),
+            _ => Err(IoError::AccessViolation),
+        }
+    }
+
+    fn read_counters(&mut self, port: u32) -> Result<u32, IoError> {
+        match port {
+            CYCLE_COUNT_LOW_PORT => Ok(self.cycles as u32),
+            CYCLE_COUNT_HIGH_PORT => Ok((self.cycles >> 32) as u32),
+            STALL_COUNT_LOW_PORT => Ok(self.stall_cycles as u32),
+            STALL_COUNT_HIGH_PORT => Ok((self.stall_cycles >> 32) as u32),
+            MICROS_LOW_PORT => Ok((self.time_ns() / 1000) as u32),
+            MICROS_HIGH_PORT => Ok(((self.time_ns() / 1000) >> 32) as u32),
+            _ => Err(IoError::AccessViolation),
+        }
+    }
+
+    fn read_syscall_address(&mut self, priv_level: PrivilegeLevel) -> Result<u32, IoError> {
+        match priv_level {
+            PrivilegeLevel::System => Ok(*self.syscall_address),
+            PrivilegeLevel::User => self.open_bus.ok_or(IoError::AccessViolation),
+        }
+    }
+
+    fn write_timer(
+        &mut self,
+        port: u32,
+        value: u32,
+        priv_level: PrivilegeLevel,
+    ) -> Result<(), IoError> {
+        match port {
+            TIMER_LOW_PORT => Err(IoError::AccessViolation),
+            TIMER_HIGH_PORT => Err(IoError::AccessViolation),
+            TIMER_ACCURACY_PORT => Err(IoError::AccessViolation),
+            TIMER_CONTROL_PORT..=TIMER_SLOT_PORT if priv_level != PrivilegeLevel::System => {
+                Err(IoError::AccessViolation)
+            }
+            TIMER_CONTROL_PORT => {
+                self.timer.control = TimerControl::from_bits_truncate(value);
+                Ok(())
+            }
+            TIMER_COMPARE_PORT => {
+                self.timer.compare = value;
+                Ok(())
+            }
+            TIMER_COUNT_PORT => {
+                self.timer.count = value;
+                Ok(())
+            }
+            TIMER_STATUS_PORT => {
+                // write one to clear
+                if (value & 0x1) != 0 {
+                    self.timer.matched = false;
                 }
                 Ok(())
             }
-            SERIAL_OUT_COUNT_ADDR => Err(IoError::AccessViolation),
-            SERIAL_IN_DATA_ADDR => Err(IoError::AccessViolation),
-            SERIAL_IN_COUNT_ADDR => Err(IoError::AccessViolation),
+            TIMER_SLOT_PORT => {
+                self.timer.slot = (value & 0xF) as u8;
+                Ok(())
+            }
+            _ => Err(IoError::AccessViolation),
+        }
+    }
+
+    fn write_serial(
+        &mut self,
+        port: u32,
+        value: u32,
+        priv_level: PrivilegeLevel,
+    ) -> Result<(), IoError> {
+        match port {
+            SERIAL_OUT_DATA_PORT => {
+                self.serial.transmit(value as u8);
+                Ok(())
+            }
+            SERIAL_OUT_COUNT_PORT => Err(IoError::AccessViolation),
+            SERIAL_IN_DATA_PORT => Err(IoError::AccessViolation),
+            SERIAL_IN_COUNT_PORT => Err(IoError::AccessViolation),
+            SERIAL_CONTROL_PORT..=SERIAL_STATUS_PORT if priv_level != PrivilegeLevel::System => {
+                Err(IoError::AccessViolation)
+            }
+            SERIAL_CONTROL_PORT => {
+                self.serial.control = SerialControl::from_bits_truncate(value);
+                Ok(())
+            }
+            SERIAL_SLOT_PORT => {
+                self.serial.slot = (value & 0xF) as u8;
+                Ok(())
+            }
+            SERIAL_STATUS_PORT => {
+                // write one to clear
+                self.serial.status &= !SerialStatus::from_bits_truncate(value);
+                Ok(())
+            }
+            _ => Err(IoError::AccessViolation),
+        }
+    }
+
+    fn write_paging(
+        &mut self,
+        port: u32,
+        value: u32,
+        priv_level: PrivilegeLevel,
+    ) -> Result<(), IoError> {
+        match port {
+            PAGE_TABLE_BASE_PORT..=TLB_FLUSH_PORT if priv_level != PrivilegeLevel::System => {
+                Err(IoError::AccessViolation)
+            }
+            PAGE_TABLE_BASE_PORT => {
+                self.paging.set_base(value);
+                Ok(())
+            }
+            PAGING_CONTROL_PORT => {
+                self.paging
+                    .set_control(PagingControl::from_bits_truncate(value));
+                Ok(())
+            }
+            TLB_FLUSH_PORT => {
+                // the value is ignored, the whole TLB gets flushed
+                self.paging.flush();
+                Ok(())
+            }
+            _ => Err(IoError::AccessViolation),
+        }
+    }
 
+    fn write_protection(
+        &mut self,
+        port: u32,
+        value: u32,
+        priv_level: PrivilegeLevel,
+    ) -> Result<(), IoError> {
+        match port {
+            PROTECTION_CONTROL_PORT | REGION_TABLE_START..=REGION_TABLE_END
+                if priv_level != PrivilegeLevel::System =>
+            {
+                Err(IoError::AccessViolation)
+            }
+            PROTECTION_CONTROL_PORT => {
+                self.protection
+                    .set_control(ProtectionControl::from_bits_truncate(value));
+                Ok(())
+            }
+            REGION_TABLE_START..=REGION_TABLE_END => {
+                let index = ((port - REGION_TABLE_START) / 4) as usize;
+                let mut region = self.protection.region(index);
+                match port % 4 {
+                    0 => region.base = value,
+                    1 => region.size = value,
+                    2 => region.flags = RegionFlags::from_bits_truncate(value),
+                    _ => return Err(IoError::AccessViolation),
+                }
+                self.protection.set_region(index, region);
+                Ok(())
+            }
             _ => Err(IoError::AccessViolation),
         }
     }
 }
 
+impl IoInterface for IoBus<'_> {
+    fn read(&mut self, addr: u32, priv_level: PrivilegeLevel) -> Result<u32, IoError> {
+        match self.devices.decode(addr) {
+            Some((Device::Timer, port)) => self.read_timer(port, priv_level),
+            Some((Device::Serial, port)) => self.read_serial(port, priv_level),
+            Some((Device::Paging, port)) => self.read_paging(port, priv_level),
+            Some((Device::Protection, port)) => self.read_protection(port, priv_level),
+            Some((Device::SerialController, port)) => self.read_serial_controller(port),
+            Some((Device::Vdp, port)) => self.read_vdp(port),
+            Some((Device::Counters, port)) => self.read_counters(port),
+            Some((Device::Led, _)) => Ok(*self.led),
+            Some((Device::SyscallAddress, _)) => self.read_syscall_address(priv_level),
+            Some((Device::TestExit, _)) => Err(IoError::AccessViolation),
+            None => self.open_bus.ok_or(IoError::AccessViolation),
+        }
+    }
+
+    fn write(&mut self, addr: u32, value: u32, priv_level: PrivilegeLevel) -> Result<(), IoError> {
+        match self.devices.decode(addr) {
+            Some((Device::Timer, port)) => self.write_timer(port, value, priv_level),
+            Some((Device::Serial, port)) => self.write_serial(port, value, priv_level),
+            Some((Device::Paging, port)) => self.write_paging(port, value, priv_level),
+            Some((Device::Protection, port)) => self.write_protection(port, value, priv_level),
+            Some((Device::SerialController, SERIAL_CONTROLLER_TX_DATA_PORT)) => {
+                self.serial.transmit(value as u8);
+                Ok(())
+            }
+            Some((Device::Vdp, VDP_H_OFFSET_PORT)) => {
+                self.vdp.set_h_offset(value);
+                Ok(())
+            }
+            Some((Device::Vdp, VDP_V_OFFSET_PORT)) => {
+                self.vdp.set_v_offset(value);
+                Ok(())
+            }
+            Some((Device::Led, _)) => {
+                *self.led = value & LED_MASK;
+                Ok(())
+            }
+            Some((Device::SyscallAddress, _)) => {
+                // the hardware only latches it in kernel mode
+                if priv_level == PrivilegeLevel::System {
+                    *self.syscall_address = value & !0x3;
+                }
+                Ok(())
+            }
+            Some((Device::TestExit, _)) => {
+                *self.test_exit = Some(if value == 0 {
+                    EnvAction::Pass
+                } else {
+                    EnvAction::Fail
+                });
+                Ok(())
+            }
+            Some((Device::SerialController | Device::Vdp | Device::Counters, _)) => Ok(()),
+            None if self.open_bus.is_some() => Ok(()),
+            None => Err(IoError::AccessViolation),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EnvAction {
     Break,
     Reset,
     Error,
+    /// The guest test succeeded.
+    Pass,
+    /// The guest test failed.
+    Fail,
 }
 
 impl EnvAction {
@@ -280,41 +917,360 @@ impl EnvAction {
             0 => Some(Self::Break),
             1 => Some(Self::Reset),
             2 => Some(Self::Error),
+            3 => Some(Self::Pass),
+            4 => Some(Self::Fail),
             _ => None,
         }
     }
 }
 
+/// Limits of `Art32::run`, a run without any is only stopped by the guest.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RunBudget {
+    pub instructions: Option<u64>,
+    /// Cycles of the timing model, retired instructions without one.
+    pub cycles: Option<u64>,
+    /// Host time.
+    pub time: Option<std::time::Duration>,
+}
+
+/// Why `Art32::run` stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunExit {
+    /// Any environment call except `Reset`, or a write to the test exit port.
+    Env(EnvAction),
+    MachineCheck(MachineCheck),
+    BudgetExhausted,
+}
+
 pub struct Art32 {
     cpu: Cpu,
-    kernel_ram: Memory,
-    system_ram: Memory,
+    memory: Vec<Ram>,
+    /// Index of the RAM the kernel image gets restored to on reset.
+    kernel_ram: usize,
+    devices: Devices,
+    open_bus: Option<u32>,
     start_time: std::time::Instant,
-    serial_buffer: VecDeque<u8>,
+    time_source: TimeSource,
+    timer: Timer,
+    serial: Serial,
+    paging: Paging,
+    protection: Protection,
+    vdp: Vdp,
+    led: u32,
+    syscall_address: u32,
+    test_exit: Option<EnvAction>,
     reservation: Reservation,
+    kernel: Box<[u8]>,
+    instruction_cache: Option<InstructionCache>,
+    block_cache: Option<BlockCache>,
+}
+
+impl Default for Art32 {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Art32 {
     pub fn new() -> Self {
-        let mut kernel_ram = Memory::new(KERNEL_RAM_SIZE);
-        kernel_ram.reset(KERNEL);
+        Self::with_kernel(KERNEL)
+    }
 
-        Self {
-            cpu: Cpu::new(),
+    pub fn with_kernel(kernel: &[u8]) -> Self {
+        Self::with_machine(&MachineDescription::art32(), kernel)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Builds the board described by `machine`, with `kernel` loaded into its kernel region.
+    pub fn with_machine(machine: &MachineDescription, kernel: &[u8]) -> Result<Self, MachineError> {
+        machine.validate()?;
+
+        let kernel_ram = machine.kernel_index();
+        let kernel_region = &machine.memory[kernel_ram];
+        if kernel.len() > (kernel_region.size as usize) {
+            return Err(MachineError::Invalid(format!(
+                "kernel image does not fit into `{}`",
+                kernel_region.name
+            )));
+        }
+
+        let mut memory: Vec<_> = machine.memory.iter().map(Ram::new).collect();
+        memory[kernel_ram].memory.reset(kernel);
+
+        let mut cpu = Cpu::new();
+        cpu.set_reset_program_counter(machine.boot_pc);
+        cpu.set_port_base(machine.cpu_ports);
+        cpu.reset();
+        cpu.set_timing_model(machine.timing_model.then(TimingModel::default));
+
+        Ok(Self {
+            cpu,
+            memory,
             kernel_ram,
-            system_ram: Memory::new(SYSTEM_RAM_SIZE),
+            devices: machine.devices,
+            open_bus: machine.open_bus,
             start_time: std::time::Instant::now(),
-            serial_buffer: VecDeque::new(),
+            time_source: machine.time_source,
+            timer: Timer::default(),
+            serial: Serial::with_output(HostOutput::stdout()),
+            paging: Paging::default(),
+            protection: Protection::default(),
+            vdp: Vdp::default(),
+            led: 0,
+            syscall_address: 0,
+            test_exit: None,
             reservation: Default::default(),
-        }
+            kernel: kernel.into(),
+            instruction_cache: Some(InstructionCache::new()),
+            block_cache: None,
+        })
     }
 
     pub fn reset(&mut self) {
         self.cpu.reset();
-        self.kernel_ram.reset(KERNEL);
+        self.memory[self.kernel_ram].memory.reset(&self.kernel);
         self.reservation.reset();
+        self.timer.reset();
+        self.serial.reset();
+        self.paging.reset();
+        self.protection.reset();
+        self.vdp.reset();
+        self.led = 0;
+        self.syscall_address = 0;
+        self.test_exit = None;
+        if let Some(cache) = &mut self.instruction_cache {
+            cache.flush();
+        }
+        if let Some(cache) = &mut self.block_cache {
+            cache.flush();
+        }
+    }
+
+    /// Writes the segments of `image` into RAM, every segment has to be inside a single region.
+    ///
+    /// Segments in the kernel region become part of the kernel image and are restored on every
+    /// reset, the other regions keep them until the guest overwrites them.
+    pub fn load_image(&mut self, image: &Image) -> Result<(), ImageError> {
+        let indices = image
+            .segments
+            .iter()
+            .map(|segment| {
+                let last = segment.last();
+                self.memory
+                    .iter()
+                    .position(|ram| {
+                        ram.offset(segment.addr).is_some()
+                            && last.and_then(|last| ram.offset(last)).is_some()
+                    })
+                    .ok_or_else(|| {
+                        ImageError::Placement(format!(
+                            "{} bytes at 0x{:0>8X} are not inside a single memory region",
+                            segment.data.len(),
+                            segment.addr
+                        ))
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (segment, index) in image.segments.iter().zip(indices) {
+            let ram = &mut self.memory[index];
+            let start = segment.addr - ram.start;
+            for (offset, &byte) in (start..).zip(&segment.data) {
+                ram.memory.write_8(offset, byte);
+            }
+
+            if index == self.kernel_ram {
+                let start = start as usize;
+                let end = start + segment.data.len();
+                let mut kernel = std::mem::take(&mut self.kernel).into_vec();
+                if kernel.len() < end {
+                    kernel.resize(end, 0);
+                }
+                kernel[start..end].copy_from_slice(&segment.data);
+                self.kernel = kernel.into_boxed_slice();
+            }
+        }
+
+        if let Some(cache) = &mut self.instruction_cache {
+            cache.flush();
+        }
+        if let Some(cache) = &mut self.block_cache {
+            cache.flush();
+        }
+        Ok(())
+    }
+
+    pub fn set_instruction_cache(&mut self, enabled: bool) {
+        if enabled != self.instruction_cache.is_some() {
+            self.instruction_cache = enabled.then(InstructionCache::new);
+        }
+    }
+
+    /// Switches between executing translated basic blocks and interpreting single instructions.
+    /// With `Some(threshold)`, code is interpreted until execution reached the start of a block
+    /// `threshold` times, see `TRANSLATION_THRESHOLD`.
+    pub fn set_block_translation(&mut self, threshold: Option<u32>) {
+        self.block_cache = threshold.map(BlockCache::new);
+    }
+
+    pub fn set_timing_model(&mut self, model: Option<TimingModel>) {
+        self.cpu.set_timing_model(model);
+    }
+
+    /// Selects the guest visible time. With emulated time the timer device counts the same
+    /// ticks, so identical inputs give identical runs.
+    pub fn set_time_source(&mut self, time_source: TimeSource) {
+        self.time_source = time_source;
+    }
+
+    /// Connects the serial receiver to a host source, replacing the previous one.
+    pub fn set_serial_input(&mut self, input: Option<SerialInput>) -> std::io::Result<()> {
+        self.serial.input = None;
+        self.serial.input = input.as_ref().map(HostInput::open).transpose()?;
+        Ok(())
+    }
+
+    /// The PTY path or TCP address of the serial input, if it has one.
+    pub fn serial_input_endpoint(&self) -> Option<&str> {
+        self.serial.input.as_ref()?.endpoint()
+    }
+
+    /// Connects the serial transmitter to a host destination, replacing the previous one.
+    ///
+    /// Without one, transmitted bytes are dropped. A PTY or TCP destination shares the
+    /// connection of the serial input if that uses the same endpoint, so connect the input first.
+    pub fn set_serial_output(&mut self, output: Option<SerialOutput>) -> std::io::Result<()> {
+        self.serial.output = None;
+        self.serial.output = output
+            .map(|output| HostOutput::open(&output, self.serial.input.as_ref()))
+            .transpose()?;
+        Ok(())
+    }
+
+    /// The PTY path or TCP address of the serial output, if it has one.
+    pub fn serial_output_endpoint(&self) -> Option<&str> {
+        self.serial.output.as_ref()?.endpoint()
+    }
+
+    /// Takes the bytes transmitted so far when the serial output is a `SerialOutput::Buffer`.
+    pub fn take_serial_output(&mut self) -> Vec<u8> {
+        self.serial
+            .output
+            .as_mut()
+            .map(HostOutput::take_buffer)
+            .unwrap_or_default()
+    }
+
+    /// Writes serial output the stdout destination still collects, for when the guest stops.
+    pub fn flush_serial_output(&mut self) {
+        if let Some(output) = &mut self.serial.output {
+            output.flush();
+        }
+    }
+
+    /// Models the FIFO depths and byte times of the UART, instead of transferring every byte
+    /// instantly. Byte times are counted in ticks of the time source's nominal clock.
+    pub fn set_serial_timing(&mut self, timing: Option<SerialTiming>) {
+        self.serial.set_timing(timing);
+    }
+
+    /// Makes `bytes` available to the serial receiver, as if they came from the host source.
+    pub fn push_serial_input(&mut self, bytes: &[u8]) {
+        self.serial.push(bytes);
+    }
+
+    /// Emulated time in periods of the nominal clock.
+    #[inline]
+    fn ticks(&self) -> u64 {
+        match self.time_source {
+            TimeSource::Instructions { .. } => self.cpu.retired_instructions(),
+            TimeSource::Host | TimeSource::Cycles { .. } => self.cpu.cycles(),
+        }
+    }
+
+    #[inline]
+    pub fn retired_instructions(&self) -> u64 {
+        self.cpu.retired_instructions()
+    }
+
+    #[inline]
+    pub fn cycles(&self) -> u64 {
+        self.cpu.cycles()
+    }
+
+    #[inline]
+    pub fn stall_cycles(&self) -> u64 {
+        self.cpu.stall_cycles()
+    }
+
+    /// Red, green and blue intensity of the Softcore LED in the low three bytes.
+    #[inline]
+    pub fn led(&self) -> u32 {
+        self.led
+    }
+
+    #[inline]
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    #[inline]
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    /// Finds the RAM backing the virtual address `addr`, for debuggers. Returns the physical
+    /// address, its memory and the offset into it. Translation uses the TLB without filling it,
+    /// permissions are not checked.
+    fn debug_ram(&mut self, addr: u32) -> Option<(u32, &mut Memory, u32)> {
+        let memory = &self.memory;
+        let addr = self.paging.peek(addr, |addr| {
+            memory
+                .iter()
+                .find_map(|ram| Some(ram.memory.read_32(ram.offset(addr)?)))
+        })?;
+
+        self.memory.iter_mut().find_map(|ram| {
+            let offset = ram.offset(addr)?;
+            Some((addr, &mut ram.memory, offset))
+        })
+    }
+
+    /// Reads memory the way the kernel sees it, for debuggers. The guest's reservation and TLB
+    /// are left alone. Returns the number of bytes read before the first one not backed by RAM.
+    pub fn read_memory(&mut self, addr: u32, bytes: &mut [u8]) -> usize {
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            match self.debug_ram(addr.wrapping_add(i as u32)) {
+                Some((_, memory, offset)) => *byte = memory.read_8(offset),
+                None => return i,
+            }
+        }
+        bytes.len()
+    }
+
+    /// Writes memory the way the kernel sees it, for debuggers. The guest's reservation and TLB
+    /// are left alone, translated code is dropped. Returns the number of bytes written before the
+    /// first one not backed by RAM.
+    pub fn write_memory(&mut self, addr: u32, bytes: &[u8]) -> usize {
+        for (i, &byte) in bytes.iter().enumerate() {
+            let Some((physical, memory, offset)) = self.debug_ram(addr.wrapping_add(i as u32))
+            else {
+                return i;
+            };
+            memory.write_8(offset, byte);
+
+            if let Some(cache) = self.instruction_cache.as_mut() {
+                cache.invalidate(physical, 1);
+            }
+            if let Some(cache) = self.block_cache.as_mut() {
+                cache.invalidate(physical, 1);
+            }
+        }
+        bytes.len()
     }
 
+    #[cfg(feature = "display")]
     pub fn draw_debug_info(
         &self,
         wgpu_state: &crate::display::WgpuState,
@@ -326,20 +1282,118 @@ impl Art32 {
             .draw_debug_info(wgpu_state, render_target, encoder, text_renderer);
     }
 
-    pub fn step(&mut self) -> Option<EnvAction> {
+    /// Executes the next instruction, returning the environment call it made if any.
+    ///
+    /// After a machine check the CPU is halted with its state intact, every further step
+    /// reports the same machine check until the system gets reset.
+    pub fn step(&mut self) -> Result<Option<EnvAction>, MachineCheck> {
+        self.step_limited(usize::MAX)
+    }
+
+    /// Like `step`, but a translated block retires at most `max_instructions`.
+    fn step_limited(&mut self, max_instructions: usize) -> Result<Option<EnvAction>, MachineCheck> {
+        let start_ticks = self.ticks();
         let mut mmu = Mmu {
-            kernel_ram: &mut self.kernel_ram,
-            system_ram: &mut self.system_ram,
+            memory: &mut self.memory,
+            paging: &self.paging,
+            protection: &self.protection,
             reservation: &mut self.reservation,
+            instruction_cache: self.instruction_cache.as_mut(),
+            block_cache: self.block_cache.as_mut(),
+            open_bus: self.open_bus,
         };
 
         let mut io_bus = IoBus {
+            devices: self.devices,
+            open_bus: self.open_bus,
             start_time: &self.start_time,
-            serial_buffer: &mut self.serial_buffer,
+            time_source: self.time_source,
+            ticks: start_ticks,
+            cycles: self.cpu.cycles(),
+            stall_cycles: self.cpu.stall_cycles(),
+            timer: &mut self.timer,
+            serial: &mut self.serial,
+            paging: &self.paging,
+            protection: &self.protection,
+            vdp: &mut self.vdp,
+            led: &mut self.led,
+            syscall_address: &mut self.syscall_address,
+            test_exit: &mut self.test_exit,
         };
 
-        self.cpu
-            .step(&mut mmu, &mut io_bus)
-            .and_then(EnvAction::new)
+        let code = if mmu.block_cache.is_some() {
+            self.cpu.step_block(&mut mmu, &mut io_bus, max_instructions)
+        } else {
+            self.cpu.step(&mut mmu, &mut io_bus)
+        };
+
+        let ticks = self.ticks() - start_ticks;
+        if let Some(slot) = self.timer.advance(ticks) {
+            self.cpu.signal_interrupt(slot);
+        }
+
+        if let Some(slot) = self.serial.poll(ticks, self.time_source.clock_hz().get()) {
+            self.cpu.signal_interrupt(slot);
+        }
+
+        match self.cpu.machine_check() {
+            Some(machine_check) => Err(machine_check),
+            None => Ok(code.and_then(EnvAction::new).or(self.test_exit.take())),
+        }
+    }
+
+    /// Steps until the guest breaks, fails or halts or the budget runs out. Resets requested by
+    /// the guest are carried out on the way.
+    pub fn run(&mut self, budget: RunBudget) -> RunExit {
+        // checking the host time on every step would dominate small steps, a power of two
+        const TIME_CHECK_INTERVAL: u64 = 0x1000;
+
+        let start = std::time::Instant::now();
+        let mut steps = 0u64;
+        let mut instructions = 0u64;
+        let mut cycles = 0u64;
+        loop {
+            if let Some(time) = budget.time {
+                if ((steps & (TIME_CHECK_INTERVAL - 1)) == 0) && (start.elapsed() >= time) {
+                    return RunExit::BudgetExhausted;
+                }
+            }
+            if budget
+                .instructions
+                .is_some_and(|budget| instructions >= budget)
+            {
+                return RunExit::BudgetExhausted;
+            }
+            if budget.cycles.is_some_and(|budget| cycles >= budget) {
+                return RunExit::BudgetExhausted;
+            }
+
+            // every instruction takes at least one cycle, so blocks stop at either budget
+            let remaining = [
+                budget.instructions.map(|budget| budget - instructions),
+                budget.cycles.map(|budget| budget - cycles),
+            ];
+            let max_instructions = remaining
+                .into_iter()
+                .flatten()
+                .min()
+                .map_or(usize::MAX, |remaining| {
+                    usize::try_from(remaining).unwrap_or(usize::MAX)
+                });
+
+            let (retired, elapsed) = (self.retired_instructions(), self.cycles());
+            let action = self.step_limited(max_instructions);
+            steps += 1;
+            // a reset restarts the counts
+            instructions += self.retired_instructions().saturating_sub(retired);
+            cycles += self.cycles().saturating_sub(elapsed);
+
+            match action {
+                Ok(Some(EnvAction::Reset)) => self.reset(),
+                Ok(Some(action)) => return RunExit::Env(action),
+                Ok(None) => {}
+                Err(machine_check) => return RunExit::MachineCheck(machine_check),
+            }
+        }
     }
 }
@@ -0,0 +1,350 @@
+use super::MemoryRegion;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const ELF_MAGIC: &[u8; 4] = b"\x7FELF";
+const ELF_CLASS_32: u8 = 1;
+const ELF_DATA_LE: u8 = 1;
+const ELF_HEADER_SIZE: usize = 0x34;
+const ELF_PROGRAM_HEADER_SIZE: usize = 0x20;
+const PT_LOAD: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// Plain bytes without addresses, loaded at an address given by the user.
+    Raw,
+    IntelHex,
+    /// A 32 bit little endian ELF file, its loadable segments go to their physical addresses.
+    Elf,
+}
+
+impl ImageFormat {
+    /// Recognizes ELF files by their magic and Intel HEX files by their extension, everything
+    /// else is a raw image.
+    pub fn detect(path: &Path, bytes: &[u8]) -> Self {
+        let extension = path.extension().and_then(|ext| ext.to_str());
+        if bytes.starts_with(ELF_MAGIC) {
+            Self::Elf
+        } else if matches!(extension, Some("hex" | "ihex")) {
+            Self::IntelHex
+        } else {
+            Self::Raw
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ImageError {
+    Io(std::io::Error),
+    /// The file is not a valid image of its format.
+    Format(String),
+    /// The image does not fit into the memory of the machine.
+    Placement(String),
+}
+
+impl std::fmt::Display for ImageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "cannot read image: {err}"),
+            Self::Format(msg) => write!(f, "invalid image: {msg}"),
+            Self::Placement(msg) => write!(f, "cannot place image: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Format(_) | Self::Placement(_) => None,
+        }
+    }
+}
+
+/// Contiguous bytes of an image and the physical address they are loaded at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub addr: u32,
+    pub data: Vec<u8>,
+}
+
+impl Segment {
+    /// Address of the last byte, `None` if the segment is empty or wraps.
+    #[inline]
+    pub(super) fn last(&self) -> Option<u32> {
+        let len = u32::try_from(self.data.len()).ok()?;
+        self.addr.checked_add(len.checked_sub(1)?)
+    }
+
+    fn check_inside(&self, region: &MemoryRegion) -> Result<(), ImageError> {
+        if fits_into(region, self.addr, self.data.len()) {
+            Ok(())
+        } else {
+            Err(ImageError::Placement(format!(
+                "{} bytes at 0x{:0>8X} do not fit into `{}`",
+                self.data.len(),
+                self.addr,
+                region.name
+            )))
+        }
+    }
+}
+
+/// Whether `len` bytes at `addr` are inside of `region`, an empty range fits nowhere.
+fn fits_into(region: &MemoryRegion, addr: u32, len: usize) -> bool {
+    let Some(last) = u32::try_from(len)
+        .ok()
+        .and_then(|len| len.checked_sub(1))
+        .and_then(|len| addr.checked_add(len))
+    else {
+        return false;
+    };
+    let region_last = region.base + (region.size - 1);
+    (addr >= region.base) && (last <= region_last)
+}
+
+/// A guest program or data, made of the segments it loads into memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub segments: Vec<Segment>,
+}
+
+impl Image {
+    pub fn raw(addr: u32, bytes: &[u8]) -> Self {
+        Self {
+            segments: vec![Segment {
+                addr,
+                data: bytes.to_vec(),
+            }],
+        }
+    }
+
+    /// Parses `bytes` in `format`, raw images are placed at `addr` which the other formats ignore.
+    ///
+    /// The zero initialized part of ELF segments is only allocated once the segment is known to
+    /// be inside one of the `memory` regions it can be loaded into.
+    pub fn parse(
+        bytes: &[u8],
+        format: ImageFormat,
+        addr: Option<u32>,
+        memory: &[MemoryRegion],
+    ) -> Result<Self, ImageError> {
+        let image = match format {
+            ImageFormat::Raw => match addr {
+                Some(addr) => Self::raw(addr, bytes),
+                None => {
+                    return Err(ImageError::Format(
+                        "a raw image needs a load address".to_owned(),
+                    ))
+                }
+            },
+            ImageFormat::IntelHex => Self::parse_intel_hex(bytes)?,
+            ImageFormat::Elf => Self::parse_elf(bytes, memory)?,
+        };
+
+        for segment in &image.segments {
+            if segment.last().is_none() {
+                return Err(ImageError::Format(format!(
+                    "segment at 0x{:0>8X} is empty or wraps",
+                    segment.addr
+                )));
+            }
+        }
+        Ok(image)
+    }
+
+    pub fn load(
+        path: impl AsRef<Path>,
+        addr: Option<u32>,
+        memory: &[MemoryRegion],
+    ) -> Result<Self, ImageError> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).map_err(ImageError::Io)?;
+        Self::parse(&bytes, ImageFormat::detect(path, &bytes), addr, memory)
+    }
+
+    fn parse_intel_hex(bytes: &[u8]) -> Result<Self, ImageError> {
+        let text = std::str::from_utf8(bytes)
+            .map_err(|_| ImageError::Format("Intel HEX file is not text".to_owned()))?;
+
+        let mut segments: Vec<Segment> = Vec::new();
+        let mut base = 0u32;
+        for (i, line) in text.lines().enumerate() {
+            let invalid = |msg: &str| ImageError::Format(format!("line {}: {msg}", i + 1));
+
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some(hex) = line.strip_prefix(':') else {
+                return Err(invalid("record does not start with `:`"));
+            };
+            // slicing the digits below needs single byte characters
+            if !hex.is_ascii() {
+                return Err(invalid("record is not hexadecimal"));
+            }
+            if ((hex.len() & 0x1) != 0) || (hex.len() < 10) {
+                return Err(invalid("truncated record"));
+            }
+            let record = (0..hex.len())
+                .step_by(2)
+                .map(|j| u8::from_str_radix(&hex[j..(j + 2)], 16))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| invalid("record is not hexadecimal"))?;
+
+            let (len, offset, kind) = (record[0] as usize, &record[1..3], record[3]);
+            if record.len() != (len + 5) {
+                return Err(invalid("record length does not match its byte count"));
+            }
+            if record.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0 {
+                return Err(invalid("checksum mismatch"));
+            }
+            let data = &record[4..(4 + len)];
+
+            match kind {
+                0x00 => {
+                    let addr = base.wrapping_add(u16::from_be_bytes([offset[0], offset[1]]) as u32);
+                    match segments.last_mut() {
+                        Some(segment)
+                            if segment.addr.wrapping_add(segment.data.len() as u32) == addr =>
+                        {
+                            segment.data.extend_from_slice(data)
+                        }
+                        _ => segments.push(Segment {
+                            addr,
+                            data: data.to_vec(),
+                        }),
+                    }
+                }
+                0x01 => break,
+                0x02 if len == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4,
+                0x04 if len == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16,
+                // start addresses, execution starts at the boot PC of the machine
+                0x03 | 0x05 => {}
+                _ => return Err(invalid(&format!("unsupported record of type {kind:0>2X}"))),
+            }
+        }
+
+        segments.retain(|segment| !segment.data.is_empty());
+        Ok(Self { segments })
+    }
+
+    fn parse_elf(bytes: &[u8], memory: &[MemoryRegion]) -> Result<Self, ImageError> {
+        let invalid = |msg: &str| ImageError::Format(format!("ELF file {msg}"));
+        let read_u16 = |offset: usize| {
+            let bytes = bytes
+                .get(offset..(offset + 2))
+                .ok_or(invalid("is truncated"))?;
+            Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+        };
+        let read_u32 = |offset: usize| {
+            let bytes = bytes
+                .get(offset..(offset + 4))
+                .ok_or(invalid("is truncated"))?;
+            Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        };
+
+        if bytes.len() < ELF_HEADER_SIZE {
+            return Err(invalid("is truncated"));
+        }
+        if (bytes[4] != ELF_CLASS_32) || (bytes[5] != ELF_DATA_LE) {
+            return Err(invalid("is not 32 bit little endian"));
+        }
+
+        let ph_offset = read_u32(0x1C)? as usize;
+        let ph_size = read_u16(0x2A)? as usize;
+        let ph_count = read_u16(0x2C)? as usize;
+        if (ph_count > 0) && (ph_size < ELF_PROGRAM_HEADER_SIZE) {
+            return Err(invalid("has invalid program headers"));
+        }
+
+        let mut segments = Vec::new();
+        for i in 0..ph_count {
+            let header = ph_offset + (i * ph_size);
+            if read_u32(header)? != PT_LOAD {
+                continue;
+            }
+
+            let offset = read_u32(header + 0x04)? as usize;
+            let addr = read_u32(header + 0x0C)?;
+            let file_size = read_u32(header + 0x10)? as usize;
+            let mem_size = read_u32(header + 0x14)? as usize;
+            if file_size > mem_size {
+                return Err(invalid("has a segment larger in the file than in memory"));
+            }
+
+            if mem_size == 0 {
+                continue;
+            }
+            if !memory
+                .iter()
+                .any(|region| fits_into(region, addr, mem_size))
+            {
+                return Err(ImageError::Placement(format!(
+                    "{mem_size} bytes at 0x{addr:0>8X} are not inside a single memory region"
+                )));
+            }
+
+            let mut data = bytes
+                .get(offset..(offset + file_size))
+                .ok_or(invalid("is truncated"))?
+                .to_vec();
+            // the rest of the segment is zero initialized, like `.bss`
+            data.resize(mem_size, 0);
+            segments.push(Segment { addr, data });
+        }
+
+        Ok(Self { segments })
+    }
+
+    /// The bytes of the image relative to the start of `region`, every segment has to be
+    /// inside of it.
+    pub fn flatten(&self, region: &MemoryRegion) -> Result<Vec<u8>, ImageError> {
+        let mut bytes = Vec::new();
+        for segment in &self.segments {
+            segment.check_inside(region)?;
+
+            let start = (segment.addr - region.base) as usize;
+            let end = start + segment.data.len();
+            if bytes.len() < end {
+                bytes.resize(end, 0);
+            }
+            bytes[start..end].copy_from_slice(&segment.data);
+        }
+        Ok(bytes)
+    }
+}
+
+/// An image file given on the command line as `<path>[@<addr>]`, the address is where a raw
+/// image gets loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageSource {
+    pub path: PathBuf,
+    pub addr: Option<u32>,
+}
+
+impl ImageSource {
+    /// Loads the image, ELF segments have to be inside of `memory`.
+    pub fn load(&self, memory: &[MemoryRegion]) -> Result<Image, ImageError> {
+        Image::load(&self.path, self.addr, memory)
+    }
+}
+
+impl FromStr for ImageSource {
+    type Err = std::convert::Infallible;
+
+    /// `<path>@<addr>` or `<path>`, an `@` not followed by an address is part of the path.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let addressed = s.rsplit_once('@').and_then(|(path, addr)| {
+            let addr = crate::parse_u32(addr).ok()?;
+            Some(Self {
+                path: path.into(),
+                addr: Some(addr),
+            })
+        });
+        Ok(addressed.unwrap_or_else(|| Self {
+            path: s.into(),
+            addr: None,
+        }))
+    }
+}
@@ -0,0 +1,464 @@
+use super::{
+    AccessKind, TimeSource, COUNTER_PORTS, KERNEL_RAM_SIZE, KERNEL_RAM_START, LED_PORTS,
+    PAGING_PORTS, PROTECTION_PORTS, SERIAL_CONTROLLER_PORTS, SERIAL_PORTS, SYSCALL_ADDRESS_PORTS,
+    SYSTEM_RAM_SIZE, SYSTEM_RAM_START, TEST_EXIT_PORTS, TIMER_PORTS, VDP_PORTS,
+};
+use crate::cpu::CPU_PORT_COUNT;
+use bitflags::bitflags;
+use serde::de::{self, Deserializer, Visitor};
+use serde::Deserialize;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+bitflags! {
+    /// Accesses a memory region permits, written as a subset of `"rwx"` in descriptions.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[repr(transparent)]
+    pub struct MemoryAccess : u32 {
+        const READ = 0x1;
+        const WRITE = 0x2;
+        const EXECUTE = 0x4;
+    }
+}
+
+impl MemoryAccess {
+    #[inline]
+    pub(super) fn permits(self, kind: AccessKind) -> bool {
+        self.contains(match kind {
+            AccessKind::Read => Self::READ,
+            AccessKind::Write => Self::WRITE,
+            AccessKind::Execute => Self::EXECUTE,
+        })
+    }
+}
+
+/// A RAM region in the physical address space.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MemoryRegion {
+    pub name: String,
+    #[serde(deserialize_with = "number")]
+    pub base: u32,
+    #[serde(deserialize_with = "number")]
+    pub size: u32,
+    #[serde(default = "MemoryAccess::all", deserialize_with = "access")]
+    pub access: MemoryAccess,
+    /// User code cannot access the region at all.
+    #[serde(default)]
+    pub system_only: bool,
+    /// The kernel image is loaded here, and restored on every reset.
+    #[serde(default)]
+    pub kernel: bool,
+}
+
+impl MemoryRegion {
+    #[inline]
+    fn range(&self) -> Option<RangeInclusive<u32>> {
+        let end = self.base.checked_add(self.size.checked_sub(1)?)?;
+        Some(self.base..=end)
+    }
+}
+
+/// I/O base addresses of the devices, a device without one does not exist.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Devices {
+    #[serde(default, deserialize_with = "optional_number")]
+    pub timer: Option<u32>,
+    #[serde(default, deserialize_with = "optional_number")]
+    pub serial: Option<u32>,
+    #[serde(default, deserialize_with = "optional_number")]
+    pub paging: Option<u32>,
+    #[serde(default, deserialize_with = "optional_number")]
+    pub protection: Option<u32>,
+    /// The UART with the register layout of the Softcore's serial controller.
+    #[serde(default, deserialize_with = "optional_number")]
+    pub serial_controller: Option<u32>,
+    #[serde(default, deserialize_with = "optional_number")]
+    pub vdp: Option<u32>,
+    /// Cycle, stall and microsecond counters of the Softcore.
+    #[serde(default, deserialize_with = "optional_number")]
+    pub counters: Option<u32>,
+    #[serde(default, deserialize_with = "optional_number")]
+    pub led: Option<u32>,
+    /// The Softcore's kernel-only syscall address register.
+    #[serde(default, deserialize_with = "optional_number")]
+    pub syscall_address: Option<u32>,
+    /// Write-only port ending a guest test, not present on real hardware.
+    #[serde(default, deserialize_with = "optional_number")]
+    pub test_exit: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Device {
+    Timer,
+    Serial,
+    Paging,
+    Protection,
+    SerialController,
+    Vdp,
+    Counters,
+    Led,
+    SyscallAddress,
+    TestExit,
+}
+
+impl Device {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Timer => "timer",
+            Self::Serial => "serial",
+            Self::Paging => "paging",
+            Self::Protection => "protection",
+            Self::SerialController => "serial_controller",
+            Self::Vdp => "vdp",
+            Self::Counters => "counters",
+            Self::Led => "led",
+            Self::SyscallAddress => "syscall_address",
+            Self::TestExit => "test_exit",
+        }
+    }
+
+    fn port_count(self) -> u32 {
+        match self {
+            Self::Timer => TIMER_PORTS,
+            Self::Serial => SERIAL_PORTS,
+            Self::Paging => PAGING_PORTS,
+            Self::Protection => PROTECTION_PORTS,
+            Self::SerialController => SERIAL_CONTROLLER_PORTS,
+            Self::Vdp => VDP_PORTS,
+            Self::Counters => COUNTER_PORTS,
+            Self::Led => LED_PORTS,
+            Self::SyscallAddress => SYSCALL_ADDRESS_PORTS,
+            Self::TestExit => TEST_EXIT_PORTS,
+        }
+    }
+}
+
+impl Devices {
+    fn iter(&self) -> impl Iterator<Item = (Device, u32)> {
+        [
+            (Device::Timer, self.timer),
+            (Device::Serial, self.serial),
+            (Device::Paging, self.paging),
+            (Device::Protection, self.protection),
+            (Device::SerialController, self.serial_controller),
+            (Device::Vdp, self.vdp),
+            (Device::Counters, self.counters),
+            (Device::Led, self.led),
+            (Device::SyscallAddress, self.syscall_address),
+            (Device::TestExit, self.test_exit),
+        ]
+        .into_iter()
+        .filter_map(|(device, base)| Some((device, base?)))
+    }
+
+    /// Finds the device owning the I/O address `addr`, returning it with the port number
+    /// relative to its base.
+    #[inline]
+    pub(super) fn decode(&self, addr: u32) -> Option<(Device, u32)> {
+        self.iter().find_map(|(device, base)| {
+            let port = addr.checked_sub(base)?;
+            (port < device.port_count()).then_some((device, port))
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum MachineError {
+    Io(std::io::Error),
+    Parse(serde_json::Error),
+    /// The description is well formed, but does not describe a usable machine.
+    Invalid(String),
+}
+
+impl std::fmt::Display for MachineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "cannot read machine description: {err}"),
+            Self::Parse(err) => write!(f, "invalid machine description: {err}"),
+            Self::Invalid(msg) => write!(f, "invalid machine description: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for MachineError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Parse(err) => Some(err),
+            Self::Invalid(_) => None,
+        }
+    }
+}
+
+/// The memory map and devices of a board, numbers can be given as JSON numbers or as strings
+/// with `0x`/`0b` prefixes and `_` separators.
+///
+/// ```json
+/// {
+///     "name": "art32",
+///     "boot_pc": "0x1000_0000",
+///     "memory": [
+///         { "name": "kernel", "base": "0x1000_0000", "size": "0x8000", "system_only": true, "kernel": true },
+///         { "name": "system", "base": "0x2000_0000", "size": "0x10_0000", "access": "rwx" }
+///     ],
+///     "devices": { "timer": "0x80", "serial": "0x90", "paging": "0xA0", "protection": "0xA8" }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MachineDescription {
+    pub name: String,
+    #[serde(deserialize_with = "number")]
+    pub boot_pc: u32,
+    pub memory: Vec<MemoryRegion>,
+    #[serde(default)]
+    pub devices: Devices,
+    /// I/O base address of the CPU's own ports.
+    #[serde(default, deserialize_with = "number")]
+    pub cpu_ports: u32,
+    /// Value read from unmapped memory and I/O addresses, writes to them are dropped. Without
+    /// one, such accesses raise access violations.
+    #[serde(default, deserialize_with = "optional_number")]
+    pub open_bus: Option<u32>,
+    /// Estimate cycles with the Softcore pipeline's `TimingModel`. Without it every instruction
+    /// takes a single cycle, so the cycle counters count retired instructions.
+    #[serde(default)]
+    pub timing_model: bool,
+    /// Where the guest visible time comes from, written like the `--time-source` option.
+    #[serde(default, deserialize_with = "time_source")]
+    pub time_source: TimeSource,
+}
+
+impl Default for MachineDescription {
+    fn default() -> Self {
+        Self::art32()
+    }
+}
+
+impl MachineDescription {
+    /// The board the built in kernel is written for.
+    pub fn art32() -> Self {
+        Self {
+            name: "art32".to_owned(),
+            boot_pc: KERNEL_RAM_START,
+            memory: vec![
+                MemoryRegion {
+                    name: "kernel".to_owned(),
+                    base: KERNEL_RAM_START,
+                    size: KERNEL_RAM_SIZE,
+                    access: MemoryAccess::all(),
+                    system_only: true,
+                    kernel: true,
+                },
+                MemoryRegion {
+                    name: "system".to_owned(),
+                    base: SYSTEM_RAM_START,
+                    size: SYSTEM_RAM_SIZE,
+                    access: MemoryAccess::all(),
+                    system_only: false,
+                    kernel: false,
+                },
+            ],
+            devices: Devices {
+                timer: Some(0x80),
+                serial: Some(0x90),
+                paging: Some(0xA0),
+                protection: Some(0xA8),
+                ..Default::default()
+            },
+            cpu_ports: 0x000,
+            open_bus: None,
+            timing_model: false,
+            time_source: TimeSource::Host,
+        }
+    }
+
+    /// The memory and I/O map decoded by the FPGA implementation in `Softcore/art32.qrz`.
+    ///
+    /// The kernel RAM, SRAM and video RAM pages are selected by the address bits above 24, and
+    /// everything not decoded reads `0xAAAAAAAA`. The hardware has no CPU ports, so they are
+    /// moved out of the way of its 12 bit I/O addresses.
+    pub fn softcore() -> Self {
+        let region = |name: &str, base, size, system_only, kernel| MemoryRegion {
+            name: name.to_owned(),
+            base,
+            size,
+            access: MemoryAccess::all(),
+            system_only,
+            kernel,
+        };
+
+        Self {
+            name: "softcore".to_owned(),
+            boot_pc: 0x0000_0000,
+            memory: vec![
+                region("kram", 0x0000_0000, 0x8000, true, true),
+                region("sram", 0x0100_0000, 0x10_0000, false, false),
+                region("bitmaps", 0x0200_0000, 0x8000, false, false),
+                region("palettes", 0x0240_0000, 0x1000, false, false),
+                region("tilemap", 0x0280_0000, 0x4000, false, false),
+            ],
+            devices: Devices {
+                serial_controller: Some(0x0),
+                vdp: Some(0x4),
+                counters: Some(0x8),
+                led: Some(0xF),
+                syscall_address: Some(0xFFF),
+                ..Default::default()
+            },
+            cpu_ports: 0x1000,
+            open_bus: Some(0xAAAA_AAAA),
+            timing_model: false,
+            time_source: TimeSource::Host,
+        }
+    }
+
+    /// Looks up a built in board by name.
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name {
+            "art32" => Some(Self::art32()),
+            "softcore" => Some(Self::softcore()),
+            _ => None,
+        }
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, MachineError> {
+        let machine: Self = serde_json::from_str(json).map_err(MachineError::Parse)?;
+        machine.validate()?;
+        Ok(machine)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, MachineError> {
+        let json = std::fs::read_to_string(path).map_err(MachineError::Io)?;
+        Self::from_json(&json)
+    }
+
+    /// Index of the region the kernel image is loaded into.
+    pub(super) fn kernel_index(&self) -> usize {
+        self.memory.iter().position(|region| region.kernel).unwrap()
+    }
+
+    /// The region the kernel image is loaded into, the description has to be valid.
+    pub fn kernel_region(&self) -> &MemoryRegion {
+        &self.memory[self.kernel_index()]
+    }
+
+    pub fn validate(&self) -> Result<(), MachineError> {
+        let invalid = |msg: String| Err(MachineError::Invalid(msg));
+
+        let mut ranges = Vec::new();
+        for region in &self.memory {
+            let Some(range) = region.range() else {
+                return invalid(format!("memory region `{}` is empty or wraps", region.name));
+            };
+            if ((region.base & 0x3) != 0) || ((region.size & 0x3) != 0) {
+                return invalid(format!(
+                    "memory region `{}` is not word aligned",
+                    region.name
+                ));
+            }
+
+            ranges.push((region.name.as_str(), range));
+        }
+        if let Some((a, b)) = overlap(&ranges) {
+            return invalid(format!("memory regions `{a}` and `{b}` overlap"));
+        }
+
+        match self.memory.iter().filter(|region| region.kernel).count() {
+            1 => {}
+            0 => return invalid("no memory region holds the kernel".to_owned()),
+            _ => return invalid("more than one memory region holds the kernel".to_owned()),
+        }
+
+        let boots = self.memory.iter().any(|region| {
+            region.access.contains(MemoryAccess::EXECUTE)
+                && region.range().unwrap().contains(&self.boot_pc)
+        });
+        if !boots || ((self.boot_pc & 0x1) != 0) {
+            return invalid(format!(
+                "boot PC 0x{:0>8X} is not in executable memory",
+                self.boot_pc
+            ));
+        }
+
+        if self.devices.serial.is_some() && self.devices.serial_controller.is_some() {
+            return invalid("serial and serial_controller share the UART".to_owned());
+        }
+
+        let Some(cpu_ports_end) = self.cpu_ports.checked_add(CPU_PORT_COUNT - 1) else {
+            return invalid("CPU ports wrap".to_owned());
+        };
+        let mut ranges = vec![("CPU", self.cpu_ports..=cpu_ports_end)];
+        for (device, base) in self.devices.iter() {
+            let Some(end) = base.checked_add(device.port_count() - 1) else {
+                return invalid(format!("{} ports wrap", device.name()));
+            };
+            ranges.push((device.name(), base..=end));
+        }
+        if let Some((a, b)) = overlap(&ranges) {
+            return invalid(format!("{a} and {b} ports overlap"));
+        }
+
+        Ok(())
+    }
+}
+
+fn overlap<'a>(ranges: &[(&'a str, RangeInclusive<u32>)]) -> Option<(&'a str, &'a str)> {
+    ranges.iter().enumerate().find_map(|(i, (a, range_a))| {
+        ranges[(i + 1)..].iter().find_map(|(b, range_b)| {
+            let overlaps = (range_a.start() <= range_b.end()) && (range_b.start() <= range_a.end());
+            overlaps.then_some((*a, *b))
+        })
+    })
+}
+
+struct NumberVisitor;
+
+impl Visitor<'_> for NumberVisitor {
+    type Value = u32;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a 32 bit number")
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<u32, E> {
+        u32::try_from(value).map_err(|_| E::custom(format!("{value} does not fit into 32 bits")))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<u32, E> {
+        crate::parse_u32(value).map_err(|err| E::custom(format!("invalid number `{value}`: {err}")))
+    }
+}
+
+fn number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    deserializer.deserialize_any(NumberVisitor)
+}
+
+fn optional_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u32>, D::Error> {
+    number(deserializer).map(Some)
+}
+
+fn time_source<'de, D: Deserializer<'de>>(deserializer: D) -> Result<TimeSource, D::Error> {
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(de::Error::custom)
+}
+
+fn access<'de, D: Deserializer<'de>>(deserializer: D) -> Result<MemoryAccess, D::Error> {
+    let flags = String::deserialize(deserializer)?;
+    flags.chars().try_fold(MemoryAccess::empty(), |access, c| {
+        let flag = match c {
+            'r' => MemoryAccess::READ,
+            'w' => MemoryAccess::WRITE,
+            'x' => MemoryAccess::EXECUTE,
+            _ => {
+                return Err(de::Error::custom(format!(
+                    "invalid access `{flags}`, expected a subset of `rwx`"
+                )))
+            }
+        };
+        Ok(access | flag)
+    })
+}
@@ -0,0 +1,189 @@
+use super::AccessKind;
+use crate::cpu::interface::{MemoryError, PrivilegeLevel};
+use bitflags::bitflags;
+use std::cell::Cell;
+
+pub const PAGE_SIZE: u32 = 0x1000;
+const PAGE_MASK: u32 = PAGE_SIZE - 1;
+const TLB_ENTRIES: usize = 64;
+
+bitflags! {
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    #[repr(transparent)]
+    pub struct PagingControl : u32 {
+        /// Translates every memory access, including the ones of system code.
+        const ENABLE = 0x1;
+    }
+}
+
+bitflags! {
+    /// Low bits of a page table entry, page directory entries only use `VALID`.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    #[repr(transparent)]
+    pub struct PageFlags : u32 {
+        const VALID = 0x01;
+        const READ = 0x02;
+        const WRITE = 0x04;
+        const EXECUTE = 0x08;
+        /// Accessible from user code, system code can access every valid page.
+        const USER = 0x10;
+    }
+}
+
+impl PageFlags {
+    #[inline]
+    fn permits(self, kind: AccessKind) -> bool {
+        self.contains(match kind {
+            AccessKind::Read => Self::READ,
+            AccessKind::Write => Self::WRITE,
+            AccessKind::Execute => Self::EXECUTE,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TlbEntry {
+    page: u32,
+    entry: u32,
+}
+
+/// Two level page table walker with a direct mapped TLB.
+///
+/// Bits [31:22] of a virtual address index the page directory at the page table base,
+/// bits [21:12] the page table the directory entry points to. Both levels hold one word
+/// per entry with the physical page address in bits [31:12] and `PageFlags` below.
+///
+/// Translating only borrows the walker, so the TLB it refills on a miss is made of `Cell`s, like
+/// the registers the I/O bus writes.
+pub struct Paging {
+    control: Cell<PagingControl>,
+    base: Cell<u32>,
+    tlb: [Cell<Option<TlbEntry>>; TLB_ENTRIES],
+    /// Set whenever translations may have changed, until the instruction caches got flushed.
+    remapped: Cell<bool>,
+}
+
+impl Default for Paging {
+    fn default() -> Self {
+        Self {
+            control: Cell::default(),
+            base: Cell::default(),
+            tlb: std::array::from_fn(|_| Cell::new(None)),
+            remapped: Cell::default(),
+        }
+    }
+}
+
+impl Paging {
+    pub fn reset(&self) {
+        self.control.set(PagingControl::empty());
+        self.base.set(0);
+        self.flush();
+    }
+
+    #[inline]
+    pub fn control(&self) -> PagingControl {
+        self.control.get()
+    }
+
+    pub fn set_control(&self, control: PagingControl) {
+        self.control.set(control);
+        self.flush();
+    }
+
+    #[inline]
+    pub fn base(&self) -> u32 {
+        self.base.get()
+    }
+
+    /// Switching page tables flushes the TLB.
+    pub fn set_base(&self, base: u32) {
+        self.base.set(base & !PAGE_MASK);
+        self.flush();
+    }
+
+    /// Has to be done after changing a page table entry that may be cached.
+    pub fn flush(&self) {
+        for entry in &self.tlb {
+            entry.set(None);
+        }
+        self.remapped.set(true);
+    }
+
+    /// Whether translations changed, without clearing the flag like `take_remapped`.
+    #[inline]
+    pub fn is_remapped(&self) -> bool {
+        self.remapped.get()
+    }
+
+    /// Returns whether translations changed since the last call.
+    #[inline]
+    pub fn take_remapped(&self) -> bool {
+        self.remapped.replace(false)
+    }
+
+    /// Translates `addr` to a physical address, walking the page table through `read_physical`
+    /// on a TLB miss.
+    #[inline]
+    pub fn translate(
+        &self,
+        addr: u32,
+        priv_level: PrivilegeLevel,
+        kind: AccessKind,
+        read_physical: impl Fn(u32) -> Option<u32>,
+    ) -> Result<u32, MemoryError> {
+        if !self.control.get().contains(PagingControl::ENABLE) {
+            return Ok(addr);
+        }
+
+        let page = addr / PAGE_SIZE;
+        let slot = &self.tlb[(page as usize) % TLB_ENTRIES];
+        let entry = match slot.get() {
+            Some(cached) if cached.page == page => cached.entry,
+            _ => {
+                let entry = self.walk(addr, read_physical)?;
+                slot.set(Some(TlbEntry { page, entry }));
+                entry
+            }
+        };
+
+        let flags = PageFlags::from_bits_truncate(entry);
+        let user_denied = (priv_level == PrivilegeLevel::User) && !flags.contains(PageFlags::USER);
+        if !flags.permits(kind) || user_denied {
+            return Err(MemoryError::PageFault);
+        }
+
+        Ok((entry & !PAGE_MASK) | (addr & PAGE_MASK))
+    }
+
+    /// Translates `addr` the way `translate` does for debuggers, without checking permissions or
+    /// filling the TLB.
+    pub fn peek(&self, addr: u32, read_physical: impl Fn(u32) -> Option<u32>) -> Option<u32> {
+        if !self.control.get().contains(PagingControl::ENABLE) {
+            return Some(addr);
+        }
+
+        let page = addr / PAGE_SIZE;
+        let entry = match self.tlb[(page as usize) % TLB_ENTRIES].get() {
+            Some(cached) if cached.page == page => cached.entry,
+            _ => self.walk(addr, read_physical).ok()?,
+        };
+        Some((entry & !PAGE_MASK) | (addr & PAGE_MASK))
+    }
+
+    #[cold]
+    fn walk(
+        &self,
+        addr: u32,
+        read_physical: impl Fn(u32) -> Option<u32>,
+    ) -> Result<u32, MemoryError> {
+        let read_entry = |table: u32, index: u32| {
+            read_physical(table + (index * 4))
+                .filter(|&entry| PageFlags::from_bits_truncate(entry).contains(PageFlags::VALID))
+                .ok_or(MemoryError::PageFault)
+        };
+
+        let directory_entry = read_entry(self.base.get(), addr >> 22)?;
+        read_entry(directory_entry & !PAGE_MASK, (addr >> 12) & 0x3FF)
+    }
+}
@@ -0,0 +1,136 @@
+use super::AccessKind;
+use crate::cpu::interface::{MemoryError, PrivilegeLevel};
+use bitflags::bitflags;
+use std::cell::Cell;
+
+pub const REGION_COUNT: usize = 8;
+
+bitflags! {
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    #[repr(transparent)]
+    pub struct ProtectionControl : u32 {
+        /// Confines user code to the regions, system code is never checked.
+        const ENABLE = 0x1;
+    }
+}
+
+bitflags! {
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    #[repr(transparent)]
+    pub struct RegionFlags : u32 {
+        const READ = 0x1;
+        const WRITE = 0x2;
+        const EXECUTE = 0x4;
+    }
+}
+
+impl RegionFlags {
+    #[inline]
+    fn permits(self, kind: AccessKind) -> bool {
+        self.contains(match kind {
+            AccessKind::Read => Self::READ,
+            AccessKind::Write => Self::WRITE,
+            AccessKind::Execute => Self::EXECUTE,
+        })
+    }
+}
+
+/// A window of `size` bytes starting at the physical address `base`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub base: u32,
+    pub size: u32,
+    pub flags: RegionFlags,
+}
+
+impl Region {
+    #[inline]
+    fn contains(&self, addr: u32, len: u32) -> bool {
+        let offset = addr.wrapping_sub(self.base);
+        (offset < self.size) && ((self.size - offset) >= len)
+    }
+}
+
+/// Protection regions for user code, a lighter alternative to paging.
+///
+/// The MMU checks accesses against the regions while `out` instructions may reprogram them, both
+/// holding shared references, hence the `Cell`s.
+#[derive(Default)]
+pub struct Protection {
+    control: Cell<ProtectionControl>,
+    regions: [Cell<Region>; REGION_COUNT],
+    /// Set whenever the regions changed, until the instruction caches got flushed.
+    changed: Cell<bool>,
+}
+
+impl Protection {
+    pub fn reset(&self) {
+        self.control.set(ProtectionControl::empty());
+        for region in &self.regions {
+            region.set(Region::default());
+        }
+        self.changed.set(true);
+    }
+
+    #[inline]
+    pub fn control(&self) -> ProtectionControl {
+        self.control.get()
+    }
+
+    #[inline]
+    pub fn set_control(&self, control: ProtectionControl) {
+        self.control.set(control);
+        self.changed.set(true);
+    }
+
+    #[inline]
+    pub fn region(&self, index: usize) -> Region {
+        self.regions[index].get()
+    }
+
+    #[inline]
+    pub fn set_region(&self, index: usize, region: Region) {
+        self.regions[index].set(region);
+        self.changed.set(true);
+    }
+
+    /// Whether the regions changed, without clearing the flag like `take_changed`.
+    #[inline]
+    pub fn is_changed(&self) -> bool {
+        self.changed.get()
+    }
+
+    /// Returns whether the regions changed since the last call.
+    #[inline]
+    pub fn take_changed(&self) -> bool {
+        self.changed.replace(false)
+    }
+
+    /// Checks that `len` bytes at the physical address `addr` lie within one region permitting
+    /// the access.
+    #[inline]
+    pub fn check(
+        &self,
+        addr: u32,
+        len: u32,
+        priv_level: PrivilegeLevel,
+        kind: AccessKind,
+    ) -> Result<(), MemoryError> {
+        if (priv_level == PrivilegeLevel::System)
+            || !self.control.get().contains(ProtectionControl::ENABLE)
+        {
+            return Ok(());
+        }
+
+        let permitted = self.regions.iter().any(|region| {
+            let region = region.get();
+            region.flags.permits(kind) && region.contains(addr, len)
+        });
+
+        if permitted {
+            Ok(())
+        } else {
+            Err(MemoryError::AccessViolation)
+        }
+    }
+}
@@ -0,0 +1,811 @@
+use bitflags::bitflags;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, LineWriter, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+bitflags! {
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    #[repr(transparent)]
+    pub struct SerialControl : u32 {
+        /// Signals the interrupt slot whenever new bytes are received.
+        const RX_INTERRUPT = 0x1;
+    }
+}
+
+/// Host side source of the bytes received by the guest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SerialInput {
+    /// The emulator's stdin, switched to raw mode if it is a terminal. Ctrl-C still stops the emulator.
+    Stdin,
+    /// A new pseudo terminal that tools like `screen` can attach to.
+    Pty,
+    /// Clients connecting to a local TCP listener, one at a time.
+    Tcp(SocketAddr),
+}
+
+impl FromStr for SerialInput {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stdin" => Ok(Self::Stdin),
+            "pty" => Ok(Self::Pty),
+            _ => match s.strip_prefix("tcp:") {
+                Some(addr) => addr
+                    .parse()
+                    .map(Self::Tcp)
+                    .map_err(|err| format!("invalid address `{addr}`: {err}")),
+                None => Err(format!(
+                    "unknown serial input `{s}`, expected `stdin`, `pty` or `tcp:<addr>`"
+                )),
+            },
+        }
+    }
+}
+
+/// Host side destination of the bytes transmitted by the guest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SerialOutput {
+    /// The emulator's stdout.
+    Stdout,
+    /// A log file, truncated when opened.
+    File(PathBuf),
+    /// A pseudo terminal, the same one as the input's if that is a PTY too.
+    Pty,
+    /// The client of a local TCP listener, the same one as the input's if it listens on the same address.
+    Tcp(SocketAddr),
+    /// An in-memory buffer, see `Art32::take_serial_output`.
+    Buffer,
+}
+
+impl FromStr for SerialOutput {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stdout" => Ok(Self::Stdout),
+            "pty" => Ok(Self::Pty),
+            "buffer" => Ok(Self::Buffer),
+            _ => {
+                if let Some(path) = s.strip_prefix("file:") {
+                    Ok(Self::File(path.into()))
+                } else if let Some(addr) = s.strip_prefix("tcp:") {
+                    addr.parse()
+                        .map(Self::Tcp)
+                        .map_err(|err| format!("invalid address `{addr}`: {err}"))
+                } else {
+                    Err(format!(
+                        "unknown serial output `{s}`, expected `stdout`, `file:<path>`, `pty`, `tcp:<addr>` or `buffer`"
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// The TCP client currently connected, shared between the reading and the writing side.
+type Client = Arc<Mutex<Option<TcpStream>>>;
+
+/// A host connection the output can share with the input.
+enum Link {
+    #[cfg(unix)]
+    Pty(File),
+    Tcp(Client),
+}
+
+/// A local TCP listener accepting clients on a background thread, one at a time.
+///
+/// Dropping it disconnects the client and closes the listener, so the address can be bound again
+/// right away.
+struct TcpServer {
+    client: Client,
+    shutdown: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TcpServer {
+    /// How often the listener checks whether it got dropped while no client connects.
+    const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+    /// Binds `addr` and passes each client to `serve`, which returns `false` to stop listening.
+    fn spawn(
+        addr: &SocketAddr,
+        mut serve: impl FnMut(&mut TcpStream) -> bool + Send + 'static,
+    ) -> io::Result<(Self, SocketAddr)> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        listener.set_nonblocking(true)?;
+
+        let client = Client::default();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let client = Arc::clone(&client);
+            let shutdown = Arc::clone(&shutdown);
+            thread::spawn(move || loop {
+                match listener.accept() {
+                    Ok((mut stream, _)) => {
+                        if stream.set_nonblocking(false).is_err() {
+                            continue;
+                        }
+                        {
+                            // checked under the lock, so `drop` either sees the client or
+                            // stops the thread before it gets served
+                            let mut client = client.lock().unwrap();
+                            if shutdown.load(Ordering::Acquire) {
+                                break;
+                            }
+                            *client = stream.try_clone().ok();
+                        }
+                        if !serve(&mut stream) {
+                            break;
+                        }
+                    }
+                    Err(_) if shutdown.load(Ordering::Acquire) => break,
+                    Err(_) => thread::sleep(Self::POLL_INTERVAL),
+                }
+            })
+        };
+
+        let server = Self {
+            client,
+            shutdown,
+            thread: Some(thread),
+        };
+        Ok((server, local_addr))
+    }
+}
+
+impl Drop for TcpServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Release);
+        if let Some(client) = self.client.lock().unwrap().take() {
+            // wakes up a thread blocked reading from the client
+            let _ = client.shutdown(std::net::Shutdown::Both);
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Bytes read from a host source by a background thread.
+pub struct HostInput {
+    bytes: Receiver<Vec<u8>>,
+    pending: Arc<AtomicBool>,
+    source: SerialInput,
+    link: Option<Link>,
+    endpoint: Option<String>,
+    _server: Option<TcpServer>,
+    #[cfg(unix)]
+    _terminal: Option<unix::Terminal>,
+}
+
+impl HostInput {
+    pub fn open(input: &SerialInput) -> io::Result<Self> {
+        let (sender, bytes) = mpsc::channel();
+        let pending = Arc::new(AtomicBool::new(false));
+        let forwarder = Forwarder {
+            sender,
+            pending: Arc::clone(&pending),
+        };
+
+        let mut host_input = Self {
+            bytes,
+            pending,
+            source: input.clone(),
+            link: None,
+            endpoint: None,
+            _server: None,
+            #[cfg(unix)]
+            _terminal: None,
+        };
+
+        match input {
+            SerialInput::Stdin => {
+                #[cfg(unix)]
+                {
+                    host_input._terminal = unix::Terminal::stdin_raw()?;
+                }
+
+                let mut stdin = io::stdin();
+                thread::spawn(move || forwarder.forward(&mut stdin));
+            }
+            SerialInput::Pty => {
+                #[cfg(unix)]
+                {
+                    let (mut master, terminal, path) = unix::open_pty()?;
+                    host_input.link = Some(Link::Pty(master.try_clone()?));
+                    host_input._terminal = Some(terminal);
+                    host_input.endpoint = Some(path);
+                    thread::spawn(move || forwarder.forward(&mut master));
+                }
+
+                #[cfg(not(unix))]
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "pseudo terminals are only supported on Unix",
+                ));
+            }
+            SerialInput::Tcp(addr) => {
+                let (server, local_addr) =
+                    TcpServer::spawn(addr, move |stream| forwarder.forward(stream))?;
+                host_input.link = Some(Link::Tcp(Arc::clone(&server.client)));
+                host_input.endpoint = Some(local_addr.to_string());
+                host_input._server = Some(server);
+            }
+        }
+
+        Ok(host_input)
+    }
+
+    /// The PTY path or TCP address host tools connect to.
+    pub fn endpoint(&self) -> Option<&str> {
+        self.endpoint.as_deref()
+    }
+
+    /// Moves all bytes received so far into `buffer`, returns whether there were any.
+    #[inline]
+    fn receive(&self, buffer: &mut VecDeque<u8>) -> bool {
+        if !self.pending.swap(false, Ordering::Acquire) {
+            return false;
+        }
+
+        let len = buffer.len();
+        for bytes in self.bytes.try_iter() {
+            buffer.extend(bytes);
+        }
+        buffer.len() > len
+    }
+}
+
+struct Forwarder {
+    sender: Sender<Vec<u8>>,
+    pending: Arc<AtomicBool>,
+}
+
+impl Forwarder {
+    /// Forwards everything read from `reader` until it gets closed.
+    ///
+    /// Returns `false` once the emulator stopped listening.
+    fn forward<R: Read>(&self, reader: &mut R) -> bool {
+        let mut buffer = [0; 256];
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => return true,
+                Ok(len) => {
+                    if self.sender.send(buffer[..len].to_vec()).is_err() {
+                        return false;
+                    }
+                    self.pending.store(true, Ordering::Release);
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(_) => return true,
+            }
+        }
+    }
+}
+
+/// Bytes the stdout sink collects before writing them.
+const STDOUT_BUFFER_SIZE: usize = 0x1000;
+/// Polls without a transmitted byte after which the stdout sink writes what it collected, so
+/// prompts without a line break show up.
+const STDOUT_IDLE_POLLS: u32 = 1000;
+
+enum Sink {
+    /// Bytes collected until a line break, a full buffer or the UART going idle.
+    Stdout(Vec<u8>),
+    File(LineWriter<File>),
+    Buffer(Vec<u8>),
+    /// Bytes written by a background thread, so a stalled reader does not stall the guest.
+    Writer(Sender<u8>),
+}
+
+/// Bytes written to a host destination.
+pub struct HostOutput {
+    sink: Sink,
+    endpoint: Option<String>,
+    /// Polls since the last transmitted byte.
+    idle_polls: u32,
+    _server: Option<TcpServer>,
+    #[cfg(unix)]
+    _terminal: Option<unix::Terminal>,
+}
+
+impl HostOutput {
+    pub fn stdout() -> Self {
+        Self::new(Sink::Stdout(Vec::new()))
+    }
+
+    fn new(sink: Sink) -> Self {
+        Self {
+            sink,
+            endpoint: None,
+            idle_polls: 0,
+            _server: None,
+            #[cfg(unix)]
+            _terminal: None,
+        }
+    }
+
+    /// Opens `output`, sharing the connection of `input` if both name the same endpoint.
+    pub fn open(output: &SerialOutput, input: Option<&HostInput>) -> io::Result<Self> {
+        let shared = input.filter(|input| match (&input.source, output) {
+            (SerialInput::Pty, SerialOutput::Pty) => true,
+            (SerialInput::Tcp(input), SerialOutput::Tcp(output)) => input == output,
+            _ => false,
+        });
+
+        if let Some(input) = shared {
+            let writer: Box<dyn Write + Send> = match input.link.as_ref().unwrap() {
+                #[cfg(unix)]
+                Link::Pty(master) => Box::new(master.try_clone()?),
+                Link::Tcp(client) => Box::new(ClientWriter(Arc::clone(client))),
+            };
+
+            let mut host_output = Self::new(Sink::Writer(spawn_writer(writer)));
+            host_output.endpoint = input.endpoint.clone();
+            return Ok(host_output);
+        }
+
+        match output {
+            SerialOutput::Stdout => Ok(Self::stdout()),
+            SerialOutput::File(path) => {
+                Ok(Self::new(Sink::File(LineWriter::new(File::create(path)?))))
+            }
+            SerialOutput::Buffer => Ok(Self::new(Sink::Buffer(Vec::new()))),
+            SerialOutput::Pty => {
+                #[cfg(unix)]
+                {
+                    let (master, terminal, path) = unix::open_pty()?;
+                    let mut host_output = Self::new(Sink::Writer(spawn_writer(master)));
+                    host_output._terminal = Some(terminal);
+                    host_output.endpoint = Some(path);
+                    Ok(host_output)
+                }
+
+                #[cfg(not(unix))]
+                Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "pseudo terminals are only supported on Unix",
+                ))
+            }
+            SerialOutput::Tcp(addr) => {
+                // a new client replaces the previous one
+                let (server, local_addr) = TcpServer::spawn(addr, |_| true)?;
+                let writer = ClientWriter(Arc::clone(&server.client));
+
+                let mut host_output = Self::new(Sink::Writer(spawn_writer(writer)));
+                host_output.endpoint = Some(local_addr.to_string());
+                host_output._server = Some(server);
+                Ok(host_output)
+            }
+        }
+    }
+
+    /// The PTY path or TCP address host tools connect to.
+    pub fn endpoint(&self) -> Option<&str> {
+        self.endpoint.as_deref()
+    }
+
+    /// Takes the bytes collected by a buffer sink.
+    pub fn take_buffer(&mut self) -> Vec<u8> {
+        match &mut self.sink {
+            Sink::Buffer(buffer) => std::mem::take(buffer),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Writes the bytes the stdout sink collected so far.
+    pub fn flush(&mut self) {
+        if let Sink::Stdout(buffer) = &mut self.sink {
+            if !buffer.is_empty() {
+                let mut stdout = io::stdout().lock();
+                let _ = stdout.write_all(buffer).and_then(|()| stdout.flush());
+                buffer.clear();
+            }
+        }
+    }
+
+    /// Called once per step, flushes the stdout sink once the UART went idle.
+    #[inline]
+    fn poll(&mut self) {
+        if let Sink::Stdout(buffer) = &self.sink {
+            if !buffer.is_empty() {
+                self.idle_polls += 1;
+                if self.idle_polls >= STDOUT_IDLE_POLLS {
+                    self.flush();
+                }
+            }
+        }
+    }
+
+    /// Errors of the host destination are ignored, like a line nobody listens on.
+    fn transmit(&mut self, byte: u8) {
+        self.idle_polls = 0;
+        match &mut self.sink {
+            Sink::Stdout(buffer) => {
+                buffer.push(byte);
+                if (byte == b'\n') || (buffer.len() >= STDOUT_BUFFER_SIZE) {
+                    self.flush();
+                }
+            }
+            Sink::File(file) => {
+                let _ = file.write_all(&[byte]);
+            }
+            Sink::Buffer(buffer) => buffer.push(byte),
+            Sink::Writer(sender) => {
+                let _ = sender.send(byte);
+            }
+        }
+    }
+}
+
+impl Drop for HostOutput {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// Writes everything sent through the returned channel to `writer` until it fails.
+fn spawn_writer<W: Write + Send + 'static>(mut writer: W) -> Sender<u8> {
+    let (sender, bytes) = mpsc::channel();
+    thread::spawn(move || {
+        while let Ok(byte) = bytes.recv() {
+            let mut buffer = vec![byte];
+            buffer.extend(bytes.try_iter());
+            if writer.write_all(&buffer).is_err() {
+                break;
+            }
+        }
+    });
+    sender
+}
+
+/// Writes to the connected TCP client, dropping the bytes while there is none.
+struct ClientWriter(Client);
+
+impl Write for ClientWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut client = self.0.lock().unwrap();
+        if let Some(stream) = client.as_mut() {
+            if stream.write_all(buf).is_err() {
+                *client = None;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+bitflags! {
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    #[repr(transparent)]
+    pub struct SerialStatus : u32 {
+        /// A received byte was dropped because the RX FIFO was full.
+        const RX_OVERRUN = 0x1;
+    }
+}
+
+/// Timing of the UART behind the serial ports, as in `serial_controller.qrz` and `uart.v`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialTiming {
+    pub baud_rate: NonZeroU32,
+    /// Capacity of the TX and the RX FIFO each.
+    pub fifo_depth: usize,
+}
+
+impl SerialTiming {
+    /// 115.2 kBaud and two 64 byte FIFOs.
+    pub const SOFTCORE: Self = Self {
+        baud_rate: NonZeroU32::new(115_200).unwrap(),
+        fifo_depth: 64,
+    };
+}
+
+/// Start bit, 8 data bits and the two stop bits `UartTx` sends.
+const TX_FRAME_BITS: u64 = 11;
+/// `UartRx` only waits for one stop bit.
+const RX_FRAME_BITS: u64 = 10;
+
+/// Bytes on one direction of the line, the front one is being shifted.
+#[derive(Default)]
+struct Line {
+    bytes: VecDeque<u8>,
+    /// Time spent shifting the front byte, in clock periods times the baud rate.
+    progress: u64,
+}
+
+impl Line {
+    /// Returns how many bytes at the front completed their frame within `ticks`.
+    fn advance(
+        &mut self,
+        ticks: u64,
+        clock_hz: u32,
+        baud_rate: NonZeroU32,
+        frame_bits: u64,
+    ) -> usize {
+        let frame = frame_bits * (clock_hz as u64);
+        self.progress += ticks * (baud_rate.get() as u64);
+
+        let mut done = 0;
+        while (done < self.bytes.len()) && (self.progress >= frame) {
+            self.progress -= frame;
+            done += 1;
+        }
+        if done == self.bytes.len() {
+            self.progress = 0;
+        }
+
+        done
+    }
+
+    fn clear(&mut self) {
+        self.bytes.clear();
+        self.progress = 0;
+    }
+}
+
+#[derive(Default)]
+pub struct Serial {
+    pub rx_buffer: VecDeque<u8>,
+    pub control: SerialControl,
+    pub status: SerialStatus,
+    pub slot: u8,
+    pub input: Option<HostInput>,
+    pub output: Option<HostOutput>,
+    /// Bytes are transferred instantly and the RX buffer is unbounded without one.
+    timing: Option<SerialTiming>,
+    tx_line: Line,
+    rx_line: Line,
+    /// Bytes arrived since the interrupt was last signalled.
+    unsignalled: bool,
+}
+
+impl Serial {
+    pub fn with_output(output: HostOutput) -> Self {
+        Self {
+            output: Some(output),
+            ..Default::default()
+        }
+    }
+
+    /// Resets the registers and FIFOs, the host connection and bytes still on the RX line stay.
+    pub fn reset(&mut self) {
+        self.rx_buffer.clear();
+        self.tx_line.clear();
+        self.control = SerialControl::empty();
+        self.status = SerialStatus::empty();
+        self.slot = 0;
+        self.unsignalled = false;
+    }
+
+    /// Switches between instant transfers and UART timing, bytes in flight are kept.
+    pub fn set_timing(&mut self, timing: Option<SerialTiming>) {
+        self.timing = timing;
+        if timing.is_none() {
+            for byte in std::mem::take(&mut self.tx_line).bytes {
+                self.send(byte);
+            }
+            let bytes = std::mem::take(&mut self.rx_line).bytes;
+            self.store(bytes);
+        }
+    }
+
+    /// Free space in the TX FIFO.
+    pub fn tx_space(&self) -> u32 {
+        match self.timing {
+            // the front byte already left the FIFO for the shift register
+            Some(timing) => {
+                (timing
+                    .fifo_depth
+                    .saturating_sub(self.tx_line.bytes.len().saturating_sub(1)))
+                    as u32
+            }
+            None => u32::MAX,
+        }
+    }
+
+    /// Bytes waiting in the TX FIFO.
+    pub fn tx_count(&self) -> u32 {
+        match self.timing {
+            // the front byte already left the FIFO for the shift register
+            Some(_) => self.tx_line.bytes.len().saturating_sub(1) as u32,
+            None => 0,
+        }
+    }
+
+    /// Moves bytes along the lines for `ticks` periods of `clock_hz` and takes the bytes received
+    /// from the host, returning the interrupt slot to signal if any.
+    ///
+    /// Bytes that arrive while the interrupt is disabled get signalled once it is enabled.
+    #[inline]
+    pub fn poll(&mut self, ticks: u64, clock_hz: u32) -> Option<usize> {
+        if let Some(output) = &mut self.output {
+            output.poll();
+        }
+        if let Some(input) = &self.input {
+            match self.timing {
+                Some(_) => {
+                    input.receive(&mut self.rx_line.bytes);
+                }
+                None => self.unsignalled |= input.receive(&mut self.rx_buffer),
+            }
+        }
+
+        if let Some(timing) = self.timing {
+            let sent = self
+                .tx_line
+                .advance(ticks, clock_hz, timing.baud_rate, TX_FRAME_BITS);
+            for _ in 0..sent {
+                let byte = self.tx_line.bytes.pop_front().unwrap();
+                self.send(byte);
+            }
+
+            let received = self
+                .rx_line
+                .advance(ticks, clock_hz, timing.baud_rate, RX_FRAME_BITS);
+            for _ in 0..received {
+                let byte = self.rx_line.bytes.pop_front().unwrap();
+                self.store([byte]);
+            }
+        }
+
+        if self.unsignalled && self.control.contains(SerialControl::RX_INTERRUPT) {
+            self.unsignalled = false;
+            Some(self.slot as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Queues `byte` for transmission, a full TX FIFO drops it like the hardware does.
+    #[inline]
+    pub fn transmit(&mut self, byte: u8) {
+        if self.timing.is_none() {
+            self.send(byte);
+        } else if self.tx_space() > 0 {
+            self.tx_line.bytes.push_back(byte);
+        }
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        match self.timing {
+            Some(_) => self.rx_line.bytes.extend(bytes),
+            None => self.store(bytes.iter().copied()),
+        }
+    }
+
+    #[inline]
+    fn send(&mut self, byte: u8) {
+        if let Some(output) = &mut self.output {
+            output.transmit(byte);
+        }
+    }
+
+    /// Stores bytes that finished arriving, overrunning the RX FIFO if it is full.
+    fn store(&mut self, bytes: impl IntoIterator<Item = u8>) {
+        let depth = self.timing.map_or(usize::MAX, |timing| timing.fifo_depth);
+        for byte in bytes {
+            if self.rx_buffer.len() < depth {
+                self.rx_buffer.push_back(byte);
+            } else {
+                self.status |= SerialStatus::RX_OVERRUN;
+            }
+            self.unsignalled = true;
+        }
+    }
+}
+
+#[cfg(unix)]
+pub(super) mod unix {
+    use std::fs::{File, OpenOptions};
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+    use std::os::unix::fs::OpenOptionsExt;
+
+    fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+        if result < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(result)
+        }
+    }
+
+    /// A terminal switched to raw mode, its original settings are restored on drop.
+    pub struct Terminal {
+        fd: RawFd,
+        original: libc::termios,
+        _file: Option<File>,
+    }
+
+    impl Terminal {
+        /// Switches `fd` to raw mode, `signals` keeps Ctrl-C and Ctrl-\ raising signals instead
+        /// of passing them through as input.
+        pub fn raw(fd: RawFd, file: Option<File>, signals: bool) -> io::Result<Self> {
+            // SAFETY: `termios` is plain data and `fd` is open for the lifetime of `Terminal`
+            unsafe {
+                let mut original: libc::termios = std::mem::zeroed();
+                check(libc::tcgetattr(fd, &mut original))?;
+
+                let mut raw = original;
+                libc::cfmakeraw(&mut raw);
+                // keep the emulator's own output readable
+                raw.c_oflag |= libc::OPOST | libc::ONLCR;
+                if signals {
+                    raw.c_lflag |= libc::ISIG;
+                }
+                check(libc::tcsetattr(fd, libc::TCSANOW, &raw))?;
+
+                Ok(Self {
+                    fd,
+                    original,
+                    _file: file,
+                })
+            }
+        }
+
+        /// Returns `None` if stdin is not a terminal.
+        pub fn stdin_raw() -> io::Result<Option<Self>> {
+            // SAFETY: stdin stays open for the whole process
+            if unsafe { libc::isatty(libc::STDIN_FILENO) } == 0 {
+                return Ok(None);
+            }
+
+            // the user's own terminal, Ctrl-C has to stop the emulator rather than reach the guest
+            Self::raw(libc::STDIN_FILENO, None, true).map(Some)
+        }
+    }
+
+    impl Drop for Terminal {
+        fn drop(&mut self) {
+            // SAFETY: see `Terminal::raw`
+            unsafe {
+                libc::tcsetattr(self.fd, libc::TCSANOW, &self.original);
+            }
+        }
+    }
+
+    /// Opens a new pseudo terminal, returning its master side, the raw slave side and its path.
+    ///
+    /// Keeping the slave side open makes reads from the master block until a client attaches,
+    /// instead of failing.
+    pub fn open_pty() -> io::Result<(File, Terminal, String)> {
+        // SAFETY: the master fd is owned by the returned `File`, `ptsname` is copied right away
+        let (master, path) = unsafe {
+            let fd = check(libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY))?;
+            let master = File::from_raw_fd(fd);
+            check(libc::grantpt(fd))?;
+            check(libc::unlockpt(fd))?;
+
+            let name = libc::ptsname(fd);
+            if name.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            let path = std::ffi::CStr::from_ptr(name)
+                .to_string_lossy()
+                .into_owned();
+            (master, path)
+        };
+
+        let slave = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(&path)?;
+        let terminal = Terminal::raw(slave.as_raw_fd(), Some(slave), false)?;
+
+        Ok((master, terminal, path))
+    }
+}
@@ -0,0 +1,572 @@
+mod image;
+mod machine;
+mod paging;
+mod protection;
+mod serial;
+mod time;
+mod timer;
+
+use super::machine::MachineDescription;
+use super::{
+    Art32, EnvAction, Mmu, RunBudget, RunExit, KERNEL_RAM_START, SYSTEM_RAM_SIZE, SYSTEM_RAM_START,
+};
+use crate::cpu::instruction::*;
+use crate::cpu::interface::{MemoryInterface, PrivilegeLevel};
+use crate::cpu::{
+    BranchCondition, Condition, ExceptionKind, MachineCheck, MachineCheckCause, Register,
+};
+use crate::memory::Memory;
+use proptest::prelude::*;
+use strum::IntoEnumIterator;
+use test_strategy::proptest;
+
+fn kernel_ram(art32: &Art32) -> &Memory {
+    &art32.memory[art32.kernel_ram].memory
+}
+
+fn kernel_ram_mut(art32: &mut Art32) -> &mut Memory {
+    &mut art32.memory[art32.kernel_ram].memory
+}
+
+fn system_ram(art32: &Art32) -> &Memory {
+    let ram = art32
+        .memory
+        .iter()
+        .find(|ram| ram.start == SYSTEM_RAM_START);
+    &ram.unwrap().memory
+}
+
+fn system_ram_mut(art32: &mut Art32) -> &mut Memory {
+    let ram = art32
+        .memory
+        .iter_mut()
+        .find(|ram| ram.start == SYSTEM_RAM_START);
+    &mut ram.unwrap().memory
+}
+
+/// Memory as seen by the CPU, without instruction caches.
+fn mmu(art32: &mut Art32) -> Mmu<'_> {
+    Mmu {
+        memory: &mut art32.memory,
+        paging: &art32.paging,
+        protection: &art32.protection,
+        reservation: &mut art32.reservation,
+        instruction_cache: None,
+        block_cache: None,
+        open_bus: art32.open_bus,
+    }
+}
+
+fn run_until_env_action(art32: &mut Art32) -> EnvAction {
+    for _ in 0..100 {
+        if let Some(action) = art32.step().unwrap() {
+            return action;
+        }
+    }
+
+    panic!("no environment call within 100 steps");
+}
+
+/// Executes `err` once, then overwrites it with `brk` and jumps back to it.
+fn self_modifying_kernel() -> Vec<u8> {
+    use Instruction::*;
+    use Register::*;
+
+    assemble(&[
+        Ldui {
+            rd: S0,
+            imm: KERNEL_RAM_START,
+        },
+        Load32 {
+            op: LoadOp::HalfU,
+            rd: A1,
+            rb: S0,
+            offset: 18,
+        },
+        // offset 8
+        Envcall(EnvAction::Error as u8),
+        Store32 {
+            op: StoreOp::Half,
+            rs: A1,
+            rb: S0,
+            offset: 8,
+        },
+        Branch32 {
+            cond: BranchCondition::True,
+            offset: -10,
+        },
+        // offset 18
+        Envcall(EnvAction::Break as u8),
+    ])
+}
+
+#[test]
+fn self_modifying_code() {
+    for (instruction_cache, block_translation) in [(false, false), (true, false), (true, true)] {
+        let mut art32 = Art32::with_kernel(&self_modifying_kernel());
+        art32.set_instruction_cache(instruction_cache);
+        art32.set_block_translation(block_translation.then_some(0));
+
+        assert_eq!(run_until_env_action(&mut art32), EnvAction::Error);
+        assert_eq!(run_until_env_action(&mut art32), EnvAction::Break);
+    }
+}
+
+#[test]
+fn block_translation_threshold() {
+    let mut art32 = Art32::with_kernel(&assemble(&[Instruction::Branch16 {
+        cond: BranchCondition::True,
+        offset: -2,
+    }]));
+    art32.set_block_translation(Some(3));
+    let translated = |art32: &Art32| {
+        let cache = art32.block_cache.as_ref().unwrap();
+        cache
+            .get(KERNEL_RAM_START, PrivilegeLevel::System)
+            .is_some()
+    };
+
+    for _ in 0..3 {
+        art32.step().unwrap();
+        assert!(!translated(&art32));
+    }
+    art32.step().unwrap();
+    assert!(translated(&art32));
+    assert_eq!(art32.retired_instructions(), 4);
+}
+
+/// Runs a few instructions in front of reading the cycle counter and remapping, then breaks.
+fn io_kernel() -> Vec<u8> {
+    use Instruction::*;
+    use Register::*;
+
+    let addi = AluI32 {
+        op: AluOp::Add,
+        rd: A0,
+        rs1: A0,
+        imm: 1,
+    };
+    assemble(&[
+        addi,
+        addi,
+        addi,
+        In {
+            rd: A1,
+            rb: Zero,
+            offset: 0x100,
+        },
+        addi,
+        Out {
+            rs: Zero,
+            rb: Zero,
+            offset: 0xA1,
+        },
+        addi,
+        Envcall(EnvAction::Break as u8),
+    ])
+}
+
+#[test]
+fn translated_blocks_around_io() {
+    let mut machine = MachineDescription::art32();
+    machine.devices.counters = Some(0x100);
+
+    for block_translation in [false, true] {
+        let mut art32 = Art32::with_machine(&machine, &io_kernel()).unwrap();
+        art32.set_block_translation(block_translation.then_some(0));
+
+        // the counter is current for an `in` following other instructions
+        art32.step().unwrap();
+        while art32.retired_instructions() < 4 {
+            art32.step().unwrap();
+        }
+        assert_eq!(art32.cpu.register(Register::A1), 3);
+
+        // the block ends once the `out` remapped memory
+        while art32.retired_instructions() < 6 {
+            art32.step().unwrap();
+        }
+        assert_eq!(art32.retired_instructions(), 6);
+        assert_eq!(run_until_env_action(&mut art32), EnvAction::Break);
+    }
+}
+
+#[test]
+fn reset_flushes_instruction_cache() {
+    let mut art32 = Art32::with_kernel(&self_modifying_kernel());
+
+    assert_eq!(run_until_env_action(&mut art32), EnvAction::Error);
+    assert_eq!(run_until_env_action(&mut art32), EnvAction::Break);
+
+    art32.reset();
+    assert_eq!(run_until_env_action(&mut art32), EnvAction::Error);
+}
+
+#[test]
+fn debug_memory_access() {
+    let mut machine = MachineDescription::art32();
+    machine.open_bus = Some(0xAAAA_AAAA);
+    let mut art32 = Art32::with_machine(&machine, &[0; 4]).unwrap();
+    let addr = KERNEL_RAM_START + 0x100;
+
+    // a debugger writing the reserved word does not break the guest's LR/SC sequence
+    assert_eq!(
+        mmu(&mut art32).read_32(addr, PrivilegeLevel::System, true),
+        Ok(0)
+    );
+    assert_eq!(art32.write_memory(addr, &[1, 2, 3, 4]), 4);
+    assert_eq!(
+        mmu(&mut art32).write_32(addr, 5, PrivilegeLevel::System, true),
+        Ok(true)
+    );
+
+    // the open bus answers the guest, but nothing lands in RAM
+    let mut bytes = [0; 4];
+    assert_eq!(art32.write_memory(KERNEL_RAM_START - 2, &[0; 4]), 0);
+    assert_eq!(art32.read_memory(KERNEL_RAM_START - 2, &mut bytes), 0);
+    let end = KERNEL_RAM_START + 0x8000;
+    assert_eq!(art32.write_memory(end - 2, &[0; 4]), 2);
+}
+
+#[test]
+fn run_env_call() {
+    let mut art32 = Art32::with_kernel(&self_modifying_kernel());
+
+    assert_eq!(
+        art32.run(RunBudget::default()),
+        RunExit::Env(EnvAction::Error)
+    );
+    assert_eq!(
+        art32.run(RunBudget::default()),
+        RunExit::Env(EnvAction::Break)
+    );
+
+    for action in [EnvAction::Pass, EnvAction::Fail] {
+        let mut art32 = Art32::with_kernel(&assemble(&[Instruction::Envcall(action as u8)]));
+        assert_eq!(art32.run(RunBudget::default()), RunExit::Env(action));
+    }
+}
+
+#[test]
+fn run_budget() {
+    let budget = RunBudget {
+        instructions: Some(10),
+        ..Default::default()
+    };
+
+    let mut art32 = Art32::with_kernel(&assemble(&[Instruction::Branch16 {
+        cond: BranchCondition::True,
+        offset: -2,
+    }]));
+    assert_eq!(art32.run(budget), RunExit::BudgetExhausted);
+    assert_eq!(art32.retired_instructions(), 10);
+
+    // resets are carried out and keep counting
+    let mut art32 = Art32::with_kernel(&assemble(&[Instruction::Envcall(EnvAction::Reset as u8)]));
+    assert_eq!(art32.run(budget), RunExit::BudgetExhausted);
+
+    let mut art32 = Art32::with_kernel(&assemble(&[Instruction::Branch16 {
+        cond: BranchCondition::True,
+        offset: -2,
+    }]));
+    let budget = RunBudget {
+        cycles: Some(100),
+        ..Default::default()
+    };
+    assert_eq!(art32.run(budget), RunExit::BudgetExhausted);
+    assert!((100..110).contains(&art32.cycles()));
+
+    let mut art32 = Art32::with_kernel(&[]);
+    let budget = RunBudget {
+        time: Some(std::time::Duration::ZERO),
+        ..Default::default()
+    };
+    assert_eq!(art32.run(budget), RunExit::BudgetExhausted);
+    assert_eq!(art32.retired_instructions(), 0);
+}
+
+#[test]
+fn run_budget_with_block_translation() {
+    use Instruction::*;
+
+    // a block of 20 additions and a branch back
+    let mut program = vec![
+        Addi16 {
+            rd: Register::A0,
+            imm: 1,
+        };
+        20
+    ];
+    program.push(Branch16 {
+        cond: BranchCondition::True,
+        offset: -40,
+    });
+    let kernel = assemble(&program);
+
+    // the budget ends in the middle of a translated block
+    for (budget, retired) in [
+        (
+            RunBudget {
+                instructions: Some(50),
+                ..Default::default()
+            },
+            50,
+        ),
+        (
+            RunBudget {
+                cycles: Some(30),
+                ..Default::default()
+            },
+            30,
+        ),
+    ] {
+        let mut art32 = Art32::with_kernel(&kernel);
+        art32.set_block_translation(Some(1));
+        assert_eq!(art32.run(budget), RunExit::BudgetExhausted);
+        assert_eq!(art32.retired_instructions(), retired);
+    }
+}
+
+#[test]
+fn run_machine_check() {
+    let mut art32 = Art32::with_kernel(&[0xFF; 4]);
+    assert!(matches!(
+        art32.run(RunBudget::default()),
+        RunExit::MachineCheck(_)
+    ));
+}
+
+fn run_until_machine_check(art32: &mut Art32) -> MachineCheck {
+    for _ in 0..100 {
+        if let Err(machine_check) = art32.step() {
+            return machine_check;
+        }
+    }
+
+    panic!("no machine check within 100 steps");
+}
+
+#[test]
+fn exception_inside_interrupt_handler() {
+    use Instruction::*;
+    use Register::*;
+
+    let faulting = Load32 {
+        op: LoadOp::Word,
+        rd: A0,
+        rb: S0,
+        offset: 2,
+    };
+    let kernel = assemble(&[
+        Ldui {
+            rd: S0,
+            imm: KERNEL_RAM_START,
+        },
+        faulting,
+    ]);
+
+    for block_translation in [false, true] {
+        let mut art32 = Art32::with_kernel(&kernel);
+        art32.set_block_translation(block_translation.then_some(0));
+
+        let machine_check = run_until_machine_check(&mut art32);
+        assert_eq!(
+            machine_check,
+            MachineCheck {
+                program_counter: KERNEL_RAM_START + 4,
+                instruction: Some(faulting.encode().0),
+                cause: MachineCheckCause::Exception(ExceptionKind::UnalignedAccess),
+            }
+        );
+
+        // the CPU stays halted
+        let retired = art32.retired_instructions();
+        assert_eq!(art32.step(), Err(machine_check));
+        assert_eq!(art32.retired_instructions(), retired);
+
+        art32.reset();
+        assert_eq!(art32.retired_instructions(), 0);
+        assert_eq!(run_until_machine_check(&mut art32), machine_check);
+    }
+}
+
+#[test]
+fn syscall_inside_interrupt_handler() {
+    let kernel = assemble(&[
+        Instruction::Ldi16 {
+            rd: Register::A0,
+            imm: 1,
+        },
+        Instruction::Syscall(3),
+    ]);
+    let mut art32 = Art32::with_kernel(&kernel);
+
+    assert_eq!(
+        run_until_machine_check(&mut art32),
+        MachineCheck {
+            program_counter: KERNEL_RAM_START + 2,
+            instruction: Some(Instruction::Syscall(3).encode().0),
+            cause: MachineCheckCause::Syscall(3),
+        }
+    );
+}
+
+#[test]
+fn fetch_fault_inside_interrupt_handler() {
+    let kernel = assemble(&[Instruction::Jump16 {
+        link: false,
+        rb: Register::Zero,
+        offset: 0x100,
+    }]);
+    let mut art32 = Art32::with_kernel(&kernel);
+
+    assert_eq!(
+        run_until_machine_check(&mut art32),
+        MachineCheck {
+            program_counter: 0x100,
+            instruction: None,
+            cause: MachineCheckCause::Exception(ExceptionKind::FetchFault),
+        }
+    );
+}
+
+fn op<T: IntoEnumIterator + std::fmt::Debug>() -> impl Strategy<Value = T> {
+    any::<proptest::sample::Selector>().prop_map(|sel| sel.select(T::iter()))
+}
+
+fn work_reg16() -> impl Strategy<Value = Register> {
+    use Register::*;
+    proptest::sample::select(vec![A0, A1, A2, A3, A4, A5, A6, A7])
+}
+
+fn work_reg32() -> impl Strategy<Value = Register> {
+    use Register::*;
+    proptest::sample::select(vec![
+        A0, A1, A2, A3, A4, A5, A6, A7, T0, T1, T2, T3, T4, T5, T6, T7,
+    ])
+}
+
+fn source_reg() -> impl Strategy<Value = Register> {
+    prop_oneof![Just(Register::Zero), work_reg32()]
+}
+
+/// Instructions that cannot fault or leave the block when `s0` points to system RAM.
+fn straight_line() -> impl Strategy<Value = Instruction> {
+    use Register::S0;
+
+    let offset = || (0..128).prop_map(|x| x * 4);
+
+    prop_oneof![
+        (work_reg16(), -512..=511).prop_map(|(rd, imm)| Instruction::Ldi16 { rd, imm }),
+        (work_reg16(), -512..=511).prop_map(|(rd, imm)| Instruction::Addi16 { rd, imm }),
+        (op(), work_reg16(), work_reg16()).prop_map(|(op, rd, rs)| Instruction::Alu16 {
+            op,
+            rd,
+            rs
+        }),
+        (op(), work_reg32(), source_reg(), -512..=511)
+            .prop_map(|(op, rd, rs1, imm)| Instruction::AluI32 { op, rd, rs1, imm }),
+        (op::<Condition>(), work_reg32(), source_reg(), source_reg())
+            .prop_map(|(cond, rd, rs1, rs2)| Instruction::Mov32 { cond, rd, rs1, rs2 }),
+        (work_reg16(), work_reg16()).prop_map(|(rs1, rs2)| Instruction::Cmp16 { rs1, rs2 }),
+        (op(), work_reg32(), source_reg(), source_reg())
+            .prop_map(|(op, rd, rs1, rs2)| Instruction::AluC { op, rd, rs1, rs2 }),
+        (op(), work_reg32(), source_reg(), source_reg())
+            .prop_map(|(op, rd, rs1, rs2)| Instruction::MulDiv { op, rd, rs1, rs2 }),
+        (op(), work_reg32(), source_reg(), source_reg())
+            .prop_map(|(op, rd, rs1, rs2)| Instruction::Fpu3 { op, rd, rs1, rs2 }),
+        (op(), work_reg32(), offset()).prop_map(|(op, rd, offset)| Instruction::Load32 {
+            op,
+            rd,
+            rb: S0,
+            offset,
+        }),
+        (op(), source_reg(), offset()).prop_map(|(op, rs, offset)| Instruction::Store32 {
+            op,
+            rs,
+            rb: S0,
+            offset,
+        }),
+        (op(), work_reg32()).prop_map(|(op, rd)| Instruction::Ldr { op, rd, rb: S0 }),
+        (op(), work_reg32(), source_reg()).prop_map(|(op, rd, rs)| Instruction::Stc {
+            op,
+            rd,
+            rs,
+            rb: S0
+        }),
+    ]
+}
+
+/// Runs `body` in a loop of `iterations`, then breaks.
+fn loop_kernel(body: &[Instruction], iterations: i32) -> Vec<u8> {
+    use Instruction::*;
+    use Register::*;
+
+    let body_size: u32 = body.iter().map(Instruction::size).sum();
+
+    let mut program = vec![
+        Ldui {
+            rd: S0,
+            imm: SYSTEM_RAM_START,
+        },
+        Ldi16 {
+            rd: S1,
+            imm: iterations,
+        },
+    ];
+    program.extend_from_slice(body);
+    program.extend_from_slice(&[
+        Addi16 { rd: S1, imm: -1 },
+        Cmp16 { rs1: S1, rs2: Zero },
+        Branch16 {
+            cond: BranchCondition::Ne,
+            offset: -((body_size + 6) as i32),
+        },
+        Envcall(EnvAction::Break as u8),
+    ]);
+
+    assemble(&program)
+}
+
+fn run_to_break(art32: &mut Art32) {
+    for _ in 0..10_000 {
+        if let Some(action) = art32.step().unwrap() {
+            assert_eq!(action, EnvAction::Break);
+            return;
+        }
+    }
+
+    panic!("kernel did not finish");
+}
+
+#[proptest(cases = 64)]
+fn block_translation_matches_interpreter(
+    #[strategy(proptest::collection::vec(straight_line(), 1..40))] body: Vec<Instruction>,
+    #[strategy(1..8)] iterations: i32,
+) {
+    let kernel = loop_kernel(&body, iterations);
+
+    let mut interpreter = Art32::with_kernel(&kernel);
+    interpreter.set_instruction_cache(false);
+    run_to_break(&mut interpreter);
+
+    let mut translated = Art32::with_kernel(&kernel);
+    translated.set_block_translation(Some(0));
+    run_to_break(&mut translated);
+
+    prop_assert_eq!(
+        format!("{:?}", interpreter.cpu),
+        format!("{:?}", translated.cpu)
+    );
+    prop_assert_eq!(
+        format!("{:?}", interpreter.reservation),
+        format!("{:?}", translated.reservation)
+    );
+
+    for addr in (0..SYSTEM_RAM_SIZE.min(0x400)).step_by(4) {
+        prop_assert_eq!(
+            system_ram(&interpreter).read_32(addr),
+            system_ram(&translated).read_32(addr)
+        );
+    }
+}
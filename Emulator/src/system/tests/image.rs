@@ -0,0 +1,249 @@
+use super::super::{
+    Art32, Image, ImageError, ImageFormat, ImageSource, MachineDescription, Segment,
+    KERNEL_RAM_START, SYSTEM_RAM_START,
+};
+use super::{kernel_ram, system_ram};
+use std::path::Path;
+
+/// Builds an Intel HEX record with its byte count and checksum.
+fn record(kind: u8, offset: u16, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend(offset.to_be_bytes());
+    bytes.push(kind);
+    bytes.extend(data);
+    let checksum = bytes.iter().fold(0u8, |sum, &b| sum.wrapping_sub(b));
+    bytes.push(checksum);
+
+    let hex: String = bytes.iter().map(|b| format!("{b:0>2X}")).collect();
+    format!(":{hex}\n")
+}
+
+fn segment(addr: u32, data: &[u8]) -> Segment {
+    Segment {
+        addr,
+        data: data.to_vec(),
+    }
+}
+
+#[test]
+fn detect() {
+    let detect = ImageFormat::detect;
+    assert_eq!(detect(Path::new("a.bin"), b"\x7FELF\x01"), ImageFormat::Elf);
+    assert_eq!(
+        detect(Path::new("a.hex"), b":00000001FF"),
+        ImageFormat::IntelHex
+    );
+    assert_eq!(detect(Path::new("a.bin"), b":00000001FF"), ImageFormat::Raw);
+}
+
+#[test]
+fn source() {
+    let source = |s: &str| s.parse::<ImageSource>().unwrap();
+
+    assert_eq!(
+        source("kernel.bin@0x1000_0000"),
+        ImageSource {
+            path: "kernel.bin".into(),
+            addr: Some(0x1000_0000),
+        }
+    );
+    assert_eq!(
+        source("build@2/kernel.elf"),
+        ImageSource {
+            path: "build@2/kernel.elf".into(),
+            addr: None,
+        }
+    );
+    assert_eq!(
+        source("build@2/kernel.bin@4096"),
+        ImageSource {
+            path: "build@2/kernel.bin".into(),
+            addr: Some(4096),
+        }
+    );
+}
+
+#[test]
+fn raw() {
+    assert!(matches!(
+        Image::parse(&[1, 2], ImageFormat::Raw, None, &[]),
+        Err(ImageError::Format(_))
+    ));
+    assert_eq!(
+        Image::parse(&[1, 2], ImageFormat::Raw, Some(0x100), &[]).unwrap(),
+        Image::raw(0x100, &[1, 2])
+    );
+    assert!(matches!(
+        Image::parse(&[], ImageFormat::Raw, Some(0x100), &[]),
+        Err(ImageError::Format(_))
+    ));
+}
+
+#[test]
+fn intel_hex() {
+    let hex = [
+        record(0x04, 0, &[0x20, 0x00]),
+        record(0x00, 0x0010, &[1, 2, 3]),
+        record(0x00, 0x0013, &[4]),
+        record(0x00, 0x0100, &[5, 6]),
+        record(0x02, 0, &[0x10, 0x00]),
+        record(0x00, 0x0002, &[7]),
+        record(0x05, 0, &[0x20, 0x00, 0x00, 0x10]),
+        record(0x01, 0, &[]),
+    ]
+    .concat();
+
+    let image = Image::parse(hex.as_bytes(), ImageFormat::IntelHex, None, &[]).unwrap();
+    assert_eq!(
+        image.segments,
+        [
+            segment(0x2000_0010, &[1, 2, 3, 4]),
+            segment(0x2000_0100, &[5, 6]),
+            segment(0x0001_0002, &[7]),
+        ]
+    );
+
+    let invalid = |hex: String| match Image::parse(hex.as_bytes(), ImageFormat::IntelHex, None, &[])
+    {
+        Err(ImageError::Format(msg)) => msg,
+        result => panic!("{result:?}"),
+    };
+    let corrupt = record(0x00, 0x0010, &[1, 2, 3]).replace("010203", "010204");
+    assert!(invalid(corrupt).contains("checksum"));
+    assert!(invalid(record(0x00, 0, &[1]).replacen(':', "", 1)).contains("line 1"));
+    assert!(invalid(format!("\n{}", record(0x06, 0, &[]))).starts_with("line 2"));
+    assert!(invalid(":0100".to_owned()).contains("truncated"));
+    // multi byte characters must not split the digits
+    assert!(invalid(":00é0000001FF".to_owned()).contains("hexadecimal"));
+}
+
+/// A 32 bit little endian ELF file with a program header for every segment, given as physical
+/// address, data and memory size.
+fn elf(segments: &[(u32, u32, &[u8], u32)]) -> Vec<u8> {
+    const HEADER_SIZE: usize = 0x34;
+    const PROGRAM_HEADER_SIZE: usize = 0x20;
+
+    let mut elf = vec![0; HEADER_SIZE];
+    elf[..7].copy_from_slice(b"\x7FELF\x01\x01\x01");
+    elf[0x1C..0x20].copy_from_slice(&(HEADER_SIZE as u32).to_le_bytes());
+    elf[0x2A..0x2C].copy_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    elf[0x2C..0x2E].copy_from_slice(&(segments.len() as u16).to_le_bytes());
+
+    let mut offset = HEADER_SIZE + (segments.len() * PROGRAM_HEADER_SIZE);
+    let mut data: Vec<u8> = Vec::new();
+    for &(kind, addr, bytes, mem_size) in segments {
+        // the virtual address differs, only the physical one counts
+        for word in [
+            kind,
+            offset as u32,
+            !addr,
+            addr,
+            bytes.len() as u32,
+            mem_size,
+            0,
+            0,
+        ] {
+            elf.extend(word.to_le_bytes());
+        }
+        data.extend(bytes);
+        offset += bytes.len();
+    }
+    elf.extend(data);
+    elf
+}
+
+#[test]
+fn elf_segments() {
+    let bytes = elf(&[
+        (1, 0x1000_0000, &[1, 2, 3, 4], 4),
+        // not loadable
+        (4, 0x1000_1000, &[9], 1),
+        (1, 0x2000_0000, &[5, 6], 5),
+    ]);
+
+    let memory = MachineDescription::art32().memory;
+    let image = Image::parse(&bytes, ImageFormat::Elf, Some(0x100), &memory).unwrap();
+    assert_eq!(
+        image.segments,
+        [
+            segment(0x1000_0000, &[1, 2, 3, 4]),
+            segment(0x2000_0000, &[5, 6, 0, 0, 0]),
+        ]
+    );
+
+    let invalid = |bytes: &[u8]| {
+        matches!(
+            Image::parse(bytes, ImageFormat::Elf, None, &memory),
+            Err(ImageError::Format(_))
+        )
+    };
+    assert!(invalid(&bytes[..0x40]));
+    assert!(invalid(&bytes[..(bytes.len() - 1)]));
+    assert!(invalid(&elf(&[(1, 0x1000_0000, &[1, 2], 1)])));
+
+    let mut big_endian = bytes.clone();
+    big_endian[5] = 2;
+    assert!(invalid(&big_endian));
+
+    // the memory size is checked before it gets allocated
+    for segment in [
+        (1, KERNEL_RAM_START, &[1u8][..], u32::MAX),
+        (1, 0x3000_0000, &[1u8][..], 1),
+    ] {
+        assert!(matches!(
+            Image::parse(&elf(&[segment]), ImageFormat::Elf, None, &memory),
+            Err(ImageError::Placement(_))
+        ));
+    }
+}
+
+#[test]
+fn flatten() {
+    let machine = MachineDescription::art32();
+    let kernel_region = machine.kernel_region();
+
+    let image = Image {
+        segments: vec![
+            segment(KERNEL_RAM_START + 4, &[1, 2]),
+            segment(KERNEL_RAM_START, &[3]),
+        ],
+    };
+    assert_eq!(image.flatten(kernel_region).unwrap(), [3, 0, 0, 0, 1, 2]);
+
+    let outside = Image::raw(KERNEL_RAM_START + 0x7FFF, &[1, 2]);
+    assert!(matches!(
+        outside.flatten(kernel_region),
+        Err(ImageError::Placement(_))
+    ));
+}
+
+#[test]
+fn load_image() {
+    let mut art32 = Art32::with_kernel(&[]);
+
+    let image = Image {
+        segments: vec![
+            segment(SYSTEM_RAM_START + 0x10, &[1, 2, 3, 4]),
+            segment(KERNEL_RAM_START + 0x20, &[5, 6, 7, 8]),
+        ],
+    };
+    art32.load_image(&image).unwrap();
+    art32.reset();
+    assert_eq!(system_ram(&art32).read_32(0x10), 0x0403_0201);
+    assert_eq!(kernel_ram(&art32).read_32(0x20), 0x0807_0605);
+
+    // nothing gets loaded if any segment does not fit
+    for segment in [
+        segment(0x3000_0000, &[1]),
+        segment(KERNEL_RAM_START + 0x7FFE, &[1, 2, 3]),
+    ] {
+        let image = Image {
+            segments: vec![self::segment(SYSTEM_RAM_START, &[9]), segment],
+        };
+        assert!(matches!(
+            art32.load_image(&image),
+            Err(ImageError::Placement(_))
+        ));
+        assert_eq!(system_ram(&art32).read_8(0), 0);
+    }
+}
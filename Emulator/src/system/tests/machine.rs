@@ -0,0 +1,336 @@
+use super::super::vdp::Vdp;
+use super::super::{Art32, EnvAction, MachineDescription, MachineError, SerialOutput};
+use super::{kernel_ram, mmu, run_until_env_action, run_until_machine_check};
+use crate::cpu::instruction::*;
+use crate::cpu::interface::{MemoryInterface, PrivilegeLevel};
+use crate::cpu::{BranchCondition, ExceptionKind, MachineCheckCause, Register};
+
+const VARIANT: &str = r#"{
+    "name": "variant",
+    "boot_pc": "0x100",
+    "memory": [
+        { "name": "rom", "base": 0, "size": "0x1000", "access": "rx", "system_only": true, "kernel": true },
+        { "name": "data", "base": "0x4000_0000", "size": 4096, "access": "rw" }
+    ],
+    "devices": { "serial": "0b1_1000_0000" }
+}"#;
+
+#[test]
+fn art32_file() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/machines/art32.json");
+    let machine = MachineDescription::load(path).unwrap();
+    assert_eq!(machine, MachineDescription::art32());
+}
+
+#[test]
+fn softcore_file() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/machines/softcore.json");
+    let machine = MachineDescription::load(path).unwrap();
+    assert_eq!(machine, MachineDescription::softcore());
+    assert_eq!(MachineDescription::builtin("softcore"), Some(machine));
+}
+
+#[test]
+fn softcore() {
+    use Instruction::*;
+    use Register::*;
+
+    let out = |rs, rb, offset| Out { rs, rb, offset };
+    let read = |rb, offset| In { rd: A1, rb, offset };
+    let store = |offset| Store32 {
+        op: StoreOp::Word,
+        rs: A1,
+        rb: S0,
+        offset,
+    };
+
+    let kernel = assemble(&[
+        Ldui {
+            rd: S0,
+            imm: 0x0100_0000,
+        },
+        AluI32 {
+            op: AluOp::Add,
+            rd: A0,
+            rs1: Zero,
+            imm: 'k' as i32,
+        },
+        out(A0, Zero, 0x1),
+        // nothing is decoded here
+        read(Zero, 0xE),
+        store(0),
+        // the CPU ports moved away from 0x20
+        read(Zero, 0x20),
+        store(4),
+        Ldui {
+            rd: A2,
+            imm: 0x0300_0000,
+        },
+        Load32 {
+            op: LoadOp::Word,
+            rd: A1,
+            rb: A2,
+            offset: 0,
+        },
+        store(8),
+        Ldi16 { rd: A0, imm: -1 },
+        out(A0, Zero, 0xF),
+        out(A0, Zero, 0x4),
+        read(Zero, 0x4),
+        store(12),
+        Ldui {
+            rd: A3,
+            imm: 0x1000,
+        },
+        out(A0, A3, -1),
+        read(A3, -1),
+        store(16),
+        out(A0, A3, 0x20),
+        read(A3, 0x20),
+        store(20),
+        Envcall(EnvAction::Break as u8),
+    ]);
+
+    let mut art32 = Art32::with_machine(&MachineDescription::softcore(), &kernel).unwrap();
+    art32.set_serial_output(Some(SerialOutput::Buffer)).unwrap();
+    assert_eq!(run_until_env_action(&mut art32), EnvAction::Break);
+
+    assert_eq!(art32.take_serial_output(), b"k");
+    assert_eq!(art32.led(), 0x00FF_FFFF);
+
+    let mut mmu = mmu(&mut art32);
+    let results: Vec<_> = (0..6)
+        .map(|i| mmu.read_32(0x0100_0000 + (i * 4), PrivilegeLevel::System, false))
+        .collect();
+    assert_eq!(
+        results,
+        [
+            Ok(0xAAAA_AAAA),
+            Ok(0xAAAA_AAAA),
+            Ok(0xAAAA_AAAA),
+            Ok(0x1FF),
+            Ok(0xFFFF_FFFC),
+            Ok(0xFFFF_FFFE),
+        ]
+    );
+
+    // the kernel RAM is invisible to user code
+    assert_eq!(mmu.read_32(0, PrivilegeLevel::User, false), Ok(0xAAAA_AAAA));
+    assert_eq!(mmu.write_32(0, 0, PrivilegeLevel::User, false), Ok(true));
+    assert!(mmu.fetch_16(0x0300_0000, PrivilegeLevel::System).is_err());
+    assert_eq!(
+        kernel_ram(&art32).read_32(0),
+        u32::from_le_bytes(kernel[..4].try_into().unwrap())
+    );
+}
+
+#[test]
+fn vdp_blanking() {
+    const LINE: u64 = 1056;
+
+    assert_eq!(Vdp::blanking(0), (true, true));
+    assert_eq!(Vdp::blanking(256), (false, true));
+    assert_eq!(Vdp::blanking((LINE * 28) + 255), (true, false));
+    assert_eq!(Vdp::blanking((LINE * 28) + 256), (false, false));
+    assert_eq!(Vdp::blanking((LINE * 628) + 256), (false, true));
+}
+
+#[test]
+fn variant() {
+    use Instruction::*;
+    use Register::*;
+
+    let machine = MachineDescription::from_json(VARIANT).unwrap();
+    assert_eq!(machine.devices.serial, Some(0x180));
+    assert_eq!(machine.devices.timer, None);
+
+    let char = |c: char| AluI32 {
+        op: AluOp::Add,
+        rd: A0,
+        rs1: Zero,
+        imm: c as i32,
+    };
+    let out = Out {
+        rs: A0,
+        rb: Zero,
+        offset: 0x180,
+    };
+
+    let mut kernel = vec![0; 0x100];
+    kernel.extend(assemble(&[
+        char('h'),
+        out,
+        char('i'),
+        out,
+        Ldui {
+            rd: A1,
+            imm: 0x4000_0000,
+        },
+        Store32 {
+            op: StoreOp::Word,
+            rs: A0,
+            rb: A1,
+            offset: 4,
+        },
+        // there is no timer on this board
+        In {
+            rd: A0,
+            rb: Zero,
+            offset: 0x80,
+        },
+    ]));
+
+    let mut art32 = Art32::with_machine(&machine, &kernel).unwrap();
+    art32.set_serial_output(Some(SerialOutput::Buffer)).unwrap();
+
+    let machine_check = run_until_machine_check(&mut art32);
+    assert_eq!(machine_check.program_counter, 0x100 + 24);
+    assert_eq!(
+        machine_check.cause,
+        MachineCheckCause::Exception(ExceptionKind::AccessViolation)
+    );
+
+    assert_eq!(art32.take_serial_output(), b"hi");
+    assert_eq!(
+        mmu(&mut art32).read_32(0x4000_0004, PrivilegeLevel::System, false),
+        Ok('i' as u32)
+    );
+    // the ROM is not writable, the data RAM not executable
+    assert!(mmu(&mut art32)
+        .write_32(0x100, 0, PrivilegeLevel::System, false)
+        .is_err());
+    assert!(mmu(&mut art32)
+        .fetch_16(0x4000_0000, PrivilegeLevel::System)
+        .is_err());
+}
+
+#[test]
+fn test_exit() {
+    use Instruction::*;
+    use Register::*;
+
+    let machine = MachineDescription::from_json(&VARIANT.replace(
+        r#""serial": "0b1_1000_0000""#,
+        r#""serial": "0b1_1000_0000", "test_exit": "0x1F0""#,
+    ))
+    .unwrap();
+    assert_eq!(machine.devices.test_exit, Some(0x1F0));
+
+    let exit = |code: i32| {
+        let mut kernel = vec![0; 0x100];
+        kernel.extend(assemble(&[
+            AluI32 {
+                op: AluOp::Add,
+                rd: A0,
+                rs1: Zero,
+                imm: code,
+            },
+            Out {
+                rs: A0,
+                rb: Zero,
+                offset: 0x1F0,
+            },
+            Envcall(EnvAction::Error as u8),
+        ]));
+        let mut art32 = Art32::with_machine(&machine, &kernel).unwrap();
+        run_until_env_action(&mut art32)
+    };
+    assert_eq!(exit(0), EnvAction::Pass);
+    assert_eq!(exit(1), EnvAction::Fail);
+    assert_eq!(exit(-1), EnvAction::Fail);
+
+    // the port is write-only
+    assert!(MachineDescription::art32().devices.test_exit.is_none());
+    let mut kernel = vec![0; 0x100];
+    kernel.extend(assemble(&[In {
+        rd: A0,
+        rb: Zero,
+        offset: 0x1F0,
+    }]));
+    let mut art32 = Art32::with_machine(&machine, &kernel).unwrap();
+    assert_eq!(
+        run_until_machine_check(&mut art32).cause,
+        MachineCheckCause::Exception(ExceptionKind::AccessViolation)
+    );
+
+    // an exit still pending from before a reset does not end the next run
+    art32.test_exit = Some(EnvAction::Fail);
+    art32.reset();
+    assert_eq!(art32.test_exit, None);
+}
+
+#[test]
+fn timing_model() {
+    let mut kernel = vec![0; 0x100];
+    kernel.extend(assemble(&[
+        Instruction::Branch16 {
+            cond: BranchCondition::True,
+            offset: 0,
+        },
+        Instruction::Envcall(EnvAction::Break as u8),
+    ]));
+
+    let run = |json: &str| {
+        let machine = MachineDescription::from_json(json).unwrap();
+        let mut art32 = Art32::with_machine(&machine, &kernel).unwrap();
+        assert_eq!(run_until_env_action(&mut art32), EnvAction::Break);
+        (art32.retired_instructions(), art32.cycles())
+    };
+
+    // the taken branch stalls fetching only with the timing model
+    assert_eq!(run(VARIANT), (2, 2));
+    let (retired, cycles) =
+        run(&VARIANT.replace(r#""devices""#, r#""timing_model": true, "devices""#));
+    assert_eq!(retired, 2);
+    assert!(cycles > retired);
+}
+
+#[test]
+fn invalid() {
+    let invalid = |from: &str, to: &str| {
+        assert!(VARIANT.contains(from));
+        MachineDescription::from_json(&VARIANT.replace(from, to))
+    };
+
+    for (from, to) in [
+        (r#""base": "0x4000_0000""#, r#""base": "0x800""#),
+        (r#""kernel": true"#, r#""kernel": false"#),
+        (r#""boot_pc": "0x100""#, r#""boot_pc": "0x4000_0000""#),
+        (r#""boot_pc": "0x100""#, r#""boot_pc": "0x101""#),
+        (r#""size": 4096"#, r#""size": 0"#),
+        (r#""size": 4096"#, r#""size": 4098"#),
+        (r#""serial": "0b1_1000_0000""#, r#""serial": "0x40""#),
+        (
+            r#""serial": "0b1_1000_0000""#,
+            r#""serial": 128, "serial_controller": 132"#,
+        ),
+        (
+            r#""serial": "0b1_1000_0000""#,
+            r#""serial": 128, "timer": 132"#,
+        ),
+    ] {
+        assert!(
+            matches!(invalid(from, to), Err(MachineError::Invalid(_))),
+            "{to}"
+        );
+    }
+
+    for (from, to) in [
+        (r#""access": "rw""#, r#""access": "rwz""#),
+        (r#""access": "rw""#, r#""acess": "rw""#),
+        (r#""serial""#, r#""uart""#),
+        (r#""base": 0"#, r#""base": "zero""#),
+        (r#""size": 4096"#, r#""size": 4294967296"#),
+    ] {
+        assert!(
+            matches!(invalid(from, to), Err(MachineError::Parse(_))),
+            "{to}"
+        );
+    }
+
+    let machine = MachineDescription::from_json(VARIANT).unwrap();
+    assert!(matches!(
+        Art32::with_machine(&machine, &[0; 0x1004]),
+        Err(MachineError::Invalid(_))
+    ));
+}
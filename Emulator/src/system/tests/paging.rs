@@ -0,0 +1,428 @@
+use super::super::paging::{PageFlags, PagingControl};
+use super::super::{Art32, KERNEL_RAM_START, SYSTEM_RAM_START};
+use super::{kernel_ram, kernel_ram_mut, mmu, system_ram, system_ram_mut};
+use crate::cpu::cache::InstructionCache;
+use crate::cpu::instruction::*;
+use crate::cpu::interface::{MemoryError, MemoryInterface, PrivilegeLevel};
+use crate::cpu::{BranchCondition, Register};
+
+const DIRECTORY: u32 = 0x1000;
+const KERNEL_TABLE: u32 = 0x2000;
+const USER_TABLE: u32 = 0x3000;
+
+const USER_BASE: u32 = 0x0040_0000;
+const CODE_PAGE: u32 = USER_BASE;
+const DATA_PAGE: u32 = USER_BASE + 0x1000;
+const READ_ONLY_PAGE: u32 = USER_BASE + 0x2000;
+
+const HANDLER: i32 = 0x80;
+const CAUSE: u32 = 0xF0;
+const FAULT_ADDRESS: u32 = 0xF4;
+
+fn entry(physical: u32, flags: PageFlags) -> [u8; 4] {
+    (physical | (flags | PageFlags::VALID).bits()).to_le_bytes()
+}
+
+/// Page tables in kernel RAM, the first kernel page is identity mapped for system code and
+/// three user pages map to the start of system RAM.
+fn page_tables(image: &mut Vec<u8>) {
+    use PageFlags as F;
+
+    let mut put = |offset: u32, entry: [u8; 4]| {
+        let offset = offset as usize;
+        if image.len() < offset + 4 {
+            image.resize(offset + 4, 0);
+        }
+        image[offset..(offset + 4)].copy_from_slice(&entry);
+    };
+
+    put(
+        DIRECTORY + ((KERNEL_RAM_START >> 22) * 4),
+        entry(KERNEL_RAM_START + KERNEL_TABLE, F::empty()),
+    );
+    put(
+        KERNEL_TABLE + (((KERNEL_RAM_START >> 12) & 0x3FF) * 4),
+        entry(KERNEL_RAM_START, F::READ | F::WRITE | F::EXECUTE),
+    );
+
+    put(
+        DIRECTORY + ((USER_BASE >> 22) * 4),
+        entry(KERNEL_RAM_START + USER_TABLE, F::empty()),
+    );
+    for (page, flags) in [F::READ | F::EXECUTE, F::READ | F::WRITE, F::READ]
+        .into_iter()
+        .enumerate()
+    {
+        put(
+            USER_TABLE + ((page as u32) * 4),
+            entry(SYSTEM_RAM_START + ((page as u32) * 0x1000), flags | F::USER),
+        );
+    }
+}
+
+/// Enables paging and runs user code at `USER_BASE`, page and fetch faults are recorded in
+/// kernel RAM.
+fn paging_kernel() -> Vec<u8> {
+    use Instruction::*;
+    use Register::*;
+
+    let out = |offset| Out {
+        rs: A0,
+        rb: Zero,
+        offset,
+    };
+    let store = |offset| Store32 {
+        op: StoreOp::Word,
+        rs: A0,
+        rb: S0,
+        offset,
+    };
+
+    let mut image = assemble(&[
+        Ldui {
+            rd: S0,
+            imm: KERNEL_RAM_START,
+        },
+        AluI32 {
+            op: AluOp::Add,
+            rd: A0,
+            rs1: S0,
+            imm: HANDLER,
+        },
+        out(0x23),
+        out(0x24),
+        Ldui {
+            rd: A0,
+            imm: KERNEL_RAM_START + DIRECTORY,
+        },
+        out(0xA0),
+        Ldi16 { rd: A0, imm: 1 },
+        out(0xA1),
+        Ldui {
+            rd: A0,
+            imm: USER_BASE,
+        },
+        out(0x33),
+        Ldi16 { rd: A0, imm: 1 },
+        out(0x32),
+        Sysret,
+    ]);
+
+    image.resize(HANDLER as usize, 0);
+    image.extend(assemble(&[
+        In {
+            rd: A0,
+            rb: Zero,
+            offset: 0x34,
+        },
+        store(CAUSE as i32),
+        In {
+            rd: A0,
+            rb: Zero,
+            offset: 0x35,
+        },
+        store(FAULT_ADDRESS as i32),
+        Branch16 {
+            cond: BranchCondition::True,
+            offset: -2,
+        },
+    ]));
+
+    page_tables(&mut image);
+    image
+}
+
+/// Runs `program` as user code, returning the recorded page fault cause and address.
+fn run_user(program: &[Instruction]) -> (Art32, u32, u32) {
+    run_user_with(program, false)
+}
+
+fn run_user_with(program: &[Instruction], block_translation: bool) -> (Art32, u32, u32) {
+    let mut art32 = Art32::with_kernel(&paging_kernel());
+    art32.set_block_translation(block_translation.then_some(0));
+
+    let mut code = assemble(program);
+    code.extend(assemble(&[Instruction::Branch16 {
+        cond: BranchCondition::True,
+        offset: -2,
+    }]));
+    for (offset, &byte) in code.iter().enumerate() {
+        system_ram_mut(&mut art32).write_8(offset as u32, byte);
+    }
+
+    for _ in 0..100 {
+        assert_eq!(art32.step(), Ok(None));
+    }
+
+    let cause = kernel_ram(&art32).read_32(CAUSE);
+    let addr = kernel_ram(&art32).read_32(FAULT_ADDRESS);
+    (art32, cause, addr)
+}
+
+fn load_address(addr: u32) -> Instruction {
+    Instruction::Ldui {
+        rd: Register::A0,
+        imm: addr,
+    }
+}
+
+fn load(offset: i32) -> Instruction {
+    Instruction::Load32 {
+        op: LoadOp::Word,
+        rd: Register::A1,
+        rb: Register::A0,
+        offset,
+    }
+}
+
+fn store(offset: i32) -> Instruction {
+    Instruction::Store32 {
+        op: StoreOp::Word,
+        rs: Register::A1,
+        rb: Register::A0,
+        offset,
+    }
+}
+
+const PAGE_FAULT: u32 = 3;
+const FETCH_FAULT: u32 = 4;
+const FETCH: u32 = 1 << 8;
+const LOAD: u32 = 2 << 8;
+const STORE: u32 = 3 << 8;
+
+#[test]
+fn user_access() {
+    let (art32, cause, _) = run_user(&[
+        load_address(DATA_PAGE),
+        Instruction::Ldi16 {
+            rd: Register::A1,
+            imm: 5,
+        },
+        store(4),
+        load(4),
+    ]);
+
+    assert_eq!(cause, 0);
+    assert_eq!(system_ram(&art32).read_32(0x1004), 5);
+}
+
+#[test]
+fn read_only_page() {
+    let (_, cause, addr) = run_user(&[load_address(READ_ONLY_PAGE), load(8), store(8)]);
+    assert_eq!(cause, STORE | PAGE_FAULT);
+    assert_eq!(addr, READ_ONLY_PAGE + 8);
+}
+
+#[test]
+fn unmapped_page() {
+    let (_, cause, addr) = run_user(&[load_address(0x0080_0000), load(4)]);
+    assert_eq!(cause, LOAD | PAGE_FAULT);
+    assert_eq!(addr, 0x0080_0004);
+}
+
+#[test]
+fn system_page() {
+    let (_, cause, addr) = run_user(&[load_address(KERNEL_RAM_START), load(0)]);
+    assert_eq!(cause, LOAD | PAGE_FAULT);
+    assert_eq!(addr, KERNEL_RAM_START);
+}
+
+#[test]
+fn no_execute() {
+    let (_, cause, addr) = run_user(&[
+        load_address(DATA_PAGE),
+        Instruction::Jump16 {
+            link: false,
+            rb: Register::A0,
+            offset: 0,
+        },
+    ]);
+    assert_eq!(cause, (PAGE_FAULT << 16) | FETCH | FETCH_FAULT);
+    assert_eq!(addr, DATA_PAGE);
+}
+
+#[test]
+fn translated_blocks() {
+    let program = [
+        load_address(DATA_PAGE),
+        Instruction::Ldi16 {
+            rd: Register::A1,
+            imm: 5,
+        },
+        store(0),
+        Instruction::Jump16 {
+            link: false,
+            rb: Register::A0,
+            offset: 0,
+        },
+    ];
+
+    let (art32, cause, addr) = run_user_with(&program, true);
+    assert_eq!(cause, (PAGE_FAULT << 16) | FETCH | FETCH_FAULT);
+    assert_eq!(addr, DATA_PAGE);
+    assert_eq!(system_ram(&art32).read_32(0x1000), 5);
+}
+
+fn paged_system() -> Art32 {
+    let mut image = Vec::new();
+    page_tables(&mut image);
+
+    let art32 = Art32::with_kernel(&image);
+    art32.paging.set_base(KERNEL_RAM_START + DIRECTORY);
+    art32.paging.set_control(PagingControl::ENABLE);
+    art32
+}
+
+#[test]
+fn disabled() {
+    let mut art32 = paged_system();
+    art32.paging.set_control(PagingControl::empty());
+    system_ram_mut(&mut art32).write_32(0x1000, 0x1234_5678);
+
+    let mut mmu = mmu(&mut art32);
+    let addr = SYSTEM_RAM_START + 0x1000;
+    assert_eq!(
+        mmu.read_32(addr, PrivilegeLevel::User, false),
+        Ok(0x1234_5678)
+    );
+    assert_eq!(
+        mmu.read_32(DATA_PAGE, PrivilegeLevel::User, false),
+        Err(MemoryError::AccessViolation)
+    );
+}
+
+#[test]
+fn aliased_code_invalidated() {
+    let mut art32 = paged_system();
+    let alias = USER_BASE + 0x3000;
+    kernel_ram_mut(&mut art32).write_32(
+        USER_TABLE + 12,
+        u32::from_le_bytes(entry(
+            SYSTEM_RAM_START,
+            PageFlags::READ | PageFlags::WRITE | PageFlags::USER,
+        )),
+    );
+    let (ldi, _) = Instruction::Ldi16 {
+        rd: Register::A0,
+        imm: 1,
+    }
+    .encode();
+    system_ram_mut(&mut art32).write_16(0, ldi as u16);
+
+    let mut cache = InstructionCache::new();
+    let mut mmu = mmu(&mut art32);
+    mmu.instruction_cache = Some(&mut cache);
+    let fetched = mmu.fetch(CODE_PAGE, PrivilegeLevel::User).unwrap();
+    assert_eq!(fetched.instruction, Instruction::decode(ldi));
+
+    // writing through another mapping of the same memory drops the cached instruction
+    let (ret, _) = Instruction::Ret.encode();
+    assert_eq!(
+        mmu.write_16(alias, ret as u16, PrivilegeLevel::User, false),
+        Ok(true)
+    );
+    let fetched = mmu.fetch(CODE_PAGE, PrivilegeLevel::User).unwrap();
+    assert_eq!(fetched.instruction, Ok(Instruction::Ret));
+}
+
+#[test]
+fn permissions() {
+    let mut art32 = paged_system();
+    let mut mmu = mmu(&mut art32);
+
+    // system code may use user pages, but still needs the access permission
+    assert_eq!(
+        mmu.write_32(DATA_PAGE, 7, PrivilegeLevel::System, false),
+        Ok(true)
+    );
+    assert_eq!(mmu.read_32(DATA_PAGE, PrivilegeLevel::User, false), Ok(7));
+    assert_eq!(
+        mmu.write_32(READ_ONLY_PAGE, 7, PrivilegeLevel::System, false),
+        Err(MemoryError::PageFault)
+    );
+
+    assert_eq!(
+        mmu.read_32(KERNEL_RAM_START, PrivilegeLevel::User, false),
+        Err(MemoryError::PageFault)
+    );
+    assert!(mmu
+        .read_32(KERNEL_RAM_START, PrivilegeLevel::System, false)
+        .is_ok());
+
+    assert_eq!(
+        mmu.fetch(READ_ONLY_PAGE, PrivilegeLevel::User),
+        Err(MemoryError::PageFault)
+    );
+    assert!(mmu.fetch(CODE_PAGE, PrivilegeLevel::User).is_ok());
+
+    // unaligned accesses are reported before translating
+    assert_eq!(
+        mmu.read_32(0x0080_0002, PrivilegeLevel::User, false),
+        Err(MemoryError::UnalignedAccess)
+    );
+}
+
+#[test]
+fn tlb_flush() {
+    let mut art32 = paged_system();
+    system_ram_mut(&mut art32).write_32(0x1000, 1);
+    system_ram_mut(&mut art32).write_32(0x2000, 2);
+
+    assert_eq!(
+        mmu(&mut art32).read_32(DATA_PAGE, PrivilegeLevel::User, false),
+        Ok(1)
+    );
+
+    // remap the data page onto the read only one, the TLB still has the old entry
+    let entry = u32::from_le_bytes(entry(
+        SYSTEM_RAM_START + 0x2000,
+        PageFlags::READ | PageFlags::USER,
+    ));
+    kernel_ram_mut(&mut art32).write_32(USER_TABLE + 4, entry);
+    assert_eq!(
+        mmu(&mut art32).read_32(DATA_PAGE, PrivilegeLevel::User, false),
+        Ok(1)
+    );
+
+    art32.paging.flush();
+    assert_eq!(
+        mmu(&mut art32).read_32(DATA_PAGE, PrivilegeLevel::User, false),
+        Ok(2)
+    );
+}
+
+#[test]
+fn debug_access() {
+    let mut art32 = paged_system();
+    system_ram_mut(&mut art32).write_32(0x1000, 1);
+    system_ram_mut(&mut art32).write_32(0x2000, 2);
+
+    let mut bytes = [0; 4];
+    assert_eq!(art32.read_memory(DATA_PAGE, &mut bytes), 4);
+    assert_eq!(u32::from_le_bytes(bytes), 1);
+
+    // the debugger did not fill the TLB, so the remapped page shows up without a flush
+    let entry = u32::from_le_bytes(entry(
+        SYSTEM_RAM_START + 0x2000,
+        PageFlags::READ | PageFlags::USER,
+    ));
+    kernel_ram_mut(&mut art32).write_32(USER_TABLE + 4, entry);
+    assert_eq!(
+        mmu(&mut art32).read_32(DATA_PAGE, PrivilegeLevel::User, false),
+        Ok(2)
+    );
+
+    // page permissions do not apply, unmapped pages are not accessible
+    assert_eq!(art32.write_memory(READ_ONLY_PAGE, &[3, 0, 0, 0]), 4);
+    assert_eq!(system_ram(&art32).read_32(0x2000), 3);
+    assert_eq!(art32.read_memory(USER_BASE + 0x3000, &mut bytes), 0);
+}
+
+#[test]
+fn reset_disables_paging() {
+    let mut art32 = paged_system();
+    art32.reset();
+
+    assert_eq!(art32.paging.control(), PagingControl::empty());
+    assert_eq!(art32.paging.base(), 0);
+}
@@ -0,0 +1,307 @@
+use super::super::protection::{ProtectionControl, Region, RegionFlags};
+use super::super::{Art32, KERNEL_RAM_START, SYSTEM_RAM_START};
+use super::{kernel_ram, mmu, system_ram, system_ram_mut};
+use crate::cpu::cache::InstructionCache;
+use crate::cpu::instruction::*;
+use crate::cpu::interface::{MemoryError, MemoryInterface, PrivilegeLevel};
+use crate::cpu::{BranchCondition, Register};
+
+const CODE: u32 = SYSTEM_RAM_START;
+const DATA: u32 = SYSTEM_RAM_START + 0x1000;
+const REGION_SIZE: i32 = 0x100;
+
+const HANDLER: i32 = 0x80;
+const CAUSE: u32 = 0xF0;
+const FAULT_ADDRESS: u32 = 0xF4;
+
+/// Confines user code to an executable and a writable region, access violations and fetch
+/// faults are recorded in kernel RAM.
+fn protection_kernel() -> Vec<u8> {
+    use Instruction::*;
+    use Register::*;
+
+    let out = |offset| Out {
+        rs: A0,
+        rb: Zero,
+        offset,
+    };
+    let store = |offset| Store32 {
+        op: StoreOp::Word,
+        rs: A0,
+        rb: S0,
+        offset,
+    };
+    let size = AluI32 {
+        op: AluOp::Add,
+        rd: A0,
+        rs1: Zero,
+        imm: REGION_SIZE,
+    };
+
+    let mut image = assemble(&[
+        Ldui {
+            rd: S0,
+            imm: KERNEL_RAM_START,
+        },
+        AluI32 {
+            op: AluOp::Add,
+            rd: A0,
+            rs1: S0,
+            imm: HANDLER,
+        },
+        out(0x21),
+        out(0x24),
+        Ldui { rd: A0, imm: CODE },
+        out(0xB0),
+        size,
+        out(0xB1),
+        Ldi16 {
+            rd: A0,
+            imm: (RegionFlags::READ | RegionFlags::EXECUTE).bits() as i32,
+        },
+        out(0xB2),
+        Ldui { rd: A0, imm: DATA },
+        out(0xB4),
+        size,
+        out(0xB5),
+        Ldi16 {
+            rd: A0,
+            imm: (RegionFlags::READ | RegionFlags::WRITE).bits() as i32,
+        },
+        out(0xB6),
+        Ldi16 { rd: A0, imm: 1 },
+        out(0xA8),
+        Ldui { rd: A0, imm: CODE },
+        out(0x33),
+        Ldi16 { rd: A0, imm: 1 },
+        out(0x32),
+        Sysret,
+    ]);
+
+    image.resize(HANDLER as usize, 0);
+    image.extend(assemble(&[
+        In {
+            rd: A0,
+            rb: Zero,
+            offset: 0x34,
+        },
+        store(CAUSE as i32),
+        In {
+            rd: A0,
+            rb: Zero,
+            offset: 0x35,
+        },
+        store(FAULT_ADDRESS as i32),
+        Branch16 {
+            cond: BranchCondition::True,
+            offset: -2,
+        },
+    ]));
+    image
+}
+
+/// Runs `program` as user code, returning the recorded access violation cause and address.
+fn run_user(program: &[Instruction]) -> (Art32, u32, u32) {
+    let mut art32 = Art32::with_kernel(&protection_kernel());
+
+    let mut code = assemble(program);
+    code.extend(assemble(&[Instruction::Branch16 {
+        cond: BranchCondition::True,
+        offset: -2,
+    }]));
+    for (offset, &byte) in code.iter().enumerate() {
+        system_ram_mut(&mut art32).write_8(offset as u32, byte);
+    }
+
+    for _ in 0..100 {
+        assert_eq!(art32.step(), Ok(None));
+    }
+
+    let cause = kernel_ram(&art32).read_32(CAUSE);
+    let addr = kernel_ram(&art32).read_32(FAULT_ADDRESS);
+    (art32, cause, addr)
+}
+
+fn load_address(rd: Register, addr: u32) -> Instruction {
+    Instruction::Ldui { rd, imm: addr }
+}
+
+fn load(rb: Register, offset: i32) -> Instruction {
+    Instruction::Load32 {
+        op: LoadOp::Word,
+        rd: Register::A2,
+        rb,
+        offset,
+    }
+}
+
+fn store(rb: Register, offset: i32) -> Instruction {
+    Instruction::Store32 {
+        op: StoreOp::Word,
+        rs: Register::A2,
+        rb,
+        offset,
+    }
+}
+
+const ACCESS_VIOLATION: u32 = 1;
+const FETCH_FAULT: u32 = 4;
+const FETCH: u32 = 1 << 8;
+const LOAD: u32 = 2 << 8;
+const STORE: u32 = 3 << 8;
+
+#[test]
+fn inside_regions() {
+    let (art32, cause, _) = run_user(&[
+        load_address(Register::A0, CODE),
+        load_address(Register::A1, DATA),
+        load(Register::A0, 0),
+        store(Register::A1, REGION_SIZE - 4),
+    ]);
+
+    assert_eq!(cause, 0);
+    assert_eq!(
+        system_ram(&art32).read_32(0x10FC),
+        system_ram(&art32).read_32(0)
+    );
+}
+
+#[test]
+fn outside_regions() {
+    let (_, cause, addr) = run_user(&[
+        load_address(Register::A1, DATA),
+        load(Register::A1, REGION_SIZE),
+    ]);
+    assert_eq!(cause, LOAD | ACCESS_VIOLATION);
+    assert_eq!(addr, DATA + (REGION_SIZE as u32));
+}
+
+#[test]
+fn read_only_region() {
+    let (_, cause, addr) = run_user(&[load_address(Register::A0, CODE), store(Register::A0, 8)]);
+    assert_eq!(cause, STORE | ACCESS_VIOLATION);
+    assert_eq!(addr, CODE + 8);
+}
+
+#[test]
+fn no_execute() {
+    let (_, cause, addr) = run_user(&[
+        load_address(Register::A1, DATA),
+        Instruction::Jump16 {
+            link: false,
+            rb: Register::A1,
+            offset: 0,
+        },
+    ]);
+    assert_eq!(cause, (ACCESS_VIOLATION << 16) | FETCH | FETCH_FAULT);
+    assert_eq!(addr, DATA);
+}
+
+fn protected_system() -> Art32 {
+    let art32 = Art32::with_kernel(&[]);
+    art32.protection.set_region(
+        3,
+        Region {
+            base: DATA,
+            size: 6,
+            flags: RegionFlags::READ | RegionFlags::WRITE,
+        },
+    );
+    art32.protection.set_control(ProtectionControl::ENABLE);
+    art32
+}
+
+#[test]
+fn whole_access_checked() {
+    let mut art32 = protected_system();
+    let mut mmu = mmu(&mut art32);
+
+    assert_eq!(mmu.read_32(DATA, PrivilegeLevel::User, false), Ok(0));
+    assert_eq!(
+        mmu.write_16(DATA + 4, 1, PrivilegeLevel::User, false),
+        Ok(true)
+    );
+    assert_eq!(
+        mmu.read_32(DATA + 4, PrivilegeLevel::User, false),
+        Err(MemoryError::AccessViolation)
+    );
+    assert_eq!(
+        mmu.read_8(DATA - 1, PrivilegeLevel::User, false),
+        Err(MemoryError::AccessViolation)
+    );
+}
+
+#[test]
+fn system_unrestricted() {
+    let mut art32 = protected_system();
+    let mut mmu = mmu(&mut art32);
+
+    assert_eq!(
+        mmu.write_32(SYSTEM_RAM_START, 1, PrivilegeLevel::System, false),
+        Ok(true)
+    );
+    assert!(mmu.fetch(SYSTEM_RAM_START, PrivilegeLevel::System).is_ok());
+    assert_eq!(
+        mmu.fetch(SYSTEM_RAM_START, PrivilegeLevel::User),
+        Err(MemoryError::AccessViolation)
+    );
+}
+
+#[test]
+fn disabled() {
+    let mut art32 = protected_system();
+    art32.protection.set_control(ProtectionControl::empty());
+    let mut mmu = mmu(&mut art32);
+
+    assert_eq!(
+        mmu.write_32(SYSTEM_RAM_START, 1, PrivilegeLevel::User, false),
+        Ok(true)
+    );
+
+    art32.reset();
+    assert_eq!(art32.protection.region(3), Region::default());
+}
+
+#[test]
+fn data_not_executable() {
+    let mut art32 = protected_system();
+    let mut mmu = mmu(&mut art32);
+
+    assert_eq!(mmu.read_16(DATA, PrivilegeLevel::User, false), Ok(0));
+    assert_eq!(
+        mmu.fetch_16(DATA, PrivilegeLevel::User),
+        Err(MemoryError::AccessViolation)
+    );
+}
+
+#[test]
+fn cached_fetch_checks_upper_half() {
+    let mut art32 = protected_system();
+    art32.protection.set_region(
+        4,
+        Region {
+            base: CODE,
+            size: 6,
+            flags: RegionFlags::EXECUTE,
+        },
+    );
+    let code = assemble(&[Instruction::AluI32 {
+        op: AluOp::Add,
+        rd: Register::A0,
+        rs1: Register::A0,
+        imm: 1,
+    }]);
+    for (offset, &byte) in code.iter().enumerate() {
+        system_ram_mut(&mut art32).write_8(4 + (offset as u32), byte);
+    }
+
+    // cached by system code, which is not confined to the regions
+    let mut cache = InstructionCache::new();
+    let mut mmu = mmu(&mut art32);
+    mmu.instruction_cache = Some(&mut cache);
+    assert!(mmu.fetch(CODE + 4, PrivilegeLevel::System).is_ok());
+    assert_eq!(
+        mmu.fetch(CODE + 4, PrivilegeLevel::User),
+        Err(MemoryError::AccessViolation)
+    );
+}
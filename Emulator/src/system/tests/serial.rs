@@ -0,0 +1,503 @@
+use super::super::serial::{HostOutput, Serial, SerialControl, SerialStatus};
+use super::super::{
+    Art32, SerialInput, SerialOutput, SerialTiming, TimeSource, KERNEL_RAM_START, SOFTCORE_CLOCK_HZ,
+};
+use super::kernel_ram;
+use crate::cpu::instruction::*;
+use crate::cpu::{BranchCondition, Register};
+use std::io::{Read, Write};
+use std::num::NonZeroU32;
+use std::time::{Duration, Instant};
+
+const HANDLER: i32 = 0x80;
+const USER: i32 = 0xC0;
+const INTERRUPTS: u32 = 0xF0;
+const RECEIVED: u32 = 0x100;
+
+/// Copies received bytes into kernel RAM and echoes them from the serial interrupt handler on slot 4.
+fn echo_kernel(rx_interrupt: bool) -> Vec<u8> {
+    use Instruction::*;
+    use Register::*;
+
+    let out = |offset| Out {
+        rs: A0,
+        rb: Zero,
+        offset,
+    };
+    let offset = |rd, imm| AluI32 {
+        op: AluOp::Add,
+        rd,
+        rs1: S0,
+        imm,
+    };
+    let input = |offset| In {
+        rd: A1,
+        rb: Zero,
+        offset,
+    };
+
+    let mut image = assemble(&[
+        Ldui {
+            rd: S0,
+            imm: KERNEL_RAM_START,
+        },
+        offset(A0, HANDLER),
+        out(0x04),
+        Ldi16 {
+            rd: A0,
+            imm: 1 << 4,
+        },
+        out(0x30),
+        Ldi16 { rd: A0, imm: 4 },
+        out(0x95),
+        Ldi16 {
+            rd: A0,
+            imm: rx_interrupt as i32,
+        },
+        out(0x94),
+        offset(S1, RECEIVED as i32),
+        offset(A0, USER),
+        out(0x33),
+        Sysret,
+    ]);
+
+    image.resize(HANDLER as usize, 0);
+    image.extend(assemble(&[
+        Addi16 { rd: A2, imm: 1 },
+        Store32 {
+            op: StoreOp::Word,
+            rs: A2,
+            rb: S0,
+            offset: INTERRUPTS as i32,
+        },
+        // offset 6
+        input(0x93),
+        Cmp16 { rs1: A1, rs2: Zero },
+        Branch16 {
+            cond: BranchCondition::Eq,
+            offset: 16,
+        },
+        input(0x92),
+        Store32 {
+            op: StoreOp::Byte,
+            rs: A1,
+            rb: S1,
+            offset: 0,
+        },
+        Out {
+            rs: A1,
+            rb: Zero,
+            offset: 0x90,
+        },
+        Addi16 { rd: S1, imm: 1 },
+        Branch16 {
+            cond: BranchCondition::True,
+            offset: -24,
+        },
+        // offset 30
+        Sysret,
+    ]));
+
+    image.resize(USER as usize, 0);
+    image.extend(assemble(&[Branch16 {
+        cond: BranchCondition::True,
+        offset: -2,
+    }]));
+    image
+}
+
+/// Echoes into a buffer unless another output is connected.
+fn echo(rx_interrupt: bool) -> Art32 {
+    let mut art32 = Art32::with_kernel(&echo_kernel(rx_interrupt));
+    art32.set_serial_output(Some(SerialOutput::Buffer)).unwrap();
+    art32
+}
+
+fn run(art32: &mut Art32, steps: usize) {
+    for _ in 0..steps {
+        assert_eq!(art32.step(), Ok(None));
+    }
+}
+
+fn received(art32: &Art32, len: u32) -> Vec<u8> {
+    (0..len)
+        .map(|i| kernel_ram(art32).read_8(RECEIVED + i))
+        .collect()
+}
+
+#[test]
+fn rx_interrupt() {
+    let mut art32 = echo(true);
+    run(&mut art32, 100);
+    assert_eq!(kernel_ram(&art32).read_32(INTERRUPTS), 0);
+
+    art32.push_serial_input(b"abc");
+    run(&mut art32, 100);
+    art32.push_serial_input(b"de");
+    run(&mut art32, 100);
+
+    assert_eq!(kernel_ram(&art32).read_32(INTERRUPTS), 2);
+    assert_eq!(received(&art32, 6), b"abcde\0");
+    assert!(art32.serial.rx_buffer.is_empty());
+    assert_eq!(art32.take_serial_output(), b"abcde");
+    assert_eq!(art32.take_serial_output(), b"");
+}
+
+#[test]
+fn rx_before_enable() {
+    let mut art32 = echo(true);
+    art32.push_serial_input(b"early");
+    run(&mut art32, 200);
+
+    assert_eq!(kernel_ram(&art32).read_32(INTERRUPTS), 1);
+    assert_eq!(received(&art32, 5), b"early");
+}
+
+#[test]
+fn rx_interrupt_disabled() {
+    let mut art32 = echo(false);
+    run(&mut art32, 100);
+
+    art32.push_serial_input(b"abc");
+    run(&mut art32, 100);
+
+    assert_eq!(kernel_ram(&art32).read_32(INTERRUPTS), 0);
+    assert_eq!(art32.serial.rx_buffer.len(), 3);
+}
+
+#[test]
+fn raw_output() {
+    let bytes = [0x01, 0x80, 0xFF, b'\r', b'\n'];
+    let mut art32 = echo(true);
+    art32.push_serial_input(&bytes);
+    run(&mut art32, 200);
+
+    assert_eq!(art32.take_serial_output(), bytes);
+}
+
+#[test]
+fn file_output() {
+    let path = std::env::temp_dir().join(format!("art32-serial-{}.log", std::process::id()));
+    let mut art32 = echo(true);
+    art32
+        .set_serial_output(Some(SerialOutput::File(path.clone())))
+        .unwrap();
+
+    art32.push_serial_input(b"log\xFF");
+    run(&mut art32, 200);
+    art32.set_serial_output(None).unwrap();
+
+    let log = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(log, b"log\xFF");
+}
+
+#[test]
+fn disconnected_output() {
+    let mut art32 = echo(true);
+    art32.set_serial_output(None).unwrap();
+    art32.push_serial_input(b"lost");
+    run(&mut art32, 200);
+
+    assert_eq!(received(&art32, 4), b"lost");
+    assert_eq!(art32.take_serial_output(), b"");
+}
+
+/// Steps until the handler received `len` bytes from the host source.
+fn run_until_received(art32: &mut Art32, len: u32) -> Vec<u8> {
+    let start = Instant::now();
+    while kernel_ram(art32).read_8(RECEIVED + len - 1) == 0 {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "serial input timed out"
+        );
+        run(art32, 100);
+    }
+
+    received(art32, len)
+}
+
+#[test]
+fn tcp_echo() {
+    let mut art32 = echo(true);
+    art32
+        .set_serial_input(Some("tcp:127.0.0.1:0".parse().unwrap()))
+        .unwrap();
+    art32
+        .set_serial_output(Some("tcp:127.0.0.1:0".parse().unwrap()))
+        .unwrap();
+
+    let endpoint = art32.serial_input_endpoint().unwrap();
+    assert_eq!(art32.serial_output_endpoint(), Some(endpoint));
+
+    let mut client = std::net::TcpStream::connect(endpoint).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    client.write_all(b"tcp").unwrap();
+    assert_eq!(run_until_received(&mut art32, 3), b"tcp");
+
+    let mut echoed = [0; 3];
+    client.read_exact(&mut echoed).unwrap();
+    assert_eq!(&echoed, b"tcp");
+}
+
+#[test]
+fn tcp_input_rebind() {
+    let mut art32 = echo(true);
+    art32
+        .set_serial_input(Some("tcp:127.0.0.1:0".parse().unwrap()))
+        .unwrap();
+    let endpoint: std::net::SocketAddr = art32.serial_input_endpoint().unwrap().parse().unwrap();
+
+    let mut client = std::net::TcpStream::connect(endpoint).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    client.write_all(b"a").unwrap();
+    assert_eq!(run_until_received(&mut art32, 1), b"a");
+
+    // disconnecting closes the client and releases the address
+    art32.set_serial_input(None).unwrap();
+    assert_eq!(client.read(&mut [0; 1]).unwrap(), 0);
+    art32
+        .set_serial_input(Some(SerialInput::Tcp(endpoint)))
+        .unwrap();
+    assert_eq!(
+        art32.serial_input_endpoint(),
+        Some(endpoint.to_string().as_str())
+    );
+}
+
+#[test]
+fn tcp_output() {
+    let mut art32 = echo(true);
+    art32
+        .set_serial_output(Some(SerialOutput::Tcp(([127, 0, 0, 1], 0).into())))
+        .unwrap();
+
+    let endpoint: std::net::SocketAddr = art32.serial_output_endpoint().unwrap().parse().unwrap();
+    let mut client = std::net::TcpStream::connect(endpoint).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_millis(10)))
+        .unwrap();
+
+    // bytes transmitted before the client got accepted are dropped, so keep sending
+    let start = Instant::now();
+    let mut echoed = [0; 1];
+    while client.peek(&mut echoed).is_err() || echoed[0] == 0 {
+        assert!(start.elapsed() < Duration::from_secs(10), "no output");
+        art32.push_serial_input(b"x");
+        run(&mut art32, 100);
+    }
+    client.read_exact(&mut echoed).unwrap();
+    assert_eq!(&echoed, b"x");
+
+    // disconnecting closes the client and releases the address
+    art32.set_serial_output(None).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    while client.read(&mut echoed).unwrap() != 0 {}
+    art32
+        .set_serial_output(Some(SerialOutput::Tcp(endpoint)))
+        .unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn pty_echo() {
+    let mut art32 = echo(true);
+    art32.set_serial_input(Some(SerialInput::Pty)).unwrap();
+    art32.set_serial_output(Some(SerialOutput::Pty)).unwrap();
+
+    let endpoint = art32.serial_input_endpoint().unwrap();
+    assert_eq!(art32.serial_output_endpoint(), Some(endpoint));
+
+    let mut terminal = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(endpoint)
+        .unwrap();
+    terminal.write_all(b"p\rty\x03").unwrap();
+
+    // raw mode passes line endings and control characters through
+    assert_eq!(run_until_received(&mut art32, 5), b"p\rty\x03");
+
+    let mut echoed = [0; 5];
+    terminal.read_exact(&mut echoed).unwrap();
+    assert_eq!(&echoed, b"p\rty\x03");
+}
+
+#[cfg(unix)]
+#[test]
+fn raw_terminal_signals() {
+    use super::super::serial::unix::{open_pty, Terminal};
+    use std::os::fd::AsRawFd;
+
+    let local_flags = |fd| {
+        // SAFETY: `termios` is plain data and `fd` is open
+        unsafe {
+            let mut termios: libc::termios = std::mem::zeroed();
+            assert_eq!(libc::tcgetattr(fd, &mut termios), 0);
+            termios.c_lflag
+        }
+    };
+
+    // a pty client's Ctrl-C is input for the guest
+    let (_master, pty, path) = open_pty().unwrap();
+    let slave = std::fs::File::open(&path).unwrap();
+    assert_eq!(local_flags(slave.as_raw_fd()) & libc::ISIG, 0);
+    drop(pty);
+
+    // on the user's terminal it stops the emulator, everything else stays raw
+    let slave_fd = slave.as_raw_fd();
+    let _terminal = Terminal::raw(slave_fd, Some(slave), true).unwrap();
+    let flags = local_flags(slave_fd);
+    assert_ne!(flags & libc::ISIG, 0);
+    assert_eq!(flags & (libc::ICANON | libc::ECHO), 0);
+}
+
+/// 100 ticks per RX and 110 ticks per TX frame.
+const SLOW: SerialTiming = SerialTiming {
+    baud_rate: NonZeroU32::new(100).unwrap(),
+    fifo_depth: 2,
+};
+const SLOW_CLOCK_HZ: u32 = 1000;
+
+fn slow_serial() -> Serial {
+    let mut serial = Serial::with_output(HostOutput::open(&SerialOutput::Buffer, None).unwrap());
+    serial.set_timing(Some(SLOW));
+    serial
+}
+
+fn sent(serial: &mut Serial) -> Vec<u8> {
+    serial.output.as_mut().unwrap().take_buffer()
+}
+
+#[test]
+fn tx_byte_time() {
+    let mut serial = slow_serial();
+    serial.transmit(1);
+    serial.transmit(2);
+    assert_eq!(serial.tx_space(), 1);
+
+    serial.poll(109, SLOW_CLOCK_HZ);
+    assert_eq!(sent(&mut serial), b"");
+    serial.poll(1, SLOW_CLOCK_HZ);
+    assert_eq!(sent(&mut serial), [1]);
+    assert_eq!(serial.tx_space(), 2);
+
+    // an idle line does not save up time for the next byte
+    serial.poll(1000, SLOW_CLOCK_HZ);
+    assert_eq!(sent(&mut serial), [2]);
+    serial.transmit(3);
+    serial.poll(109, SLOW_CLOCK_HZ);
+    assert_eq!(sent(&mut serial), b"");
+    serial.poll(1, SLOW_CLOCK_HZ);
+    assert_eq!(sent(&mut serial), [3]);
+}
+
+#[test]
+fn tx_fifo_full() {
+    let mut serial = slow_serial();
+    for byte in 1..=5 {
+        serial.transmit(byte);
+    }
+
+    // one byte in the shift register and two in the FIFO, the rest got dropped
+    assert_eq!(serial.tx_space(), 0);
+    serial.poll(1000, SLOW_CLOCK_HZ);
+    assert_eq!(sent(&mut serial), [1, 2, 3]);
+}
+
+#[test]
+fn rx_overrun() {
+    let mut serial = slow_serial();
+    serial.control = SerialControl::RX_INTERRUPT;
+    serial.slot = 3;
+    serial.push(&[1, 2, 3, 4]);
+
+    assert_eq!(serial.poll(99, SLOW_CLOCK_HZ), None);
+    assert!(serial.rx_buffer.is_empty());
+    assert_eq!(serial.poll(1, SLOW_CLOCK_HZ), Some(3));
+    assert_eq!(serial.rx_buffer, [1]);
+    assert_eq!(serial.status, SerialStatus::empty());
+
+    assert_eq!(serial.poll(300, SLOW_CLOCK_HZ), Some(3));
+    assert_eq!(serial.rx_buffer, [1, 2]);
+    assert_eq!(serial.status, SerialStatus::RX_OVERRUN);
+}
+
+#[test]
+fn instant_transfers() {
+    let mut serial = slow_serial();
+    serial.transmit(1);
+    serial.push(&[2, 3, 4]);
+
+    // switching modes delivers the bytes in flight
+    serial.set_timing(None);
+    assert_eq!(sent(&mut serial), [1]);
+    assert_eq!(serial.rx_buffer, [2, 3, 4]);
+    assert_eq!(serial.tx_space(), u32::MAX);
+
+    serial.transmit(5);
+    assert_eq!(sent(&mut serial), [5]);
+}
+
+#[test]
+fn kernel_flow_control() {
+    const TICKS_PER_BYTE: u64 = 11 * (SOFTCORE_CLOCK_HZ.get() as u64) / 115_200;
+
+    let mut art32 = Art32::new();
+    art32.set_serial_output(Some(SerialOutput::Buffer)).unwrap();
+    art32.set_time_source(TimeSource::Cycles {
+        clock_hz: SOFTCORE_CLOCK_HZ,
+    });
+    art32.set_serial_timing(Some(SerialTiming {
+        fifo_depth: 4,
+        ..SerialTiming::SOFTCORE
+    }));
+
+    // `serial_print_char` waits for room once the FIFO is full
+    run(&mut art32, 1000);
+    assert_eq!(art32.take_serial_output(), b"");
+    assert_eq!(art32.serial.tx_space(), 0);
+
+    let mut output = Vec::new();
+    while output.len() < 12 {
+        assert!(art32.cycles() < 20 * TICKS_PER_BYTE, "output stalled");
+        run(&mut art32, 100);
+        output.extend(art32.take_serial_output());
+    }
+
+    assert_eq!(output, b"Hello world!");
+    assert!(art32.cycles() >= 12 * TICKS_PER_BYTE);
+}
+
+#[test]
+fn parse_input() {
+    assert_eq!("stdin".parse(), Ok(SerialInput::Stdin));
+    assert_eq!("pty".parse(), Ok(SerialInput::Pty));
+    assert_eq!(
+        "tcp:127.0.0.1:2323".parse(),
+        Ok(SerialInput::Tcp(([127, 0, 0, 1], 2323).into()))
+    );
+    assert!("tcp:localhost".parse::<SerialInput>().is_err());
+    assert!("uart".parse::<SerialInput>().is_err());
+}
+
+#[test]
+fn parse_output() {
+    assert_eq!("stdout".parse(), Ok(SerialOutput::Stdout));
+    assert_eq!("buffer".parse(), Ok(SerialOutput::Buffer));
+    assert_eq!(
+        "file:serial.log".parse(),
+        Ok(SerialOutput::File("serial.log".into()))
+    );
+    assert_eq!(
+        "tcp:127.0.0.1:2323".parse(),
+        Ok(SerialOutput::Tcp(([127, 0, 0, 1], 2323).into()))
+    );
+    assert!("stdin".parse::<SerialOutput>().is_err());
+}
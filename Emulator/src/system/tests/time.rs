@@ -0,0 +1,161 @@
+use super::super::{Art32, TimeSource, KERNEL_RAM_START, SOFTCORE_CLOCK_HZ};
+use super::{kernel_ram, run_until_env_action};
+use crate::cpu::instruction::*;
+use crate::cpu::{Register, TimingModel};
+use crate::system::EnvAction;
+use std::num::NonZeroU32;
+
+const RESULTS: u32 = 0x100;
+
+/// Stores the retired instruction count followed by the time and its accuracy into kernel RAM.
+fn time_kernel() -> Vec<u8> {
+    use Instruction::*;
+    use Register::*;
+
+    let mut program = vec![
+        Ldui {
+            rd: S0,
+            imm: KERNEL_RAM_START,
+        },
+        Ldi16 { rd: A0, imm: 0 },
+    ];
+
+    // keep the timing model busy with a few load-use stalls
+    for _ in 0..4 {
+        program.extend([
+            Load32 {
+                op: LoadOp::Word,
+                rd: A0,
+                rb: S0,
+                offset: 0,
+            },
+            Alu16 {
+                op: AluOp::Add,
+                rd: A0,
+                rs: A0,
+            },
+        ]);
+    }
+
+    for (i, port) in [0x62, 0x80, 0x81, 0x82].into_iter().enumerate() {
+        program.push(In {
+            rd: A1,
+            rb: Zero,
+            offset: port,
+        });
+        program.push(Store32 {
+            op: StoreOp::Word,
+            rs: A1,
+            rb: S0,
+            offset: (RESULTS as i32) + (i as i32) * 4,
+        });
+    }
+
+    program.push(Envcall(EnvAction::Break as u8));
+    assemble(&program)
+}
+
+fn read_time(time_source: TimeSource) -> [u32; 4] {
+    read_time_with(time_source, None)
+}
+
+fn read_time_with(time_source: TimeSource, timing_model: Option<TimingModel>) -> [u32; 4] {
+    let mut art32 = Art32::with_kernel(&time_kernel());
+    art32.set_time_source(time_source);
+    art32.set_timing_model(timing_model);
+
+    assert_eq!(run_until_env_action(&mut art32), EnvAction::Break);
+    std::array::from_fn(|i| kernel_ram(&art32).read_32(RESULTS + (i as u32) * 4))
+}
+
+#[test]
+fn instruction_time() {
+    let time_source = TimeSource::Instructions {
+        clock_hz: SOFTCORE_CLOCK_HZ,
+    };
+    let [retired, low, high, accuracy] = read_time(time_source);
+
+    // the time is read by the instruction after the store following the counter read
+    assert_eq!(low, (retired + 2) * 25);
+    assert_eq!(high, 0);
+    assert_eq!(accuracy, 25);
+}
+
+#[test]
+fn cycle_time() {
+    let time_source = TimeSource::Cycles {
+        clock_hz: SOFTCORE_CLOCK_HZ,
+    };
+    let timed = || read_time_with(time_source, Some(TimingModel::default()));
+    let [retired, low, _, accuracy] = timed();
+
+    assert_eq!(low % 25, 0);
+    assert!(low > (retired + 2) * 25);
+    assert_eq!(accuracy, 25);
+    assert_eq!(timed(), timed());
+
+    // without the timing model every instruction takes a single cycle
+    let [retired, low, _, _] = read_time(time_source);
+    assert_eq!(low, (retired + 2) * 25);
+}
+
+#[test]
+fn accuracy_rounds_up() {
+    let time_source = TimeSource::Instructions {
+        clock_hz: NonZeroU32::new(3).unwrap(),
+    };
+    let [retired, low, _, accuracy] = read_time(time_source);
+
+    assert_eq!(low, (((retired as u64) + 2) * 1_000_000_000 / 3) as u32);
+    assert_eq!(accuracy, 333_333_334);
+}
+
+#[test]
+fn host_time() {
+    let [_, _, _, accuracy] = read_time(TimeSource::Host);
+    assert_eq!(accuracy, 1);
+}
+
+#[test]
+fn parse() {
+    assert_eq!("host".parse(), Ok(TimeSource::Host));
+    assert_eq!(
+        "instructions".parse(),
+        Ok(TimeSource::Instructions {
+            clock_hz: SOFTCORE_CLOCK_HZ
+        })
+    );
+    assert_eq!(
+        "cycles:1_000_000".parse(),
+        Ok(TimeSource::Cycles {
+            clock_hz: NonZeroU32::new(1_000_000).unwrap()
+        })
+    );
+
+    for invalid in ["", "host:100", "cycles:0", "cycles:fast", "wall"] {
+        assert!(invalid.parse::<TimeSource>().is_err(), "{invalid}");
+    }
+}
+
+#[test]
+fn machine_description() {
+    use super::super::MachineDescription;
+
+    let json = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/machines/art32.json"))
+        .unwrap();
+    let json = json.replacen('{', r#"{ "time_source": "instructions:1000", "#, 1);
+    let machine = MachineDescription::from_json(&json).unwrap();
+    assert_eq!(
+        machine.time_source,
+        TimeSource::Instructions {
+            clock_hz: NonZeroU32::new(1000).unwrap()
+        }
+    );
+
+    // runs are reproducible without setting the time source from code
+    let art32 = Art32::with_machine(&machine, &time_kernel()).unwrap();
+    assert_eq!(art32.time_source, machine.time_source);
+
+    let json = json.replace("instructions:1000", "cycles:0");
+    assert!(MachineDescription::from_json(&json).is_err());
+}
@@ -0,0 +1,200 @@
+use super::super::timer::{Timer, TimerControl};
+use super::super::{Art32, KERNEL_RAM_START};
+use super::kernel_ram;
+use crate::cpu::instruction::*;
+use crate::cpu::{BranchCondition, Register};
+
+#[test]
+fn periodic() {
+    let mut timer = Timer {
+        control: TimerControl::ENABLE | TimerControl::PERIODIC | TimerControl::INTERRUPT,
+        compare: 10,
+        slot: 5,
+        ..Default::default()
+    };
+
+    assert_eq!(timer.advance(9), None);
+    assert!(!timer.matched);
+    assert_eq!(timer.advance(1), Some(5));
+    assert!(timer.matched);
+    assert_eq!(timer.count, 0);
+
+    // several periods within one step only fire once
+    assert_eq!(timer.advance(34), Some(5));
+    assert_eq!(timer.count, 4);
+    assert!(timer.control.contains(TimerControl::ENABLE));
+}
+
+#[test]
+fn one_shot() {
+    let mut timer = Timer {
+        control: TimerControl::ENABLE | TimerControl::INTERRUPT,
+        compare: 10,
+        slot: 2,
+        ..Default::default()
+    };
+
+    assert_eq!(timer.advance(15), Some(2));
+    assert_eq!(timer.count, 10);
+    assert!(!timer.control.contains(TimerControl::ENABLE));
+    assert_eq!(timer.advance(100), None);
+}
+
+#[test]
+fn compare_zero() {
+    let mut timer = Timer {
+        control: TimerControl::ENABLE | TimerControl::PERIODIC | TimerControl::INTERRUPT,
+        compare: 0,
+        slot: 1,
+        ..Default::default()
+    };
+
+    // matches when the count wraps, not on every advance
+    assert_eq!(timer.advance(1), None);
+    assert_eq!(timer.advance(u32::MAX as u64 - 2), None);
+    assert_eq!(timer.count, u32::MAX - 1);
+    assert_eq!(timer.advance(3), Some(1));
+    assert_eq!(timer.count, 1);
+
+    timer.control = TimerControl::ENABLE | TimerControl::INTERRUPT;
+    timer.count = u32::MAX;
+    assert_eq!(timer.advance(1), Some(1));
+    assert_eq!(timer.count, 0);
+    assert!(!timer.control.contains(TimerControl::ENABLE));
+}
+
+#[test]
+fn interrupt_disabled() {
+    let mut timer = Timer {
+        control: TimerControl::ENABLE | TimerControl::PERIODIC,
+        compare: 10,
+        ..Default::default()
+    };
+
+    assert_eq!(timer.advance(10), None);
+    assert!(timer.matched);
+
+    timer.control = TimerControl::empty();
+    timer.matched = false;
+    assert_eq!(timer.advance(10), None);
+    assert!(!timer.matched);
+}
+
+const HANDLER: i32 = 0x80;
+const USER: i32 = 0xA0;
+const COUNTER: u32 = 0x100;
+const PERIOD: i32 = 500;
+
+/// Counts timer interrupts on slot 3 into kernel RAM while looping at system privilege.
+fn timer_kernel(control: TimerControl) -> Vec<u8> {
+    use Instruction::*;
+    use Register::*;
+
+    let out = |offset| Out {
+        rs: A0,
+        rb: Zero,
+        offset,
+    };
+    let offset = |imm| AluI32 {
+        op: AluOp::Add,
+        rd: A0,
+        rs1: S0,
+        imm,
+    };
+
+    let mut image = assemble(&[
+        Ldui {
+            rd: S0,
+            imm: KERNEL_RAM_START,
+        },
+        offset(HANDLER),
+        out(0x03),
+        Ldi16 {
+            rd: A0,
+            imm: 1 << 3,
+        },
+        out(0x30),
+        Ldi16 { rd: A0, imm: 3 },
+        out(0x87),
+        Ldi16 {
+            rd: A0,
+            imm: PERIOD,
+        },
+        out(0x84),
+        Ldi16 {
+            rd: A0,
+            imm: control.bits() as i32,
+        },
+        out(0x83),
+        offset(USER),
+        out(0x33),
+        Sysret,
+    ]);
+
+    image.resize(HANDLER as usize, 0);
+    image.extend(assemble(&[
+        Addi16 { rd: A2, imm: 1 },
+        Store32 {
+            op: StoreOp::Word,
+            rs: A2,
+            rb: S0,
+            offset: COUNTER as i32,
+        },
+        Ldi16 { rd: A0, imm: 1 },
+        out(0x86),
+        Sysret,
+    ]));
+
+    image.resize(USER as usize, 0);
+    image.extend(assemble(&[Branch16 {
+        cond: BranchCondition::True,
+        offset: -2,
+    }]));
+    image
+}
+
+fn run_for(art32: &mut Art32, cycles: u64) {
+    while art32.cycles() < cycles {
+        assert_eq!(art32.step(), Ok(None));
+    }
+}
+
+#[test]
+fn periodic_interrupt() {
+    let control = TimerControl::ENABLE | TimerControl::PERIODIC | TimerControl::INTERRUPT;
+    let mut art32 = Art32::with_kernel(&timer_kernel(control));
+
+    run_for(&mut art32, 10 * (PERIOD as u64) + 50);
+    assert_eq!(kernel_ram(&art32).read_32(COUNTER), 10);
+    assert!(!art32.timer.matched);
+}
+
+#[test]
+fn one_shot_interrupt() {
+    let control = TimerControl::ENABLE | TimerControl::INTERRUPT;
+    let mut art32 = Art32::with_kernel(&timer_kernel(control));
+
+    run_for(&mut art32, 10 * (PERIOD as u64));
+    assert_eq!(kernel_ram(&art32).read_32(COUNTER), 1);
+    assert!(!art32.timer.control.contains(TimerControl::ENABLE));
+}
+
+#[test]
+fn deterministic() {
+    let control = TimerControl::ENABLE | TimerControl::PERIODIC | TimerControl::INTERRUPT;
+    let kernel = timer_kernel(control);
+
+    let trace = |block_translation: bool| {
+        let mut art32 = Art32::with_kernel(&kernel);
+        art32.set_block_translation(block_translation.then_some(0));
+        (0..5000)
+            .map(|_| {
+                art32.step().unwrap();
+                art32.timer.count
+            })
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(trace(false), trace(false));
+    assert_eq!(trace(true), trace(true));
+}
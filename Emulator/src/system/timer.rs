@@ -0,0 +1,59 @@
+use bitflags::bitflags;
+
+bitflags! {
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    #[repr(transparent)]
+    pub struct TimerControl : u32 {
+        const ENABLE = 0x1;
+        /// Restarts counting from zero after a match instead of disabling the timer.
+        const PERIODIC = 0x2;
+        const INTERRUPT = 0x4;
+    }
+}
+
+/// Counts emulated clock ticks and signals a hardware interrupt once the count reaches the
+/// compare value. A compare value of zero matches when the 32 bit count wraps around, so the
+/// period is 2^32 ticks.
+#[derive(Debug, Default)]
+pub struct Timer {
+    pub control: TimerControl,
+    pub compare: u32,
+    pub count: u32,
+    pub matched: bool,
+    pub slot: u8,
+}
+
+impl Timer {
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Advances the timer by `ticks`, returning the interrupt slot to signal on a match.
+    pub fn advance(&mut self, ticks: u64) -> Option<usize> {
+        if !self.control.contains(TimerControl::ENABLE) {
+            return None;
+        }
+
+        let count = (self.count as u64) + ticks;
+        let period = match self.compare {
+            0 => 1 << 32,
+            compare => compare as u64,
+        };
+        if count < period {
+            self.count = count as u32;
+            return None;
+        }
+
+        self.matched = true;
+        if self.control.contains(TimerControl::PERIODIC) {
+            self.count = ((count - period) % period) as u32;
+        } else {
+            self.count = self.compare;
+            self.control.remove(TimerControl::ENABLE);
+        }
+
+        self.control
+            .contains(TimerControl::INTERRUPT)
+            .then_some(self.slot as usize)
+    }
+}
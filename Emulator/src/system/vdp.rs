@@ -0,0 +1,49 @@
+// 800x600 timing of the Softcore's sync generator, every line and frame starts with its
+// front porch, sync and back porch before the active part.
+const H_BLANK_CLOCKS: u64 = 40 + 128 + 88;
+const LINE_CLOCKS: u64 = H_BLANK_CLOCKS + 800;
+const V_BLANK_LINES: u64 = 1 + 4 + 23;
+const FRAME_LINES: u64 = V_BLANK_LINES + 600;
+
+const OFFSET_MASK: u32 = 0x1FF;
+
+/// Registers of the Softcore's video display processor. Its pixel clock is the nominal CPU clock,
+/// so the beam position follows from the emulated ticks.
+#[derive(Debug, Default)]
+pub struct Vdp {
+    h_offset: u32,
+    v_offset: u32,
+}
+
+impl Vdp {
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    #[inline]
+    pub fn h_offset(&self) -> u32 {
+        self.h_offset
+    }
+
+    #[inline]
+    pub fn set_h_offset(&mut self, offset: u32) {
+        self.h_offset = offset & OFFSET_MASK;
+    }
+
+    #[inline]
+    pub fn v_offset(&self) -> u32 {
+        self.v_offset
+    }
+
+    #[inline]
+    pub fn set_v_offset(&mut self, offset: u32) {
+        self.v_offset = offset & OFFSET_MASK;
+    }
+
+    /// Whether the beam is in horizontal and in vertical blanking `ticks` clocks after reset.
+    #[inline]
+    pub fn blanking(ticks: u64) -> (bool, bool) {
+        let line = (ticks / LINE_CLOCKS) % FRAME_LINES;
+        ((ticks % LINE_CLOCKS) < H_BLANK_CLOCKS, line < V_BLANK_LINES)
+    }
+}